@@ -0,0 +1,13 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use smallchain::encoding::{Decode, Encode};
+use smallchain::prelude::*;
+
+// The encoding is canonical: whatever decodes must encode back to the same bytes.
+fuzz_target!(|bytes: &[u8]| {
+    if let Ok(block) = Block::decode(bytes) {
+        assert_eq!(block.encode(), bytes);
+        let _ = block.hash();
+    }
+});
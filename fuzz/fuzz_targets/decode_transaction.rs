@@ -0,0 +1,16 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use smallchain::encoding::{Decode, Encode};
+use smallchain::prelude::*;
+
+// The raw transactions of `/node/{address}/transaction/raw`, and the ones inside blocks.
+fuzz_target!(|bytes: &[u8]| {
+    if let Ok(transaction) = Transaction::decode(bytes) {
+        assert_eq!(transaction.encode(), bytes);
+        let _ = transaction.cost();
+    }
+    if let Ok(transaction) = BlockTransaction::decode(bytes) {
+        assert_eq!(transaction.encode(), bytes);
+    }
+});
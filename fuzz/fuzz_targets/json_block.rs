@@ -0,0 +1,16 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use smallchain::encoding::Encode;
+use smallchain::prelude::*;
+
+// Blocks submitted to the REST API, read from the data directory or from a capture file.
+fuzz_target!(|bytes: &[u8]| {
+    if let Ok(block) = serde_json::from_slice::<Block>(bytes) {
+        let _ = block.encode();
+        let _ = block.hash();
+        let _ = block.fees();
+        let json = serde_json::to_string(&block).unwrap();
+        assert_eq!(serde_json::from_str::<Block>(&json).unwrap(), block);
+    }
+});
@@ -0,0 +1,21 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use smallchain::prelude::*;
+use std::str::FromStr;
+
+// The parameters of the REST API, which are parsed from the path and the query of requests.
+fuzz_target!(|s: &str| {
+    if let Ok(hash) = BlockHash::from_str(s) {
+        assert_eq!(BlockHash::from_str(&hash.to_string()), Ok(hash));
+    }
+    if let Ok(address) = Address::from_str(s) {
+        assert_eq!(address.to_string(), s);
+    }
+    if let Ok(id) = TransactionId::from_str(s) {
+        assert_eq!(TransactionId::from_str(&id.to_string()), Ok(id));
+    }
+    if let Ok(amount) = Amount::from_str(s) {
+        assert_eq!(Amount::from_str(&amount.to_string()), Ok(amount));
+    }
+});
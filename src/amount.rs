@@ -0,0 +1,215 @@
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+
+/// The number of units in a coin.
+pub const UNITS_PER_COIN: u64 = 100_000_000;
+
+/// The number of decimal digits of the units of a coin.
+const COIN_DECIMALS: usize = 8;
+
+/// An amount of money, counted in the smallest indivisible unit.
+///
+/// It is formatted and parsed in coins, e.g. `"1.5 coins"`. In JSON and in the configuration
+/// file, an amount is a string of coins, with at most 8 decimals and an optional `coins` suffix,
+/// or an integer number of coins.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct Amount(u64);
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum AmountParseError {
+    /// The amount is not a non-negative decimal number.
+    Invalid,
+    /// The amount has more than 8 decimals.
+    TooPrecise,
+    /// The amount does not fit in 64 bits of units.
+    Overflow,
+}
+
+impl fmt::Display for AmountParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AmountParseError::Invalid => write!(f, "not a non-negative number of coins"),
+            AmountParseError::TooPrecise => {
+                write!(f, "coins have at most {COIN_DECIMALS} decimals")
+            }
+            AmountParseError::Overflow => write!(f, "the amount is too large"),
+        }
+    }
+}
+
+impl std::error::Error for AmountParseError {}
+
+impl Amount {
+    pub const ZERO: Amount = Amount(0);
+
+    pub const fn from_units(units: u64) -> Self {
+        Amount(units)
+    }
+
+    /// An amount of whole coins. Panics on overflow, so it is meant for constants.
+    pub const fn from_coins(coins: u64) -> Self {
+        match coins.checked_mul(UNITS_PER_COIN) {
+            Some(units) => Amount(units),
+            None => panic!("The amount of coins overflows"),
+        }
+    }
+
+    pub const fn units(&self) -> u64 {
+        self.0
+    }
+
+    /// The number of whole coins, rounded down.
+    pub const fn whole_coins(&self) -> u64 {
+        self.0 / UNITS_PER_COIN
+    }
+
+    pub fn checked_add(self, other: Amount) -> Option<Amount> {
+        self.0.checked_add(other.0).map(Amount)
+    }
+
+    pub fn checked_sub(self, other: Amount) -> Option<Amount> {
+        self.0.checked_sub(other.0).map(Amount)
+    }
+
+    pub fn checked_mul(self, factor: u64) -> Option<Amount> {
+        self.0.checked_mul(factor).map(Amount)
+    }
+
+    pub fn saturating_add(self, other: Amount) -> Amount {
+        Amount(self.0.saturating_add(other.0))
+    }
+
+    pub fn saturating_sub(self, other: Amount) -> Amount {
+        Amount(self.0.saturating_sub(other.0))
+    }
+
+    pub fn saturating_mul(self, factor: u64) -> Amount {
+        Amount(self.0.saturating_mul(factor))
+    }
+}
+
+impl fmt::Display for Amount {
+    /// Format the amount in coins, without trailing zero decimals, e.g. `1.5 coins`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let whole = self.0 / UNITS_PER_COIN;
+        let fraction = self.0 % UNITS_PER_COIN;
+        write!(f, "{whole}")?;
+        if fraction > 0 {
+            let decimals = format!("{fraction:0COIN_DECIMALS$}");
+            write!(f, ".{}", decimals.trim_end_matches('0'))?;
+        }
+        if self.0 == UNITS_PER_COIN {
+            write!(f, " coin")
+        } else {
+            write!(f, " coins")
+        }
+    }
+}
+
+impl FromStr for Amount {
+    type Err = AmountParseError;
+
+    /// Parse an amount of coins, such as `1.5`, `1.5 coins` or `1 coin`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let number = s
+            .strip_suffix("coins")
+            .or_else(|| s.strip_suffix("coin"))
+            .unwrap_or(s)
+            .trim_end();
+        let (whole, fraction) = number.split_once('.').unwrap_or((number, ""));
+        let is_digits = |part: &str| part.bytes().all(|byte| byte.is_ascii_digit());
+        if whole.is_empty() || !is_digits(whole) || !is_digits(fraction) {
+            return Err(AmountParseError::Invalid);
+        }
+        if fraction.len() > COIN_DECIMALS {
+            return Err(AmountParseError::TooPrecise);
+        }
+        let whole: u64 = whole.parse().map_err(|_| AmountParseError::Overflow)?;
+        let fraction: u64 = format!("{fraction:0<COIN_DECIMALS$}")
+            .parse()
+            .map_err(|_| AmountParseError::Invalid)?;
+        whole
+            .checked_mul(UNITS_PER_COIN)
+            .and_then(|units| units.checked_add(fraction))
+            .map(Amount)
+            .ok_or(AmountParseError::Overflow)
+    }
+}
+
+impl Serialize for Amount {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Amount {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct AmountVisitor;
+
+        impl Visitor<'_> for AmountVisitor {
+            type Value = Amount;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "an amount of coins, e.g. \"1.5 coins\" or 2")
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<Amount, E> {
+                value.parse().map_err(E::custom)
+            }
+
+            fn visit_u64<E: de::Error>(self, coins: u64) -> Result<Amount, E> {
+                coins
+                    .checked_mul(UNITS_PER_COIN)
+                    .map(Amount)
+                    .ok_or_else(|| E::custom(AmountParseError::Overflow))
+            }
+
+            fn visit_i64<E: de::Error>(self, coins: i64) -> Result<Amount, E> {
+                let coins =
+                    u64::try_from(coins).map_err(|_| E::custom(AmountParseError::Invalid))?;
+                self.visit_u64(coins)
+            }
+        }
+
+        deserializer.deserialize_any(AmountVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_and_parse_coins() {
+        let amount = Amount::from_units(150_000_000);
+        assert_eq!(amount.to_string(), "1.5 coins");
+        assert_eq!(Amount::from_coins(1).to_string(), "1 coin");
+        assert_eq!(Amount::from_units(1).to_string(), "0.00000001 coins");
+        for raw in ["1.5", "1.5 coins", " 1.50000000coins ", "1.5 coin"] {
+            assert_eq!(raw.parse(), Ok(amount));
+        }
+        assert_eq!(
+            "0.000000001".parse::<Amount>(),
+            Err(AmountParseError::TooPrecise)
+        );
+        for raw in ["", ".5", "-1", "1e3", "one coin", "1.5.0"] {
+            assert_eq!(raw.parse::<Amount>(), Err(AmountParseError::Invalid));
+        }
+        assert_eq!(
+            "184467440738".parse::<Amount>(),
+            Err(AmountParseError::Overflow)
+        );
+
+        let json = serde_json::to_string(&amount).unwrap();
+        assert_eq!(json, "\"1.5 coins\"");
+        assert_eq!(serde_json::from_str::<Amount>(&json).unwrap(), amount);
+        assert_eq!(
+            serde_json::from_str::<Amount>("2").unwrap(),
+            Amount::from_coins(2)
+        );
+        assert!(serde_json::from_str::<Amount>("-2").is_err());
+    }
+}
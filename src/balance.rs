@@ -0,0 +1,170 @@
+use crate::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+
+/// The coins held by an address. Its updates are checked, so that an invalid block or
+/// transaction cannot make a balance wrap around.
+#[derive(
+    Copy, Clone, Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize,
+)]
+#[serde(transparent)]
+pub struct Balance(Amount);
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum BalanceError {
+    /// The balance would exceed the maximum number of coins.
+    Overflow,
+    /// The balance would become negative.
+    Underflow,
+}
+
+impl fmt::Display for BalanceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BalanceError::Overflow => write!(f, "the balance overflows"),
+            BalanceError::Underflow => write!(f, "the balance is too low"),
+        }
+    }
+}
+
+impl Balance {
+    pub fn new(amount: Amount) -> Self {
+        Balance(amount)
+    }
+
+    pub fn amount(&self) -> Amount {
+        self.0
+    }
+
+    /// Add coins to the balance. On error, the balance is left untouched.
+    pub fn credit(&mut self, amount: Amount) -> Result<(), BalanceError> {
+        self.0 = self.0.checked_add(amount).ok_or(BalanceError::Overflow)?;
+        Ok(())
+    }
+
+    /// Remove coins from the balance. On error, the balance is left untouched.
+    pub fn debit(&mut self, amount: Amount) -> Result<(), BalanceError> {
+        self.0 = self.0.checked_sub(amount).ok_or(BalanceError::Underflow)?;
+        Ok(())
+    }
+}
+
+impl fmt::Display for Balance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Move the amount plus the fee of a transaction from its sender, and the amount to its receiver,
+/// if it is not a burn. On error, the balances are left untouched.
+pub fn apply_transaction(
+    balance: &mut HashMap<Address, Balance>,
+    transaction: &Transaction,
+) -> Result<(), BalanceError> {
+    let cost = transaction.cost().ok_or(BalanceError::Overflow)?;
+    let mut sender_balance = balance
+        .get(&transaction.sender)
+        .copied()
+        .unwrap_or_default();
+    sender_balance.debit(cost)?;
+    let Some(receiver) = transaction.receiver else {
+        balance.insert(transaction.sender, sender_balance);
+        return Ok(());
+    };
+    let mut receiver_balance = if receiver == transaction.sender {
+        sender_balance
+    } else {
+        balance.get(&receiver).copied().unwrap_or_default()
+    };
+    receiver_balance.credit(transaction.amount)?;
+    balance.insert(transaction.sender, sender_balance);
+    balance.insert(receiver, receiver_balance);
+    Ok(())
+}
+
+/// Like `apply_transaction`, but without checking the balances, when the nodes skip the
+/// `balances` rule: the balance of the sender stops at zero, creating the missing coins, and the
+/// one of the receiver at the maximum amount.
+pub fn force_transaction(balance: &mut HashMap<Address, Balance>, transaction: &Transaction) {
+    let cost = transaction.amount.saturating_add(transaction.fee).min(
+        balance
+            .get(&transaction.sender)
+            .map_or(Amount::ZERO, Balance::amount),
+    );
+    let sender_balance = balance.entry(transaction.sender).or_default();
+    *sender_balance = Balance::new(sender_balance.amount().saturating_sub(cost));
+    if let Some(receiver) = transaction.receiver {
+        let receiver_balance = balance.entry(receiver).or_default();
+        *receiver_balance =
+            Balance::new(receiver_balance.amount().saturating_add(transaction.amount));
+    }
+}
+
+/// Undo `apply_transaction`. On error, the balances are left untouched.
+pub fn revert_transaction(
+    balance: &mut HashMap<Address, Balance>,
+    transaction: &Transaction,
+) -> Result<(), BalanceError> {
+    let cost = transaction.cost().ok_or(BalanceError::Overflow)?;
+    let Some(receiver) = transaction.receiver else {
+        let mut sender_balance = balance
+            .get(&transaction.sender)
+            .copied()
+            .unwrap_or_default();
+        sender_balance.credit(cost)?;
+        balance.insert(transaction.sender, sender_balance);
+        return Ok(());
+    };
+    let mut receiver_balance = balance.get(&receiver).copied().unwrap_or_default();
+    receiver_balance.debit(transaction.amount)?;
+    let mut sender_balance = if transaction.sender == receiver {
+        receiver_balance
+    } else {
+        balance
+            .get(&transaction.sender)
+            .copied()
+            .unwrap_or_default()
+    };
+    sender_balance.credit(cost)?;
+    balance.insert(receiver, receiver_balance);
+    balance.insert(transaction.sender, sender_balance);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reject_wrapping_balances() {
+        let units = |units| Balance::new(Amount::from_units(units));
+        let mut balance = units(u64::MAX - 1);
+        assert_eq!(
+            balance.credit(Amount::from_units(2)),
+            Err(BalanceError::Overflow)
+        );
+        assert_eq!(balance, units(u64::MAX - 1));
+
+        let (alice, bob) = (Address::new(1), Address::new(2));
+        let mut balances = HashMap::from([(alice, units(10)), (bob, units(u64::MAX))]);
+        let transfer =
+            Transaction::new(alice, bob, Amount::from_units(5)).with_fee(Amount::from_units(1));
+        assert_eq!(
+            apply_transaction(&mut balances, &transfer),
+            Err(BalanceError::Overflow)
+        );
+        assert_eq!(balances[&alice], units(10));
+
+        balances.insert(bob, units(0));
+        apply_transaction(&mut balances, &transfer).unwrap();
+        assert_eq!(balances[&alice], units(4));
+        // The receiver already spent the coins, so the transaction cannot be reverted.
+        balances.insert(bob, units(4));
+        assert_eq!(
+            revert_transaction(&mut balances, &transfer),
+            Err(BalanceError::Underflow)
+        );
+        assert_eq!(balances[&alice], units(4));
+    }
+}
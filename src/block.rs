@@ -1,15 +1,47 @@
+use crate::encoding::{Decode, DecodeError, Decoder, Encode, Encoder, ENCODING_VERSION};
 use crate::prelude::*;
 use rand::Rng;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use sha2::{Digest, Sha256};
+use std::ops::Range;
+
+/// Serialize and deserialize a type as a string, using its `Display` and `FromStr` implementations.
+/// This is the canonical JSON representation of addresses, identifiers and hashes, whose `u64` or
+/// byte-array representations would not be usable by clients.
+macro_rules! serde_as_string {
+    ($ty:ty) => {
+        impl Serialize for $ty {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.collect_str(self)
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $ty {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                let raw = String::deserialize(deserializer)?;
+                raw.parse().map_err(|err| {
+                    serde::de::Error::custom(format!(
+                        "invalid {}: {raw:?} ({err})",
+                        stringify!($ty)
+                    ))
+                })
+            }
+        }
+    };
+}
 
 /// In the blockchain, each address is associated to a certain amount of coins. Transactions can
 /// update this amount. Each node in the network is also identified by an address. Mining a block
 /// rewards the address of the miner with a certain amount of coins.
-#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
 pub struct Address(u64);
 
+serde_as_string!(Address);
+
 impl Address {
+    /// The address of the faucet, which the genesis block credits with `GENESIS_ALLOCATION`.
+    pub const FAUCET: Address = Address(0);
+
     pub fn new_random() -> Self {
         Address(rand::thread_rng().gen())
     }
@@ -19,27 +51,139 @@ impl Address {
     pub(crate) fn new(id: u64) -> Self {
         Address(id)
     }
+}
+
+/// The alphabet of the base58 encoding of addresses. It omits `0`, `O`, `I` and `l`, which are
+/// easily confused with each other.
+const BASE58_ALPHABET: &[u8; 58] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
 
-    pub fn from_str(s: &str) -> Result<Self, std::num::ParseIntError> {
-        Ok(Address(s.parse::<u64>()?))
+/// How many base58 digits encode the identifier and checksum of an address (96 bits).
+const ADDRESS_ENCODED_DIGITS: usize = 17;
+
+impl Address {
+    /// The checksum of an address, computed from the network prefix and the identifier.
+    fn checksum(&self) -> u32 {
+        let mut hasher = Sha256::new();
+        hasher.update(ADDRESS_PREFIX.as_bytes());
+        hasher.update(self.0.to_be_bytes());
+        let digest = hasher.finalize();
+        u32::from_be_bytes([digest[0], digest[1], digest[2], digest[3]])
+    }
+}
+
+/// The reasons why a string is not a valid address.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum AddressParseError {
+    /// The address does not start with `ADDRESS_PREFIX`.
+    MissingPrefix,
+    /// The address does not have the expected number of digits.
+    InvalidLength(usize),
+    /// The address contains a character outside the base58 alphabet.
+    InvalidCharacter(char),
+    /// The digits do not encode an identifier followed by its checksum.
+    InvalidChecksum,
+}
+
+impl std::fmt::Display for AddressParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            AddressParseError::MissingPrefix => write!(f, "missing prefix {ADDRESS_PREFIX:?}"),
+            AddressParseError::InvalidLength(len) => write!(
+                f,
+                "expected {ADDRESS_ENCODED_DIGITS} digits after the prefix, found {len}"
+            ),
+            AddressParseError::InvalidCharacter(c) => write!(f, "invalid character {c:?}"),
+            AddressParseError::InvalidChecksum => write!(f, "invalid checksum"),
+        }
+    }
+}
+
+impl std::str::FromStr for Address {
+    type Err = AddressParseError;
+
+    /// Parse an address in the format of its `Display` representation, checking its checksum.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let digits = s
+            .strip_prefix(ADDRESS_PREFIX)
+            .ok_or(AddressParseError::MissingPrefix)?;
+        let len = digits.chars().count();
+        if len != ADDRESS_ENCODED_DIGITS {
+            return Err(AddressParseError::InvalidLength(len));
+        }
+        let mut value: u128 = 0;
+        for c in digits.chars() {
+            let digit = BASE58_ALPHABET
+                .iter()
+                .position(|&symbol| symbol as char == c)
+                .ok_or(AddressParseError::InvalidCharacter(c))?;
+            // 17 base58 digits fit in 100 bits, so this cannot overflow.
+            value = value * 58 + digit as u128;
+        }
+        if value >> 96 != 0 {
+            return Err(AddressParseError::InvalidChecksum);
+        }
+        let address = Address((value >> 32) as u64);
+        if address.checksum() != value as u32 {
+            return Err(AddressParseError::InvalidChecksum);
+        }
+        Ok(address)
     }
 }
 
 impl std::fmt::Display for Address {
+    /// Encode the identifier followed by a 32-bit checksum as fixed-width base58, after the
+    /// network prefix.
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "@{}", self.0)
+        let mut value = ((self.0 as u128) << 32) | self.checksum() as u128;
+        let mut digits = [0u8; ADDRESS_ENCODED_DIGITS];
+        for digit in digits.iter_mut().rev() {
+            *digit = BASE58_ALPHABET[(value % 58) as usize];
+            value /= 58;
+        }
+        // The alphabet is ASCII.
+        let digits = std::str::from_utf8(&digits).unwrap();
+        write!(f, "{ADDRESS_PREFIX}{digits}")
     }
 }
 
-/// The identifier of the transaction. This needs to be unique at least among the transactions that
-/// are part of the same block.
-#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+/// How the identifiers of the transactions are chosen, in the protocol parameters: they were
+/// random in version 0, and are derived from the transactions by `TransactionId::of` since
+/// version 1. The blocks of one version are invalid in the other.
+pub const TRANSACTION_ID_VERSION: u8 = 1;
+
+/// The identifier of the transaction, derived from its content by `TransactionId::of`, so that
+/// the same transaction sent to several nodes has the same identifier everywhere.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
 pub struct TransactionId(u64);
 
+serde_as_string!(TransactionId);
+
 impl TransactionId {
+    /// An identifier that is unrelated to any transaction, in the tests.
+    #[cfg(test)]
     pub fn new_random() -> Self {
         TransactionId(rand::thread_rng().gen())
     }
+
+    /// The identifier of a transaction on top of the block `prefix_hash`: the first 8 bytes, as a
+    /// little-endian integer, of the SHA-256 hash of the encoding of `prefix_hash` and `info`.
+    pub fn of(prefix_hash: &BlockHash, info: &Transaction) -> Self {
+        let mut encoder = Encoder::default();
+        prefix_hash.encode_into(&mut encoder);
+        info.encode_into(&mut encoder);
+        let digest = Sha256::digest(encoder.finish());
+        TransactionId(u64::from_le_bytes(digest[..8].try_into().unwrap()))
+    }
+}
+
+impl std::str::FromStr for TransactionId {
+    type Err = std::num::ParseIntError;
+
+    /// Parse an identifier, with or without the `$` prefix of its `Display` representation.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.strip_prefix('$').unwrap_or(s);
+        Ok(TransactionId(s.parse::<u64>()?))
+    }
 }
 
 impl std::fmt::Display for TransactionId {
@@ -52,19 +196,108 @@ impl std::fmt::Display for TransactionId {
 pub struct Transaction {
     /// The address of the sender of the transaction.
     pub sender: Address,
-    /// The address of the receiver of the transaction.
-    pub receiver: Address,
+    /// The address of the receiver of the transaction, or `None` for a burn, which destroys the
+    /// coins. In JSON, the field is required: a burn has a `null` receiver.
+    #[serde(deserialize_with = "Option::deserialize")]
+    pub receiver: Option<Address>,
     /// The amount of transferred coins.
-    pub amount: u64,
+    pub amount: Amount,
+    /// The coins paid by the sender to the miner of the block that includes the transaction.
+    #[serde(default)]
+    pub fee: Amount,
+    /// Arbitrary data attached by the sender, which makes the transaction heavier.
+    #[serde(default, skip_serializing_if = "Memo::is_empty")]
+    pub memo: Memo,
 }
 
 impl Transaction {
-    pub fn new(sender: Address, receiver: Address, amount: u64) -> Self {
+    pub fn new(sender: Address, receiver: Address, amount: Amount) -> Self {
         Transaction {
             sender,
-            receiver,
+            receiver: Some(receiver),
+            amount,
+            fee: Amount::ZERO,
+            memo: Memo::default(),
+        }
+    }
+
+    /// Create a transaction that destroys the amount of coins of the sender.
+    pub fn burn(sender: Address, amount: Amount) -> Self {
+        Transaction {
+            sender,
+            receiver: None,
             amount,
+            fee: Amount::ZERO,
+            memo: Memo::default(),
+        }
+    }
+
+    /// Attach a memo.
+    pub fn with_memo(mut self, memo: Memo) -> Self {
+        self.memo = memo;
+        self
+    }
+
+    /// The share of the capacity of a block taken by the transaction: a base weight plus a weight
+    /// for each byte of the memo. Unlike the encoded size, it does not depend on the hashes.
+    pub fn weight(&self) -> u64 {
+        TRANSACTION_BASE_WEIGHT + WEIGHT_PER_MEMO_BYTE * self.memo.len() as u64
+    }
+
+    /// Whether the transaction destroys its amount instead of moving it to a receiver.
+    pub fn is_burn(&self) -> bool {
+        self.receiver.is_none()
+    }
+
+    /// Set the fee paid to the miner.
+    pub fn with_fee(mut self, fee: Amount) -> Self {
+        self.fee = fee;
+        self
+    }
+
+    /// The coins taken from the sender: the amount plus the fee, or `None` on overflow.
+    pub fn cost(&self) -> Option<Amount> {
+        self.amount.checked_add(self.fee)
+    }
+}
+
+/// The data attached to a transaction: a string of at most `MEMO_MAX_BYTES` bytes.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Ord, PartialOrd, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Memo(String);
+
+impl Memo {
+    /// The length of the memo, in bytes.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl TryFrom<String> for Memo {
+    type Error = String;
+
+    fn try_from(memo: String) -> Result<Self, Self::Error> {
+        if memo.len() > MEMO_MAX_BYTES {
+            return Err(format!(
+                "the memo has {} bytes, more than {MEMO_MAX_BYTES}",
+                memo.len()
+            ));
         }
+        Ok(Memo(memo))
+    }
+}
+
+impl From<Memo> for String {
+    fn from(memo: Memo) -> Self {
+        memo.0
     }
 }
 
@@ -80,13 +313,19 @@ pub struct BlockTransaction {
 }
 
 impl BlockTransaction {
-    pub fn new_with_random_id(prefix_hash: BlockHash, info: Transaction) -> Self {
+    /// A transaction on top of the block `prefix_hash`, with the identifier derived from them.
+    pub fn new(prefix_hash: BlockHash, info: Transaction) -> Self {
         BlockTransaction {
-            id: TransactionId::new_random(),
+            id: TransactionId::of(&prefix_hash, &info),
             prefix_hash,
             info,
         }
     }
+
+    /// Whether the identifier is the one derived from the content of the transaction.
+    pub fn has_valid_id(&self) -> bool {
+        self.id == TransactionId::of(&self.prefix_hash, &self.info)
+    }
 }
 
 impl std::fmt::Display for BlockTransaction {
@@ -96,38 +335,91 @@ impl std::fmt::Display for BlockTransaction {
 }
 
 /// The hash of a block. This is used to uniquely identify a block in the blockchain.
-#[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+#[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
 // TODO: it would be much more idiomatic to make this Copy. Vec<u8> is an overkill, because we
 // always know the number of bits.
 pub struct BlockHash(Vec<u8>);
 
+serde_as_string!(BlockHash);
+
 impl BlockHash {
+    /// The SHA-256 hash of some bytes, e.g. an encoded block.
+    pub fn digest(bytes: &[u8]) -> Self {
+        BlockHash(Sha256::digest(bytes).to_vec())
+    }
+
     pub fn inner(&self) -> &[u8] {
         &self.0
     }
 
-    pub fn from_str(s: &str) -> Result<Self, std::num::ParseIntError> {
-        let mut bytes = vec![];
-        for i in 0..s.len() / 2 {
-            let byte = u8::from_str_radix(&s[2 * i..2 * i + 2], 16)?;
-            bytes.push(byte);
+    /// Count the number of leading zero **bits** in the hash.
+    pub fn leading_zero_bits(&self) -> u32 {
+        leading_zero_bits(self.inner())
+    }
+}
+
+/// Count the number of leading zero bits in a sequence of bytes.
+fn leading_zero_bits(bytes: &[u8]) -> u32 {
+    let mut leading_zeros = 0;
+    for &value in bytes {
+        debug_assert!((value == 0) == (value.leading_zeros() == 8));
+        if value == 0 {
+            leading_zeros += 8;
+        } else {
+            leading_zeros += value.leading_zeros();
+            break;
         }
-        Ok(BlockHash(bytes))
     }
+    leading_zeros
+}
 
-    /// Count the number of leading zero **bits** in the hash.
-    pub fn leading_zero_bits(&self) -> u32 {
-        let mut leading_zeros = 0;
-        for &value in self.inner().iter() {
-            debug_assert!((value == 0) == (value.leading_zeros() == 8));
-            if value == 0 {
-                leading_zeros += 8;
-            } else {
-                leading_zeros += value.leading_zeros();
-                break;
-            }
+/// The reason why a string is not a valid block hash.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum HashParseError {
+    /// The hash contains a character that is not a hexadecimal digit.
+    InvalidCharacter(char),
+    /// The hash has an odd number of hexadecimal digits.
+    OddLength(usize),
+    /// The hash has more bytes than the binary encoding can store.
+    TooLong(usize),
+}
+
+impl std::fmt::Display for HashParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            HashParseError::InvalidCharacter(c) => write!(f, "invalid character {c:?}"),
+            HashParseError::OddLength(len) => write!(f, "odd number of hex digits ({len})"),
+            HashParseError::TooLong(len) => write!(f, "too many bytes ({len})"),
+        }
+    }
+}
+
+impl std::str::FromStr for BlockHash {
+    type Err = HashParseError;
+
+    /// Parse a hash, with or without the `#` prefix of its `Display` representation.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.strip_prefix('#').unwrap_or(s);
+        let digits = s
+            .chars()
+            .map(|c| {
+                c.to_digit(16)
+                    .map(|digit| digit as u8)
+                    .ok_or(HashParseError::InvalidCharacter(c))
+            })
+            .collect::<Result<Vec<u8>, _>>()?;
+        if digits.len() % 2 != 0 {
+            return Err(HashParseError::OddLength(digits.len()));
         }
-        leading_zeros
+        let bytes: Vec<u8> = digits
+            .chunks(2)
+            .map(|pair| pair[0] << 4 | pair[1])
+            .collect();
+        // The encoding of a block prefixes the hash with its length in one byte.
+        if bytes.len() > u8::MAX as usize {
+            return Err(HashParseError::TooLong(bytes.len()));
+        }
+        Ok(BlockHash(bytes))
     }
 }
 
@@ -168,6 +460,51 @@ impl Block {
         self.prefix_hash.inner().is_empty()
     }
 
+    /// The sum of the fees of the transactions, or `None` on overflow.
+    pub fn fees(&self) -> Option<Amount> {
+        self.transactions
+            .iter()
+            .try_fold(Amount::ZERO, |fees, transaction| {
+                fees.checked_add(transaction.info.fee)
+            })
+    }
+
+    /// The sum of the amounts destroyed by the burn transactions, or `None` on overflow.
+    pub fn burned(&self) -> Option<Amount> {
+        self.transactions
+            .iter()
+            .filter(|transaction| transaction.info.is_burn())
+            .try_fold(Amount::ZERO, |burned, transaction| {
+                burned.checked_add(transaction.info.amount)
+            })
+    }
+
+    /// The coins credited to the miner, i.e., the coins per mined block plus the fees, or `None`
+    /// on overflow.
+    pub fn reward(&self) -> Option<Amount> {
+        self.fees()?.checked_add(COINS_PER_MINED_BLOCK)
+    }
+
+    /// The total weight of the transactions.
+    pub fn weight(&self) -> u64 {
+        self.transactions
+            .iter()
+            .map(|transaction| transaction.info.weight())
+            .sum()
+    }
+
+    /// With the base-fee policy, the part of the fees that is burned, i.e., the base fee of each
+    /// transaction, or `None` on overflow.
+    pub fn burned_fees(&self, base_fee: Amount) -> Option<Amount> {
+        base_fee.checked_mul(self.transactions.len() as u64)
+    }
+
+    /// With the base-fee policy, the coins credited to the miner, i.e., the coins per mined block
+    /// plus the tips, or `None` on overflow or if a fee is below the base fee.
+    pub fn reward_with_base_fee(&self, base_fee: Amount) -> Option<Amount> {
+        self.reward()?.checked_sub(self.burned_fees(base_fee)?)
+    }
+
     pub fn new(
         transactions: Vec<BlockTransaction>,
         prefix_hash: BlockHash,
@@ -184,17 +521,26 @@ impl Block {
 
     /// Compute the hash of the block.
     pub fn hash(&self) -> BlockHash {
-        let mut hasher = Sha256::new();
-        let serialized: Vec<u8> = bincode::serialize(self).expect("Failed to serialize a block");
-        hasher.update(serialized);
-        let hash = hasher.finalize();
-        BlockHash(hash.to_vec())
+        BlockHash::digest(&self.hashed_bytes())
+    }
+
+    /// The bytes whose SHA-256 is the hash of the block: its encoding without the version byte, so
+    /// that bumping `ENCODING_VERSION` does not change the hash of the genesis block.
+    pub fn hashed_bytes(&self) -> Vec<u8> {
+        let mut encoded = self.encode();
+        encoded.remove(0);
+        encoded
     }
 
     /// Check if the nonce of the block is valid. Note: this does not check whether the transactions
     /// in the block are valid.
     pub fn is_valid_nonce(&self) -> bool {
-        self.hash().leading_zero_bits() >= MINING_DIFFICULTY
+        self.meets_difficulty(MINING_DIFFICULTY)
+    }
+
+    /// Check if the hash of the block has at least `difficulty` leading zero bits.
+    pub fn meets_difficulty(&self, difficulty: u32) -> bool {
+        self.hash().leading_zero_bits() >= difficulty
     }
 }
 
@@ -204,23 +550,300 @@ impl std::fmt::Display for Block {
     }
 }
 
+impl Encode for Address {
+    fn encode_into(&self, encoder: &mut Encoder) {
+        encoder.u64(self.0);
+    }
+}
+
+impl Decode for Address {
+    fn decode_from(decoder: &mut Decoder) -> Result<Self, DecodeError> {
+        Ok(Address(decoder.u64()?))
+    }
+}
+
+impl Encode for TransactionId {
+    fn encode_into(&self, encoder: &mut Encoder) {
+        encoder.u64(self.0);
+    }
+}
+
+impl Decode for TransactionId {
+    fn decode_from(decoder: &mut Decoder) -> Result<Self, DecodeError> {
+        Ok(TransactionId(decoder.u64()?))
+    }
+}
+
+impl Encode for BlockHash {
+    fn encode_into(&self, encoder: &mut Encoder) {
+        encoder.short_bytes(&self.0);
+    }
+}
+
+impl Decode for BlockHash {
+    fn decode_from(decoder: &mut Decoder) -> Result<Self, DecodeError> {
+        Ok(BlockHash(decoder.short_bytes()?.to_vec()))
+    }
+}
+
+impl Encode for Transaction {
+    fn encode_into(&self, encoder: &mut Encoder) {
+        encoder.u64(self.sender.0);
+        match self.receiver {
+            Some(receiver) => {
+                encoder.u8(1);
+                encoder.u64(receiver.0);
+            }
+            None => encoder.u8(0),
+        }
+        encoder.u64(self.amount.units());
+        encoder.u64(self.fee.units());
+        encoder.short_bytes(self.memo.as_str().as_bytes());
+    }
+}
+
+impl Transaction {
+    /// Decode a transaction of a block encoded with an older version, in which the fields added
+    /// since then take their default value: no fee, a receiver and no memo.
+    fn decode_versioned(decoder: &mut Decoder, version: u8) -> Result<Self, DecodeError> {
+        let sender = Address(decoder.u64()?);
+        let has_receiver = if version >= 3 { decoder.u8()? } else { 1 };
+        let receiver = match has_receiver {
+            0 => None,
+            1 => Some(Address(decoder.u64()?)),
+            _ => return Err(DecodeError::InvalidField("receiver")),
+        };
+        let amount = Amount::from_units(decoder.u64()?);
+        let fee = if version >= 2 {
+            Amount::from_units(decoder.u64()?)
+        } else {
+            Amount::ZERO
+        };
+        let memo = if version >= 4 {
+            String::from_utf8(decoder.short_bytes()?.to_vec())
+                .map_err(|_| DecodeError::InvalidField("memo"))?
+        } else {
+            String::new()
+        };
+        Ok(Transaction {
+            sender,
+            receiver,
+            amount,
+            fee,
+            memo: Memo(memo),
+        })
+    }
+}
+
+impl Decode for Transaction {
+    fn decode_from(decoder: &mut Decoder) -> Result<Self, DecodeError> {
+        Transaction::decode_versioned(decoder, ENCODING_VERSION)
+    }
+}
+
+impl Encode for BlockTransaction {
+    fn encode_into(&self, encoder: &mut Encoder) {
+        encoder.u64(self.id.0);
+        self.prefix_hash.encode_into(encoder);
+        self.info.encode_into(encoder);
+    }
+}
+
+impl BlockTransaction {
+    /// Decode a transaction of a block encoded with an older version.
+    fn decode_versioned(decoder: &mut Decoder, version: u8) -> Result<Self, DecodeError> {
+        Ok(BlockTransaction {
+            id: TransactionId(decoder.u64()?),
+            prefix_hash: BlockHash::decode_from(decoder)?,
+            info: Transaction::decode_versioned(decoder, version)?,
+        })
+    }
+}
+
+impl Decode for BlockTransaction {
+    fn decode_from(decoder: &mut Decoder) -> Result<Self, DecodeError> {
+        BlockTransaction::decode_versioned(decoder, ENCODING_VERSION)
+    }
+}
+
+impl Encode for Block {
+    fn encode_into(&self, encoder: &mut Encoder) {
+        encoder.u8(ENCODING_VERSION);
+        self.prefix_hash.encode_into(encoder);
+        encoder.u64(self.miner.0);
+        let count = u32::try_from(self.transactions.len()).expect("Too many transactions");
+        encoder.u32(count);
+        for transaction in &self.transactions {
+            transaction.encode_into(encoder);
+        }
+        encoder.u64(self.nonce);
+    }
+}
+
+impl Decode for Block {
+    fn decode_from(decoder: &mut Decoder) -> Result<Self, DecodeError> {
+        let version = decoder.u8()?;
+        if !(1..=ENCODING_VERSION).contains(&version) {
+            return Err(DecodeError::UnsupportedVersion(version));
+        }
+        let prefix_hash = BlockHash::decode_from(decoder)?;
+        let miner = Address(decoder.u64()?);
+        let count = decoder.u32()? as usize;
+        // Each transaction takes at least 35 bytes; don't trust `count` to preallocate memory.
+        let mut transactions = Vec::with_capacity(count.min(decoder.remaining() / 35));
+        for _ in 0..count {
+            transactions.push(BlockTransaction::decode_versioned(decoder, version)?);
+        }
+        let nonce = decoder.u64()?;
+        Ok(Block {
+            transactions,
+            prefix_hash,
+            miner,
+            nonce,
+        })
+    }
+}
+
 /// Attempt to mine a block using the nounces generated by an iterator.
 pub fn attempt_mining_block(
     prefix_hash: BlockHash,
     miner: Address,
     transactions: Vec<BlockTransaction>,
     nonces: impl Iterator<Item = u64>,
+) -> Option<Block> {
+    attempt_mining_block_with_difficulty(
+        prefix_hash,
+        miner,
+        transactions,
+        nonces,
+        MINING_DIFFICULTY,
+    )
+}
+
+/// Like `attempt_mining_block`, but with a custom mining difficulty.
+pub fn attempt_mining_block_with_difficulty(
+    prefix_hash: BlockHash,
+    miner: Address,
+    transactions: Vec<BlockTransaction>,
+    nonces: impl Iterator<Item = u64>,
+    difficulty: u32,
 ) -> Option<Block> {
     let mut new_block = Block::new(transactions, prefix_hash, miner, 0);
     for nonce in nonces {
         new_block.nonce = nonce;
-        if new_block.is_valid_nonce() {
+        if new_block.meets_difficulty(difficulty) {
             return Some(new_block);
         }
     }
     None
 }
 
+/// A strategy to look for the nonce of a block.
+pub trait Hasher: Sync {
+    /// Return the first nonce in the range such that the hash of the block with that nonce has at
+    /// least `difficulty` leading zero bits, if any.
+    fn find_nonce(&self, block: &Block, nonces: Range<u64>, difficulty: u32) -> Option<u64>;
+}
+
+/// Encode and hash the whole block for each nonce.
+pub struct NaiveHasher;
+
+impl Hasher for NaiveHasher {
+    fn find_nonce(&self, block: &Block, nonces: Range<u64>, difficulty: u32) -> Option<u64> {
+        let mut block = block.clone();
+        nonces.into_iter().find(|&nonce| {
+            block.nonce = nonce;
+            block.meets_difficulty(difficulty)
+        })
+    }
+}
+
+/// Hash the encoding of the block up to the nonce once, which is the last field of the encoding,
+/// and then only hash the bytes of each nonce starting from that state.
+pub struct MidstateHasher;
+
+impl Hasher for MidstateHasher {
+    fn find_nonce(&self, block: &Block, nonces: Range<u64>, difficulty: u32) -> Option<u64> {
+        let hashed = block.hashed_bytes();
+        let midstate = Sha256::new_with_prefix(&hashed[..hashed.len() - 8]);
+        nonces.into_iter().find(|&nonce| {
+            let digest = midstate
+                .clone()
+                .chain_update(nonce.to_le_bytes())
+                .finalize();
+            leading_zero_bits(&digest) >= difficulty
+        })
+    }
+}
+
+/// Hash the encoding of the block up to the nonce once, like `MidstateHasher`, and then hash the
+/// nonces in batches of `MINING_HASHER_BATCH_NONCES`, in lanes laid out for SIMD: all the lanes
+/// are hashed before any digest is checked.
+pub struct BatchedHasher;
+
+impl BatchedHasher {
+    /// The digests of the block whose encoding up to the nonce is hashed in `midstate`, for the
+    /// `MINING_HASHER_BATCH_NONCES` nonces starting at `first_nonce`.
+    pub fn hash_batch(
+        midstate: &Sha256,
+        first_nonce: u64,
+    ) -> [[u8; 32]; MINING_HASHER_BATCH_NONCES] {
+        let mut lanes: [Sha256; MINING_HASHER_BATCH_NONCES] =
+            std::array::from_fn(|_| midstate.clone());
+        for (lane, state) in lanes.iter_mut().enumerate() {
+            state.update(first_nonce.wrapping_add(lane as u64).to_le_bytes());
+        }
+        lanes.map(|state| state.finalize().into())
+    }
+}
+
+impl Hasher for BatchedHasher {
+    fn find_nonce(&self, block: &Block, nonces: Range<u64>, difficulty: u32) -> Option<u64> {
+        let hashed = block.hashed_bytes();
+        let midstate = Sha256::new_with_prefix(&hashed[..hashed.len() - 8]);
+        let batch = MINING_HASHER_BATCH_NONCES as u64;
+        let mut first_nonce = nonces.start;
+        while first_nonce < nonces.end {
+            let digests = Self::hash_batch(&midstate, first_nonce);
+            let lanes = (nonces.end - first_nonce).min(batch) as usize;
+            if let Some(lane) = digests[..lanes]
+                .iter()
+                .position(|digest| leading_zero_bits(digest) >= difficulty)
+            {
+                return Some(first_nonce + lane as u64);
+            }
+            first_nonce = first_nonce.saturating_add(batch);
+        }
+        None
+    }
+}
+
+/// A stub of a hasher that offloads the batches of nonces to a GPU through OpenCL. There is no
+/// OpenCL kernel yet, so the batches are hashed on the CPU like with `BatchedHasher`.
+#[cfg(feature = "gpu")]
+pub struct GpuHasher;
+
+#[cfg(feature = "gpu")]
+impl Hasher for GpuHasher {
+    fn find_nonce(&self, block: &Block, nonces: Range<u64>, difficulty: u32) -> Option<u64> {
+        BatchedHasher.find_nonce(block, nonces, difficulty)
+    }
+}
+
+/// Like `attempt_mining_block_with_difficulty`, but looking for the nonce with a custom hasher.
+pub fn attempt_mining_block_with_hasher(
+    prefix_hash: BlockHash,
+    miner: Address,
+    transactions: Vec<BlockTransaction>,
+    nonces: Range<u64>,
+    difficulty: u32,
+    hasher: &dyn Hasher,
+) -> Option<Block> {
+    let mut new_block = Block::new(transactions, prefix_hash, miner, 0);
+    new_block.nonce = hasher.find_nonce(&new_block, nonces, difficulty)?;
+    Some(new_block)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -241,4 +864,369 @@ mod tests {
         block = attempt_mining_block(block.hash(), miner, vec![], 0..=u64::MAX).unwrap();
         assert!(block.hash().leading_zero_bits() >= MINING_DIFFICULTY);
     }
+
+    #[test]
+    fn hashers_find_the_same_nonce() {
+        let block = Block::new(vec![], Block::genesis().hash(), Address::new(2), 0);
+        let naive = NaiveHasher.find_nonce(&block, 0..100_000, 10);
+        assert!(naive.is_some());
+        assert_eq!(MidstateHasher.find_nonce(&block, 0..100_000, 10), naive);
+        assert_eq!(
+            MidstateHasher.find_nonce(&block, 0..naive.unwrap(), 10),
+            None
+        );
+        assert_eq!(BatchedHasher.find_nonce(&block, 0..100_000, 10), naive);
+        assert_eq!(
+            BatchedHasher.find_nonce(&block, 0..naive.unwrap(), 10),
+            None
+        );
+        // A range that ends in the middle of a batch
+        let nonce = naive.unwrap();
+        assert_eq!(
+            BatchedHasher.find_nonce(&block, nonce..nonce + 1, 10),
+            Some(nonce)
+        );
+        #[cfg(feature = "gpu")]
+        assert_eq!(GpuHasher.find_nonce(&block, 0..100_000, 10), naive);
+    }
+
+    /// The hash of the genesis block must never change, otherwise all existing blockchains become
+    /// invalid. It does not depend on `ENCODING_VERSION`, which is not hashed.
+    #[test]
+    fn frozen_genesis_hash() {
+        assert_eq!(
+            Block::genesis().hash().to_string(),
+            "#c90232586b801f9558a76f2f963eccd831d9fe6775e4c8f1446b2331aa2132f2"
+        );
+        let mut encoded = Block::genesis().encode();
+        for version in 1..=ENCODING_VERSION {
+            encoded[0] = version;
+            assert_eq!(Block::decode(&encoded), Ok(Block::genesis()), "{version}");
+        }
+    }
+
+    /// The blocks encoded with an older version must still decode, with the default value of the
+    /// fields added since then.
+    #[test]
+    fn decode_blocks_of_older_versions() {
+        let prefix_hash = Block::genesis().hash();
+        let transaction = |info| BlockTransaction {
+            id: TransactionId(7),
+            prefix_hash: prefix_hash.clone(),
+            info,
+        };
+        let transfer = Transaction::new(Address::new(1), Address::new(2), Amount::from_units(300));
+        let fixtures = [
+            (
+                concat!(
+                    "01",                                                                 // version
+                    "20c90232586b801f9558a76f2f963eccd831d9fe6775e4c8f1446b2331aa2132f2", // prefix hash
+                    "0300000000000000",                                                   // miner
+                    "01000000",                                                           // count
+                    "0700000000000000",                                                   // id
+                    "20c90232586b801f9558a76f2f963eccd831d9fe6775e4c8f1446b2331aa2132f2", // prefix hash
+                    "0100000000000000",                                                   // sender
+                    "0200000000000000", // receiver
+                    "2c01000000000000", // amount
+                    "0201000000000000", // nonce
+                ),
+                transfer.clone(),
+            ),
+            (
+                concat!(
+                    "03",                                                                 // version
+                    "20c90232586b801f9558a76f2f963eccd831d9fe6775e4c8f1446b2331aa2132f2", // prefix hash
+                    "0300000000000000",                                                   // miner
+                    "01000000",                                                           // count
+                    "0700000000000000",                                                   // id
+                    "20c90232586b801f9558a76f2f963eccd831d9fe6775e4c8f1446b2331aa2132f2", // prefix hash
+                    "0100000000000000",                                                   // sender
+                    "00",                                                                 // None
+                    "2c01000000000000",                                                   // amount
+                    "0500000000000000",                                                   // fee
+                    "0201000000000000",                                                   // nonce
+                ),
+                Transaction::burn(Address::new(1), Amount::from_units(300))
+                    .with_fee(Amount::from_units(5)),
+            ),
+        ];
+        for (hex, info) in fixtures {
+            let encoded: Vec<u8> = (0..hex.len())
+                .step_by(2)
+                .map(|index| u8::from_str_radix(&hex[index..index + 2], 16).unwrap())
+                .collect();
+            let block = Block::new(
+                vec![transaction(info)],
+                prefix_hash.clone(),
+                Address::new(3),
+                0x0102,
+            );
+            assert_eq!(Block::decode(&encoded), Ok(block), "{hex}");
+        }
+    }
+
+    /// The encoding of a block must never change, unless `ENCODING_VERSION` is bumped.
+    #[test]
+    fn frozen_block_encoding() {
+        let prefix_hash = Block::genesis().hash();
+        let transaction = BlockTransaction {
+            id: TransactionId(7),
+            prefix_hash: prefix_hash.clone(),
+            info: Transaction::new(Address::new(1), Address::new(2), Amount::from_units(300))
+                .with_fee(Amount::from_units(5))
+                .with_memo(Memo::try_from("hi".to_string()).unwrap()),
+        };
+        let block = Block::new(vec![transaction], prefix_hash, Address::new(3), 0x0102);
+        let encoded = block.encode();
+        let hex: String = encoded.iter().map(|byte| format!("{byte:02x}")).collect();
+        assert_eq!(
+            hex,
+            concat!(
+                "04",                                                                 // version
+                "20c90232586b801f9558a76f2f963eccd831d9fe6775e4c8f1446b2331aa2132f2", // prefix hash
+                "0300000000000000",                                                   // miner
+                "01000000",                                                           // count
+                "0700000000000000",                                                   // id
+                "20c90232586b801f9558a76f2f963eccd831d9fe6775e4c8f1446b2331aa2132f2", // prefix hash
+                "0100000000000000",                                                   // sender
+                "01",                                                                 // Some
+                "0200000000000000",                                                   // receiver
+                "2c01000000000000",                                                   // amount
+                "0500000000000000",                                                   // fee
+                "026869",                                                             // memo
+                "0201000000000000",                                                   // nonce
+            )
+        );
+        assert_eq!(Block::decode(&encoded), Ok(block));
+    }
+
+    #[test]
+    fn raw_transaction_encoding() {
+        let transaction =
+            Transaction::new(Address::new(1), Address::new(2), Amount::from_coins(300))
+                .with_fee(Amount::from_coins(5));
+        let encoded = transaction.encode();
+        assert_eq!(encoded.len(), 34);
+        assert_eq!(Transaction::decode(&encoded), Ok(transaction));
+        assert_eq!(
+            Transaction::decode(&encoded[..33]),
+            Err(DecodeError::UnexpectedEnd)
+        );
+
+        // The memo must be valid UTF-8.
+        let mut invalid = encoded;
+        invalid[33] = 1;
+        invalid.push(0xff);
+        assert_eq!(
+            Transaction::decode(&invalid),
+            Err(DecodeError::InvalidField("memo"))
+        );
+
+        let burn = Transaction::burn(Address::new(1), Amount::from_coins(10));
+        let encoded = burn.encode();
+        assert_eq!(encoded.len(), 26);
+        assert_eq!(Transaction::decode(&encoded), Ok(burn));
+        let mut invalid = encoded;
+        invalid[8] = 2;
+        assert_eq!(
+            Transaction::decode(&invalid),
+            Err(DecodeError::InvalidField("receiver"))
+        );
+    }
+
+    #[test]
+    fn content_derived_transaction_ids() {
+        let prefix_hash = Block::genesis().hash();
+        let transfer = Transaction::new(Address::new(1), Address::new(2), Amount::from_coins(3));
+        let transaction = BlockTransaction::new(prefix_hash.clone(), transfer.clone());
+        assert!(transaction.has_valid_id());
+        assert_eq!(transaction.id.to_string(), "$17139258912344007771");
+
+        // The same transfer on top of the same block has the same identifier, unlike a transfer
+        // with a memo or on top of another block
+        assert_eq!(
+            BlockTransaction::new(prefix_hash.clone(), transfer.clone()).id,
+            transaction.id
+        );
+        let with_memo = transfer
+            .clone()
+            .with_memo(Memo::try_from("again".to_string()).unwrap());
+        assert_ne!(
+            BlockTransaction::new(prefix_hash.clone(), with_memo).id,
+            transaction.id
+        );
+        let other_block = Block::new(vec![], prefix_hash, Address::new(3), 0);
+        assert_ne!(
+            BlockTransaction::new(other_block.hash(), transfer).id,
+            transaction.id
+        );
+
+        let tampered = BlockTransaction {
+            id: TransactionId(7),
+            ..transaction
+        };
+        assert!(!tampered.has_valid_id());
+    }
+
+    #[test]
+    fn canonical_json() {
+        let prefix_hash = Block::genesis().hash();
+        let transaction = BlockTransaction {
+            id: TransactionId(7),
+            prefix_hash: prefix_hash.clone(),
+            info: Transaction::new(Address::new(1), Address::new(2), Amount::from_coins(300)),
+        };
+        let block = Block::new(vec![transaction], prefix_hash, Address::new(3), 0x0102);
+        let json = serde_json::to_value(&block).unwrap();
+        let hash = "#c90232586b801f9558a76f2f963eccd831d9fe6775e4c8f1446b2331aa2132f2";
+        let [sender, receiver, miner] = [1, 2, 3].map(|id| Address::new(id).to_string());
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "transactions": [{
+                    "id": "$7",
+                    "prefix_hash": hash,
+                    "info": { "sender": sender, "receiver": receiver, "amount": "300 coins", "fee": "0 coins" },
+                }],
+                "prefix_hash": hash,
+                "miner": miner,
+                "nonce": 258,
+            })
+        );
+        assert_eq!(serde_json::from_value::<Block>(json).unwrap(), block);
+        assert!(serde_json::from_str::<Address>("\"@x\"").is_err());
+
+        // A burn has a `null` receiver, which cannot be left out by mistake.
+        let burn = Transaction::burn(Address::new(1), Amount::from_coins(300));
+        let json = serde_json::to_value(&burn).unwrap();
+        assert_eq!(json["receiver"], serde_json::Value::Null);
+        assert_eq!(serde_json::from_value::<Transaction>(json).unwrap(), burn);
+        let without_receiver = serde_json::json!({ "sender": sender, "amount": "300 coins" });
+        assert!(serde_json::from_value::<Transaction>(without_receiver).is_err());
+
+        // The memo is left out if empty, and cannot be longer than `MEMO_MAX_BYTES`.
+        let memo = |length| {
+            let memo = "x".repeat(length);
+            serde_json::json!({ "sender": sender, "receiver": null, "amount": "1", "memo": memo })
+        };
+        let with_memo = serde_json::from_value::<Transaction>(memo(MEMO_MAX_BYTES)).unwrap();
+        assert_eq!(with_memo.weight(), TRANSACTION_BASE_WEIGHT + 4 * 255);
+        assert_eq!(serde_json::to_value(&burn).unwrap().get("memo"), None);
+        assert!(serde_json::from_value::<Transaction>(memo(MEMO_MAX_BYTES + 1)).is_err());
+    }
+
+    #[test]
+    fn address_encoding() {
+        let address = Address::new(42);
+        let encoded = address.to_string();
+        assert_eq!(encoded, "smc111111111115rD5cFJ");
+        assert_eq!(encoded.parse(), Ok(address));
+        for id in [0, 1, u64::MAX] {
+            assert_eq!(Address::new(id).to_string().parse(), Ok(Address::new(id)));
+        }
+
+        // Any single mistyped digit is detected.
+        for position in ADDRESS_PREFIX.len()..encoded.len() {
+            for &symbol in BASE58_ALPHABET.iter() {
+                let mut mistyped = encoded.clone().into_bytes();
+                if mistyped[position] == symbol {
+                    continue;
+                }
+                mistyped[position] = symbol;
+                let mistyped = String::from_utf8(mistyped).unwrap();
+                assert!(mistyped.parse::<Address>().is_err(), "{mistyped}");
+            }
+        }
+
+        assert_eq!(
+            "42".parse::<Address>(),
+            Err(AddressParseError::MissingPrefix)
+        );
+        assert_eq!(
+            "smc1abc".parse::<Address>(),
+            Err(AddressParseError::InvalidLength(3))
+        );
+        let with_zero = format!(
+            "{}0{}",
+            ADDRESS_PREFIX,
+            &encoded[ADDRESS_PREFIX.len() + 1..]
+        );
+        assert_eq!(
+            with_zero.parse::<Address>(),
+            Err(AddressParseError::InvalidCharacter('0'))
+        );
+    }
+
+    #[test]
+    fn parse_malformed_hashes() {
+        let hash = Block::genesis().hash();
+        assert_eq!(hash.to_string().parse(), Ok(hash.clone()));
+        assert_eq!(hash.to_string()[1..].parse(), Ok(hash));
+        assert_eq!(
+            "#abc".parse::<BlockHash>(),
+            Err(HashParseError::OddLength(3))
+        );
+        // Found by fuzzing: slicing the string by bytes used to split multi-byte characters.
+        assert_eq!(
+            "aé".parse::<BlockHash>(),
+            Err(HashParseError::InvalidCharacter('é'))
+        );
+        assert_eq!(
+            "00".repeat(256).parse::<BlockHash>(),
+            Err(HashParseError::TooLong(256))
+        );
+    }
+
+    #[test]
+    fn round_trip_display_and_from_str() {
+        // The prefix of the genesis block is the empty hash, displayed as `#`
+        let mut hashes = vec![Block::genesis().prefix_hash, Block::genesis().hash()];
+        hashes.extend((0..=u8::MAX).map(|len| BlockHash((0..len).collect())));
+        for hash in hashes {
+            let displayed = hash.to_string();
+            assert_eq!(displayed.parse(), Ok(hash.clone()), "{displayed}");
+            assert_eq!(displayed[1..].parse(), Ok(hash.clone()), "{displayed}");
+            assert_eq!(displayed.to_uppercase().parse(), Ok(hash), "{displayed}");
+        }
+        assert_eq!("".parse(), Ok(Block::genesis().prefix_hash));
+        assert_eq!(
+            "#c90232586b801f9558a76f2f963eccd831d9fe6775e4c8f1446b2331aa2132f".parse::<BlockHash>(),
+            Err(HashParseError::OddLength(63))
+        );
+        assert_eq!(
+            "##00".parse::<BlockHash>(),
+            Err(HashParseError::InvalidCharacter('#'))
+        );
+
+        for id in [0, 1, 42, u64::MAX] {
+            let transaction_id = TransactionId(id);
+            let displayed = transaction_id.to_string();
+            assert_eq!(displayed.parse(), Ok(transaction_id), "{displayed}");
+            assert_eq!(displayed[1..].parse(), Ok(transaction_id), "{displayed}");
+            let address = Address::new(id);
+            assert_eq!(address.to_string().parse(), Ok(address), "{address}");
+        }
+        assert!("$".parse::<TransactionId>().is_err());
+        assert!("$$42".parse::<TransactionId>().is_err());
+    }
+
+    #[test]
+    fn decode_invalid_blocks() {
+        let encoded = Block::genesis().encode();
+        assert_eq!(
+            Block::decode(&encoded[..encoded.len() - 1]),
+            Err(DecodeError::UnexpectedEnd)
+        );
+        let mut trailing = encoded.clone();
+        trailing.push(0);
+        assert_eq!(Block::decode(&trailing), Err(DecodeError::TrailingBytes(1)));
+        for version in [0, ENCODING_VERSION + 1] {
+            let mut unsupported = encoded.clone();
+            unsupported[0] = version;
+            assert_eq!(
+                Block::decode(&unsupported),
+                Err(DecodeError::UnsupportedVersion(version))
+            );
+        }
+    }
 }
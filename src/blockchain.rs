@@ -1,28 +1,390 @@
+use crate::encoding::{Encode, Encoder};
 use crate::prelude::*;
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(from = "StoredBlockChain")]
 pub struct BlockChain {
     chain: Vec<BlockHash>,
     blocks: HashMap<BlockHash, Block>,
-    balance: HashMap<Address, u64>,
+    balance: HashMap<Address, Balance>,
+    /// The coins destroyed by the burn transactions and the base fees of the blocks.
+    burned: Amount,
+    /// The base fee of each block of the chain, zero for the blocks appended without the base-fee
+    /// policy.
+    base_fees: Vec<Amount>,
+    /// The coins destroyed by the base fees of the blocks.
+    burned_fees: Amount,
+    /// The balances that each block of the chain changed, as they were before the block was
+    /// appended, so that popping the block restores exactly them. `None` for the genesis block and
+    /// the blocks of blockchains stored before the changes were recorded.
+    previous_balances: Vec<Option<PreviousBalances>>,
+    /// How many leading zero bits the hash of each block must have.
+    difficulty: u32,
+    /// The rules that the transactions of each block must satisfy.
+    rules: TransactionRules,
+    /// The heights of the blocks mined by each address, in increasing order.
+    #[serde(skip)]
+    mined_heights: HashMap<Address, Vec<usize>>,
+    /// The receipt of each transaction in the blocks.
+    #[serde(skip)]
+    receipts: HashMap<TransactionId, Receipt>,
 }
 
+/// The serialized fields of a blockchain, from which its indexes are rebuilt.
+#[derive(Deserialize)]
+struct StoredBlockChain {
+    chain: Vec<BlockHash>,
+    blocks: HashMap<BlockHash, Block>,
+    balance: HashMap<Address, Balance>,
+    #[serde(default)]
+    burned: Amount,
+    #[serde(default)]
+    base_fees: Vec<Amount>,
+    #[serde(default)]
+    burned_fees: Amount,
+    #[serde(default)]
+    previous_balances: Vec<Option<PreviousBalances>>,
+    difficulty: u32,
+    #[serde(default)]
+    rules: TransactionRules,
+}
+
+impl From<StoredBlockChain> for BlockChain {
+    fn from(stored: StoredBlockChain) -> Self {
+        let mut mined_heights: HashMap<Address, Vec<usize>> = HashMap::new();
+        for (height, block_hash) in stored.chain.iter().enumerate().skip(1) {
+            if let Some(block) = stored.blocks.get(block_hash) {
+                mined_heights.entry(block.miner).or_default().push(height);
+            }
+        }
+        // Blockchains stored before the base-fee policy have no base fees
+        let mut base_fees = stored.base_fees;
+        base_fees.resize(stored.chain.len(), Amount::ZERO);
+        // Blockchains stored before the previous balances were recorded have none of them
+        let mut previous_balances = stored.previous_balances;
+        if previous_balances.len() != stored.chain.len() {
+            previous_balances = vec![None; stored.chain.len()];
+        }
+        // The receipts are regenerated by replaying the transactions of the blocks
+        let mut receipts = HashMap::new();
+        let mut balance = HashMap::from([(Address::FAUCET, Balance::new(GENESIS_ALLOCATION))]);
+        for (height, block_hash) in stored.chain.iter().enumerate().skip(1) {
+            let Some(block) = stored.blocks.get(block_hash) else {
+                continue;
+            };
+            let base_fee = base_fees[height];
+            receipts.extend(
+                block_receipts(&mut balance, block, block_hash, height, base_fee)
+                    .into_iter()
+                    .map(|receipt| (receipt.transaction_id, receipt)),
+            );
+            if let Some(reward) = block.reward_with_base_fee(base_fee) {
+                let _ = balance.entry(block.miner).or_default().credit(reward);
+            }
+        }
+        BlockChain {
+            chain: stored.chain,
+            blocks: stored.blocks,
+            balance: stored.balance,
+            burned: stored.burned,
+            base_fees,
+            burned_fees: stored.burned_fees,
+            previous_balances,
+            difficulty: stored.difficulty,
+            rules: stored.rules,
+            mined_heights,
+            receipts,
+        }
+    }
+}
+
+/// Apply the transactions of a block, at the given height and with the given base fee, to the
+/// balances, and return their receipts. The reward of the miner is not credited.
+fn block_receipts(
+    balance: &mut HashMap<Address, Balance>,
+    block: &Block,
+    block_hash: &BlockHash,
+    height: usize,
+    base_fee: Amount,
+) -> Vec<Receipt> {
+    let mut receipts = Vec::with_capacity(block.transactions.len());
+    for (index, t) in block.transactions.iter().enumerate() {
+        if apply_transaction(balance, &t.info).is_err() {
+            // The `balances` rule is skipped
+            force_transaction(balance, &t.info);
+        }
+        let balance_of = |address| balance.get(&address).map_or(Amount::ZERO, Balance::amount);
+        receipts.push(Receipt {
+            transaction_id: t.id,
+            block_hash: block_hash.clone(),
+            height,
+            index,
+            fee: t.info.fee,
+            burned_fee: base_fee,
+            // The fees pay at least the base fee, as checked when the block was appended
+            miner_fee: t.info.fee.saturating_sub(base_fee),
+            sender_balance: balance_of(t.info.sender),
+            receiver_balance: t.info.receiver.map(balance_of),
+        });
+    }
+    receipts
+}
+
+/// The balances of the addresses changed by a block, before the block was appended. `None` for
+/// the addresses that had no balance.
+type PreviousBalances = HashMap<Address, Option<Balance>>;
+
+/// The outcome of a transaction included in a block of the blockchain.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Receipt {
+    pub transaction_id: TransactionId,
+    /// The hash of the block that includes the transaction.
+    pub block_hash: BlockHash,
+    /// The height of the block that includes the transaction.
+    pub height: usize,
+    /// The position of the transaction in the block.
+    pub index: usize,
+    /// The fee paid by the sender.
+    pub fee: Amount,
+    /// The part of the fee that was burned, with the base-fee policy.
+    pub burned_fee: Amount,
+    /// The part of the fee that was credited to the miner.
+    pub miner_fee: Amount,
+    /// The balance of the sender right after the transaction, before the reward of the miner.
+    pub sender_balance: Amount,
+    /// The balance of the receiver right after the transaction, before the reward of the miner,
+    /// unless the transaction is a burn.
+    pub receiver_balance: Option<Amount>,
+}
+
+/// The check that the coins held by the addresses, plus the burned ones, are exactly the ones
+/// allocated to the faucet in the genesis block plus the ones issued to the miners. Fees move coins
+/// from the senders to the miners, or burn them with the base-fee policy.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SupplyAudit {
+    /// The height at which the blockchain was audited.
+    pub height: usize,
+    /// The sum of the balances of all addresses, in units.
+    pub total_balance: u128,
+    /// The coins destroyed by burn transactions and base fees, in units.
+    pub burned_coins: u128,
+    /// The coins allocated to the faucet plus the ones issued to the miners of the blocks, in
+    /// units.
+    pub issued_coins: u128,
+    /// Whether the total balance plus the burned coins equals the issued coins.
+    pub consistent: bool,
+}
+
+/// The state of the fee market of a blockchain.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FeeMarket {
+    pub fee_policy: FeePolicy,
+    /// The base fee that the next block must pay, if the base-fee policy is enabled.
+    pub next_base_fee: Option<Amount>,
+    /// The coins destroyed by the base fees of the blocks.
+    pub burned_fees: Amount,
+    /// The base fees of the last blocks, most recent first. Zero for the blocks appended without
+    /// the base-fee policy.
+    pub recent_base_fees: Vec<Amount>,
+}
+
+/// The parameters of the protocol that the nodes of a network must share to agree on the blocks,
+/// e.g. a node built with another mining reward would compute other balances from the same
+/// blocks. Nodes send the hash of their parameters with their blocks, so that peers with other
+/// parameters are detected instead of silently forking.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ProtocolParams {
+    pub encoding_version: u8,
+    pub genesis_hash: BlockHash,
+    pub genesis_allocation: Amount,
+    pub coins_per_mined_block: Amount,
+    pub mining_difficulty: u32,
+    pub memo_max_bytes: usize,
+    pub transaction_base_weight: u64,
+    pub weight_per_memo_byte: u64,
+    pub base_fee_initial: Amount,
+    pub base_fee_target_transactions: usize,
+    pub base_fee_max_transactions: usize,
+    pub base_fee_max_change_denominator: u128,
+    pub transaction_rules: TransactionRules,
+    /// How the identifiers of the transactions are chosen, see `TRANSACTION_ID_VERSION`. Zero in
+    /// the parameters saved before the identifiers were derived from the transactions.
+    #[serde(default)]
+    pub transaction_id_version: u8,
+}
+
+impl ProtocolParams {
+    /// The parameters of this build, with the given rules and the default mining difficulty.
+    pub fn with_rules(transaction_rules: TransactionRules) -> Self {
+        ProtocolParams {
+            encoding_version: crate::encoding::ENCODING_VERSION,
+            genesis_hash: Block::genesis().hash(),
+            genesis_allocation: GENESIS_ALLOCATION,
+            coins_per_mined_block: COINS_PER_MINED_BLOCK,
+            mining_difficulty: MINING_DIFFICULTY,
+            memo_max_bytes: MEMO_MAX_BYTES,
+            transaction_base_weight: TRANSACTION_BASE_WEIGHT,
+            weight_per_memo_byte: WEIGHT_PER_MEMO_BYTE,
+            base_fee_initial: BASE_FEE_INITIAL,
+            base_fee_target_transactions: BASE_FEE_TARGET_TRANSACTIONS,
+            base_fee_max_transactions: BASE_FEE_MAX_TRANSACTIONS,
+            base_fee_max_change_denominator: BASE_FEE_MAX_CHANGE_DENOMINATOR,
+            transaction_rules,
+            transaction_id_version: TRANSACTION_ID_VERSION,
+        }
+    }
+
+    /// The hash of the binary encoding of the parameters.
+    pub fn hash(&self) -> BlockHash {
+        BlockHash::digest(&self.encode())
+    }
+
+    /// The names of the parameters that differ between the two sets.
+    pub fn differences(&self, other: &ProtocolParams) -> Vec<&'static str> {
+        macro_rules! differing {
+            ($($field:ident),*) => {
+                [$((stringify!($field), self.$field != other.$field)),*]
+            };
+        }
+        differing!(
+            encoding_version,
+            genesis_hash,
+            genesis_allocation,
+            coins_per_mined_block,
+            mining_difficulty,
+            memo_max_bytes,
+            transaction_base_weight,
+            weight_per_memo_byte,
+            base_fee_initial,
+            base_fee_target_transactions,
+            base_fee_max_transactions,
+            base_fee_max_change_denominator,
+            transaction_rules,
+            transaction_id_version
+        )
+        .into_iter()
+        .filter(|(_, differs)| *differs)
+        .map(|(name, _)| name)
+        .collect()
+    }
+}
+
+impl Encode for ProtocolParams {
+    fn encode_into(&self, encoder: &mut Encoder) {
+        encoder.u8(self.encoding_version);
+        self.genesis_hash.encode_into(encoder);
+        encoder.u64(self.genesis_allocation.units());
+        encoder.u64(self.coins_per_mined_block.units());
+        encoder.u32(self.mining_difficulty);
+        encoder.u64(self.memo_max_bytes as u64);
+        encoder.u64(self.transaction_base_weight);
+        encoder.u64(self.weight_per_memo_byte);
+        encoder.u64(self.base_fee_initial.units());
+        encoder.u64(self.base_fee_target_transactions as u64);
+        encoder.u64(self.base_fee_max_transactions as u64);
+        encoder.u64(self.base_fee_max_change_denominator as u64);
+        let rules = &self.transaction_rules;
+        encoder.u8(rules.allow_zero_amount.into());
+        encoder.u8(rules.allow_self_transfer.into());
+        encoder.u8(match rules.fee_policy {
+            FeePolicy::MinerTakesAll => 0,
+            FeePolicy::BaseFee => 1,
+        });
+        encoder.u64(rules.max_block_weight);
+        encoder.u8(self.transaction_id_version);
+        // Parameters without skipped rules keep the hash that they had before rules could be skipped
+        if !rules.skipped.is_empty() {
+            encoder.u64(rules.skipped.bits().into());
+        }
+    }
+}
+
+/// The filters of a search among the blocks of a blockchain. Missing filters match all blocks.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BlockSearch {
+    /// The address that mined the blocks.
+    pub miner: Option<Address>,
+    /// The minimum height of the blocks.
+    pub min_height: Option<usize>,
+    /// The maximum height of the blocks.
+    pub max_height: Option<usize>,
+    /// The minimum number of transactions in the blocks.
+    pub min_txs: Option<usize>,
+}
+
+impl Default for BlockChain {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// A blockchain always contains at least the genesis block, so it cannot be empty.
+#[allow(clippy::len_without_is_empty)]
 impl BlockChain {
     pub fn new() -> Self {
+        Self::with_difficulty(MINING_DIFFICULTY)
+    }
+
+    /// Create a blockchain whose blocks must have a custom mining difficulty.
+    pub fn with_difficulty(difficulty: u32) -> Self {
         let genesis = Block::genesis();
         BlockChain {
             chain: vec![genesis.hash()],
             blocks: HashMap::from([(genesis.hash(), genesis)]),
-            balance: HashMap::new(),
+            balance: HashMap::from([(Address::FAUCET, Balance::new(GENESIS_ALLOCATION))]),
+            burned: Amount::ZERO,
+            base_fees: vec![Amount::ZERO],
+            burned_fees: Amount::ZERO,
+            previous_balances: vec![None],
+            difficulty,
+            rules: TransactionRules::default(),
+            mined_heights: HashMap::new(),
+            receipts: HashMap::new(),
+        }
+    }
+
+    /// How many leading zero bits the hash of each block must have.
+    pub fn difficulty(&self) -> u32 {
+        self.difficulty
+    }
+
+    /// The rules that the transactions of each block must satisfy.
+    pub fn rules(&self) -> &TransactionRules {
+        &self.rules
+    }
+
+    /// The parameters of the protocol that the blocks of the blockchain follow.
+    pub fn params(&self) -> ProtocolParams {
+        ProtocolParams {
+            mining_difficulty: self.difficulty,
+            ..ProtocolParams::with_rules(self.rules)
         }
     }
 
+    /// Change the rules that the transactions of the blocks appended from now on must satisfy.
+    pub fn set_rules(&mut self, rules: TransactionRules) {
+        self.rules = rules;
+    }
+
     pub fn len(&self) -> usize {
         self.chain.len()
     }
 
+    /// The height of the last block, where the genesis block has height zero.
+    pub fn height(&self) -> usize {
+        self.chain.len() - 1
+    }
+
+    /// The expected number of hashes computed to mine the blocks after the genesis block, i.e.
+    /// 2 to the power of the difficulty for each of them, saturating at `u128::MAX`.
+    pub fn cumulative_work(&self) -> u128 {
+        let work_per_block = 1u128.checked_shl(self.difficulty).unwrap_or(u128::MAX);
+        work_per_block.saturating_mul(self.height() as u128)
+    }
+
     pub fn contains(&self, block_hash: &BlockHash) -> bool {
         self.blocks.contains_key(block_hash)
     }
@@ -31,6 +393,95 @@ impl BlockChain {
         self.blocks.get(block_hash)
     }
 
+    /// The hashes of the blocks, from the genesis to the last one.
+    pub fn hashes(&self) -> impl Iterator<Item = &BlockHash> {
+        self.chain.iter()
+    }
+
+    /// The hash of the block at some height of the blockchain.
+    pub fn hash_at(&self, height: usize) -> Option<&BlockHash> {
+        self.chain.get(height)
+    }
+
+    /// The blocks, from the genesis to the last one.
+    pub fn blocks(&self) -> impl Iterator<Item = &Block> {
+        self.chain.iter().map(|block_hash| &self.blocks[block_hash])
+    }
+
+    /// The heights of the blocks that match the filters of a search, in increasing order. The
+    /// blocks of a miner are found through an index, without scanning the blockchain.
+    pub fn search_blocks(&self, search: &BlockSearch) -> Vec<usize> {
+        let min_height = search.min_height.unwrap_or(0);
+        let max_height = search.max_height.unwrap_or(usize::MAX).min(self.height());
+        if min_height > max_height {
+            return vec![];
+        }
+        let has_enough_transactions = |height: &usize| {
+            let block = &self.blocks[&self.chain[*height]];
+            block.transactions.len() >= search.min_txs.unwrap_or(0)
+        };
+        match search.miner {
+            Some(miner) => {
+                let heights = self
+                    .mined_heights
+                    .get(&miner)
+                    .map_or(&[][..], Vec::as_slice);
+                let start = heights.partition_point(|height| *height < min_height);
+                let end = heights.partition_point(|height| *height <= max_height);
+                heights[start..end]
+                    .iter()
+                    .copied()
+                    .filter(has_enough_transactions)
+                    .collect()
+            }
+            None => (min_height..=max_height)
+                .filter(has_enough_transactions)
+                .collect(),
+        }
+    }
+
+    /// Check that the balances add up to the coins allocated to the faucet and issued to the
+    /// miners, to detect bookkeeping bugs.
+    pub fn audit_supply(&self) -> SupplyAudit {
+        let total_balance = self
+            .balance
+            .values()
+            .map(|balance| balance.amount().units() as u128)
+            .sum();
+        let burned_coins = self.burned.units() as u128;
+        let issued_coins = GENESIS_ALLOCATION.units() as u128
+            + self.height() as u128 * COINS_PER_MINED_BLOCK.units() as u128;
+        SupplyAudit {
+            height: self.height(),
+            total_balance,
+            burned_coins,
+            issued_coins,
+            consistent: total_balance + burned_coins == issued_coins,
+        }
+    }
+
+    /// The height of the block that includes a transaction, if any.
+    pub fn transaction_height(&self, transaction_id: &TransactionId) -> Option<usize> {
+        self.receipt(transaction_id).map(|receipt| receipt.height)
+    }
+
+    /// The receipt of a transaction, if it is in the blockchain.
+    pub fn receipt(&self, transaction_id: &TransactionId) -> Option<&Receipt> {
+        self.receipts.get(transaction_id)
+    }
+
+    /// How many blocks confirm a transaction: the one that includes it and the ones after it. Zero
+    /// if the transaction is not in the blockchain.
+    pub fn confirmations(&self, transaction_id: &TransactionId) -> usize {
+        self.transaction_height(transaction_id)
+            .map_or(0, |height| self.confirmations_at(height))
+    }
+
+    /// How many blocks confirm a transaction included at `height`.
+    pub fn confirmations_at(&self, height: usize) -> usize {
+        self.len().saturating_sub(height)
+    }
+
     pub fn last_hash(&self) -> &BlockHash {
         let Some(block_hash) = self.chain.last() else {
             panic!("The blockchain of a node is empty");
@@ -46,57 +497,143 @@ impl BlockChain {
         block
     }
 
-    pub fn balance_mut(&mut self, address: Address) -> &mut u64 {
-        self.balance.entry(address).or_insert(0)
+    pub fn balance_mut(&mut self, address: Address) -> &mut Balance {
+        self.balance.entry(address).or_default()
     }
 
-    pub fn balance(&self) -> &HashMap<Address, u64> {
+    pub fn balance(&self) -> &HashMap<Address, Balance> {
         &self.balance
     }
 
-    #[allow(dead_code)]
-    pub fn balance_of(&self, address: Address) -> u64 {
-        *self.balance.get(&address).unwrap_or(&0)
+    /// The coins destroyed by the burn transactions of the blockchain.
+    pub fn burned(&self) -> Amount {
+        self.burned
     }
 
-    /// Appends a block to the blockchain. Returns an error if adding the block would make the
-    /// blockchain invalid (e.g., invalid transactions, invalid block hash, etc.)
-    pub fn append_block(&mut self, block: Block) -> Result<(), ()> {
-        if &block.prefix_hash != self.last_hash() {
-            warn!("Tried to append a block with an invalid prefix");
-            return Err(());
-        }
-        if !block.is_valid_nonce() {
-            warn!("Tried to append an invalid block");
-            return Err(());
-        }
-
-        // Check that the ids of the transactions are unique
-        let mut transaction_ids = HashSet::new();
-        for t in &block.transactions {
-            if !transaction_ids.insert(t.id) {
-                warn!("Tried to append a block with duplicate transaction ids");
-                return Err(());
-            }
+    /// The coins destroyed by the base fees of the blocks.
+    pub fn burned_fees(&self) -> Amount {
+        self.burned_fees
+    }
+
+    /// The base fee of the block at the given height, zero if the block was appended without the
+    /// base-fee policy or if there is no such block.
+    pub fn base_fee_at(&self, height: usize) -> Amount {
+        self.base_fees.get(height).copied().unwrap_or(Amount::ZERO)
+    }
+
+    /// The base fee that the next block must pay, or `None` without the base-fee policy. It is
+    /// `BASE_FEE_INITIAL` if the last block was appended without the policy.
+    pub fn next_base_fee(&self) -> Option<Amount> {
+        if self.rules.fee_policy != FeePolicy::BaseFee {
+            return None;
+        }
+        let last_base_fee = self.base_fee_at(self.height());
+        if last_base_fee == Amount::ZERO {
+            return Some(BASE_FEE_INITIAL);
         }
+        Some(FeePolicy::next_base_fee(
+            last_base_fee,
+            self.last_block().transactions.len(),
+        ))
+    }
 
-        // Check and update the balance
-        for t in &block.transactions {
-            if t.prefix_hash != block.prefix_hash {
-                warn!("Tried to append a block with a transaction with an invalid `prefix_hash`");
-                return Err(());
-            }
-            if *self.balance_mut(t.info.sender) < t.info.amount {
-                warn!("Tried to append a block with invalid transactions");
-                return Err(());
+    /// The state of the fee market: the fee policy, the base fee of the next block, the burned
+    /// fees, and the base fees of the last blocks, most recent first.
+    pub fn fee_market(&self, recent_blocks: usize) -> FeeMarket {
+        FeeMarket {
+            fee_policy: self.rules.fee_policy,
+            next_base_fee: self.next_base_fee(),
+            burned_fees: self.burned_fees,
+            recent_base_fees: self
+                .base_fees
+                .iter()
+                .rev()
+                .take(recent_blocks)
+                .copied()
+                .collect(),
+        }
+    }
+
+    pub fn balance_of(&self, address: Address) -> Amount {
+        self.balance
+            .get(&address)
+            .map_or(Amount::ZERO, Balance::amount)
+    }
+
+    /// Check a block with the `BLOCK_RULES` that the rules of the blockchain do not skip, in
+    /// order. Returns the name of the first rule that the block breaks.
+    pub fn validate_block(&self, block: &Block) -> Result<(), &'static str> {
+        for rule in BLOCK_RULES {
+            if self.rules.skipped.contains(rule.name()) {
+                continue;
             }
-            *self.balance_mut(t.info.sender) -= t.info.amount;
-            *self.balance_mut(t.info.receiver) += t.info.amount;
+            rule.check_block(self, block).map_err(|()| rule.name())?;
         }
-        *self.balance_mut(block.miner) += COINS_PER_MINED_BLOCK;
+        Ok(())
+    }
 
-        // Add the block to the blockchain
+    /// Appends a block to the blockchain. Returns an error if adding the block would make the
+    /// blockchain invalid (e.g., invalid transactions, invalid block hash, etc.), with the name of
+    /// the rule that the block breaks.
+    pub fn append_block(&mut self, block: Block) -> Result<(), &'static str> {
+        self.validate_block(&block)?;
+
+        // The overflows of the reward and of the burned coins break the `balances` rule
+        let base_fee = self.next_base_fee().unwrap_or(Amount::ZERO);
+        let Some(reward) = block.reward_with_base_fee(base_fee) else {
+            warn!("Tried to append a block whose reward overflows");
+            return Err("balances");
+        };
+        let Some(burned_fees) = block
+            .burned_fees(base_fee)
+            .and_then(|burned_fees| self.burned_fees.checked_add(burned_fees))
+        else {
+            warn!("Tried to append a block whose burned fees overflow");
+            return Err("balances");
+        };
+        let Some(burned) = block
+            .burned()
+            .and_then(|burned| burned.checked_add(block.burned_fees(base_fee)?))
+            .and_then(|burned| self.burned.checked_add(burned))
+        else {
+            warn!("Tried to append a block whose burned coins overflow");
+            return Err("balances");
+        };
+
+        // Check and update the balance, leaving it untouched if any update fails
         let block_hash = block.hash();
+        let height = self.chain.len();
+        let mut balance = self.balance.clone();
+        let receipts = block_receipts(&mut balance, &block, &block_hash, height, base_fee);
+        if let Err(err) = balance.entry(block.miner).or_default().credit(reward) {
+            warn!("Tried to append a block whose reward cannot be credited: {err}");
+            return Err("balances");
+        }
+        // Remember the changed balances, since forced transactions cannot be reverted from the
+        // transactions alone
+        let previous_balances = block
+            .transactions
+            .iter()
+            .flat_map(|t| std::iter::once(t.info.sender).chain(t.info.receiver))
+            .chain([block.miner])
+            .map(|address| (address, self.balance.get(&address).copied()))
+            .collect();
+        self.previous_balances.push(Some(previous_balances));
+        self.balance = balance;
+        self.burned = burned;
+        self.burned_fees = burned_fees;
+        self.base_fees.push(base_fee);
+
+        // Add the block to the blockchain
+        self.receipts.extend(
+            receipts
+                .into_iter()
+                .map(|receipt| (receipt.transaction_id, receipt)),
+        );
+        self.mined_heights
+            .entry(block.miner)
+            .or_default()
+            .push(self.chain.len());
         self.chain.push(block_hash.clone());
         self.blocks.insert(block_hash, block.clone());
 
@@ -109,30 +646,141 @@ impl BlockChain {
             return None;
         }
 
+        // The balance is updated before removing the block, so that a bookkeeping bug leaves the
+        // blockchain untouched.
+        let reverted_balance = match self.previous_balances.last() {
+            Some(Some(previous_balances)) => Ok(self.restored_balance(previous_balances)),
+            _ => self.reverted_balance(self.last_block()),
+        };
+        match reverted_balance {
+            Ok(balance) => {
+                self.balance = balance;
+                let base_fee = self.base_fee_at(self.height());
+                let burned_fees = self
+                    .last_block()
+                    .burned_fees(base_fee)
+                    .unwrap_or(Amount::ZERO);
+                let burned = self.last_block().burned().unwrap_or(Amount::ZERO);
+                self.burned = self
+                    .burned
+                    .saturating_sub(burned)
+                    .saturating_sub(burned_fees);
+                self.burned_fees = self.burned_fees.saturating_sub(burned_fees);
+            }
+            Err(err) => {
+                error!(
+                    "Cannot revert the balance of block {}: {err}",
+                    self.last_hash()
+                );
+                return None;
+            }
+        }
+
         let block_hash = self.chain.pop().unwrap();
+        self.base_fees.pop();
+        self.previous_balances.pop();
         let block = self.blocks.remove(&block_hash).unwrap();
-
-        *self.balance_mut(block.miner) -= COINS_PER_MINED_BLOCK;
-        for t in &block.transactions {
-            *self.balance_mut(t.info.sender) += t.info.amount;
-            *self.balance_mut(t.info.receiver) -= t.info.amount;
+        for transaction in &block.transactions {
+            self.receipts.remove(&transaction.id);
+        }
+        if let Some(heights) = self.mined_heights.get_mut(&block.miner) {
+            heights.pop();
+            if heights.is_empty() {
+                self.mined_heights.remove(&block.miner);
+            }
         }
 
         Some(block)
     }
 
-    /// Pops blocks until the block with the given hash is the latest
-    pub fn pop_until(&mut self, block_hash: &BlockHash) {
+    /// The balance with the previous balances of the addresses changed by the last block.
+    fn restored_balance(&self, previous_balances: &PreviousBalances) -> HashMap<Address, Balance> {
+        let mut balance = self.balance.clone();
+        for (address, previous_balance) in previous_balances {
+            match previous_balance {
+                Some(previous_balance) => balance.insert(*address, *previous_balance),
+                None => balance.remove(address),
+            };
+        }
+        balance
+    }
+
+    /// The balance before a block, which must be the last one, was appended, computed from its
+    /// transactions. Used for the blocks whose previous balances were not recorded.
+    fn reverted_balance(&self, block: &Block) -> Result<HashMap<Address, Balance>, BalanceError> {
+        let mut balance = self.balance.clone();
+        let reward = block
+            .reward_with_base_fee(self.base_fee_at(self.height()))
+            .ok_or(BalanceError::Overflow)?;
+        balance.entry(block.miner).or_default().debit(reward)?;
+        for t in block.transactions.iter().rev() {
+            revert_transaction(&mut balance, &t.info)?;
+        }
+        Ok(balance)
+    }
+
+    /// Pops blocks until the block with the given hash is the latest. Returns an error if the
+    /// block cannot be reached.
+    pub fn pop_until(&mut self, block_hash: &BlockHash) -> Result<(), ()> {
         while self.last_hash() != block_hash {
-            self.pop_block().unwrap();
+            if self.pop_block().is_none() {
+                return Err(());
+            }
+        }
+        Ok(())
+    }
+
+    /// Update a store so that it holds the blocks of the blockchain, keeping the stored blocks
+    /// that the blockchain still contains and replacing the others, e.g. after a reorganization.
+    pub fn persist(&self, store: &mut dyn BlockStore) -> std::io::Result<()> {
+        let stored: Vec<BlockHash> = (1..=store.len())
+            .filter_map(|height| store.hash_at(height).cloned())
+            .collect();
+        match self.store_update(&stored) {
+            Some(update) => update.apply(store),
+            None => store.store_balances(&self.stored_balances()),
+        }
+    }
+
+    /// The balances after the last block, as stored with the blocks.
+    pub fn stored_balances(&self) -> StoredBalances {
+        StoredBalances {
+            height: self.height(),
+            hash: self.last_hash().clone(),
+            balances: self
+                .balance
+                .iter()
+                .map(|(&address, balance)| (address, balance.amount()))
+                .filter(|(_, amount)| *amount != Amount::ZERO)
+                .collect(),
+        }
+    }
+
+    /// The update that brings a store holding the blocks `stored`, from height 1, in sync with the
+    /// blockchain, or `None` if it already is.
+    pub fn store_update(&self, stored: &[BlockHash]) -> Option<StoreUpdate> {
+        let mut kept = stored.len().min(self.height());
+        while kept > 0 && stored[kept - 1] != self.chain[kept] {
+            kept -= 1;
+        }
+        if kept == stored.len() && kept == self.height() {
+            return None;
         }
+        Some(StoreUpdate {
+            height: kept,
+            blocks: self.chain[kept + 1..]
+                .iter()
+                .map(|block_hash| self.blocks[block_hash].clone())
+                .collect(),
+            balances: self.stored_balances(),
+        })
     }
 
     /// Appends a list of block to the blockchain. Returns an error if adding the block would make
     /// the blockchain invalid (e.g., invalid transactions, invalid block hash, etc.)
     pub fn append_blocks(&mut self, blocks: impl IntoIterator<Item = Block>) -> Result<(), ()> {
         for block in blocks {
-            self.append_block(block)?;
+            self.append_block(block).map_err(|_| ())?;
         }
         Ok(())
     }
@@ -153,7 +801,310 @@ mod tests {
             blockchain.append_block(new_block).unwrap();
         }
         assert!(blockchain.len() == 4);
-        assert!(blockchain.balance().len() == 1);
-        assert!(blockchain.balance_of(miner) == 3 * COINS_PER_MINED_BLOCK);
+        assert!(blockchain.balance().len() == 2);
+        assert!(blockchain.balance_of(miner) == COINS_PER_MINED_BLOCK.saturating_mul(3));
+        assert!(blockchain.balance_of(Address::FAUCET) == GENESIS_ALLOCATION);
+    }
+
+    #[test]
+    fn reject_self_transfers() {
+        let miner = Address::new(1);
+        let mut blockchain = BlockChain::with_difficulty(0);
+        let block = attempt_mining_block_with_difficulty(
+            blockchain.last_hash().clone(),
+            miner,
+            vec![],
+            0..1,
+            0,
+        )
+        .unwrap();
+        blockchain.append_block(block).unwrap();
+        let self_transfer = BlockTransaction::new(
+            blockchain.last_hash().clone(),
+            Transaction::new(miner, miner, Amount::from_coins(10)),
+        );
+        let block = attempt_mining_block_with_difficulty(
+            blockchain.last_hash().clone(),
+            miner,
+            vec![self_transfer],
+            0..1,
+            0,
+        )
+        .unwrap();
+        assert!(blockchain.append_block(block.clone()).is_err());
+
+        blockchain.set_rules(TransactionRules {
+            allow_self_transfer: true,
+            ..TransactionRules::default()
+        });
+        assert!(blockchain.append_block(block).is_ok());
+    }
+
+    #[test]
+    fn search_blocks() {
+        let (alice, bob) = (Address::new(1), Address::new(2));
+        let mut blockchain = BlockChain::with_difficulty(0);
+        for miner in [alice, bob, alice, alice] {
+            let transactions = if miner == bob {
+                vec![BlockTransaction::new(
+                    blockchain.last_hash().clone(),
+                    Transaction::new(alice, bob, Amount::from_coins(10)),
+                )]
+            } else {
+                vec![]
+            };
+            let block = attempt_mining_block_with_difficulty(
+                blockchain.last_hash().clone(),
+                miner,
+                transactions,
+                0..1,
+                0,
+            )
+            .unwrap();
+            blockchain.append_block(block).unwrap();
+        }
+        let search = |blockchain: &BlockChain, miner, min_height, max_height, min_txs| {
+            blockchain.search_blocks(&BlockSearch {
+                miner,
+                min_height,
+                max_height,
+                min_txs,
+            })
+        };
+        assert_eq!(
+            search(&blockchain, Some(alice), None, None, None),
+            vec![1, 3, 4]
+        );
+        assert_eq!(
+            search(&blockchain, Some(alice), Some(2), Some(3), None),
+            vec![3]
+        );
+        assert_eq!(search(&blockchain, None, None, None, Some(1)), vec![2]);
+        assert_eq!(
+            search(&blockchain, None, Some(3), Some(100), None),
+            vec![3, 4]
+        );
+        assert!(search(&blockchain, Some(Address::new(3)), None, None, None).is_empty());
+
+        // The index follows the blocks that are popped, and it is rebuilt after deserialization.
+        blockchain.pop_block().unwrap();
+        assert_eq!(
+            search(&blockchain, Some(alice), None, None, None),
+            vec![1, 3]
+        );
+        let json = serde_json::to_string(&blockchain).unwrap();
+        let blockchain: BlockChain = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            search(&blockchain, Some(alice), None, None, None),
+            vec![1, 3]
+        );
+    }
+
+    #[test]
+    fn audit_the_supply() {
+        let miner = Address::new(1);
+        let mut blockchain = BlockChain::with_difficulty(0);
+        for _ in 0..2 {
+            let block = attempt_mining_block_with_difficulty(
+                blockchain.last_hash().clone(),
+                miner,
+                vec![],
+                0..1,
+                0,
+            )
+            .unwrap();
+            blockchain.append_block(block).unwrap();
+        }
+        let transfer = BlockTransaction::new(
+            blockchain.last_hash().clone(),
+            Transaction::new(miner, Address::new(2), Amount::from_coins(300))
+                .with_fee(Amount::from_coins(5)),
+        );
+        let block = attempt_mining_block_with_difficulty(
+            blockchain.last_hash().clone(),
+            Address::new(3),
+            vec![transfer],
+            0..1,
+            0,
+        )
+        .unwrap();
+        blockchain.append_block(block).unwrap();
+        let audit = blockchain.audit_supply();
+        assert!(audit.consistent);
+        assert_eq!(
+            audit.issued_coins,
+            GENESIS_ALLOCATION.units() as u128 + 3 * COINS_PER_MINED_BLOCK.units() as u128
+        );
+
+        blockchain
+            .balance_mut(miner)
+            .credit(Amount::from_units(1))
+            .unwrap();
+        assert!(!blockchain.audit_supply().consistent);
+    }
+
+    #[test]
+    fn burn_coins() {
+        let miner = Address::new(1);
+        let mut blockchain = BlockChain::with_difficulty(0);
+        let mine = |blockchain: &mut BlockChain, transactions| {
+            let block = attempt_mining_block_with_difficulty(
+                blockchain.last_hash().clone(),
+                miner,
+                transactions,
+                0..1,
+                0,
+            )
+            .unwrap();
+            blockchain.append_block(block)
+        };
+        mine(&mut blockchain, vec![]).unwrap();
+        let burn = BlockTransaction::new(
+            blockchain.last_hash().clone(),
+            Transaction::burn(miner, Amount::from_coins(300)),
+        );
+        mine(&mut blockchain, vec![burn]).unwrap();
+        assert_eq!(blockchain.burned(), Amount::from_coins(300));
+        assert_eq!(blockchain.balance_of(miner), Amount::from_coins(1700));
+        let audit = blockchain.audit_supply();
+        assert!(audit.consistent);
+        assert_eq!(audit.burned_coins, Amount::from_coins(300).units() as u128);
+
+        blockchain.pop_block().unwrap();
+        assert_eq!(blockchain.burned(), Amount::ZERO);
+        assert_eq!(blockchain.balance_of(miner), COINS_PER_MINED_BLOCK);
+        assert!(blockchain.audit_supply().consistent);
+    }
+
+    #[test]
+    fn burn_base_fees() {
+        let (miner, receiver) = (Address::new(1), Address::new(2));
+        let mut blockchain = BlockChain::with_difficulty(0);
+        blockchain.set_rules(TransactionRules {
+            fee_policy: FeePolicy::BaseFee,
+            ..TransactionRules::DEFAULT
+        });
+        let mine = |blockchain: &mut BlockChain, transactions| {
+            let block = attempt_mining_block_with_difficulty(
+                blockchain.last_hash().clone(),
+                miner,
+                transactions,
+                0..1,
+                0,
+            )
+            .unwrap();
+            blockchain.append_block(block)
+        };
+        assert_eq!(blockchain.next_base_fee(), Some(BASE_FEE_INITIAL));
+        mine(&mut blockchain, vec![]).unwrap();
+        let base_fee = blockchain.next_base_fee().unwrap();
+        assert!(base_fee < BASE_FEE_INITIAL);
+
+        // A transaction below the base fee is rejected
+        let transfer = |blockchain: &BlockChain, fee| {
+            BlockTransaction::new(
+                blockchain.last_hash().clone(),
+                Transaction::new(miner, receiver, Amount::from_coins(10)).with_fee(fee),
+            )
+        };
+        let below = transfer(&blockchain, base_fee.saturating_sub(Amount::from_units(1)));
+        assert!(mine(&mut blockchain, vec![below]).is_err());
+
+        // The base fee is burned, and the miner earns the tip
+        let tip = Amount::from_coins(2);
+        let paying = transfer(&blockchain, base_fee.saturating_add(tip));
+        mine(&mut blockchain, vec![paying]).unwrap();
+        assert_eq!(blockchain.base_fee_at(2), base_fee);
+        assert_eq!(blockchain.burned_fees(), base_fee);
+        assert_eq!(blockchain.burned(), base_fee);
+        // The miner paid the base fee and the tip, and earned the tip back
+        assert_eq!(
+            blockchain.balance_of(miner),
+            Amount::from_coins(1990).saturating_sub(base_fee)
+        );
+        assert!(blockchain.audit_supply().consistent);
+
+        blockchain.pop_block().unwrap();
+        assert_eq!(blockchain.burned_fees(), Amount::ZERO);
+        assert_eq!(blockchain.burned(), Amount::ZERO);
+        assert_eq!(blockchain.balance_of(miner), COINS_PER_MINED_BLOCK);
+        assert_eq!(blockchain.next_base_fee(), Some(base_fee));
+        assert!(blockchain.audit_supply().consistent);
+    }
+
+    #[test]
+    fn count_confirmations() {
+        let miner = Address::new(1);
+        let mut blockchain = BlockChain::with_difficulty(0);
+        let mine = |blockchain: &mut BlockChain, transactions| {
+            let block = attempt_mining_block_with_difficulty(
+                blockchain.last_hash().clone(),
+                miner,
+                transactions,
+                0..1,
+                0,
+            )
+            .unwrap();
+            blockchain.append_block(block).unwrap();
+        };
+        mine(&mut blockchain, vec![]);
+        let transaction = BlockTransaction::new(
+            blockchain.last_hash().clone(),
+            Transaction::new(miner, Address::new(2), Amount::from_coins(10)),
+        );
+        assert_eq!(blockchain.confirmations(&transaction.id), 0);
+        mine(&mut blockchain, vec![transaction.clone()]);
+        mine(&mut blockchain, vec![]);
+        assert_eq!(blockchain.transaction_height(&transaction.id), Some(2));
+        assert_eq!(blockchain.confirmations(&transaction.id), 2);
+    }
+
+    #[test]
+    fn generate_receipts() {
+        let (sender, receiver) = (Address::FAUCET, Address::new(2));
+        let mut blockchain = BlockChain::with_difficulty(0);
+        let transactions: Vec<BlockTransaction> = (1..=2)
+            .map(|coins| {
+                BlockTransaction::new(
+                    blockchain.last_hash().clone(),
+                    Transaction::new(sender, receiver, Amount::from_coins(coins))
+                        .with_fee(Amount::from_coins(1)),
+                )
+            })
+            .collect();
+        let block = attempt_mining_block_with_difficulty(
+            blockchain.last_hash().clone(),
+            Address::new(1),
+            transactions.clone(),
+            0..1,
+            0,
+        )
+        .unwrap();
+        blockchain.append_block(block).unwrap();
+
+        let receipt = blockchain.receipt(&transactions[1].id).unwrap();
+        assert_eq!(&receipt.block_hash, blockchain.last_hash());
+        assert_eq!((receipt.height, receipt.index), (1, 1));
+        assert_eq!(receipt.miner_fee, Amount::from_coins(1));
+        // The balances include the previous transactions of the block
+        assert_eq!(receipt.receiver_balance, Some(Amount::from_coins(3)));
+        assert_eq!(
+            receipt.sender_balance,
+            GENESIS_ALLOCATION.saturating_sub(Amount::from_coins(5))
+        );
+
+        // The receipts are not stored, but regenerated when the blockchain is loaded
+        let json = serde_json::to_string(&blockchain).unwrap();
+        assert!(!json.contains("receipts"));
+        let loaded: BlockChain = serde_json::from_str(&json).unwrap();
+        for transaction in &transactions {
+            assert_eq!(
+                loaded.receipt(&transaction.id),
+                blockchain.receipt(&transaction.id)
+            );
+        }
+
+        blockchain.pop_block().unwrap();
+        assert!(blockchain.receipt(&transactions[0].id).is_none());
     }
 }
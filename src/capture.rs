@@ -0,0 +1,328 @@
+use crate::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use tokio::sync::{mpsc, oneshot};
+
+/// What went through the network, as recorded in a capture file, one JSON object per line.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Record {
+    /// A message delivered to the inbox of a node.
+    Delivered {
+        /// When the message was delivered, in milliseconds since the UNIX epoch.
+        timestamp: u64,
+        destination: Address,
+        message: Message,
+    },
+    /// A block sent by a node in response to a query.
+    Reply {
+        /// When the block was sent, in milliseconds since the UNIX epoch.
+        timestamp: u64,
+        source: Address,
+        block: Block,
+    },
+}
+
+/// The file to which the network records what it delivers, written in a task of its own so that
+/// the network does not wait for the disk.
+struct Capture {
+    commands: mpsc::UnboundedSender<CaptureCommand>,
+}
+
+enum CaptureCommand {
+    /// Append a serialized record to the file.
+    Write(String),
+    /// Report when the previous records are written.
+    Flush(oneshot::Sender<()>),
+}
+
+static CAPTURE: OnceLock<Capture> = OnceLock::new();
+
+/// The blocks that the network serves to the replayed node, indexed by their hash.
+static REPLAYED_BLOCKS: OnceLock<HashMap<BlockHash, Block>> = OnceLock::new();
+
+/// Start recording every message delivered by the network to `path`, overwriting the file. Must
+/// be called within a Tokio runtime.
+pub fn start(path: &Path) -> Result<(), ()> {
+    let file =
+        File::create(path).map_err(|err| error!("Failed to create {}: {err}", path.display()))?;
+    let (commands, receiver) = mpsc::unbounded_channel();
+    if CAPTURE.set(Capture { commands }).is_err() {
+        error!("The network is already being captured");
+        return Err(());
+    }
+    tokio::spawn(write_records(
+        path.to_path_buf(),
+        BufWriter::new(file),
+        receiver,
+    ));
+    info!("Capturing the network messages to {}", path.display());
+    Ok(())
+}
+
+/// Wait until the records captured so far are written, if the network is being captured.
+pub async fn flush() {
+    let Some(capture) = CAPTURE.get() else {
+        return;
+    };
+    let (flushed, done) = oneshot::channel();
+    if capture
+        .commands
+        .send(CaptureCommand::Flush(flushed))
+        .is_ok()
+    {
+        let _ = done.await;
+    }
+}
+
+/// Append the records sent by the network to the capture file, as many at a time as are waiting.
+async fn write_records(
+    path: PathBuf,
+    mut file: BufWriter<File>,
+    mut commands: mpsc::UnboundedReceiver<CaptureCommand>,
+) {
+    let mut received = vec![];
+    while commands
+        .recv_many(&mut received, CAPTURE_MAX_BATCH_RECORDS)
+        .await
+        > 0
+    {
+        let mut lines = vec![];
+        let mut flushed = vec![];
+        for command in received.drain(..) {
+            match command {
+                CaptureCommand::Write(line) => lines.push(line),
+                CaptureCommand::Flush(done) => flushed.push(done),
+            }
+        }
+        let written = tokio::task::spawn_blocking(move || {
+            let result = lines
+                .iter()
+                .try_for_each(|line| writeln!(file, "{line}"))
+                .and_then(|()| file.flush());
+            (file, result)
+        })
+        .await;
+        let Ok((returned_file, result)) = written else {
+            error!(
+                "The task capturing the network panicked, {} is no longer written",
+                path.display()
+            );
+            return;
+        };
+        file = returned_file;
+        if let Err(err) = result {
+            warn!(
+                "Failed to write captured messages to {}: {err}",
+                path.display()
+            );
+        }
+        for done in flushed {
+            let _ = done.send(());
+        }
+    }
+}
+
+/// Record a message delivered by the network, if the network is being captured.
+pub fn record(record: impl FnOnce() -> Record) {
+    let Some(capture) = CAPTURE.get() else {
+        return;
+    };
+    let line = match serde_json::to_string(&record()) {
+        Ok(line) => line,
+        Err(err) => {
+            warn!("Failed to serialize a captured message: {err}");
+            return;
+        }
+    };
+    let _ = capture.commands.send(CaptureCommand::Write(line));
+}
+
+/// Read the records of a capture file.
+pub fn read(path: &Path) -> Result<Vec<Record>, ()> {
+    let file =
+        File::open(path).map_err(|err| error!("Failed to open {}: {err}", path.display()))?;
+    let mut records = vec![];
+    for (index, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|err| error!("Failed to read {}: {err}", path.display()))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record = serde_json::from_str(&line).map_err(|err| {
+            error!(
+                "Invalid record at line {} of {}: {err}",
+                index + 1,
+                path.display()
+            )
+        })?;
+        records.push(record);
+    }
+    Ok(records)
+}
+
+/// A block of the replayed capture, which the network serves instead of the nodes that sent it.
+pub fn replayed_block(block_hash: &BlockHash) -> Option<Block> {
+    REPLAYED_BLOCKS.get()?.get(block_hash).cloned()
+}
+
+/// Feed the messages of a capture file that were delivered to a node (by default, the destination
+/// of the first message) into a new node with the same address, one at a time and in their
+/// original order. The node does not mine, and it fetches missing blocks from the ones of the
+/// capture, so that the replay is deterministic. Returns the node at the end of the replay.
+pub async fn replay(path: &Path, address: Option<Address>) -> Result<Node, ()> {
+    let records = read(path)?;
+    let first_destination = records.iter().find_map(|record| match record {
+        Record::Delivered { destination, .. } => Some(*destination),
+        Record::Reply { .. } => None,
+    });
+    let Some(address) = address.or(first_destination) else {
+        error!("There are no messages to replay in {}", path.display());
+        return Err(());
+    };
+
+    let mut blocks = HashMap::new();
+    for record in &records {
+        match record {
+            Record::Delivered {
+                message: Message::NewBlock { block, .. },
+                ..
+            }
+            | Record::Reply { block, .. } => {
+                blocks.insert(block.hash(), block.clone());
+            }
+            Record::Delivered { .. } => {}
+        }
+    }
+    if REPLAYED_BLOCKS.set(blocks).is_err() {
+        error!("A capture has already been replayed");
+        return Err(());
+    }
+
+    let mut node = Node::with_address(address);
+    let mut replayed = 0;
+    for record in records {
+        let Record::Delivered {
+            timestamp,
+            destination,
+            message,
+        } = record
+        else {
+            continue;
+        };
+        if destination != address {
+            continue;
+        }
+        debug!("Replaying the message delivered at {timestamp}: {message:?}");
+        node.replay_message(message).await;
+        replayed += 1;
+    }
+    info!("Replayed {replayed} messages to node {address}");
+    Ok(node)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_captured_records() {
+        let (alice, bob) = (Address::new(1), Address::new(2));
+        let genesis = Block::genesis();
+        let transaction = BlockTransaction::new(
+            genesis.hash(),
+            Transaction::new(alice, bob, Amount::from_coins(10)),
+        );
+        let records = [
+            Record::Delivered {
+                timestamp: 1,
+                destination: bob,
+                message: Message::Transaction {
+                    transaction: transaction.clone(),
+                    source: alice,
+                },
+            },
+            Record::Reply {
+                timestamp: 2,
+                source: alice,
+                block: genesis.clone(),
+            },
+        ];
+        let path = std::env::temp_dir().join(format!("smallchain-capture-{}", std::process::id()));
+        let lines: Vec<String> = records
+            .iter()
+            .map(|record| serde_json::to_string(record).unwrap())
+            .collect();
+        std::fs::write(&path, lines.join("\n")).unwrap();
+
+        let read_records = read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(read_records.len(), 2);
+        let Record::Delivered {
+            destination,
+            message:
+                Message::Transaction {
+                    transaction: read_transaction,
+                    source,
+                },
+            ..
+        } = &read_records[0]
+        else {
+            panic!("Unexpected record {:?}", read_records[0]);
+        };
+        assert_eq!((*destination, *source), (bob, alice));
+        assert_eq!(read_transaction, &transaction);
+        let Record::Reply { block, .. } = &read_records[1] else {
+            panic!("Unexpected record {:?}", read_records[1]);
+        };
+        assert_eq!(block, &genesis);
+    }
+
+    #[tokio::test]
+    async fn replay_the_messages_delivered_to_a_node() {
+        let (alice, bob) = (Address::new(1), Address::new(2));
+        let mut blocks = vec![Block::genesis()];
+        for _ in 0..2 {
+            let prefix_hash = blocks.last().unwrap().hash();
+            let block = attempt_mining_block(prefix_hash, alice, vec![], 0..=u64::MAX);
+            blocks.push(block.unwrap());
+        }
+        let new_block = |block: &Block, blockchain_length, destination| Record::Delivered {
+            timestamp: 1,
+            destination,
+            message: Message::NewBlock {
+                block: block.clone(),
+                blockchain_length,
+                source: alice,
+                params_hash: None,
+            },
+        };
+        // Bob hears of the last block only, and fetches the one before it from the reply
+        // captured, while the message to another node is not replayed.
+        let records = [
+            new_block(&blocks[2], 3, bob),
+            Record::Reply {
+                timestamp: 2,
+                source: alice,
+                block: blocks[1].clone(),
+            },
+            new_block(&blocks[1], 2, Address::new(3)),
+        ];
+        let path = std::env::temp_dir().join(format!("smallchain-replay-{}", std::process::id()));
+        let lines: Vec<String> = records
+            .iter()
+            .map(|record| serde_json::to_string(record).unwrap())
+            .collect();
+        std::fs::write(&path, lines.join("\n")).unwrap();
+
+        let node = replay(&path, None).await.unwrap();
+        assert_eq!(node.address(), bob);
+        assert_eq!(node.blockchain().hash_at(2), Some(&blocks[2].hash()));
+        // The blocks of a capture are served for the whole run, so only one can be replayed.
+        assert!(replay(&path, Some(bob)).await.is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
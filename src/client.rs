@@ -0,0 +1,26 @@
+use crate::prelude::*;
+use hyper::body::HttpBody;
+
+/// Send a GET request to a server running on the local host and return the body of the response.
+/// Returns an error if the server cannot be reached or if it does not reply with a success status.
+pub async fn get(port: u16, path_and_query: &str) -> Result<String, ()> {
+    let uri: hyper::Uri = format!("http://127.0.0.1:{port}{path_and_query}")
+        .parse()
+        .map_err(|err| error!("Invalid request path {path_and_query:?}: {err}"))?;
+    let response = hyper::Client::new()
+        .get(uri.clone())
+        .await
+        .map_err(|err| error!("Failed to query {uri}: {err}"))?;
+    let status = response.status();
+    let mut body = response.into_body();
+    let mut bytes = vec![];
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|err| error!("Failed to read the response from {uri}: {err}"))?;
+        bytes.extend_from_slice(&chunk);
+    }
+    if !status.is_success() {
+        error!("Request to {uri} failed with status {status}");
+        return Err(());
+    }
+    String::from_utf8(bytes).map_err(|err| error!("Invalid response from {uri}: {err}"))
+}
@@ -0,0 +1,687 @@
+use crate::prelude::*;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::RwLock;
+use std::time::Duration;
+
+/// The settings of the simulation that can be changed while it runs.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// After how many seconds a pending transaction that has not been mined is broadcast again.
+    /// Zero disables the re-broadcast.
+    pub rebroadcast_after_secs: u64,
+    /// Every how many seconds each node announces the height, the hash and the cumulative work of
+    /// its last block to its peers, so that the ones that missed a block notice that they are
+    /// behind. Zero disables the announcements.
+    pub heartbeat_interval_secs: u64,
+    /// The rules that the transactions of the blocks must satisfy. Only affects new nodes, and
+    /// cannot be reloaded, because the blockchains of the running nodes already apply them.
+    pub transaction_rules: TransactionRules,
+    /// The rules that transactions must satisfy to be admitted in the mempools.
+    pub transaction_policy: TransactionPolicy,
+    /// Named settings from which to create nodes.
+    pub node_templates: BTreeMap<String, NodeConfig>,
+    /// Whether each new node serves its read-only endpoints on its own HTTP port.
+    pub node_ports: bool,
+    /// The maximum throughput of each link between two nodes, in bytes per second. Zero means
+    /// unlimited.
+    pub link_bandwidth: u64,
+    /// The settings of the links between the nodes of two regions, e.g. `[region_links.eu.us]`.
+    /// Links are symmetric, so each pair of regions only needs to be configured once.
+    pub region_links: BTreeMap<String, BTreeMap<String, LinkConfig>>,
+    /// In gossip mode, to how many random peers each node sends a block or a transaction. Zero
+    /// disables the gossip mode: blocks and transactions are sent to all the nodes, which do not
+    /// forward them.
+    pub gossip_fanout: usize,
+    /// In gossip mode, the probability that a node forwards a block or a transaction that it has
+    /// just learned about.
+    pub gossip_forward_probability: f64,
+    /// Whether nodes announce the hashes of their new blocks and transactions, letting the peers
+    /// request the ones that they miss, instead of sending them.
+    pub inventory: bool,
+    /// The probability that the network delivers a message twice.
+    pub duplicate_probability: f64,
+    /// The probability that the network delays a message by a random amount, so that it can be
+    /// delivered after messages sent later on the same link.
+    pub reorder_probability: f64,
+    /// The maximum number of nodes in the world. Zero means unlimited.
+    pub max_nodes: usize,
+    /// The maximum sum of the hashrates of the nodes in the world. Zero means unlimited.
+    pub max_total_hashrate: f64,
+    /// Whether the nodes whose task fails are restarted, with an exponential backoff, from their
+    /// blocks and pending transactions. Otherwise, they stay in the world as failed nodes.
+    pub restart_failed_nodes: bool,
+    /// How long the demo waits between two transactions, in milliseconds.
+    pub demo_interval_millis: u64,
+    /// The fraction of the transactions of the demo that pay a high fee.
+    pub demo_priority_fraction: f64,
+    /// The most verbose level of the logs.
+    pub log_level: LogLevel,
+    /// The name of the network backend that carries the messages between the nodes, e.g. one
+    /// registered by a downstream crate. By default, the network is simulated in the process.
+    /// Cannot be reloaded, because the world already uses its network.
+    pub network_backend: Option<String>,
+    /// With the TCP network backend, the socket address on which to accept the connections of the
+    /// other processes, e.g. `0.0.0.0:7000`. Cannot be reloaded.
+    pub tcp_listen: Option<String>,
+    /// With the TCP network backend, the `host:port` addresses of the other processes to connect
+    /// to. Connections that fail or break are retried, waiting longer after each failure. Cannot
+    /// be reloaded.
+    pub tcp_peers: Vec<String>,
+    /// With the TCP network backend, the `host:port` addresses of processes from which to discover
+    /// the other processes of the network, which are only connected to while this process is not
+    /// connected to any other. Cannot be reloaded.
+    pub bootnodes: Vec<String>,
+    /// With the TCP network backend, the seed processes to connect to, e.g. `[peers]` with
+    /// `seeds = ["seed.example.org:7000"]`. Cannot be reloaded.
+    pub peers: PeersConfig,
+    /// With the TCP network backend, the static keys that authenticate the processes, which then
+    /// encrypt their connections, e.g. `[tcp_encryption]` with `key` and `peer_keys`. By default,
+    /// the connections are neither authenticated nor encrypted. Cannot be reloaded.
+    pub tcp_encryption: Option<TcpEncryptionConfig>,
+}
+
+impl Config {
+    const DEFAULT: Config = Config {
+        rebroadcast_after_secs: NODE_DEFAULT_REBROADCAST_AFTER_SECS,
+        heartbeat_interval_secs: NODE_DEFAULT_HEARTBEAT_INTERVAL_SECS,
+        transaction_rules: TransactionRules::DEFAULT,
+        transaction_policy: TransactionPolicy::DEFAULT,
+        node_templates: BTreeMap::new(),
+        node_ports: false,
+        link_bandwidth: 0,
+        region_links: BTreeMap::new(),
+        gossip_fanout: 0,
+        gossip_forward_probability: 1.0,
+        inventory: false,
+        duplicate_probability: 0.0,
+        reorder_probability: 0.0,
+        max_nodes: WORLD_DEFAULT_MAX_NODES,
+        max_total_hashrate: 0.0,
+        restart_failed_nodes: false,
+        demo_interval_millis: 1000,
+        demo_priority_fraction: 0.1,
+        log_level: LogLevel::Info,
+        network_backend: None,
+        tcp_listen: None,
+        tcp_peers: Vec::new(),
+        bootnodes: Vec::new(),
+        peers: PeersConfig { seeds: Vec::new() },
+        tcp_encryption: None,
+    };
+
+    /// Read the settings from a TOML file. Missing settings have their default value.
+    pub fn load(path: &Path) -> Result<Config, ()> {
+        let raw_config = std::fs::read_to_string(path)
+            .map_err(|err| error!("Failed to read {}: {err}", path.display()))?;
+        toml::from_str(&raw_config)
+            .map_err(|err| error!("Invalid configuration in {}: {err}", path.display()))
+    }
+
+    /// Check that the settings are consistent, logging the ones that are not.
+    pub fn validate(&self) -> Result<(), ()> {
+        for (name, probability) in [
+            (
+                "gossip_forward_probability",
+                self.gossip_forward_probability,
+            ),
+            ("duplicate_probability", self.duplicate_probability),
+            ("reorder_probability", self.reorder_probability),
+            ("demo_priority_fraction", self.demo_priority_fraction),
+        ] {
+            if !(0.0..=1.0).contains(&probability) {
+                error!("Invalid {name} {probability}: it must be between 0 and 1");
+                return Err(());
+            }
+        }
+        if self.transaction_rules.max_block_weight < TRANSACTION_BASE_WEIGHT {
+            error!(
+                "Invalid max_block_weight {}: it must be at least the weight of a transaction, {TRANSACTION_BASE_WEIGHT}",
+                self.transaction_rules.max_block_weight
+            );
+            return Err(());
+        }
+        for (name, template) in &self.node_templates {
+            if template.validate().is_err() {
+                error!("Invalid node template {name:?}");
+                return Err(());
+            }
+        }
+        if let Some(name) = &self.network_backend {
+            if !network_backend_exists(name) {
+                error!("Invalid network_backend {name:?}: no such backend is registered");
+                return Err(());
+            }
+        }
+        if let Some(tcp_listen) = &self.tcp_listen {
+            if let Err(err) = tcp_listen.parse::<std::net::SocketAddr>() {
+                error!("Invalid tcp_listen {tcp_listen:?}: {err}");
+                return Err(());
+            }
+        }
+        if (self.tcp_listen.is_some()
+            || !self.tcp_peers.is_empty()
+            || !self.bootnodes.is_empty()
+            || !self.peers.seeds.is_empty()
+            || self.tcp_encryption.is_some())
+            && self.network_backend() != TCP_NETWORK_BACKEND
+        {
+            warn!(
+                "Ignoring tcp_listen, tcp_peers, bootnodes, peers and tcp_encryption, which only \
+                 apply to the tcp network backend"
+            );
+        }
+        if let Some(encryption) = &self.tcp_encryption {
+            let Some(key) = encryption.key() else {
+                error!("Invalid key of tcp_encryption: it must be 32 bytes in hexadecimal");
+                return Err(());
+            };
+            if encryption.peer_keys.is_empty()
+                || encryption.peer_keys().len() < encryption.peer_keys.len()
+            {
+                error!(
+                    "Invalid peer_keys of tcp_encryption: they must be 32 bytes in hexadecimal, \
+                     and at least one"
+                );
+                return Err(());
+            }
+            if encryption.peer_keys().contains(&key) {
+                error!(
+                    "Invalid peer_keys of tcp_encryption: they contain the key of this process, \
+                     while each process must have its own key"
+                );
+                return Err(());
+            }
+        }
+        if self.max_total_hashrate < 0.0 || !self.max_total_hashrate.is_finite() {
+            error!(
+                "Invalid max_total_hashrate {}: it must be a non-negative number",
+                self.max_total_hashrate
+            );
+            return Err(());
+        }
+        Ok(())
+    }
+
+    /// How long a pending transaction waits before being broadcast again, if it is at all.
+    pub fn rebroadcast_after(&self) -> Option<Duration> {
+        (self.rebroadcast_after_secs > 0).then(|| Duration::from_secs(self.rebroadcast_after_secs))
+    }
+
+    /// How often the nodes announce their tip, if they do at all.
+    pub fn heartbeat_interval(&self) -> Option<Duration> {
+        (self.heartbeat_interval_secs > 0)
+            .then(|| Duration::from_secs(self.heartbeat_interval_secs))
+    }
+
+    /// Whether a node that has just learned about a block or a transaction forwards it to its
+    /// peers. Only in gossip mode, with the forward probability.
+    pub fn should_forward(&self) -> bool {
+        self.gossip_fanout > 0 && rand::thread_rng().gen_bool(self.gossip_forward_probability)
+    }
+
+    /// The maximum throughput of each link, in bytes per second, if it is limited.
+    pub fn link_bandwidth(&self) -> Option<u64> {
+        (self.link_bandwidth > 0).then_some(self.link_bandwidth)
+    }
+
+    /// The name of the network backend.
+    pub fn network_backend(&self) -> String {
+        self.network_backend
+            .clone()
+            .unwrap_or_else(|| DEFAULT_NETWORK_BACKEND.to_string())
+    }
+
+    /// The maximum number of nodes in the world, if it is limited.
+    pub fn max_nodes(&self) -> Option<usize> {
+        (self.max_nodes > 0).then_some(self.max_nodes)
+    }
+
+    /// The maximum sum of the hashrates of the nodes in the world, if it is limited.
+    pub fn max_total_hashrate(&self) -> Option<f64> {
+        (self.max_total_hashrate > 0.0).then_some(self.max_total_hashrate)
+    }
+
+    /// The settings of the link between a node in `source_region` and one in
+    /// `destination_region`. The links that involve a node without a region, or two regions
+    /// without settings, have no latency and the default bandwidth.
+    pub fn link(
+        &self,
+        source_region: Option<&str>,
+        destination_region: Option<&str>,
+    ) -> LinkConfig {
+        let region_link = |from: &str, to: &str| self.region_links.get(from)?.get(to);
+        let mut link = match (source_region, destination_region) {
+            (Some(source), Some(destination)) => region_link(source, destination)
+                .or_else(|| region_link(destination, source))
+                .cloned()
+                .unwrap_or_default(),
+            _ => LinkConfig::default(),
+        };
+        if link.bandwidth == 0 {
+            link.bandwidth = self.link_bandwidth;
+        }
+        link
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config::DEFAULT
+    }
+}
+
+/// The verbosity of the logs.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Error,
+    Warn,
+    #[default]
+    Info,
+    Debug,
+    Trace,
+}
+
+/// Why new settings could not replace the current ones.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ReloadError {
+    /// The settings could not be read, or are invalid; the reason is logged.
+    Invalid,
+    /// The settings change a field that cannot change while the simulation runs.
+    Immutable { field: &'static str },
+}
+
+impl ReloadError {
+    /// A short machine-readable identifier of the error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ReloadError::Invalid => "invalid_config",
+            ReloadError::Immutable { .. } => "immutable_setting",
+        }
+    }
+}
+
+impl std::fmt::Display for ReloadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ReloadError::Invalid => write!(f, "the settings are invalid, see the logs"),
+            ReloadError::Immutable { field } => {
+                write!(f, "{field} cannot change while the simulation runs")
+            }
+        }
+    }
+}
+
+/// Reads the settings again, e.g. from the configuration file.
+pub type ConfigLoader = std::sync::Arc<dyn Fn() -> Result<Config, ()> + Send + Sync>;
+
+/// The settings of a single node.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NodeConfig {
+    /// The mining speed, relative to the default one: the number of nonces that the node tries in
+    /// a row is multiplied by this factor. Zero disables mining.
+    pub hashrate: f64,
+    /// The maximum number of pending transactions in the mempool, if any.
+    pub max_mempool_transactions: Option<usize>,
+    /// Which messages the node accepts from its peers.
+    pub peer_policy: PeerPolicy,
+    /// How the node computes the hashes of the blocks that it mines.
+    pub hasher: HasherKind,
+    /// The region of the node, which determines the latency and the bandwidth of its links.
+    pub region: Option<String>,
+    /// Free-form labels shown in the list of the nodes, e.g. the role of the node in an
+    /// experiment.
+    pub labels: BTreeMap<String, String>,
+}
+
+impl NodeConfig {
+    /// Check that the settings are consistent, logging the errors.
+    pub fn validate(&self) -> Result<(), ()> {
+        if !(0.0..=NODE_MAX_HASHRATE).contains(&self.hashrate) {
+            error!(
+                "Invalid hashrate {}: it must be a number between 0 and {NODE_MAX_HASHRATE}",
+                self.hashrate
+            );
+            return Err(());
+        }
+        Ok(())
+    }
+}
+
+impl Default for NodeConfig {
+    fn default() -> Self {
+        NodeConfig {
+            hashrate: 1.0,
+            max_mempool_transactions: None,
+            peer_policy: PeerPolicy::default(),
+            hasher: HasherKind::default(),
+            region: None,
+            labels: BTreeMap::new(),
+        }
+    }
+}
+
+/// The settings of the links between two regions.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LinkConfig {
+    /// How long a message takes to travel on the link, after being transmitted, in milliseconds.
+    pub latency_millis: u64,
+    /// The maximum throughput of the link, in bytes per second. Zero means the default
+    /// `link_bandwidth`.
+    pub bandwidth: u64,
+}
+
+impl LinkConfig {
+    pub fn latency(&self) -> Duration {
+        Duration::from_millis(self.latency_millis)
+    }
+
+    /// The maximum throughput of the link, in bytes per second, if it is limited.
+    pub fn bandwidth(&self) -> Option<u64> {
+        (self.bandwidth > 0).then_some(self.bandwidth)
+    }
+}
+
+/// The processes to which the TCP network backend connects at startup.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PeersConfig {
+    /// The `host:port` addresses of the seed processes. Each host name is resolved again every
+    /// `NETWORK_TCP_SEED_REFRESH_INTERVAL`, and all its addresses are connected to, retrying the
+    /// connections that fail or break while waiting longer after each failure.
+    pub seeds: Vec<String>,
+}
+
+/// The static keys with which the TCP network backend authenticates the processes and encrypts
+/// their connections. The keys are secret, so they are neither serialized nor printed.
+#[derive(Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TcpEncryptionConfig {
+    /// The key of this process: 32 bytes in hexadecimal, e.g. from `openssl rand -hex 32`.
+    #[serde(skip_serializing)]
+    pub key: String,
+    /// The keys of the processes that may connect to this one, or that it connects to, in
+    /// hexadecimal.
+    #[serde(skip_serializing)]
+    pub peer_keys: Vec<String>,
+}
+
+impl TcpEncryptionConfig {
+    /// The key of this process, unless it is invalid.
+    pub fn key(&self) -> Option<[u8; 32]> {
+        parse_key(&self.key)
+    }
+
+    /// The valid keys of the other processes.
+    pub fn peer_keys(&self) -> Vec<[u8; 32]> {
+        self.peer_keys
+            .iter()
+            .filter_map(|key| parse_key(key))
+            .collect()
+    }
+}
+
+impl std::fmt::Debug for TcpEncryptionConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("TcpEncryptionConfig")
+            .finish_non_exhaustive()
+    }
+}
+
+/// Parse a key of 32 bytes written in hexadecimal.
+fn parse_key(hex: &str) -> Option<[u8; 32]> {
+    if hex.len() != 64 || !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return None;
+    }
+    let mut key = [0; 32];
+    for (index, byte) in key.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[2 * index..2 * index + 2], 16).ok()?;
+    }
+    Some(key)
+}
+
+/// Which messages a node accepts from its peers.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PeerPolicy {
+    /// Blocks and transactions.
+    #[default]
+    All,
+    /// Only blocks. The transactions broadcast by the peers are rejected, so the node only mines
+    /// the transactions of its own clients.
+    BlocksOnly,
+}
+
+/// How a node computes the hashes of the blocks that it mines. With the same hashrate, a node
+/// with a faster hasher goes through its nonces sooner, so it mines more blocks per second.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HasherKind {
+    /// Encode and hash the whole block for each nonce.
+    #[default]
+    Naive,
+    /// Hash the part of the block before the nonce once, and only the nonce for each nonce.
+    Midstate,
+    /// Like `Midstate`, but hashing the nonces in batches.
+    Batched,
+    /// Offload the batches of nonces to a GPU (a stub, for now).
+    #[cfg(feature = "gpu")]
+    Gpu,
+}
+
+impl HasherKind {
+    pub fn hasher(self) -> &'static dyn Hasher {
+        match self {
+            HasherKind::Naive => &NaiveHasher,
+            HasherKind::Midstate => &MidstateHasher,
+            HasherKind::Batched => &BatchedHasher,
+            #[cfg(feature = "gpu")]
+            HasherKind::Gpu => &GpuHasher,
+        }
+    }
+}
+
+static CONFIG: RwLock<Config> = RwLock::new(Config::DEFAULT);
+
+/// Get a copy of the current settings.
+pub fn config() -> Config {
+    CONFIG.read().unwrap().clone()
+}
+
+/// Replace the current settings. Nodes read them again when they need them.
+pub fn set_config(config: Config) {
+    crate::logging::set_level(config.log_level);
+    *CONFIG.write().unwrap() = config;
+}
+
+/// Replace the current settings with new ones, e.g. read again from the configuration file,
+/// unless they change the settings that cannot change while the simulation runs.
+pub async fn reload_config(new_config: Config) -> Result<(), ReloadError> {
+    if new_config.transaction_rules != config().transaction_rules {
+        warn!("Cannot reload the settings, because they change the transaction rules");
+        return Err(ReloadError::Immutable {
+            field: "transaction_rules",
+        });
+    }
+    if new_config.network_backend != config().network_backend {
+        warn!("Cannot reload the settings, because they change the network backend");
+        return Err(ReloadError::Immutable {
+            field: "network_backend",
+        });
+    }
+    if new_config.tcp_listen != config().tcp_listen {
+        warn!("Cannot reload the settings, because they change the TCP listening address");
+        return Err(ReloadError::Immutable {
+            field: "tcp_listen",
+        });
+    }
+    if new_config.tcp_peers != config().tcp_peers {
+        warn!("Cannot reload the settings, because they change the TCP peers");
+        return Err(ReloadError::Immutable { field: "tcp_peers" });
+    }
+    if new_config.bootnodes != config().bootnodes {
+        warn!("Cannot reload the settings, because they change the bootnodes");
+        return Err(ReloadError::Immutable { field: "bootnodes" });
+    }
+    if new_config.peers != config().peers {
+        warn!("Cannot reload the settings, because they change the seed peers");
+        return Err(ReloadError::Immutable { field: "peers" });
+    }
+    if new_config.tcp_encryption != config().tcp_encryption {
+        warn!("Cannot reload the settings, because they change the keys of the TCP connections");
+        return Err(ReloadError::Immutable {
+            field: "tcp_encryption",
+        });
+    }
+    if new_config == config() {
+        info!("The reloaded settings did not change");
+        return Ok(());
+    }
+    info!("Reload the settings");
+    set_config(new_config.clone());
+    stats()
+        .await
+        .record_world_event(WorldEvent::ConfigChanged {
+            config: Box::new(new_config),
+        })
+        .await;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_seed_peers() {
+        let config: Config = toml::from_str(
+            r#"
+            network_backend = "tcp"
+
+            [peers]
+            seeds = ["seed.example.org:7000", "10.0.0.2:7000"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.peers.seeds,
+            vec!["seed.example.org:7000", "10.0.0.2:7000"]
+        );
+        assert!(config.validate().is_ok());
+        assert!(toml::from_str::<Config>("[peers]\nbootnodes = []").is_err());
+    }
+
+    #[test]
+    fn validate_tcp_keys() {
+        let (a, b) = ("0123456789abcdef".repeat(4), "F".repeat(64));
+        let encryption = |key: &str, peer_keys: &[&str]| Config {
+            network_backend: Some(TCP_NETWORK_BACKEND.to_string()),
+            tcp_encryption: Some(TcpEncryptionConfig {
+                key: key.to_string(),
+                peer_keys: peer_keys.iter().map(|key| key.to_string()).collect(),
+            }),
+            ..Config::default()
+        };
+        let config = encryption(&a, &[&b]);
+        assert!(config.validate().is_ok());
+        let keys = config.tcp_encryption.as_ref().unwrap();
+        assert_eq!(keys.key().unwrap()[..2], [0x01, 0x23]);
+        assert_eq!(keys.peer_keys(), vec![[0xff; 32]]);
+        assert!(encryption(&a[1..], &[&b]).validate().is_err());
+        assert!(encryption(&a.replace('0', "+"), &[&b]).validate().is_err());
+        assert!(encryption(&a, &[]).validate().is_err());
+        assert!(encryption(&a, &[&b, "secret"]).validate().is_err());
+        assert!(encryption(&a, &[&b, &a]).validate().is_err());
+
+        // The keys are secret.
+        let json = serde_json::to_string(&config).unwrap();
+        assert!(!json.contains(&a) && !json.contains(&b));
+        assert!(!format!("{config:?}").contains(&a));
+    }
+
+    #[test]
+    fn parse_node_templates() {
+        let config: Config = toml::from_str(
+            r#"
+            rebroadcast_after_secs = 10
+
+            [node_templates.miner-small]
+            hashrate = 0.5
+            max_mempool_transactions = 100
+
+            [node_templates.relay]
+            hashrate = 0.0
+            peer_policy = "blocks_only"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.rebroadcast_after_secs, 10);
+        assert_eq!(config.transaction_policy, TransactionPolicy::default());
+        let miner = &config.node_templates["miner-small"];
+        assert_eq!(miner.hashrate, 0.5);
+        assert_eq!(miner.max_mempool_transactions, Some(100));
+        assert_eq!(miner.peer_policy, PeerPolicy::All);
+        assert_eq!(
+            config.node_templates["relay"].peer_policy,
+            PeerPolicy::BlocksOnly
+        );
+    }
+
+    #[test]
+    fn reject_invalid_hashrates_in_node_templates() {
+        let template = |hashrate: &str| -> Config {
+            toml::from_str(&format!("[node_templates.miner]\nhashrate = {hashrate}")).unwrap()
+        };
+        assert_eq!(template("0.0").validate(), Ok(()));
+        assert_eq!(template(&NODE_MAX_HASHRATE.to_string()).validate(), Ok(()));
+        for hashrate in ["-1.0", "1e300", "inf", "nan"] {
+            assert_eq!(template(hashrate).validate(), Err(()), "{hashrate}");
+        }
+    }
+
+    #[test]
+    fn symmetric_region_links() {
+        let config: Config = toml::from_str(
+            r#"
+            link_bandwidth = 1000
+
+            [region_links.eu.us]
+            latency_millis = 80
+
+            [region_links.us.asia]
+            latency_millis = 150
+            bandwidth = 500
+            "#,
+        )
+        .unwrap();
+        let link = config.link(Some("us"), Some("eu"));
+        assert_eq!(link.latency(), Duration::from_millis(80));
+        assert_eq!(link.bandwidth(), Some(1000));
+        let link = config.link(Some("us"), Some("asia"));
+        assert_eq!(link.latency(), Duration::from_millis(150));
+        assert_eq!(link.bandwidth(), Some(500));
+        let link = config.link(Some("eu"), None);
+        assert_eq!(link.latency(), Duration::ZERO);
+        assert_eq!(link.bandwidth(), Some(1000));
+    }
+
+    #[tokio::test]
+    async fn reject_reloading_the_transaction_rules() {
+        let mut new_config = config();
+        new_config.transaction_rules.fee_policy = FeePolicy::BaseFee;
+        assert_eq!(
+            reload_config(new_config).await,
+            Err(ReloadError::Immutable {
+                field: "transaction_rules"
+            })
+        );
+        assert_eq!(config().transaction_rules, TransactionRules::DEFAULT);
+        assert_eq!(reload_config(config()).await, Ok(()));
+    }
+}
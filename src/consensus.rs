@@ -0,0 +1,77 @@
+use crate::prelude::*;
+use std::ops::Range;
+
+/// The rules by which the nodes agree on a blockchain. Downstream crates can implement their own
+/// engine and register it with `World::set_consensus_engine`, to prototype other consensus
+/// algorithms with the network, the world and the scenarios of the simulation.
+///
+/// The engine comes on top of the checks of `BlockChain::append_block`, which every block must
+/// still pass, including the proof of work at the difficulty of the blockchain unless the
+/// `proof_of_work` rule is skipped.
+pub trait ConsensusEngine: Send + Sync {
+    /// A short name of the engine, shown in the logs and in the details of the nodes.
+    fn name(&self) -> &str;
+
+    /// Check a block in the context of the blockchain that it extends, before appending it.
+    fn validate_block(&self, blockchain: &BlockChain, block: &Block) -> Result<(), ()>;
+
+    /// The fork choice: whether a node should switch from its `current` blockchain to a valid
+    /// `candidate` blockchain. Nodes only download the candidates that are announced as longer
+    /// than their blockchain.
+    fn prefers(&self, current: &BlockChain, candidate: &BlockChain) -> bool;
+
+    /// Try to produce a block on top of the last block of `blockchain`, completing the
+    /// `template` built for the node, by trying the given nonces with `hasher`. Returns `None`
+    /// if no block could be produced with these nonces.
+    fn produce_block(
+        &self,
+        blockchain: &BlockChain,
+        template: Block,
+        nonces: Range<u64>,
+        hasher: &dyn Hasher,
+    ) -> Option<Block>;
+}
+
+impl std::fmt::Debug for dyn ConsensusEngine {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// The default engine: blocks need a proof of work, and the longest blockchain wins.
+pub struct ProofOfWork;
+
+impl ConsensusEngine for ProofOfWork {
+    fn name(&self) -> &str {
+        "proof_of_work"
+    }
+
+    fn validate_block(&self, blockchain: &BlockChain, block: &Block) -> Result<(), ()> {
+        if !block.meets_difficulty(blockchain.difficulty()) {
+            warn!("The proof of work of block {block} is invalid");
+            return Err(());
+        }
+        Ok(())
+    }
+
+    fn prefers(&self, current: &BlockChain, candidate: &BlockChain) -> bool {
+        candidate.len() > current.len()
+    }
+
+    fn produce_block(
+        &self,
+        blockchain: &BlockChain,
+        template: Block,
+        nonces: Range<u64>,
+        hasher: &dyn Hasher,
+    ) -> Option<Block> {
+        attempt_mining_block_with_hasher(
+            template.prefix_hash,
+            template.miner,
+            template.transactions,
+            nonces,
+            blockchain.difficulty(),
+            hasher,
+        )
+    }
+}
@@ -1,8 +1,288 @@
+use crate::amount::Amount;
+
 /// How many coins a new mined block gives to the miner.
-pub const COINS_PER_MINED_BLOCK: u64 = 1000;
+pub const COINS_PER_MINED_BLOCK: Amount = Amount::from_coins(1000);
+
+/// The maximum length of the memo of a transaction, in bytes.
+pub const MEMO_MAX_BYTES: usize = 255;
+
+/// The weight of a transaction without a memo.
+pub const TRANSACTION_BASE_WEIGHT: u64 = 100;
+
+/// The weight added to a transaction by each byte of its memo.
+pub const WEIGHT_PER_MEMO_BYTE: u64 = 4;
+
+/// The default maximum total weight of the transactions of a block, e.g. 1000 transactions
+/// without a memo.
+pub const DEFAULT_MAX_BLOCK_WEIGHT: u64 = 1000 * TRANSACTION_BASE_WEIGHT;
+
+/// With the base-fee policy, the base fee of the first block.
+pub const BASE_FEE_INITIAL: Amount = Amount::from_coins(1);
+
+/// With the base-fee policy, how many transactions per block keep the base fee constant.
+pub const BASE_FEE_TARGET_TRANSACTIONS: usize = 8;
+
+/// With the base-fee policy, the maximum number of transactions in a block.
+pub const BASE_FEE_MAX_TRANSACTIONS: usize = 2 * BASE_FEE_TARGET_TRANSACTIONS;
+
+/// With the base-fee policy, the base fee changes by at most its fraction `1 / this` per block.
+pub const BASE_FEE_MAX_CHANGE_DENOMINATOR: u128 = 8;
+
+/// How many of the last blocks to show the base fee of.
+pub const BASE_FEE_RECENT_BLOCKS: usize = 20;
+
+/// How many coins the faucet holds in the genesis block.
+pub const GENESIS_ALLOCATION: Amount = Amount::from_coins(1_000_000);
+
+/// How many coins the faucet sends, if the request does not say.
+pub const FAUCET_DEFAULT_AMOUNT: Amount = Amount::from_coins(100);
+
+/// The maximum number of coins that the faucet sends at once.
+pub const FAUCET_MAX_AMOUNT: Amount = COINS_PER_MINED_BLOCK;
+
+/// How long an address waits before getting coins from the faucet again.
+pub const FAUCET_COOLDOWN: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// The human-readable prefix of encoded addresses, which identifies the network they belong to.
+pub const ADDRESS_PREFIX: &str = "smc1";
+
+/// The default size, in bytes, after which a log file is rotated.
+pub const LOG_DEFAULT_MAX_FILE_BYTES: u64 = 10 * 1024 * 1024;
+
+/// The default number of rotated log files to keep.
+pub const LOG_DEFAULT_MAX_FILES: usize = 5;
 
 /// How many leading zero bits the hash of a mined block must have.
 pub const MINING_DIFFICULTY: u32 = 20;
 
+/// How many nonces the batched hasher hashes per call, one per lane.
+pub const MINING_HASHER_BATCH_NONCES: usize = 8;
+
 /// How many nonces to try in a row when mining, before yielding and reacting to the network.
+/// This is the initial budget, which adapts to the number of messages waiting to be processed.
 pub const NODE_MINING_NONCE_STEP: u64 = 1000;
+
+/// The smallest number of nonces to try in a row, when the node has many messages to process.
+pub const NODE_MIN_NONCE_STEP: u64 = NODE_MINING_NONCE_STEP / 10;
+
+/// The largest number of nonces to try in a row, when the node has no messages to process.
+pub const NODE_MAX_NONCE_STEP: u64 = NODE_MINING_NONCE_STEP * 8;
+
+/// The largest hashrate of a node, relative to the default mining speed, so that the number of
+/// nonces that it tries in a row stays far from the largest nonce.
+pub const NODE_MAX_HASHRATE: f64 = 1_000_000.0;
+
+/// From how many waiting messages the inbox of a node is considered deep, halving the nonce budget.
+pub const NODE_INBOX_DEEP_THRESHOLD: usize = 16;
+
+/// How many messages a node processes at most between two mining attempts.
+pub const NODE_INBOX_BATCH: usize = 64;
+
+/// The default smallest amount of a transaction admitted in a mempool.
+pub const MEMPOOL_DEFAULT_MIN_AMOUNT: Amount = Amount::from_units(1);
+
+/// How many transactions on top of an unknown block a mempool holds at most for each sender.
+pub const MEMPOOL_MAX_FUTURE_TRANSACTIONS_PER_SENDER: usize = 16;
+
+/// How many transactions on top of an unknown block a mempool holds at most.
+pub const MEMPOOL_MAX_FUTURE_TRANSACTIONS: usize = 1024;
+
+/// After how many blocks appended to the blockchain a mempool drops a transaction on top of an
+/// unknown block, which is unlikely to ever arrive.
+pub const MEMPOOL_FUTURE_TRANSACTION_EXPIRY_BLOCKS: usize = 10;
+
+/// How many of the most recently received blocks and transactions a node remembers, to drop the
+/// messages that carry them again.
+pub const NODE_SEEN_CACHE_CAPACITY: usize = 4096;
+
+/// How many of its most recent client transactions a node tracks the propagation of.
+pub const NODE_MAX_TRACKED_PROPAGATIONS: usize = 1024;
+
+/// How many of the outcomes of its most recent client transactions a node remembers.
+pub const NODE_MAX_SUBMISSIONS: usize = 256;
+
+/// How many of the blocks prepared for external miners a node remembers.
+pub const NODE_MAX_WORK: usize = 16;
+
+/// The default number of seconds after which a pending transaction is broadcast again.
+pub const NODE_DEFAULT_REBROADCAST_AFTER_SECS: u64 = 30;
+
+/// How often a node looks for pending transactions to broadcast again.
+pub const NODE_REBROADCAST_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// The default number of seconds between two announcements of the tip of a node to its peers.
+pub const NODE_DEFAULT_HEARTBEAT_INTERVAL_SECS: u64 = 5;
+
+/// How long a query waits for the lock of the queried node before failing. The queried node may be
+/// waiting, with its lock held, for a query to the querying node.
+pub const NETWORK_QUERY_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// How many of the most recent deliveries of a block or a transaction to a node the fake network
+/// remembers, to count the redundant ones in its statistics.
+pub const NETWORK_SEEN_CAPACITY: usize = 65536;
+
+/// How long the server waits for the open requests to finish, after a shutdown is requested.
+pub const SERVER_SHUTDOWN_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// How long a query to a node in another process waits for the answer before failing, with the
+/// TCP network backend.
+pub const NETWORK_TCP_QUERY_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// How often the TCP network backend tells the other processes which nodes it hosts, and connects
+/// again to the peers whose connection failed.
+pub const NETWORK_TCP_HELLO_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// The maximum size of a frame received by the TCP network backend. Larger frames close the
+/// connection.
+pub const NETWORK_TCP_MAX_FRAME_BYTES: usize = 16 * 1024 * 1024;
+
+/// How long the TCP network backend waits for the next frame of a connection before closing it.
+/// The other process sends at least one frame every `NETWORK_TCP_HELLO_INTERVAL`.
+pub const NETWORK_TCP_READ_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// How many bytes of a frame the TCP network backend allocates before they arrive. The buffer of
+/// a larger frame grows as its bytes arrive.
+pub const NETWORK_TCP_READ_CHUNK_BYTES: usize = 64 * 1024;
+
+/// How many frames the TCP network backend queues for a connection before dropping the next ones,
+/// e.g. while the other process does not read them.
+pub const NETWORK_TCP_MAX_QUEUED_FRAMES: usize = 4096;
+
+/// How many queries of another process the TCP network backend answers at the same time, on each
+/// connection. The queries beyond are refused.
+pub const NETWORK_TCP_MAX_CONCURRENT_QUERIES: usize = 16;
+
+/// How long the TCP network backend waits for a connection to another process to be established,
+/// or for the addresses of a seed to be resolved.
+pub const NETWORK_TCP_CONNECT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// How long the TCP network backend waits for the handshake of a new connection, when the
+/// connections are encrypted.
+pub const NETWORK_TCP_HANDSHAKE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// How long the TCP network backend waits before connecting again to a process after the first
+/// failure. The delay doubles after each consecutive failure.
+pub const NETWORK_TCP_RECONNECT_MIN_DELAY: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// The maximum delay of the TCP network backend before connecting again to a process that keeps
+/// failing.
+pub const NETWORK_TCP_RECONNECT_MAX_DELAY: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// How often the TCP network backend resolves the host names of the seeds again, to connect to
+/// their new addresses.
+pub const NETWORK_TCP_SEED_REFRESH_INTERVAL: std::time::Duration =
+    std::time::Duration::from_secs(300);
+
+/// How often the TCP network backend tells the other processes which processes it is connected
+/// to, so that they connect to them as well.
+pub const NETWORK_TCP_PEER_EXCHANGE_INTERVAL: std::time::Duration =
+    std::time::Duration::from_secs(10);
+
+/// How many socket addresses of processes the TCP network backend tells another process about at
+/// once, and accepts from it.
+pub const NETWORK_TCP_MAX_EXCHANGED_PEERS: usize = 64;
+
+/// How many socket addresses of processes that it is told about the TCP network backend remembers
+/// to connect to them.
+pub const NETWORK_TCP_MAX_DISCOVERED_PEERS: usize = 64;
+
+/// The maximum extra delay of a message that the network duplicates or reorders.
+pub const NETWORK_FAULT_MAX_DELAY: std::time::Duration = std::time::Duration::from_millis(500);
+
+/// How often to check the nodes, when waiting for them to converge or for a new block.
+pub const WORLD_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(100);
+
+/// By default, how often the supply of the blockchains of the nodes is audited, in seconds.
+pub const WORLD_DEFAULT_AUDIT_INTERVAL_SECS: u64 = 10;
+
+/// By default, how often the tip of each node is sampled, in milliseconds.
+pub const STATS_DEFAULT_TIP_SAMPLE_MILLIS: u64 = 250;
+
+/// By default, how many of the most recent snapshots of the nodes are kept.
+pub const PERSISTENCE_DEFAULT_SNAPSHOTS_KEPT: usize = 10;
+
+/// How long to wait before storing again the blocks of a node after failing to store them.
+pub const PERSISTENCE_STORE_RETRY_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// The fraction of the transactions of the demo that are invalid on purpose.
+pub const DEMO_INVALID_FRACTION: f64 = 0.05;
+
+/// How many blocks of transactions without a memo the mempool of a node holds at most, before the
+/// demo waits for the miners to include them.
+pub const DEMO_MAX_PENDING_BLOCKS: u64 = 10;
+
+/// How many changes of its tip are remembered for each node.
+pub const STATS_MAX_TIP_SAMPLES: usize = 10_000;
+
+/// How many mined blocks are remembered, with the times at which the nodes adopted them.
+pub const STATS_MAX_MINED_BLOCKS: usize = 100_000;
+
+/// How many reorganizations of the blockchains of the nodes are remembered.
+pub const STATS_MAX_REORGS: usize = 10_000;
+
+/// How many changes to the world are remembered in the timeline.
+pub const STATS_MAX_TIMELINE_ENTRIES: usize = 10_000;
+
+/// How many transactions sent by the demo and not yet mined are remembered, to measure their
+/// confirmation latency.
+pub const STATS_MAX_PENDING_DEMO_TRANSACTIONS: usize = 10_000;
+
+/// By default, the maximum number of nodes in the world.
+pub const WORLD_DEFAULT_MAX_NODES: usize = 1000;
+
+/// The maximum number of nodes created at once, even if the world has no maximum.
+pub const WORLD_MAX_NEW_NODES: usize = 1000;
+
+/// How long to wait before restarting a node after its first failure. The wait doubles after each
+/// further failure.
+pub const WORLD_RESTART_MIN_BACKOFF: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// The maximum wait before restarting a failed node. A node that runs for longer than this before
+/// failing again is restarted after the minimum wait.
+pub const WORLD_RESTART_MAX_BACKOFF: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// The default timeout of long-poll requests, in seconds.
+pub const SERVER_LONG_POLL_DEFAULT_TIMEOUT_SECS: u64 = 30;
+
+/// The maximum timeout of long-poll requests, in seconds.
+pub const SERVER_LONG_POLL_MAX_TIMEOUT_SECS: u64 = 3600;
+
+/// The default number of items in a page of a list endpoint.
+pub const SERVER_DEFAULT_PAGE_LIMIT: usize = 100;
+
+/// The maximum number of items in a page of a list endpoint.
+pub const SERVER_MAX_PAGE_LIMIT: usize = 1000;
+
+/// The default number of confirmations to wait for, when waiting for a transaction.
+pub const SERVER_DEFAULT_CONFIRMATIONS: usize = 1;
+
+/// How many events the event bus buffers for each subscriber that falls behind.
+pub const EVENTS_CHANNEL_CAPACITY: usize = 1024;
+
+/// How many of the most recent events the event bus keeps, to resend them to reconnecting clients.
+pub const EVENTS_HISTORY_CAPACITY: usize = 1024;
+
+/// How many watch-only wallets can be created at most.
+pub const WALLETS_MAX_COUNT: usize = 1000;
+
+/// How many addresses a watch-only wallet can watch at most.
+pub const WALLET_MAX_ADDRESSES: usize = 1000;
+
+/// The default maximum size of the body of a request, in bytes.
+pub const SERVER_DEFAULT_MAX_BODY_BYTES: u64 = 16 * 1024;
+
+/// The default width of the buckets of the histogram of block intervals, in milliseconds.
+pub const STATS_DEFAULT_BUCKET_MILLIS: u64 = 1000;
+
+/// The maximum number of buckets of a histogram. Requests whose bucket width would need more are
+/// rejected.
+pub const STATS_MAX_HISTOGRAM_BUCKETS: u64 = 10_000;
+
+/// The default width of the buckets of the histogram of balances, in coins.
+pub const STATS_DEFAULT_WEALTH_BUCKET_COINS: u64 = COINS_PER_MINED_BLOCK.whole_coins();
+
+/// By default, how many blocks apart the wealth distribution is sampled along the blockchain.
+pub const STATS_DEFAULT_WEALTH_SAMPLE_BLOCKS: usize = 10;
+
+/// How many captured network messages are written to the capture file at most at once.
+pub const CAPTURE_MAX_BATCH_RECORDS: usize = 1024;
@@ -0,0 +1,316 @@
+use crate::prelude::*;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+
+/// The invalid transactions that the demo sends on purpose, to show how the nodes reject them.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum InvalidKind {
+    /// The sender cannot afford the transaction.
+    InsufficientFunds,
+    /// The sender and the receiver are the same address.
+    SelfTransfer,
+    /// The amount is positive but smaller than the minimum amount.
+    Dust,
+}
+
+impl std::fmt::Display for InvalidKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            InvalidKind::InsufficientFunds => "insufficient funds",
+            InvalidKind::SelfTransfer => "self transfer",
+            InvalidKind::Dust => "dust",
+        })
+    }
+}
+
+/// Create some nodes and periodically send transactions to them. The demo observes the world to
+/// send only the transactions that can succeed, except for a few invalid ones sent on purpose and
+/// labeled as such in the logs.
+pub async fn run_demo(num_nodes: usize, seed: Option<u64>) {
+    let mut rng = match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+    let mut nodes = vec![];
+    for _ in 0..num_nodes.max(1) {
+        match world().await.add_node().await {
+            Ok(address) => nodes.push(address),
+            Err(err) => {
+                error!("Cannot create more demo nodes: {err}");
+                break;
+            }
+        }
+    }
+    if nodes.is_empty() {
+        return;
+    }
+
+    loop {
+        let demo_config = config();
+        send_transaction(&nodes, &demo_config, &mut rng).await;
+        tokio::time::sleep(std::time::Duration::from_millis(
+            demo_config.demo_interval_millis,
+        ))
+        .await;
+    }
+}
+
+/// Send a transaction to one of the demo nodes that have the most recent blockchain, unless its
+/// mempool is full or already holds the transactions of `DEMO_MAX_PENDING_BLOCKS` blocks.
+async fn send_transaction(nodes: &[Address], demo_config: &Config, rng: &mut StdRng) {
+    // While the nodes have not converged, the others would hold the transaction or mine it on a
+    // blockchain that is about to be dropped
+    let convergence = world().await.convergence().await;
+    let mut targets: Vec<Address> = convergence
+        .tips
+        .iter()
+        .flat_map(|tip| &tip.supporters)
+        .copied()
+        .filter(|address| nodes.contains(address))
+        .collect();
+    if let Some(highest) = convergence.tips.first() {
+        let synced: Vec<Address> = targets
+            .iter()
+            .copied()
+            .filter(|address| highest.supporters.contains(address))
+            .collect();
+        if !synced.is_empty() {
+            targets = synced;
+        }
+    }
+    let Some(&node_address) = targets.choose(rng) else {
+        warn!("Demo: None of the demo nodes is running");
+        return;
+    };
+    let Some(node) = world().await.get_node(node_address).await else {
+        return;
+    };
+
+    let lane = if rng.gen_bool(demo_config.demo_priority_fraction) {
+        DemoLane::Priority
+    } else {
+        DemoLane::Normal
+    };
+    let invalid = rng.gen_bool(DEMO_INVALID_FRACTION).then(|| {
+        let mut kinds = vec![InvalidKind::InsufficientFunds];
+        if !demo_config.transaction_rules.allow_self_transfer {
+            kinds.push(InvalidKind::SelfTransfer);
+        }
+        if demo_config.transaction_policy.min_amount > Amount::from_units(1) {
+            kinds.push(InvalidKind::Dust);
+        }
+        *kinds.choose(rng).unwrap()
+    });
+    let transaction = {
+        let readable_node = node.read().await;
+        let mempool = readable_node.mempool();
+        let max_pending =
+            mempool.rules().max_block_weight / TRANSACTION_BASE_WEIGHT * DEMO_MAX_PENDING_BLOCKS;
+        let max_pending = usize::try_from(max_pending).unwrap_or(usize::MAX);
+        let capacity = mempool.capacity().unwrap_or(usize::MAX).min(max_pending);
+        if mempool.len() >= capacity {
+            info!(
+                "Demo: The mempool of node {node_address} has {} pending transactions, waiting \
+                for the miners",
+                mempool.len()
+            );
+            return;
+        }
+        plan_transaction(
+            mempool,
+            &demo_config.transaction_policy,
+            nodes,
+            lane,
+            invalid,
+            rng,
+        )
+    };
+    let Some(transaction) = transaction else {
+        info!("Demo: No demo address can afford a transaction on node {node_address} yet");
+        return;
+    };
+
+    if let Some(kind) = invalid {
+        info!(
+            "Demo: Sending an intentionally invalid transaction ({kind}) of {} from {} to node \
+            {node_address}",
+            transaction.amount, transaction.sender
+        );
+    }
+    let result = node.write().await.add_client_transaction(transaction).await;
+    match (result, invalid) {
+        (Ok(transaction_id), None) => {
+            stats()
+                .await
+                .record_demo_transaction(transaction_id, lane)
+                .await;
+        }
+        (Err(err), None) => {
+            warn!("Demo: Node {node_address} rejected a transaction that looked valid: {err}");
+        }
+        (Ok(transaction_id), Some(kind)) => {
+            warn!(
+                "Demo: Node {node_address} accepted transaction {transaction_id}, which was \
+                intentionally invalid ({kind})"
+            );
+        }
+        (Err(err), Some(kind)) => {
+            info!(
+                "Demo: Node {node_address} rejected the intentionally invalid transaction \
+                ({kind}), as expected: {err}"
+            );
+        }
+    }
+}
+
+/// Build a transaction between the `addresses` that the mempool would admit, or one that it
+/// would reject for the given reason. The fee depends on the lane, and the amount is at most half
+/// of the balance of the sender, so that it can send other transactions before the next block.
+/// Returns `None` if no address can afford a transaction.
+fn plan_transaction(
+    mempool: &MemPool,
+    policy: &TransactionPolicy,
+    addresses: &[Address],
+    lane: DemoLane,
+    invalid: Option<InvalidKind>,
+    rng: &mut impl Rng,
+) -> Option<Transaction> {
+    // Priority transactions pay more than any normal one
+    let fee = Amount::from_coins(match lane {
+        DemoLane::Normal => rng.gen_range(0..=10),
+        DemoLane::Priority => rng.gen_range(20..=30),
+    })
+    .max(mempool.base_fee().unwrap_or(Amount::ZERO));
+    let balance_of = |address| {
+        mempool
+            .balance()
+            .get(address)
+            .map_or(Amount::ZERO, Balance::amount)
+    };
+    let receiver_for = |sender: Address, rng: &mut dyn rand::RngCore| {
+        let others: Vec<Address> = addresses
+            .iter()
+            .copied()
+            .filter(|&address| address != sender)
+            .collect();
+        others
+            .choose(rng)
+            .copied()
+            .unwrap_or_else(Address::new_random)
+    };
+
+    if let Some(kind) = invalid {
+        let sender = *addresses.choose(rng)?;
+        let receiver = receiver_for(sender, rng);
+        let transaction = match kind {
+            InvalidKind::InsufficientFunds => {
+                let amount = balance_of(&sender).saturating_add(Amount::from_coins(1));
+                Transaction::new(sender, receiver, amount)
+            }
+            InvalidKind::SelfTransfer => Transaction::new(sender, sender, Amount::from_coins(1)),
+            InvalidKind::Dust => Transaction::new(
+                sender,
+                receiver,
+                policy.min_amount.saturating_sub(Amount::from_units(1)),
+            ),
+        };
+        return Some(transaction.with_fee(fee));
+    }
+
+    // The smallest amount that is not dust, in whole coins
+    let mut min_amount = policy.min_amount;
+    if let Some(max_fee_multiple) = policy.max_fee_multiple.filter(|&multiple| multiple > 0) {
+        min_amount = min_amount.max(Amount::from_units(fee.units().div_ceil(max_fee_multiple)));
+    }
+    let min_coins = min_amount.units().div_ceil(UNITS_PER_COIN).max(1);
+    let senders: Vec<(Address, u64)> = addresses
+        .iter()
+        .map(|address| {
+            let spendable = balance_of(address).saturating_sub(fee);
+            (*address, spendable.whole_coins() / 2)
+        })
+        .filter(|&(_, max_coins)| max_coins >= min_coins)
+        .collect();
+    let &(sender, max_coins) = senders.choose(rng)?;
+    let amount = Amount::from_coins(rng.gen_range(min_coins..=max_coins));
+    let receiver = receiver_for(sender, rng);
+    Some(Transaction::new(sender, receiver, amount).with_fee(fee))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plan_valid_and_invalid_transactions() {
+        let (alice, bob, carol) = (Address::new(1), Address::new(2), Address::new(3));
+        let mut blockchain = BlockChain::with_difficulty(0);
+        let block = attempt_mining_block_with_difficulty(
+            blockchain.last_hash().clone(),
+            alice,
+            vec![],
+            0..1,
+            0,
+        )
+        .unwrap();
+        blockchain.append_block(block).unwrap();
+        let policy = TransactionPolicy {
+            min_amount: Amount::from_coins(1),
+            max_fee_multiple: None,
+        };
+        let mut mempool = MemPool::new(&blockchain);
+        mempool.set_policy(policy.clone());
+        let mut rng = StdRng::seed_from_u64(0);
+        let add = |mempool: &mut MemPool, transaction: Transaction| {
+            let transaction = BlockTransaction::new(blockchain.last_hash().clone(), transaction);
+            mempool.add_transaction(transaction)
+        };
+
+        // Only alice can afford a transaction, which carol cannot afford before receiving some coins
+        for lane in [DemoLane::Normal, DemoLane::Priority] {
+            let transaction =
+                plan_transaction(&mempool, &policy, &[alice, bob], lane, None, &mut rng).unwrap();
+            assert_eq!(transaction.sender, alice);
+            assert_eq!(add(&mut mempool, transaction), Ok(()));
+        }
+        assert!(plan_transaction(
+            &mempool,
+            &policy,
+            &[carol],
+            DemoLane::Normal,
+            None,
+            &mut rng
+        )
+        .is_none());
+
+        for (kind, expected) in [
+            (InvalidKind::InsufficientFunds, TxError::InsufficientFunds),
+            (InvalidKind::SelfTransfer, TxError::SelfTransfer),
+        ] {
+            let transaction = plan_transaction(
+                &mempool,
+                &policy,
+                &[alice],
+                DemoLane::Normal,
+                Some(kind),
+                &mut rng,
+            )
+            .unwrap();
+            assert_eq!(add(&mut mempool, transaction), Err(expected));
+        }
+        let transaction = plan_transaction(
+            &mempool,
+            &policy,
+            &[alice],
+            DemoLane::Normal,
+            Some(InvalidKind::Dust),
+            &mut rng,
+        )
+        .unwrap();
+        assert!(matches!(
+            add(&mut mempool, transaction),
+            Err(TxError::BelowMinimumAmount { .. })
+        ));
+    }
+}
@@ -0,0 +1,198 @@
+//! The binary format used to hash, store and transmit blocks and transactions.
+//!
+//! Unlike a serde-based format, the layout is explicit: changing the fields of a struct does not
+//! silently change the encoding (and thus the hashes) of existing blocks. Any change to the
+//! layout must bump `ENCODING_VERSION`.
+//!
+//! All integers are little-endian. The layout of version 4 is:
+//!
+//! ```text
+//! Block:
+//!     version:      u8 (= 4)
+//!     prefix_hash:  BlockHash
+//!     miner:        u64
+//!     transactions: u32 (count), followed by that many BlockTransaction
+//!     nonce:        u64
+//!
+//! BlockTransaction:
+//!     id:           u64
+//!     prefix_hash:  BlockHash
+//!     info:         Transaction
+//!
+//! Transaction:
+//!     sender:       u64
+//!     has_receiver: u8 (0 for a burn, which has no receiver, 1 otherwise)
+//!     receiver:     u64 (only if `has_receiver` is 1)
+//!     amount:       u64
+//!     fee:          u64
+//!     memo:         u8 (length, at most 255), followed by that many bytes of UTF-8
+//!
+//! BlockHash:
+//!     length:       u8 (0 for the prefix of the genesis block, 32 otherwise)
+//!     bytes:        `length` bytes
+//! ```
+//!
+//! Version 1 did not have the `fee` field, version 2 did not have the `has_receiver` field, and
+//! version 3 did not have the `memo` field. The blocks encoded with an older version are still
+//! decoded, with the default value of the fields that they lack: no fee, a receiver and no memo.
+//!
+//! The hash of a block is the SHA-256 of its encoding without the version byte, so that bumping
+//! the version does not change the hash of the genesis block, nor of any block whose layout is
+//! the same in both versions.
+//!
+//! The encoding of a `Transaction` is also the raw format in which clients build and submit
+//! transactions.
+//!
+//! The `id` of a `BlockTransaction` is derived from the rest of it: it is the first 8 bytes, as a
+//! little-endian `u64`, of the SHA-256 hash of the encoding of its `prefix_hash` and `info`.
+//!
+//! The nonce is the last field of a block, so that miners can hash the rest of the block once
+//! and only update the hash state with each candidate nonce.
+
+/// The version of the encoding, which is the first byte of every encoded block. Blocks are
+/// encoded with this version, and decoded from any version since 1.
+pub const ENCODING_VERSION: u8 = 4;
+
+/// A value that can be written in the binary format.
+pub trait Encode {
+    fn encode_into(&self, encoder: &mut Encoder);
+
+    /// Encode the value in a new buffer.
+    fn encode(&self) -> Vec<u8> {
+        let mut encoder = Encoder::default();
+        self.encode_into(&mut encoder);
+        encoder.finish()
+    }
+}
+
+/// A value that can be read from the binary format.
+pub trait Decode: Sized {
+    fn decode_from(decoder: &mut Decoder) -> Result<Self, DecodeError>;
+
+    /// Decode a value that spans all the given bytes.
+    fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let mut decoder = Decoder::new(bytes);
+        let value = Self::decode_from(&mut decoder)?;
+        decoder.finish()?;
+        Ok(value)
+    }
+}
+
+/// The reason why some bytes could not be decoded.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum DecodeError {
+    /// The encoding has a version that is not supported.
+    UnsupportedVersion(u8),
+    /// The bytes ended before the end of the value.
+    UnexpectedEnd,
+    /// There are some bytes after the end of the value.
+    TrailingBytes(usize),
+    /// A field has an invalid value.
+    InvalidField(&'static str),
+}
+
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DecodeError::UnsupportedVersion(version) => {
+                write!(f, "unsupported encoding version {version}")
+            }
+            DecodeError::UnexpectedEnd => write!(f, "unexpected end of the input"),
+            DecodeError::TrailingBytes(count) => write!(f, "{count} trailing bytes"),
+            DecodeError::InvalidField(field) => write!(f, "invalid {field}"),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// A buffer in which values are encoded.
+#[derive(Default)]
+pub struct Encoder {
+    bytes: Vec<u8>,
+}
+
+impl Encoder {
+    pub fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u128(&mut self, value: u128) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// Write a sequence of at most 255 bytes, prefixed by its length.
+    pub fn short_bytes(&mut self, value: &[u8]) {
+        let length = u8::try_from(value.len()).expect("Sequence of bytes too long to be encoded");
+        self.u8(length);
+        self.bytes.extend_from_slice(value);
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+/// A cursor over the bytes from which values are decoded.
+pub struct Decoder<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Decoder { bytes }
+    }
+
+    fn take(&mut self, count: usize) -> Result<&'a [u8], DecodeError> {
+        if self.bytes.len() < count {
+            return Err(DecodeError::UnexpectedEnd);
+        }
+        let (taken, rest) = self.bytes.split_at(count);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn u32(&mut self) -> Result<u32, DecodeError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, DecodeError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    pub fn u128(&mut self) -> Result<u128, DecodeError> {
+        Ok(u128::from_le_bytes(self.take(16)?.try_into().unwrap()))
+    }
+
+    /// Read a sequence of bytes prefixed by its length.
+    pub fn short_bytes(&mut self) -> Result<&'a [u8], DecodeError> {
+        let length = self.u8()?;
+        self.take(length as usize)
+    }
+
+    /// How many bytes have not been decoded yet.
+    pub fn remaining(&self) -> usize {
+        self.bytes.len()
+    }
+
+    /// Check that all the bytes have been decoded.
+    pub fn finish(self) -> Result<(), DecodeError> {
+        if self.bytes.is_empty() {
+            Ok(())
+        } else {
+            Err(DecodeError::TrailingBytes(self.bytes.len()))
+        }
+    }
+}
@@ -0,0 +1,289 @@
+use crate::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use tokio::sync::broadcast;
+use tokio::sync::OnceCell;
+
+/// Something that happened in the simulation, published to the subscribers of the event bus.
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    /// A node joined the network.
+    NodeAdded { node: Address },
+    /// A node left the network.
+    NodeRemoved { node: Address },
+    /// A node mined a new block on top of its local blockchain.
+    BlockMined {
+        node: Address,
+        height: usize,
+        block: Block,
+    },
+    /// A node switched to a longer blockchain received from the network.
+    BlockchainAdopted {
+        node: Address,
+        height: usize,
+        last_block_hash: BlockHash,
+        reorg_depth: usize,
+    },
+    /// A node accepted a transaction from a client.
+    TransactionAccepted {
+        node: Address,
+        transaction: BlockTransaction,
+    },
+    /// The balances of the blockchain of a node do not add up to the issued coins.
+    SupplyAlarm { node: Address, audit: SupplyAudit },
+    /// The task of a node failed, stopping the node.
+    NodeFailed { node: Address, reason: String },
+    /// A failed node started running again.
+    NodeRestarted { node: Address },
+    /// A node failed to store its blocks, which it retries.
+    StoreFailed { node: Address, reason: String },
+    /// A node accepted in its mempool, or mined, a transaction that involves an address watched by
+    /// a wallet.
+    WalletTransaction {
+        wallet: String,
+        node: Address,
+        #[serde(flatten)]
+        transaction: WalletTransaction,
+    },
+}
+
+impl Event {
+    /// The name of the type of the event, as serialized in the `type` field.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Event::NodeAdded { .. } => "node_added",
+            Event::NodeRemoved { .. } => "node_removed",
+            Event::BlockMined { .. } => "block_mined",
+            Event::BlockchainAdopted { .. } => "blockchain_adopted",
+            Event::TransactionAccepted { .. } => "transaction_accepted",
+            Event::SupplyAlarm { .. } => "supply_alarm",
+            Event::NodeFailed { .. } => "node_failed",
+            Event::NodeRestarted { .. } => "node_restarted",
+            Event::StoreFailed { .. } => "store_failed",
+            Event::WalletTransaction { .. } => "wallet_transaction",
+        }
+    }
+
+    /// The node at which the event happened.
+    pub fn node(&self) -> Address {
+        match self {
+            Event::NodeAdded { node }
+            | Event::NodeRemoved { node }
+            | Event::BlockMined { node, .. }
+            | Event::BlockchainAdopted { node, .. }
+            | Event::TransactionAccepted { node, .. }
+            | Event::SupplyAlarm { node, .. }
+            | Event::NodeFailed { node, .. }
+            | Event::NodeRestarted { node }
+            | Event::StoreFailed { node, .. }
+            | Event::WalletTransaction { node, .. } => *node,
+        }
+    }
+
+    /// The wallet that the event is about, if any.
+    pub fn wallet(&self) -> Option<&str> {
+        match self {
+            Event::WalletTransaction { wallet, .. } => Some(wallet),
+            Event::NodeAdded { .. }
+            | Event::NodeRemoved { .. }
+            | Event::BlockMined { .. }
+            | Event::BlockchainAdopted { .. }
+            | Event::TransactionAccepted { .. }
+            | Event::SupplyAlarm { .. }
+            | Event::NodeFailed { .. }
+            | Event::NodeRestarted { .. }
+            | Event::StoreFailed { .. } => None,
+        }
+    }
+
+    /// Whether the event moves coins from or to the address.
+    pub fn touches(&self, address: Address) -> bool {
+        let touched_by = |transaction: &BlockTransaction| {
+            transaction.info.sender == address || transaction.info.receiver == Some(address)
+        };
+        match self {
+            Event::BlockMined { block, .. } => {
+                block.miner == address || block.transactions.iter().any(touched_by)
+            }
+            Event::TransactionAccepted { transaction, .. } => touched_by(transaction),
+            Event::WalletTransaction { transaction, .. } => touched_by(&transaction.transaction),
+            Event::NodeAdded { .. }
+            | Event::NodeRemoved { .. }
+            | Event::BlockchainAdopted { .. }
+            | Event::SupplyAlarm { .. }
+            | Event::NodeFailed { .. }
+            | Event::NodeRestarted { .. }
+            | Event::StoreFailed { .. } => false,
+        }
+    }
+}
+
+/// An event published on the bus, with its sequence number.
+#[derive(Clone, Debug, Serialize)]
+pub struct EventRecord {
+    /// The identifier of the event. Identifiers are assigned in increasing order, starting from 1.
+    pub id: u64,
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+/// A subscription to a subset of the events. Each non-empty list restricts the matching events.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EventFilter {
+    /// The accepted types of events (e.g., `block_mined`).
+    pub types: Vec<String>,
+    /// The nodes whose events are accepted.
+    pub nodes: Vec<Address>,
+    /// The addresses of interest: only events that move coins from or to them are accepted.
+    pub addresses: Vec<Address>,
+    /// The watch-only wallets whose `wallet_transaction` events are accepted.
+    pub wallets: Vec<String>,
+}
+
+impl EventFilter {
+    pub fn matches(&self, event: &Event) -> bool {
+        (self.types.is_empty() || self.types.iter().any(|kind| kind == event.kind()))
+            && (self.nodes.is_empty() || self.nodes.contains(&event.node()))
+            && (self.addresses.is_empty()
+                || self.addresses.iter().any(|&address| event.touches(address)))
+            && (self.wallets.is_empty()
+                || event
+                    .wallet()
+                    .is_some_and(|wallet| self.wallets.iter().any(|name| name == wallet)))
+    }
+}
+
+/// The bus on which the events of the simulation are published.
+pub struct EventBus {
+    sender: broadcast::Sender<EventRecord>,
+    /// The most recent events, so that reconnecting subscribers can resume the stream.
+    /// The lock is also held while publishing, so that events are sent in the order of their ids.
+    history: Mutex<History>,
+}
+
+struct History {
+    next_id: u64,
+    records: VecDeque<EventRecord>,
+}
+
+static EVENTS: OnceCell<EventBus> = OnceCell::const_new();
+
+/// Get the singleton of the event bus.
+pub async fn events() -> &'static EventBus {
+    EVENTS.get_or_init(|| async { EventBus::new() }).await
+}
+
+impl EventBus {
+    fn new() -> Self {
+        let (sender, _) = broadcast::channel(EVENTS_CHANNEL_CAPACITY);
+        EventBus {
+            sender,
+            history: Mutex::new(History {
+                next_id: 1,
+                records: VecDeque::new(),
+            }),
+        }
+    }
+
+    /// Publish an event to the current subscribers, if any.
+    pub fn publish(&self, event: Event) {
+        let mut history = self.history.lock().unwrap();
+        let record = EventRecord {
+            id: history.next_id,
+            event,
+        };
+        history.next_id += 1;
+        if history.records.len() >= EVENTS_HISTORY_CAPACITY {
+            history.records.pop_front();
+        }
+        history.records.push_back(record.clone());
+        // Sending only fails when nobody is subscribed, in which case the event is not needed.
+        let _ = self.sender.send(record);
+    }
+
+    /// Receive the events published from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<EventRecord> {
+        self.sender.subscribe()
+    }
+
+    /// Receive the events published after the one with identifier `last_id`. The events that have
+    /// already been published are returned, as far as they are still in the history, and the
+    /// following ones are sent to the receiver.
+    pub fn subscribe_after(
+        &self,
+        last_id: u64,
+    ) -> (Vec<EventRecord>, broadcast::Receiver<EventRecord>) {
+        let history = self.history.lock().unwrap();
+        if history
+            .records
+            .front()
+            .is_some_and(|oldest| oldest.id > last_id.saturating_add(1))
+        {
+            warn!("Some events after {last_id} are no longer in the history and cannot be resent");
+        }
+        let missed = history
+            .records
+            .iter()
+            .filter(|record| record.id > last_id)
+            .cloned()
+            .collect();
+        (missed, self.sender.subscribe())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filter_events() {
+        let node = Address::new(1);
+        let wallet = Address::new(2);
+        let transaction = BlockTransaction::new(
+            Block::genesis().hash(),
+            Transaction::new(wallet, Address::new(3), Amount::from_coins(10)),
+        );
+        let accepted = Event::TransactionAccepted { node, transaction };
+        let added = Event::NodeAdded { node };
+
+        let everything = EventFilter::default();
+        assert!(everything.matches(&accepted) && everything.matches(&added));
+
+        let wallet_filter: EventFilter =
+            serde_json::from_value(serde_json::json!({ "addresses": [wallet] })).unwrap();
+        assert!(wallet_filter.matches(&accepted));
+        assert!(!wallet_filter.matches(&added));
+
+        let type_filter: EventFilter = serde_json::from_value(serde_json::json!({
+            "types": ["node_added"],
+            "nodes": [Address::new(4)],
+        }))
+        .unwrap();
+        assert!(!type_filter.matches(&accepted));
+        assert!(!type_filter.matches(&added));
+    }
+
+    #[test]
+    fn resume_after_last_event() {
+        let bus = EventBus::new();
+        let node = Address::new(1);
+        for _ in 0..EVENTS_HISTORY_CAPACITY + 2 {
+            bus.publish(Event::NodeAdded { node });
+        }
+        let last_id = EVENTS_HISTORY_CAPACITY as u64;
+        let (missed, mut receiver) = bus.subscribe_after(last_id);
+        let missed_ids: Vec<u64> = missed.iter().map(|record| record.id).collect();
+        assert_eq!(missed_ids, vec![last_id + 1, last_id + 2]);
+
+        // Only the most recent events are kept.
+        let (missed, _) = bus.subscribe_after(0);
+        assert_eq!(missed.len(), EVENTS_HISTORY_CAPACITY);
+        assert_eq!(missed[0].id, 3);
+
+        bus.publish(Event::NodeRemoved { node });
+        assert_eq!(receiver.try_recv().unwrap().id, last_id + 3);
+    }
+}
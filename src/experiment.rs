@@ -0,0 +1,258 @@
+use crate::prelude::*;
+use crate::report::Report;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+
+/// The scenario of an experiment, read from a TOML file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scenario {
+    /// How long each run lasts, in seconds.
+    pub duration_secs: u64,
+    /// Whether to run the demo workload, which sends random transactions.
+    #[serde(default)]
+    pub demo: bool,
+    /// How many nodes the demo creates.
+    #[serde(default = "default_demo_nodes")]
+    pub demo_nodes: usize,
+    /// The seed of the first run. Run `i` uses the seed `seed + i`.
+    #[serde(default)]
+    pub seed: u64,
+}
+
+fn default_demo_nodes() -> usize {
+    3
+}
+
+/// The metrics of an experiment, aggregated across its runs.
+#[derive(Debug, Serialize)]
+pub struct ExperimentReport {
+    /// How many runs completed successfully.
+    pub runs: usize,
+    /// The fraction of mined blocks that did not end up in the longest blockchain.
+    pub fork_rate: Aggregate,
+    /// The average, over the mined blocks, of the time it took for 95% of the nodes that adopted
+    /// a block to do so, in milliseconds.
+    pub propagation_p95_millis: Aggregate,
+    pub reorgs: Aggregate,
+    pub blocks_per_minute: Aggregate,
+    pub transactions_per_second: Aggregate,
+}
+
+/// The mean and the (population) standard deviation of a metric.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Aggregate {
+    pub mean: f64,
+    pub stddev: f64,
+}
+
+impl Aggregate {
+    pub fn new(values: &[f64]) -> Self {
+        if values.is_empty() {
+            return Aggregate {
+                mean: 0.0,
+                stddev: 0.0,
+            };
+        }
+        let mean = values.iter().sum::<f64>() / values.len() as f64;
+        let variance = values
+            .iter()
+            .map(|value| (value - mean).powi(2))
+            .sum::<f64>()
+            / values.len() as f64;
+        Aggregate {
+            mean,
+            stddev: variance.sqrt(),
+        }
+    }
+}
+
+impl std::fmt::Display for Aggregate {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:.3} ± {:.3}", self.mean, self.stddev)
+    }
+}
+
+impl ExperimentReport {
+    fn new(reports: &[Report]) -> Self {
+        let metric =
+            |f: fn(&Report) -> f64| Aggregate::new(&reports.iter().map(f).collect::<Vec<_>>());
+        ExperimentReport {
+            runs: reports.len(),
+            fork_rate: metric(|report| {
+                if report.forks.mined_blocks == 0 {
+                    0.0
+                } else {
+                    report.forks.orphaned_blocks as f64 / report.forks.mined_blocks as f64
+                }
+            }),
+            propagation_p95_millis: metric(|report| report.propagation.mean_p95_millis),
+            reorgs: metric(|report| report.forks.reorgs as f64),
+            blocks_per_minute: metric(|report| report.throughput.blocks_per_minute),
+            transactions_per_second: metric(|report| report.throughput.transactions_per_second),
+        }
+    }
+}
+
+impl std::fmt::Display for ExperimentReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "SmallChain experiment report ({} runs)", self.runs)?;
+        writeln!(f)?;
+        writeln!(f, "  {:<26}{}", "fork rate:", self.fork_rate)?;
+        writeln!(
+            f,
+            "  {:<26}{}",
+            "mean p95 delay (ms):", self.propagation_p95_millis
+        )?;
+        writeln!(f, "  {:<26}{}", "reorganizations:", self.reorgs)?;
+        writeln!(
+            f,
+            "  {:<26}{}",
+            "blocks per minute:", self.blocks_per_minute
+        )?;
+        writeln!(
+            f,
+            "  {:<26}{}",
+            "transactions per second:", self.transactions_per_second
+        )?;
+        Ok(())
+    }
+}
+
+/// Run the scenario described in `config` several times, each in a separate process with its
+/// own world, and write the reports of the runs and the combined report in `output_dir`.
+pub async fn run(
+    config: &Path,
+    runs: usize,
+    jobs: Option<usize>,
+    output_dir: &Path,
+) -> Result<(), ()> {
+    let raw_config = std::fs::read_to_string(config)
+        .map_err(|err| error!("Failed to read {}: {err}", config.display()))?;
+    let scenario: Scenario = toml::from_str(&raw_config)
+        .map_err(|err| error!("Invalid scenario in {}: {err}", config.display()))?;
+    let executable = std::env::current_exe()
+        .map_err(|err| error!("Cannot find the path of the executable: {err}"))?;
+    let jobs = jobs
+        .or_else(|| std::thread::available_parallelism().ok().map(usize::from))
+        .unwrap_or(1);
+    info!(
+        "Running {runs} runs of {}, {jobs} at a time",
+        config.display()
+    );
+
+    let semaphore = Arc::new(Semaphore::new(jobs.max(1)));
+    let mut handles = vec![];
+    for run_index in 0..runs {
+        let run_dir = output_dir.join(format!("run-{run_index}"));
+        let mut command = tokio::process::Command::new(&executable);
+        command
+            .arg("--port=0")
+            .arg(format!("--duration={}", scenario.duration_secs))
+            .arg(format!("--seed={}", scenario.seed + run_index as u64))
+            .arg(format!("--demo-nodes={}", scenario.demo_nodes))
+            .arg(format!("--results-dir={}", run_dir.display()));
+        if scenario.demo {
+            command.arg("--demo");
+        }
+        let semaphore = semaphore.clone();
+        handles.push(tokio::spawn(async move {
+            let _permit = semaphore.acquire().await;
+            run_once(run_index, command, run_dir).await
+        }));
+    }
+
+    let mut reports = vec![];
+    for handle in handles {
+        if let Ok(Some(report)) = handle.await {
+            reports.push(report);
+        }
+    }
+    if reports.is_empty() {
+        error!("No run completed successfully");
+        return Err(());
+    }
+
+    let experiment_report = ExperimentReport::new(&reports);
+    let json = serde_json::to_string_pretty(&experiment_report)
+        .map_err(|err| error!("Failed to serialize the experiment report: {err}"))?;
+    std::fs::write(output_dir.join("report.json"), json)
+        .and_then(|()| std::fs::write(output_dir.join("report.txt"), experiment_report.to_string()))
+        .map_err(|err| error!("Failed to write the experiment report: {err}"))?;
+    print!("{experiment_report}");
+    Ok(())
+}
+
+/// Execute a single run, returning its report.
+async fn run_once(
+    run_index: usize,
+    mut command: tokio::process::Command,
+    run_dir: PathBuf,
+) -> Option<Report> {
+    if let Err(err) = std::fs::create_dir_all(&run_dir) {
+        error!("Failed to create {}: {err}", run_dir.display());
+        return None;
+    }
+    let log_path = run_dir.join("log.txt");
+    let log_file = match std::fs::File::create(&log_path) {
+        Ok(log_file) => log_file,
+        Err(err) => {
+            error!("Failed to create {}: {err}", log_path.display());
+            return None;
+        }
+    };
+    command.stdout(log_file).stderr(std::process::Stdio::null());
+
+    info!("Starting run {run_index}");
+    match command.status().await {
+        Ok(status) if status.success() => {}
+        Ok(status) => {
+            error!("Run {run_index} failed with {status}");
+            return None;
+        }
+        Err(err) => {
+            error!("Failed to start run {run_index}: {err}");
+            return None;
+        }
+    }
+
+    let report_path = run_dir.join("report.json");
+    let report = std::fs::read_to_string(&report_path)
+        .map_err(|err| err.to_string())
+        .and_then(|raw_report| serde_json::from_str(&raw_report).map_err(|err| err.to_string()));
+    match report {
+        Ok(report) => {
+            info!("Run {run_index} completed");
+            Some(report)
+        }
+        Err(err) => {
+            error!("Failed to read {}: {err}", report_path.display());
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aggregate_mean_and_stddev() {
+        assert_eq!(
+            Aggregate::new(&[2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0]),
+            Aggregate {
+                mean: 5.0,
+                stddev: 2.0
+            }
+        );
+        assert_eq!(
+            Aggregate::new(&[]),
+            Aggregate {
+                mean: 0.0,
+                stddev: 0.0
+            }
+        );
+    }
+}
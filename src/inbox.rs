@@ -0,0 +1,525 @@
+use crate::encoding::{Decode, DecodeError, Decoder, Encode, Encoder};
+use crate::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashSet, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// A message delivered by the network to a node.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Message {
+    /// A new block, advertised by a node whose local blockchain now has the given length.
+    NewBlock {
+        block: Block,
+        blockchain_length: usize,
+        source: Address,
+        /// The hash of the protocol parameters of the source, missing in the captures recorded
+        /// before it was sent.
+        #[serde(default)]
+        params_hash: Option<BlockHash>,
+    },
+    /// A new pending transaction, broadcast by a node.
+    Transaction {
+        transaction: BlockTransaction,
+        source: Address,
+    },
+    /// Whether a peer accepted a transaction broadcast by the node.
+    TransactionReceipt {
+        transaction_id: TransactionId,
+        peer: Address,
+        accepted: bool,
+    },
+    /// The announcement of new blocks and transactions, which the node can request if it does not
+    /// know them.
+    Inventory { items: Vec<Item>, source: Address },
+    /// A request for blocks and transactions announced by the node.
+    GetData { items: Vec<Item>, source: Address },
+    /// The announcement that a node leaves the network, so that its peers stop waiting for it.
+    Departure { source: Address },
+    /// The periodic announcement of the last block of a node, which the peers with less work
+    /// request to catch up.
+    Heartbeat {
+        blockchain_length: usize,
+        last_block_hash: BlockHash,
+        cumulative_work: u128,
+        source: Address,
+    },
+}
+
+impl Message {
+    /// The lane of the inbox in which the message waits.
+    pub fn lane(&self) -> Lane {
+        match self {
+            Message::NewBlock { .. } | Message::Departure { .. } | Message::Heartbeat { .. } => {
+                Lane::Consensus
+            }
+            Message::Transaction { .. } | Message::TransactionReceipt { .. } => Lane::Transactions,
+            Message::Inventory { items, .. } | Message::GetData { items, .. } => {
+                if items.iter().any(|item| matches!(item, Item::Block(_))) {
+                    Lane::Consensus
+                } else {
+                    Lane::Transactions
+                }
+            }
+        }
+    }
+
+    /// The node that sent the message.
+    pub fn source(&self) -> Address {
+        match self {
+            Message::NewBlock { source, .. }
+            | Message::Transaction { source, .. }
+            | Message::Inventory { source, .. }
+            | Message::GetData { source, .. }
+            | Message::Departure { source }
+            | Message::Heartbeat { source, .. } => *source,
+            Message::TransactionReceipt { peer, .. } => *peer,
+        }
+    }
+
+    /// The type of the message, as named in captures and in the network statistics.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Message::NewBlock { .. } => "new_block",
+            Message::Transaction { .. } => "transaction",
+            Message::TransactionReceipt { .. } => "transaction_receipt",
+            Message::Inventory { .. } => "inventory",
+            Message::GetData { .. } => "get_data",
+            Message::Departure { .. } => "departure",
+            Message::Heartbeat { .. } => "heartbeat",
+        }
+    }
+
+    /// The block or the transaction that the message carries, if any.
+    pub fn item(&self) -> Option<Item> {
+        match self {
+            Message::NewBlock { block, .. } => Some(Item::Block(block.hash())),
+            Message::Transaction { transaction, .. } => Some(Item::Transaction(transaction.id)),
+            Message::TransactionReceipt { .. }
+            | Message::Inventory { .. }
+            | Message::GetData { .. }
+            | Message::Departure { .. }
+            | Message::Heartbeat { .. } => None,
+        }
+    }
+
+    /// The number of bytes that the message takes on the wire: the binary encoding of its block or
+    /// transaction, followed by its other fields.
+    pub fn size(&self) -> usize {
+        match self {
+            Message::NewBlock {
+                block, params_hash, ..
+            } => {
+                block.encode().len()
+                    + 8
+                    + 8
+                    + params_hash.as_ref().map_or(0, |hash| hash.inner().len())
+            }
+            Message::Transaction { transaction, .. } => transaction.encode().len() + 8,
+            Message::TransactionReceipt { .. } => 8 + 8 + 1,
+            Message::Departure { .. } => 8,
+            Message::Heartbeat {
+                last_block_hash, ..
+            } => 8 + last_block_hash.encode().len() + 16 + 8,
+            Message::Inventory { items, .. } | Message::GetData { items, .. } => {
+                items.iter().map(Item::size).sum::<usize>() + 8
+            }
+        }
+    }
+}
+
+/// A block or a transaction, identified by its hash or its identifier.
+#[derive(Clone, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Item {
+    Block(BlockHash),
+    Transaction(TransactionId),
+}
+
+impl Item {
+    /// The number of bytes that the item takes on the wire: a tag, followed by the encoding of the
+    /// hash or of the identifier.
+    pub fn size(&self) -> usize {
+        match self {
+            Item::Block(block_hash) => 1 + block_hash.encode().len(),
+            Item::Transaction(_) => 1 + 8,
+        }
+    }
+}
+
+/// The binary encoding of a message, sent by the TCP network: a tag identifying the type of the
+/// message, followed by its fields in order. Lengths are `u64`, and optional hashes are preceded
+/// by a `u8` that is 1 if they are present.
+impl Encode for Message {
+    fn encode_into(&self, encoder: &mut Encoder) {
+        match self {
+            Message::NewBlock {
+                block,
+                blockchain_length,
+                source,
+                params_hash,
+            } => {
+                encoder.u8(0);
+                block.encode_into(encoder);
+                encoder.u64(*blockchain_length as u64);
+                source.encode_into(encoder);
+                match params_hash {
+                    Some(params_hash) => {
+                        encoder.u8(1);
+                        params_hash.encode_into(encoder);
+                    }
+                    None => encoder.u8(0),
+                }
+            }
+            Message::Transaction {
+                transaction,
+                source,
+            } => {
+                encoder.u8(1);
+                transaction.encode_into(encoder);
+                source.encode_into(encoder);
+            }
+            Message::TransactionReceipt {
+                transaction_id,
+                peer,
+                accepted,
+            } => {
+                encoder.u8(2);
+                transaction_id.encode_into(encoder);
+                peer.encode_into(encoder);
+                encoder.u8(u8::from(*accepted));
+            }
+            Message::Inventory { items, source } => {
+                encoder.u8(3);
+                encode_items(items, encoder);
+                source.encode_into(encoder);
+            }
+            Message::GetData { items, source } => {
+                encoder.u8(4);
+                encode_items(items, encoder);
+                source.encode_into(encoder);
+            }
+            Message::Departure { source } => {
+                encoder.u8(5);
+                source.encode_into(encoder);
+            }
+            Message::Heartbeat {
+                blockchain_length,
+                last_block_hash,
+                cumulative_work,
+                source,
+            } => {
+                encoder.u8(6);
+                encoder.u64(*blockchain_length as u64);
+                last_block_hash.encode_into(encoder);
+                encoder.u128(*cumulative_work);
+                source.encode_into(encoder);
+            }
+        }
+    }
+}
+
+impl Decode for Message {
+    fn decode_from(decoder: &mut Decoder) -> Result<Self, DecodeError> {
+        let length = |decoder: &mut Decoder| {
+            usize::try_from(decoder.u64()?).map_err(|_| DecodeError::InvalidField("length"))
+        };
+        let message = match decoder.u8()? {
+            0 => Message::NewBlock {
+                block: Block::decode_from(decoder)?,
+                blockchain_length: length(decoder)?,
+                source: Address::decode_from(decoder)?,
+                params_hash: match decoder.u8()? {
+                    0 => None,
+                    1 => Some(BlockHash::decode_from(decoder)?),
+                    _ => return Err(DecodeError::InvalidField("params_hash")),
+                },
+            },
+            1 => Message::Transaction {
+                transaction: BlockTransaction::decode_from(decoder)?,
+                source: Address::decode_from(decoder)?,
+            },
+            2 => Message::TransactionReceipt {
+                transaction_id: TransactionId::decode_from(decoder)?,
+                peer: Address::decode_from(decoder)?,
+                accepted: match decoder.u8()? {
+                    0 => false,
+                    1 => true,
+                    _ => return Err(DecodeError::InvalidField("accepted")),
+                },
+            },
+            3 => Message::Inventory {
+                items: decode_items(decoder)?,
+                source: Address::decode_from(decoder)?,
+            },
+            4 => Message::GetData {
+                items: decode_items(decoder)?,
+                source: Address::decode_from(decoder)?,
+            },
+            5 => Message::Departure {
+                source: Address::decode_from(decoder)?,
+            },
+            6 => Message::Heartbeat {
+                blockchain_length: length(decoder)?,
+                last_block_hash: BlockHash::decode_from(decoder)?,
+                cumulative_work: decoder.u128()?,
+                source: Address::decode_from(decoder)?,
+            },
+            _ => return Err(DecodeError::InvalidField("message type")),
+        };
+        Ok(message)
+    }
+}
+
+/// Write a list of items, prefixed by their number as a `u32`.
+fn encode_items(items: &[Item], encoder: &mut Encoder) {
+    let count = u32::try_from(items.len()).expect("Too many items");
+    encoder.u32(count);
+    for item in items {
+        item.encode_into(encoder);
+    }
+}
+
+/// Read a list of items written by `encode_items`.
+fn decode_items(decoder: &mut Decoder) -> Result<Vec<Item>, DecodeError> {
+    let count = decoder.u32()? as usize;
+    // Each item takes at least 2 bytes; don't trust `count` to preallocate memory.
+    let mut items = Vec::with_capacity(count.min(decoder.remaining() / 2));
+    for _ in 0..count {
+        items.push(Item::decode_from(decoder)?);
+    }
+    Ok(items)
+}
+
+impl Encode for Item {
+    fn encode_into(&self, encoder: &mut Encoder) {
+        match self {
+            Item::Block(block_hash) => {
+                encoder.u8(0);
+                block_hash.encode_into(encoder);
+            }
+            Item::Transaction(transaction_id) => {
+                encoder.u8(1);
+                transaction_id.encode_into(encoder);
+            }
+        }
+    }
+}
+
+impl Decode for Item {
+    fn decode_from(decoder: &mut Decoder) -> Result<Self, DecodeError> {
+        match decoder.u8()? {
+            0 => Ok(Item::Block(BlockHash::decode_from(decoder)?)),
+            1 => Ok(Item::Transaction(TransactionId::decode_from(decoder)?)),
+            _ => Err(DecodeError::InvalidField("item type")),
+        }
+    }
+}
+
+/// The priority lanes of an inbox, from the highest to the lowest priority.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Lane {
+    /// Messages about blocks, which can change the local blockchain.
+    Consensus,
+    /// Messages about pending transactions.
+    Transactions,
+}
+
+/// The queue of the messages that have been delivered to a node but not yet processed.
+/// The network pushes messages without locking the node, which processes them in its run loop.
+/// Messages about blocks are processed before messages about transactions, so that a node that is
+/// flooded with transactions still reacts promptly to new blocks.
+/// The blocks and the transactions that the node has recently received are dropped when they are
+/// received again, without waiting for the node to process them.
+#[derive(Debug, Default)]
+pub struct Inbox {
+    lanes: Mutex<Lanes>,
+    seen: Mutex<SeenCache>,
+    duplicates: AtomicU64,
+}
+
+#[derive(Debug, Default)]
+struct Lanes {
+    consensus: VecDeque<Message>,
+    transactions: VecDeque<Message>,
+}
+
+impl Inbox {
+    pub fn push(&self, message: Message) {
+        if let Some(item) = message.item() {
+            if !self.seen.lock().unwrap().insert(item) {
+                self.duplicates.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+        }
+        let mut lanes = self.lanes.lock().unwrap();
+        match message.lane() {
+            Lane::Consensus => lanes.consensus.push_back(message),
+            Lane::Transactions => lanes.transactions.push_back(message),
+        }
+    }
+
+    /// Take the oldest message of the highest-priority lane that is not empty.
+    pub fn pop(&self) -> Option<Message> {
+        let mut lanes = self.lanes.lock().unwrap();
+        lanes
+            .consensus
+            .pop_front()
+            .or_else(|| lanes.transactions.pop_front())
+    }
+
+    /// How many messages are waiting to be processed.
+    pub fn len(&self) -> usize {
+        let lanes = self.lanes.lock().unwrap();
+        lanes.consensus.len() + lanes.transactions.len()
+    }
+
+    /// How many messages are waiting in a lane.
+    pub fn lane_len(&self, lane: Lane) -> usize {
+        let lanes = self.lanes.lock().unwrap();
+        match lane {
+            Lane::Consensus => lanes.consensus.len(),
+            Lane::Transactions => lanes.transactions.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Whether a message that carries the block or the transaction has been recently received.
+    pub fn has_seen(&self, item: &Item) -> bool {
+        self.seen.lock().unwrap().items.contains(item)
+    }
+
+    /// How many messages have been dropped because they carried a recently received block or
+    /// transaction.
+    pub fn duplicates(&self) -> u64 {
+        self.duplicates.load(Ordering::Relaxed)
+    }
+}
+
+/// The `NODE_SEEN_CACHE_CAPACITY` most recently received blocks and transactions.
+#[derive(Debug, Default)]
+struct SeenCache {
+    items: HashSet<Item>,
+    order: VecDeque<Item>,
+}
+
+impl SeenCache {
+    /// Record that an item has been received, returning whether it is not a recent duplicate.
+    /// The oldest item is forgotten when the cache is full.
+    fn insert(&mut self, item: Item) -> bool {
+        if !self.items.insert(item.clone()) {
+            return false;
+        }
+        self.order.push_back(item);
+        if self.order.len() > NODE_SEEN_CACHE_CAPACITY {
+            if let Some(oldest) = self.order.pop_front() {
+                self.items.remove(&oldest);
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drop_recent_duplicates() {
+        let inbox = Inbox::default();
+        let transaction = |amount| Message::Transaction {
+            transaction: BlockTransaction::new(
+                Block::genesis().hash(),
+                Transaction::new(Address::new(1), Address::new(2), Amount::from_coins(amount)),
+            ),
+            source: Address::new(1),
+        };
+        let first = transaction(1);
+        inbox.push(first.clone());
+        inbox.push(first.clone());
+        assert_eq!((inbox.len(), inbox.duplicates()), (1, 1));
+
+        // Receipts are never duplicates.
+        for _ in 0..2 {
+            inbox.push(Message::TransactionReceipt {
+                transaction_id: TransactionId::new_random(),
+                peer: Address::new(2),
+                accepted: true,
+            });
+        }
+        assert_eq!((inbox.len(), inbox.duplicates()), (3, 1));
+
+        // Old items are forgotten.
+        for amount in 0..NODE_SEEN_CACHE_CAPACITY as u64 {
+            inbox.push(transaction(amount + 2));
+        }
+        inbox.push(first);
+        assert_eq!(inbox.duplicates(), 1);
+    }
+
+    #[test]
+    fn encode_and_decode_messages() {
+        let (alice, bob) = (Address::new(1), Address::new(2));
+        let genesis = Block::genesis();
+        let transaction = BlockTransaction::new(
+            genesis.hash(),
+            Transaction::new(alice, bob, Amount::from_coins(1)),
+        );
+        let messages = vec![
+            Message::NewBlock {
+                block: genesis.clone(),
+                blockchain_length: 1,
+                source: alice,
+                params_hash: Some(genesis.hash()),
+            },
+            Message::NewBlock {
+                block: genesis.clone(),
+                blockchain_length: 1,
+                source: alice,
+                params_hash: None,
+            },
+            Message::Transaction {
+                transaction: transaction.clone(),
+                source: alice,
+            },
+            Message::TransactionReceipt {
+                transaction_id: transaction.id,
+                peer: bob,
+                accepted: false,
+            },
+            Message::Inventory {
+                items: vec![
+                    Item::Block(genesis.hash()),
+                    Item::Transaction(transaction.id),
+                ],
+                source: alice,
+            },
+            Message::GetData {
+                items: vec![],
+                source: bob,
+            },
+            Message::Departure { source: bob },
+            Message::Heartbeat {
+                blockchain_length: 1,
+                last_block_hash: genesis.hash(),
+                cumulative_work: u128::MAX,
+                source: alice,
+            },
+        ];
+        for message in messages {
+            let decoded = Message::decode(&message.encode()).unwrap();
+            assert_eq!(
+                serde_json::to_string(&decoded).unwrap(),
+                serde_json::to_string(&message).unwrap()
+            );
+        }
+        assert_eq!(
+            Message::decode(&[7]).unwrap_err(),
+            DecodeError::InvalidField("message type")
+        );
+    }
+}
@@ -0,0 +1,53 @@
+#![deny(unused_must_use)]
+// Invalid blocks and transactions are logged where they are detected, so the callers only need to
+// know whether an operation succeeded.
+#![allow(clippy::result_unit_err)]
+
+pub mod amount;
+pub mod balance;
+pub mod block;
+pub mod blockchain;
+pub mod consensus;
+pub mod constants;
+pub mod encoding;
+pub mod mempool;
+pub mod policy;
+pub mod prelude;
+pub mod store;
+pub mod validation;
+
+#[cfg(feature = "runtime")]
+pub mod capture;
+#[cfg(feature = "runtime")]
+pub mod client;
+#[cfg(feature = "runtime")]
+pub mod config;
+#[cfg(feature = "runtime")]
+pub mod demo;
+#[cfg(feature = "runtime")]
+pub mod events;
+#[cfg(feature = "runtime")]
+pub mod experiment;
+#[cfg(feature = "runtime")]
+pub mod inbox;
+#[cfg(feature = "runtime")]
+pub mod logging;
+#[cfg(feature = "runtime")]
+pub mod network;
+#[cfg(feature = "runtime")]
+pub mod node;
+#[cfg(feature = "runtime")]
+pub mod persistence;
+#[cfg(feature = "runtime")]
+pub mod report;
+#[cfg(feature = "runtime")]
+pub mod server;
+#[cfg(feature = "runtime")]
+pub mod stats;
+#[cfg(feature = "runtime")]
+pub mod wallet;
+#[cfg(feature = "runtime")]
+pub mod world;
+
+#[cfg(feature = "wasm")]
+pub mod wasm;
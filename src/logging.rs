@@ -0,0 +1,303 @@
+use crate::config::LogLevel;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id};
+use tracing::{Event, Subscriber};
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_subscriber::field::RecordFields;
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::fmt::format::{DefaultFields, Writer};
+use tracing_subscriber::fmt::time::{FormatTime, SystemTime};
+use tracing_subscriber::fmt::FormatFields;
+use tracing_subscriber::layer::{Context, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::reload;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::Layer;
+use tracing_subscriber::Registry;
+
+/// Where and how to write the logs, in addition to the standard output.
+pub struct LogFiles {
+    /// The main log file.
+    pub path: PathBuf,
+    /// The size, in bytes, after which a log file is rotated.
+    pub max_size: u64,
+    /// How many rotated files to keep, in addition to the current one.
+    pub max_files: usize,
+    /// Also write the logs of each node to a separate file, next to the main log file.
+    pub per_node: bool,
+}
+
+/// Changes the level of the global logger.
+static LEVEL: OnceLock<reload::Handle<LevelFilter, Registry>> = OnceLock::new();
+
+/// Change the most verbose level of the global logger, if it is installed.
+pub fn set_level(level: LogLevel) {
+    let filter = match level {
+        LogLevel::Error => LevelFilter::ERROR,
+        LogLevel::Warn => LevelFilter::WARN,
+        LogLevel::Info => LevelFilter::INFO,
+        LogLevel::Debug => LevelFilter::DEBUG,
+        LogLevel::Trace => LevelFilter::TRACE,
+    };
+    if let Some(handle) = LEVEL.get() {
+        if let Err(err) = handle.reload(filter) {
+            tracing::error!("Failed to change the level of the logs: {err}");
+        }
+    }
+}
+
+/// Install the global logger, which writes to the standard output and, optionally, to files.
+/// The returned guard must be kept alive until the end of the program, to flush the main log file.
+/// The logs are at the `info` level until `set_level` is called.
+pub fn init(log_files: Option<LogFiles>) -> std::io::Result<Option<WorkerGuard>> {
+    let (level_layer, level_handle) = reload::Layer::new(LevelFilter::INFO);
+    let _ = LEVEL.set(level_handle);
+    let stdout_layer = tracing_subscriber::fmt::layer();
+    let Some(log_files) = log_files else {
+        tracing_subscriber::registry()
+            .with(level_layer)
+            .with(stdout_layer)
+            .init();
+        return Ok(None);
+    };
+
+    let file = RotatingFile::open(&log_files.path, log_files.max_size, log_files.max_files)?;
+    let (writer, guard) = tracing_appender::non_blocking(file);
+    let file_layer = tracing_subscriber::fmt::layer()
+        .with_ansi(false)
+        .fmt_fields(PlainFields(DefaultFields::new()))
+        .with_writer(writer);
+    let node_layer = if log_files.per_node {
+        Some(NodeFilesLayer::new(&log_files)?)
+    } else {
+        None
+    };
+    tracing_subscriber::registry()
+        .with(level_layer)
+        .with(stdout_layer)
+        .with(file_layer)
+        .with(node_layer)
+        .init();
+    Ok(Some(guard))
+}
+
+/// The default formatter of fields, as a distinct type. The fields of spans are formatted once per
+/// formatter type, so without it the log file would reuse the colored fields of the standard output.
+struct PlainFields(DefaultFields);
+
+impl<'writer> FormatFields<'writer> for PlainFields {
+    fn format_fields<R: RecordFields>(
+        &self,
+        writer: Writer<'writer>,
+        fields: R,
+    ) -> std::fmt::Result {
+        self.0.format_fields(writer, fields)
+    }
+}
+
+/// A log file that is renamed to `<path>.1` when it exceeds a maximum size. Older files are
+/// shifted to `<path>.2`, `<path>.3` and so on, deleting the ones beyond the maximum number.
+pub struct RotatingFile {
+    path: PathBuf,
+    max_size: u64,
+    max_files: usize,
+    file: File,
+    size: u64,
+}
+
+impl RotatingFile {
+    pub fn open(path: &Path, max_size: u64, max_files: usize) -> std::io::Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        Ok(RotatingFile {
+            path: path.to_path_buf(),
+            max_size,
+            max_files,
+            file,
+            size,
+        })
+    }
+
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{index}"));
+        path.into()
+    }
+
+    fn rotate(&mut self) -> std::io::Result<()> {
+        self.file.flush()?;
+        if self.max_files == 0 {
+            std::fs::remove_file(&self.path)?;
+        } else {
+            for index in (1..self.max_files).rev() {
+                let from = self.rotated_path(index);
+                if from.exists() {
+                    std::fs::rename(from, self.rotated_path(index + 1))?;
+                }
+            }
+            std::fs::rename(&self.path, self.rotated_path(1))?;
+        }
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.size > 0 && self.size + buf.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// The name of the span in which each node runs. Its `address` field identifies the node.
+pub const NODE_SPAN: &str = "node";
+
+/// The address of the node of a span, stored in the span extensions.
+struct NodeAddress(String);
+
+/// A layer that writes the events emitted in the span of a node to the log file of that node.
+struct NodeFilesLayer {
+    /// The directory of the log files of the nodes.
+    dir: PathBuf,
+    max_size: u64,
+    max_files: usize,
+    files: Mutex<HashMap<String, RotatingFile>>,
+}
+
+impl NodeFilesLayer {
+    fn new(log_files: &LogFiles) -> std::io::Result<Self> {
+        let parent = log_files.path.parent().unwrap_or(Path::new(""));
+        let dir = parent.join("nodes");
+        std::fs::create_dir_all(&dir)?;
+        Ok(NodeFilesLayer {
+            dir,
+            max_size: log_files.max_size,
+            max_files: log_files.max_files,
+            files: Mutex::new(HashMap::new()),
+        })
+    }
+}
+
+impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for NodeFilesLayer {
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        if attrs.metadata().name() != NODE_SPAN {
+            return;
+        }
+        let mut visitor = FieldsVisitor::default();
+        attrs.record(&mut visitor);
+        let Some(address) = visitor.address else {
+            return;
+        };
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(NodeAddress(address));
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let Some(scope) = ctx.event_scope(event) else {
+            return;
+        };
+        let Some(address) = scope
+            .into_iter()
+            .find_map(|span| span.extensions().get::<NodeAddress>().map(|a| a.0.clone()))
+        else {
+            return;
+        };
+
+        let mut line = String::new();
+        let _ = SystemTime.format_time(&mut Writer::new(&mut line));
+        let mut visitor = FieldsVisitor::default();
+        event.record(&mut visitor);
+        let metadata = event.metadata();
+        line.push_str(&format!(
+            " {:>5} {}: {}\n",
+            metadata.level(),
+            metadata.target(),
+            visitor.fields
+        ));
+
+        let mut files = self.files.lock().unwrap();
+        if !files.contains_key(&address) {
+            let path = self.dir.join(format!("{address}.log"));
+            match RotatingFile::open(&path, self.max_size, self.max_files) {
+                Ok(file) => {
+                    files.insert(address.clone(), file);
+                }
+                Err(err) => {
+                    eprintln!("Failed to open the log file {}: {err}", path.display());
+                    return;
+                }
+            }
+        }
+        if let Some(file) = files.get_mut(&address) {
+            let _ = file.write_all(line.as_bytes());
+        }
+    }
+}
+
+/// Collect the fields of spans and events as text, remembering the `address` field.
+#[derive(Default)]
+struct FieldsVisitor {
+    fields: String,
+    address: Option<String>,
+}
+
+impl Visit for FieldsVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        let value = format!("{value:?}");
+        if field.name() == "address" {
+            self.address = Some(value.clone());
+        }
+        if !self.fields.is_empty() {
+            self.fields.push(' ');
+        }
+        if field.name() == "message" {
+            self.fields.push_str(&value);
+        } else {
+            self.fields.push_str(&format!("{}={value}", field.name()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotate_by_size() {
+        let dir = std::env::temp_dir().join(format!("smallchain-logging-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let path = dir.join("run.log");
+        let mut file = RotatingFile::open(&path, 10, 2).unwrap();
+        for line in ["aaaaaaaa\n", "bbbbbbbb\n", "cccccccc\n", "dddddddd\n"] {
+            file.write_all(line.as_bytes()).unwrap();
+        }
+        file.flush().unwrap();
+        let read = |path: PathBuf| std::fs::read_to_string(path).unwrap();
+        assert_eq!(read(path.clone()), "dddddddd\n");
+        assert_eq!(read(dir.join("run.log.1")), "cccccccc\n");
+        assert_eq!(read(dir.join("run.log.2")), "bbbbbbbb\n");
+        assert!(!dir.join("run.log.3").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,76 +1,605 @@
 #![deny(unused_must_use)]
 
-use clap::Parser;
-use prelude::*;
-use rand::prelude::SliceRandom;
-use rand::Rng;
-
-mod block;
-mod blockchain;
-mod constants;
-mod mempool;
-mod network;
-mod node;
-mod prelude;
-mod server;
-mod world;
+use clap::{Parser, Subcommand};
+use smallchain::prelude::*;
+use smallchain::{capture, client, demo, experiment, logging, persistence, report, server};
+use std::sync::Arc;
 
 /// Simulator of a simple blockchain.
-#[derive(Parser)]
+#[derive(Clone, Parser)]
 struct Args {
-    /// The port on which the server will listen.
-    #[clap(long, short)]
+    /// The port on which the server will listen, or of the server to query.
+    #[clap(long, short, global = true)]
     port: Option<u16>,
     /// Enable the demo mode.
     #[clap(long, short, action)]
     demo: bool,
+    /// How many nodes to create in the demo mode.
+    #[clap(long, default_value_t = 3)]
+    demo_nodes: usize,
+    /// The seed of the random transactions generated in the demo mode.
+    #[clap(long)]
+    seed: Option<u64>,
+    /// The fraction of the transactions generated in the demo mode that pay a high fee, whose
+    /// confirmation latency is measured separately [default: 0.1].
+    #[clap(long)]
+    demo_priority_fraction: Option<f64>,
+    /// Stop the simulation after the given number of seconds.
+    #[clap(long)]
+    duration: Option<u64>,
+    /// The directory in which to write a report of the run, when the server is stopped.
+    #[clap(long)]
+    results_dir: Option<std::path::PathBuf>,
+    /// The directory in which to save the blockchain and the mempool of the nodes when the server
+    /// is stopped, and from which to restore them at startup.
+    #[clap(long, global = true)]
+    data_dir: Option<std::path::PathBuf>,
+    /// How often, in seconds, to save a snapshot of the nodes to the `snapshots` directory of the
+    /// data directory. Zero disables the snapshots.
+    #[clap(long, default_value_t = 0, requires = "data_dir")]
+    snapshot_interval: u64,
+    /// How many of the most recent snapshots to keep, at least one.
+    #[clap(
+        long,
+        default_value_t = PERSISTENCE_DEFAULT_SNAPSHOTS_KEPT,
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    snapshot_keep_last: usize,
+    /// Also keep every snapshot whose number is a multiple of this, e.g. to keep a snapshot every
+    /// hour with snapshots every minute. Zero keeps none of the older snapshots.
+    #[clap(long, default_value_t = 0)]
+    snapshot_keep_every: u64,
+    /// The maximum size of the body of a request to the server, in bytes.
+    #[clap(long, default_value_t = SERVER_DEFAULT_MAX_BODY_BYTES)]
+    max_body_size: u64,
+    /// Reject the requests that change the simulation, e.g. to create nodes or send transactions,
+    /// with status 405.
+    #[clap(long, action)]
+    read_only: bool,
+    /// The token that the requests to the admin endpoints must carry, as
+    /// `Authorization: Bearer {token}`. By default, a random token is generated and logged.
+    #[clap(long)]
+    admin_token: Option<String>,
+    /// The port on which to serve the admin endpoints, if not the one of the other endpoints.
+    #[clap(long)]
+    admin_port: Option<u16>,
+    /// How often, in seconds, to check that the balances of the blockchain of each node add up to
+    /// the issued coins. Zero disables the audit.
+    #[clap(long, default_value_t = WORLD_DEFAULT_AUDIT_INTERVAL_SECS)]
+    audit_interval: u64,
+    /// How often, in milliseconds, to sample the tip of each node, for `GET /stats/tips`. Zero
+    /// disables the sampling.
+    #[clap(long, default_value_t = STATS_DEFAULT_TIP_SAMPLE_MILLIS)]
+    tip_sample_interval: u64,
+    /// The TOML file with the settings of the simulation. The options below take precedence.
+    #[clap(long)]
+    config: Option<std::path::PathBuf>,
+    /// After how many seconds a pending transaction that has not been mined is broadcast again, or
+    /// zero to never broadcast it again [default: 30].
+    #[clap(long)]
+    rebroadcast_after: Option<u64>,
+    /// Every how many seconds each node announces its tip to its peers, so that the ones that
+    /// missed a block catch up, or zero to never announce it [default: 5].
+    #[clap(long)]
+    heartbeat_interval: Option<u64>,
+    /// The maximum throughput of each link between two nodes, in bytes per second. By default,
+    /// unlimited.
+    #[clap(long)]
+    link_bandwidth: Option<u64>,
+    /// Send each block and transaction to this many random peers, which forward them in turn,
+    /// instead of sending them to all the nodes.
+    #[clap(long)]
+    gossip_fanout: Option<usize>,
+    /// In gossip mode, the probability that a node forwards a block or a transaction that it has
+    /// just learned about [default: 1].
+    #[clap(long)]
+    gossip_forward_probability: Option<f64>,
+    /// Announce the hashes of new blocks and transactions, which the peers request if they miss
+    /// them, instead of sending them.
+    #[clap(long, action)]
+    inventory: bool,
+    /// The probability that the network delivers a message twice.
+    #[clap(long)]
+    duplicate_probability: Option<f64>,
+    /// The probability that the network delays a message, so that it can be delivered after the
+    /// messages sent later on the same link.
+    #[clap(long)]
+    reorder_probability: Option<f64>,
+    /// The network backend that carries the messages between the nodes [default: simulated].
+    #[clap(long)]
+    network_backend: Option<String>,
+    /// With `--network-backend=tcp`, the socket address on which to accept the connections of
+    /// other smallchain processes, e.g. `0.0.0.0:7000`.
+    #[clap(long)]
+    tcp_listen: Option<String>,
+    /// With `--network-backend=tcp`, the `host:port` address of another smallchain process to
+    /// connect to. Can be repeated.
+    #[clap(long = "tcp-peer", value_name = "ADDRESS")]
+    tcp_peers: Vec<String>,
+    /// With `--network-backend=tcp`, the `host:port` address of a smallchain process from which
+    /// to discover the other processes of the network, and connect to them. Can be repeated.
+    #[clap(long = "bootnode", value_name = "ADDRESS")]
+    bootnodes: Vec<String>,
+    /// The maximum number of nodes in the world, or zero for no limit [default: 1000].
+    #[clap(long)]
+    max_nodes: Option<usize>,
+    /// The maximum sum of the hashrates of the nodes in the world. By default, unlimited.
+    #[clap(long)]
+    max_total_hashrate: Option<f64>,
+    /// Restart the nodes whose task fails, waiting longer after each failure, instead of leaving
+    /// them failed.
+    #[clap(long, action)]
+    restart_failed_nodes: bool,
+    /// Let each node serve its read-only endpoints on its own HTTP port, on the local interface.
+    #[clap(long, action)]
+    node_ports: bool,
+    /// Allow transactions that move zero coins.
+    #[clap(long, action)]
+    allow_zero_amount: bool,
+    /// Allow transactions that move coins from an address to the same address.
+    #[clap(long, action)]
+    allow_self_transfer: bool,
+    /// Burn a base fee of each transaction, adjusted by how full the previous block was, and only
+    /// credit the rest of the fees to the miners.
+    #[clap(long, action)]
+    base_fee: bool,
+    /// The maximum total weight of the transactions of a block. Each transaction weighs 100, plus
+    /// 4 for each byte of its memo [default: 100000].
+    #[clap(long)]
+    max_block_weight: Option<u64>,
+    /// Skip a validation rule of the blocks and of the transactions, e.g. `balances`. Can be
+    /// repeated.
+    #[clap(long = "skip-rule", value_name = "RULE", value_parser = RULE_NAMES)]
+    skipped_rules: Vec<String>,
+    /// The smallest amount of coins of a transaction admitted in the mempools, e.g. `0.5`.
+    /// Smaller amounts are dust [default: 0.00000001].
+    #[clap(long)]
+    min_amount: Option<Amount>,
+    /// Reject the transactions whose fee exceeds their amount multiplied by this factor.
+    #[clap(long)]
+    max_fee_multiple: Option<u64>,
+    /// Record every message delivered by the network to this file, to replay them later.
+    #[clap(long)]
+    capture: Option<std::path::PathBuf>,
+    /// Also write the logs to this file.
+    #[clap(long)]
+    log_file: Option<std::path::PathBuf>,
+    /// The size, in bytes, after which the log files are rotated.
+    #[clap(long, default_value_t = LOG_DEFAULT_MAX_FILE_BYTES)]
+    log_max_size: u64,
+    /// How many rotated log files to keep.
+    #[clap(long, default_value_t = LOG_DEFAULT_MAX_FILES)]
+    log_max_files: usize,
+    /// Also write the logs of each node to a separate file, in a `nodes` directory next to the
+    /// log file.
+    #[clap(long, action, requires = "log_file")]
+    log_per_node: bool,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Clone, Subcommand)]
+enum Command {
+    /// Query the statistics of a running simulation.
+    Stats {
+        #[command(subcommand)]
+        command: StatsCommand,
+    },
+    /// Run the same scenario several times, in separate processes, and aggregate the results.
+    Experiment {
+        /// The TOML file describing the scenario.
+        #[clap(long)]
+        config: std::path::PathBuf,
+        /// How many times to run the scenario.
+        #[clap(long, default_value_t = 10)]
+        runs: usize,
+        /// How many runs to execute in parallel. By default, one per CPU.
+        #[clap(long)]
+        jobs: Option<usize>,
+        /// The directory in which to write the reports of the runs and the combined report.
+        #[clap(long, default_value = "experiment")]
+        output_dir: std::path::PathBuf,
+    },
+    /// Feed the messages of a capture file into a single node, which does not mine, and print the
+    /// resulting blockchain.
+    Replay {
+        /// The file written with `--capture`.
+        file: std::path::PathBuf,
+        /// The address of the node whose messages to replay. By default, the destination of the
+        /// first captured message.
+        #[clap(long)]
+        node: Option<String>,
+    },
+    /// Maintain the nodes saved in the data directory.
+    Db {
+        #[command(subcommand)]
+        command: DbCommand,
+    },
+}
+
+#[derive(Clone, Subcommand)]
+enum DbCommand {
+    /// Rebuild the saved nodes by replaying their blocks, cutting each blockchain before its
+    /// first invalid block, and save them again.
+    Reindex,
+}
+
+#[derive(Clone, Subcommand)]
+enum StatsCommand {
+    /// Export the statistics of a running simulation.
+    Export {
+        /// The exported table: `blocks` or `nodes`.
+        #[clap(long, default_value = "blocks")]
+        table: String,
+        /// The format of the export: `csv` or `json`.
+        #[clap(long, default_value = "csv")]
+        format: String,
+        /// The file to write to. If missing, the export is printed to the standard output.
+        #[clap(long, short)]
+        output: Option<std::path::PathBuf>,
+    },
+}
+
+/// Read the settings of the simulation from the configuration file, if any, overriding them with
+/// the command line options.
+fn load_config(args: &Args) -> Result<Config, ()> {
+    let mut config = match &args.config {
+        Some(path) => Config::load(path)?,
+        None => Config::default(),
+    };
+    if let Some(rebroadcast_after) = args.rebroadcast_after {
+        config.rebroadcast_after_secs = rebroadcast_after;
+    }
+    if let Some(heartbeat_interval) = args.heartbeat_interval {
+        config.heartbeat_interval_secs = heartbeat_interval;
+    }
+    if let Some(link_bandwidth) = args.link_bandwidth {
+        config.link_bandwidth = link_bandwidth;
+    }
+    if let Some(gossip_fanout) = args.gossip_fanout {
+        config.gossip_fanout = gossip_fanout;
+    }
+    if let Some(gossip_forward_probability) = args.gossip_forward_probability {
+        config.gossip_forward_probability = gossip_forward_probability;
+    }
+    if let Some(duplicate_probability) = args.duplicate_probability {
+        config.duplicate_probability = duplicate_probability;
+    }
+    if let Some(reorder_probability) = args.reorder_probability {
+        config.reorder_probability = reorder_probability;
+    }
+    if let Some(network_backend) = &args.network_backend {
+        config.network_backend = Some(network_backend.clone());
+    }
+    if let Some(tcp_listen) = &args.tcp_listen {
+        config.tcp_listen = Some(tcp_listen.clone());
+    }
+    config.tcp_peers.extend(args.tcp_peers.iter().cloned());
+    config.bootnodes.extend(args.bootnodes.iter().cloned());
+    if let Some(max_nodes) = args.max_nodes {
+        config.max_nodes = max_nodes;
+    }
+    if let Some(max_total_hashrate) = args.max_total_hashrate {
+        config.max_total_hashrate = max_total_hashrate;
+    }
+    if let Some(demo_priority_fraction) = args.demo_priority_fraction {
+        config.demo_priority_fraction = demo_priority_fraction;
+    }
+    config.inventory |= args.inventory;
+    config.node_ports |= args.node_ports;
+    config.restart_failed_nodes |= args.restart_failed_nodes;
+    config.transaction_rules.allow_zero_amount |= args.allow_zero_amount;
+    config.transaction_rules.allow_self_transfer |= args.allow_self_transfer;
+    if args.base_fee {
+        config.transaction_rules.fee_policy = FeePolicy::BaseFee;
+    }
+    if let Some(max_block_weight) = args.max_block_weight {
+        config.transaction_rules.max_block_weight = max_block_weight;
+    }
+    if !args.skipped_rules.is_empty() {
+        let mut skipped = Vec::<String>::from(config.transaction_rules.skipped);
+        skipped.extend(args.skipped_rules.iter().cloned());
+        config.transaction_rules.skipped =
+            SkippedRules::try_from(skipped).expect("the names are checked by clap");
+    }
+    if let Some(min_amount) = args.min_amount {
+        config.transaction_policy.min_amount = min_amount;
+    }
+    if args.max_fee_multiple.is_some() {
+        config.transaction_policy.max_fee_multiple = args.max_fee_multiple;
+    }
+    config.validate()?;
+    Ok(config)
+}
+
+/// Export the statistics of the simulation running on the given port.
+async fn export_stats(
+    port: Option<u16>,
+    table: &str,
+    format: &str,
+    output: Option<&std::path::Path>,
+) -> Result<(), ()> {
+    let Some(port) = port else {
+        error!("The port of the server to query is required");
+        return Err(());
+    };
+    let export = client::get(
+        port,
+        &format!(
+            "/{}/stats/export?table={table}&format={format}",
+            server::API_VERSION
+        ),
+    )
+    .await?;
+    match output {
+        Some(path) => std::fs::write(path, export)
+            .map_err(|err| error!("Failed to write {}: {err}", path.display())),
+        None => {
+            print!("{export}");
+            Ok(())
+        }
+    }
+}
+
+/// Replay the messages of a capture file and print the resulting blockchain of the node.
+async fn replay_capture(file: &std::path::Path, node: Option<&str>) -> Result<(), ()> {
+    let address = match node {
+        Some(raw_address) => Some(
+            raw_address
+                .parse::<Address>()
+                .map_err(|err| error!("Invalid address {raw_address:?}: {err}"))?,
+        ),
+        None => None,
+    };
+    let node = capture::replay(file, address).await?;
+    for (height, block) in node.blockchain().blocks().enumerate() {
+        println!(
+            "{height}\t{block}\tminer {}\t{} transactions",
+            block.miner,
+            block.transactions.len()
+        );
+    }
+    println!(
+        "Node {}: height {}, {} pending transactions",
+        node.address(),
+        node.blockchain().height(),
+        node.mempool().len()
+    );
+    Ok(())
+}
+
+/// Read the configuration file again, and apply the settings that can change, whenever the process
+/// receives `SIGHUP`.
+#[cfg(unix)]
+async fn reload_config_on_hangup(config_loader: ConfigLoader) {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(err) => {
+            error!("Cannot handle SIGHUP: {err}");
+            return;
+        }
+    };
+    while hangups.recv().await.is_some() {
+        info!("Received SIGHUP, reloading the configuration file");
+        match config_loader() {
+            Ok(new_config) => {
+                if let Err(err) = reload_config(new_config).await {
+                    error!("Failed to reload the configuration file: {err}");
+                }
+            }
+            Err(()) => error!("Failed to reload the configuration file"),
+        }
+    }
 }
 
 #[tokio::main(flavor = "multi_thread")]
 async fn main() {
-    tracing_subscriber::fmt::init();
-
     let args = Args::parse();
 
+    let log_files = args.log_file.as_ref().map(|path| logging::LogFiles {
+        path: path.clone(),
+        max_size: args.log_max_size,
+        max_files: args.log_max_files,
+        per_node: args.log_per_node,
+    });
+    let _log_guard = match logging::init(log_files) {
+        Ok(guard) => guard,
+        Err(err) => {
+            eprintln!("Failed to open the log file: {err}");
+            std::process::exit(1);
+        }
+    };
+
+    match &args.command {
+        Some(Command::Stats {
+            command:
+                StatsCommand::Export {
+                    table,
+                    format,
+                    output,
+                },
+        }) => {
+            if export_stats(args.port, table, format, output.as_deref())
+                .await
+                .is_err()
+            {
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some(Command::Experiment {
+            config,
+            runs,
+            jobs,
+            output_dir,
+        }) => {
+            if experiment::run(config, *runs, *jobs, output_dir)
+                .await
+                .is_err()
+            {
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some(Command::Replay { .. } | Command::Db { .. }) | None => {}
+    }
+
+    let Ok(config) = load_config(&args) else {
+        std::process::exit(1);
+    };
+    set_config(config.clone());
+    if let Some(Command::Replay { file, node }) = &args.command {
+        if replay_capture(file, node.as_deref()).await.is_err() {
+            std::process::exit(1);
+        }
+        return;
+    }
+    if let Some(Command::Db {
+        command: DbCommand::Reindex,
+    }) = &args.command
+    {
+        let Some(data_dir) = &args.data_dir else {
+            error!("The data directory to reindex is required");
+            std::process::exit(1);
+        };
+        match persistence::reindex(data_dir) {
+            Ok(reindexed) => info!("Rebuilt {reindexed} nodes in {}", data_dir.display()),
+            Err(err) => {
+                error!(
+                    "Failed to rebuild the nodes in {}: {err}",
+                    data_dir.display()
+                );
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    stats()
+        .await
+        .record_world_event(WorldEvent::ConfigChanged {
+            config: Box::new(config),
+        })
+        .await;
+
+    if let Some(path) = &args.capture {
+        if capture::start(path).is_err() {
+            std::process::exit(1);
+        }
+    }
+
+    if let Some(data_dir) = &args.data_dir {
+        if let Err(err) = persistence::store_blocks(data_dir) {
+            error!(
+                "Failed to store the blocks of the nodes in {}: {err}",
+                data_dir.display()
+            );
+            std::process::exit(1);
+        }
+        match persistence::restore(data_dir).await {
+            Ok(restored) => info!("Restored {restored} nodes from {}", data_dir.display()),
+            Err(err) => {
+                error!(
+                    "Failed to restore the nodes from {}: {err}",
+                    data_dir.display()
+                );
+                std::process::exit(1);
+            }
+        }
+    }
+
     if args.demo {
-        tokio::spawn(async {
-            let nodes = [
-                world::world().await.add_node().await,
-                world::world().await.add_node().await,
-                world::world().await.add_node().await,
-            ];
-
-            let mut max_amount = 100;
-            loop {
-                let node_addr = *nodes.choose(&mut rand::thread_rng()).unwrap();
-                let source_addr = *nodes.choose(&mut rand::thread_rng()).unwrap();
-                let destination_addr = *nodes.choose(&mut rand::thread_rng()).unwrap();
-                let amount = rand::thread_rng().gen_range(0..=max_amount);
-
-                let transaction = Transaction::new(source_addr, destination_addr, amount);
-
-                let succeeded = world::world()
-                    .await
-                    .get_node(node_addr)
-                    .await
-                    .unwrap()
-                    .write()
-                    .await
-                    .add_client_transaction(transaction)
-                    .await
-                    .is_ok();
-
-                if succeeded {
-                    max_amount += 100;
-                } else {
-                    max_amount -= 10;
-                    max_amount = max_amount.max(100);
-                }
+        tokio::spawn(demo::run_demo(args.demo_nodes, args.seed));
+    }
+
+    // Accept and establish the connections of the network backend, if it has any, before the
+    // nodes first send a message
+    world().await.network();
+
+    // Start collecting statistics
+    stats().await;
+    tokio::spawn(notify_wallets());
 
-                tokio::time::sleep(std::time::Duration::from_millis(1000)).await;
+    if args.tip_sample_interval > 0 {
+        let interval = std::time::Duration::from_millis(args.tip_sample_interval);
+        tokio::spawn(sample_tips(interval));
+    }
+
+    let snapshots = args
+        .data_dir
+        .clone()
+        .map(|data_dir| persistence::Snapshots {
+            data_dir,
+            retention: persistence::SnapshotRetention {
+                keep_last: args.snapshot_keep_last,
+                keep_every: args.snapshot_keep_every,
+            },
+        });
+    if let Some(snapshots) = snapshots.clone().filter(|_| args.snapshot_interval > 0) {
+        let interval = std::time::Duration::from_secs(args.snapshot_interval);
+        tokio::spawn(persistence::take_snapshots(snapshots, interval));
+    }
+
+    if args.audit_interval > 0 {
+        let interval = std::time::Duration::from_secs(args.audit_interval);
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+                world().await.audit_supply().await;
             }
         });
     }
 
-    server::serve(args.port).await;
+    let duration = async {
+        match args.duration {
+            Some(secs) => tokio::time::sleep(std::time::Duration::from_secs(secs)).await,
+            None => std::future::pending().await,
+        }
+    };
+    let admin_token = args.admin_token.clone().unwrap_or_else(|| {
+        let token: String = (0..16)
+            .map(|_| format!("{:02x}", rand::random::<u8>()))
+            .collect();
+        info!("The token of the admin endpoints is {token}");
+        token
+    });
+    // Without a configuration file, there is nothing to reload
+    let config_loader: Option<ConfigLoader> = args.config.is_some().then(|| {
+        let args = args.clone();
+        Arc::new(move || load_config(&args)) as ConfigLoader
+    });
+    #[cfg(unix)]
+    if let Some(config_loader) = config_loader.clone() {
+        tokio::spawn(reload_config_on_hangup(config_loader));
+    }
+    let admin = server::AdminOptions {
+        token: admin_token,
+        port: args.admin_port,
+        config_loader,
+        snapshots,
+    };
+    tokio::select! {
+        _ = server::serve(args.port, args.max_body_size, args.read_only, admin) => {}
+        _ = tokio::signal::ctrl_c() => info!("Shutting down"),
+        _ = duration => info!("Stopping the simulation after {}s", args.duration.unwrap_or(0)),
+    }
+    capture::flush().await;
+
+    if let Some(data_dir) = &args.data_dir {
+        match persistence::save(data_dir).await {
+            Ok(()) => info!("Saved the nodes to {}", data_dir.display()),
+            Err(err) => error!("Failed to save the nodes to {}: {err}", data_dir.display()),
+        }
+    }
+
+    if let Some(results_dir) = &args.results_dir {
+        let report = report::Report::collect(args.demo).await;
+        match report.write(results_dir) {
+            Ok(()) => info!("Wrote the report of the run to {}", results_dir.display()),
+            Err(err) => error!(
+                "Failed to write the report to {}: {err}",
+                results_dir.display()
+            ),
+        }
+    }
 }
@@ -2,13 +2,64 @@ use crate::prelude::*;
 use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
 
+/// Builds the blocks that the nodes mine, choosing their transactions, the order of the
+/// transactions and the other fields of the block but the nonce. Library users can replace the
+/// default builder, e.g. to study policies that order the transactions to extract more value.
+pub trait BlockTemplateBuilder: Send + Sync {
+    /// Build the block to mine for `miner` on top of the last block of `blockchain`, with some of
+    /// the pending transactions of `mempool`. The nonce of the block is ignored. A block that is
+    /// not valid on top of `blockchain` is dropped after being mined.
+    fn build(&self, blockchain: &BlockChain, mempool: &MemPool, miner: Address) -> Block;
+}
+
+/// The default builder, which selects the transactions with `MemPool::select_transactions`.
+pub struct DefaultTemplateBuilder;
+
+impl BlockTemplateBuilder for DefaultTemplateBuilder {
+    fn build(&self, blockchain: &BlockChain, mempool: &MemPool, miner: Address) -> Block {
+        Block::new(
+            mempool.select_transactions(blockchain),
+            blockchain.last_hash().clone(),
+            miner,
+            0,
+        )
+    }
+}
+
+/// A transaction on top of a block that is not in the blockchain yet.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct HeldTransaction {
+    transaction: BlockTransaction,
+    /// The height of the blockchain when the transaction was held.
+    height: usize,
+}
+
 /// A mempool is a sequence of pending transactions that have not yet been included in a block.
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct MemPool {
     transaction_ids: HashSet<TransactionId>,
     transactions: Vec<BlockTransaction>,
-    balance: HashMap<Address, u64>,
+    balance: HashMap<Address, Balance>,
     prefix_hash: BlockHash,
+    /// Transactions whose `prefix_hash` is a block that is not in the blockchain yet, by sender.
+    /// They are admitted when the blockchain reaches that block.
+    future: HashMap<Address, Vec<HeldTransaction>>,
+    /// The height of the block on top of which the transactions are admitted.
+    #[serde(default)]
+    height: usize,
+    /// The rules of the blockchain, which transactions must satisfy to be valid.
+    #[serde(default)]
+    rules: TransactionRules,
+    /// With the base-fee policy, the base fee that transactions must pay to be included in the
+    /// next block.
+    #[serde(default)]
+    base_fee: Option<Amount>,
+    /// The rules that transactions must satisfy, in addition to being valid, to be admitted.
+    #[serde(default)]
+    policy: TransactionPolicy,
+    /// The maximum number of transactions, if any.
+    #[serde(default)]
+    capacity: Option<usize>,
 }
 
 impl MemPool {
@@ -18,9 +69,49 @@ impl MemPool {
             transactions: vec![],
             balance: blockchain.balance().clone(),
             prefix_hash: blockchain.last_hash().clone(),
+            future: HashMap::new(),
+            height: blockchain.height(),
+            rules: *blockchain.rules(),
+            base_fee: blockchain.next_base_fee(),
+            policy: TransactionPolicy::default(),
+            capacity: None,
         }
     }
 
+    /// Limit the number of transactions admitted from now on.
+    pub fn set_capacity(&mut self, capacity: Option<usize>) {
+        self.capacity = capacity;
+    }
+
+    /// The maximum number of transactions, if any.
+    pub fn capacity(&self) -> Option<usize> {
+        self.capacity
+    }
+
+    /// The block on top of which the transactions are admitted.
+    pub fn prefix_hash(&self) -> &BlockHash {
+        &self.prefix_hash
+    }
+
+    /// The rules of the blockchain, which transactions must satisfy to be valid.
+    pub fn rules(&self) -> &TransactionRules {
+        &self.rules
+    }
+
+    /// With the base-fee policy, the base fee that transactions must pay.
+    pub fn base_fee(&self) -> Option<Amount> {
+        self.base_fee
+    }
+
+    pub fn policy(&self) -> &TransactionPolicy {
+        &self.policy
+    }
+
+    /// Change the rules applied to the transactions admitted from now on.
+    pub fn set_policy(&mut self, policy: TransactionPolicy) {
+        self.policy = policy;
+    }
+
     /// The transactions in the mempool.
     pub fn transactions(&self) -> &Vec<BlockTransaction> {
         &self.transactions
@@ -31,47 +122,494 @@ impl MemPool {
         self.transactions.len()
     }
 
-    pub fn balance(&self) -> &HashMap<Address, u64> {
+    /// Whether the mempool has no transactions.
+    pub fn is_empty(&self) -> bool {
+        self.transactions.is_empty()
+    }
+
+    /// Whether the mempool contains or holds the transaction.
+    pub fn contains(&self, transaction_id: &TransactionId) -> bool {
+        self.transaction_ids.contains(transaction_id)
+            || self
+                .future
+                .values()
+                .flatten()
+                .any(|held| &held.transaction.id == transaction_id)
+    }
+
+    /// Whether the mempool contains the transaction, not counting the held ones.
+    pub fn contains_pending(&self, transaction_id: &TransactionId) -> bool {
+        self.transaction_ids.contains(transaction_id)
+    }
+
+    /// The number of transactions waiting for the blockchain to reach their `prefix_hash`.
+    pub fn future_len(&self) -> usize {
+        self.future.values().map(Vec::len).sum()
+    }
+
+    pub fn balance(&self) -> &HashMap<Address, Balance> {
         &self.balance
     }
 
-    pub fn balance_of(&mut self, address: Address) -> u64 {
-        self.balance.get(&address).copied().unwrap_or(0)
+    pub fn balance_of(&mut self, address: Address) -> Amount {
+        self.balance
+            .get(&address)
+            .map_or(Amount::ZERO, Balance::amount)
     }
 
-    pub fn balance_mut_of(&mut self, address: Address) -> &mut u64 {
-        self.balance.entry(address).or_insert(0)
+    pub fn balance_mut_of(&mut self, address: Address) -> &mut Balance {
+        self.balance.entry(address).or_default()
     }
 
-    /// Add a transaction, checking whether it is valid.
-    pub fn add_transaction(&mut self, transaction: BlockTransaction) -> Result<(), ()> {
-        if transaction.prefix_hash != self.prefix_hash {
-            warn!("Transaction {transaction} has a `prefix_hash` that is invalid for this mempool");
-            return Err(());
-        }
-        if self.transaction_ids.contains(&transaction.id) {
-            warn!("Transaction {transaction} is already in the mempool");
-            return Err(());
-        }
-        if self.balance_of(transaction.info.sender) < transaction.info.amount {
-            warn!(
-                "Insufficient funds to transfer {} from {} to {}",
-                transaction.info.amount, transaction.info.sender, transaction.info.receiver
-            );
-            return Err(());
+    /// Add a transaction, checking whether it is valid and whether it satisfies the policy.
+    pub fn add_transaction(&mut self, transaction: BlockTransaction) -> Result<(), TxError> {
+        self.admit_transaction(transaction).map_err(|(_, err)| err)
+    }
+
+    /// Like `add_transaction`, but also returns the name of the rule that the transaction breaks.
+    pub fn admit_transaction(
+        &mut self,
+        transaction: BlockTransaction,
+    ) -> Result<(), (&'static str, TxError)> {
+        self.validate_transaction(&transaction)?;
+        if apply_transaction(&mut self.balance, &transaction.info).is_err() {
+            // The `balances` rule is skipped
+            force_transaction(&mut self.balance, &transaction.info);
         }
         self.transactions.push(transaction.clone());
         self.transaction_ids.insert(transaction.id);
-        *self.balance_mut_of(transaction.info.sender) -= transaction.info.amount;
-        *self.balance_mut_of(transaction.info.receiver) += transaction.info.amount;
         Ok(())
     }
 
-    /// Reset the mempool to its initial state.
+    /// Check a transaction with the `TRANSACTION_RULES` that the rules of the blockchain do not
+    /// skip, in order. Returns the name of the first rule that the transaction breaks, and why.
+    pub fn validate_transaction(
+        &self,
+        transaction: &BlockTransaction,
+    ) -> Result<(), (&'static str, TxError)> {
+        for rule in TRANSACTION_RULES {
+            if self.rules.skipped.contains(rule.name()) {
+                continue;
+            }
+            rule.check_transaction(self, transaction)
+                .map_err(|err| (rule.name(), err))?;
+        }
+        Ok(())
+    }
+
+    /// Select the transactions to include in the next block on top of `blockchain`: greedily by
+    /// decreasing fee per unit of weight, skipping the ones that the sender cannot afford and the
+    /// ones that do not fit in the maximum weight of the block. Skipped transactions are
+    /// reconsidered after the selected ones have credited their receivers, so the selection is a
+    /// maximal subset of the mempool that is valid in the returned order. With the base-fee
+    /// policy, transactions below the base fee are skipped and at most
+    /// `BASE_FEE_MAX_TRANSACTIONS` are selected.
+    pub fn select_transactions(&self, blockchain: &BlockChain) -> Vec<BlockTransaction> {
+        let base_fee = blockchain.next_base_fee();
+        let max_transactions = base_fee.map_or(usize::MAX, |_| BASE_FEE_MAX_TRANSACTIONS);
+        let mut free_weight = blockchain.rules().max_block_weight;
+        let mut candidates: Vec<&BlockTransaction> = self
+            .transactions
+            .iter()
+            .filter(|transaction| &transaction.prefix_hash == blockchain.last_hash())
+            .filter(|transaction| base_fee.is_none_or(|base_fee| transaction.info.fee >= base_fee))
+            .collect();
+        // The sort is stable, so transactions with the same fee rate keep their admission order.
+        // The fee rates are compared by cross-multiplying, to avoid rounding.
+        candidates.sort_by(|a, b| {
+            let (a, b) = (&a.info, &b.info);
+            let a_rate = a.fee.units() as u128 * b.weight() as u128;
+            let b_rate = b.fee.units() as u128 * a.weight() as u128;
+            b_rate.cmp(&a_rate)
+        });
+
+        let mut balance = blockchain.balance().clone();
+        let mut selected = vec![];
+        loop {
+            let mut progress = false;
+            candidates.retain(|transaction| {
+                if selected.len() < max_transactions
+                    && transaction.info.weight() <= free_weight
+                    && apply_transaction(&mut balance, &transaction.info).is_ok()
+                {
+                    free_weight -= transaction.info.weight();
+                    selected.push((*transaction).clone());
+                    progress = true;
+                    false
+                } else {
+                    true
+                }
+            });
+            if !progress || candidates.is_empty() {
+                break;
+            }
+        }
+        selected
+    }
+
+    /// Hold a transaction whose `prefix_hash` is a block that is not in the blockchain yet, for
+    /// example because the block is still being propagated. Returns an error if the transaction is
+    /// already held or if too many transactions are held, in total or for its sender. The policy
+    /// is checked when the transaction is admitted. The transaction is dropped if the block has
+    /// not arrived after `MEMPOOL_FUTURE_TRANSACTION_EXPIRY_BLOCKS` more blocks.
+    pub fn add_future_transaction(&mut self, transaction: BlockTransaction) -> Result<(), TxError> {
+        let from_sender = self
+            .future
+            .get(&transaction.info.sender)
+            .map_or(&[][..], Vec::as_slice);
+        if from_sender
+            .iter()
+            .any(|held| held.transaction.id == transaction.id)
+        {
+            warn!("Transaction {transaction} is already held by the mempool");
+            return Err(TxError::Duplicate);
+        }
+        if from_sender.len() >= MEMPOOL_MAX_FUTURE_TRANSACTIONS_PER_SENDER
+            || self.future_len() >= MEMPOOL_MAX_FUTURE_TRANSACTIONS
+        {
+            warn!("Too many transactions on top of unknown blocks to hold {transaction}");
+            return Err(TxError::TooManyHeld);
+        }
+        self.future
+            .entry(transaction.info.sender)
+            .or_default()
+            .push(HeldTransaction {
+                transaction,
+                height: self.height,
+            });
+        Ok(())
+    }
+
+    /// Reset the mempool to its initial state on top of the last block of `blockchain`.
+    /// The held transactions on top of that block are admitted, the ones on top of other blocks
+    /// of the blockchain and the expired ones are dropped, and the others are still held.
     pub fn reset(&mut self, blockchain: &BlockChain) {
         self.transactions.clear();
         self.transaction_ids.clear();
         self.balance = blockchain.balance().clone();
         self.prefix_hash = blockchain.last_hash().clone();
+        self.height = blockchain.height();
+        self.rules = *blockchain.rules();
+        self.base_fee = blockchain.next_base_fee();
+        for (sender, held_transactions) in std::mem::take(&mut self.future) {
+            for held in held_transactions {
+                let transaction = &held.transaction;
+                if transaction.prefix_hash == self.prefix_hash {
+                    debug!("Admitting held transaction {transaction}");
+                    let _ = self.add_transaction(held.transaction);
+                } else if blockchain.contains(&transaction.prefix_hash) {
+                    debug!("Dropping held transaction {transaction}, which is no longer valid");
+                } else if self.height.saturating_sub(held.height)
+                    >= MEMPOOL_FUTURE_TRANSACTION_EXPIRY_BLOCKS
+                {
+                    debug!("Dropping held transaction {transaction}, whose block never arrived");
+                } else {
+                    self.future.entry(sender).or_default().push(held);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn select_transactions_by_fee() {
+        let miner = Address::new(1);
+        let alice = Address::new(2);
+        let bob = Address::new(3);
+        let mut blockchain = BlockChain::with_difficulty(0);
+        let mine = |blockchain: &BlockChain, transactions| {
+            attempt_mining_block_with_difficulty(
+                blockchain.last_hash().clone(),
+                miner,
+                transactions,
+                0..1,
+                0,
+            )
+            .unwrap()
+        };
+        blockchain.append_block(mine(&blockchain, vec![])).unwrap();
+
+        let mut mempool = MemPool::new(&blockchain);
+        let mut add = |transaction: Transaction| {
+            let transaction = BlockTransaction::new(blockchain.last_hash().clone(), transaction);
+            mempool.add_transaction(transaction.clone()).unwrap();
+            transaction
+        };
+        let to_alice =
+            add(Transaction::new(miner, alice, Amount::from_coins(600))
+                .with_fee(Amount::from_coins(1)));
+        let alice_to_bob =
+            add(Transaction::new(alice, bob, Amount::from_coins(500))
+                .with_fee(Amount::from_coins(50)));
+        let to_bob =
+            add(Transaction::new(miner, bob, Amount::from_coins(100))
+                .with_fee(Amount::from_coins(20)));
+
+        // Alice can only pay after receiving her coins, despite the higher fee.
+        let selected = mempool.select_transactions(&blockchain);
+        assert_eq!(selected, vec![to_bob, to_alice, alice_to_bob]);
+
+        blockchain
+            .append_block(mine(&blockchain, selected))
+            .unwrap();
+        assert_eq!(blockchain.balance_of(alice), Amount::from_coins(50));
+        assert_eq!(
+            blockchain.balance_of(miner),
+            Amount::from_coins(2 * COINS_PER_MINED_BLOCK.whole_coins() - 721 + 71)
+        );
+    }
+
+    #[test]
+    fn build_custom_block_templates() {
+        // Keep the admission order of the transactions and pay the reward to another address.
+        struct FirstComeFirstServed {
+            beneficiary: Address,
+        }
+        impl BlockTemplateBuilder for FirstComeFirstServed {
+            fn build(&self, blockchain: &BlockChain, mempool: &MemPool, _: Address) -> Block {
+                let transactions = mempool.transactions().clone();
+                Block::new(
+                    transactions,
+                    blockchain.last_hash().clone(),
+                    self.beneficiary,
+                    0,
+                )
+            }
+        }
+
+        let miner = Address::new(1);
+        let alice = Address::new(2);
+        let beneficiary = Address::new(3);
+        let mut blockchain = BlockChain::with_difficulty(0);
+        let block = DefaultTemplateBuilder.build(&blockchain, &MemPool::new(&blockchain), miner);
+        blockchain.append_block(block).unwrap();
+
+        let mut mempool = MemPool::new(&blockchain);
+        let mut add = |transaction: Transaction| {
+            let transaction = BlockTransaction::new(blockchain.last_hash().clone(), transaction);
+            mempool.add_transaction(transaction.clone()).unwrap();
+            transaction
+        };
+        let low_fee =
+            add(Transaction::new(miner, alice, Amount::from_coins(10))
+                .with_fee(Amount::from_coins(1)));
+        let high_fee =
+            add(Transaction::new(miner, alice, Amount::from_coins(10))
+                .with_fee(Amount::from_coins(5)));
+
+        let builder = FirstComeFirstServed { beneficiary };
+        let block = builder.build(&blockchain, &mempool, miner);
+        assert_eq!(block.transactions, vec![low_fee, high_fee]);
+        blockchain.append_block(block).unwrap();
+        assert_eq!(
+            blockchain.balance_of(beneficiary),
+            COINS_PER_MINED_BLOCK
+                .checked_add(Amount::from_coins(6))
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn select_transactions_by_fee_rate() {
+        let miner = Address::new(1);
+        let receiver = Address::new(2);
+        let mut blockchain = BlockChain::with_difficulty(0);
+        blockchain.set_rules(TransactionRules {
+            max_block_weight: 4 * TRANSACTION_BASE_WEIGHT,
+            ..TransactionRules::DEFAULT
+        });
+        let mine = |blockchain: &BlockChain, transactions| {
+            attempt_mining_block_with_difficulty(
+                blockchain.last_hash().clone(),
+                miner,
+                transactions,
+                0..1,
+                0,
+            )
+            .unwrap()
+        };
+        blockchain.append_block(mine(&blockchain, vec![])).unwrap();
+
+        let mut mempool = MemPool::new(&blockchain);
+        let prefix_hash = blockchain.last_hash().clone();
+        let transfer = |fee, memo_bytes| {
+            let memo = Memo::try_from("x".repeat(memo_bytes)).unwrap();
+            let transaction = Transaction::new(miner, receiver, Amount::from_coins(1))
+                .with_fee(Amount::from_coins(fee))
+                .with_memo(memo);
+            BlockTransaction::new(prefix_hash.clone(), transaction)
+        };
+        // Each byte of memo weighs 4, so 50 bytes weigh twice a transaction without a memo.
+        let heavy = transfer(10, 50);
+        let light = [transfer(8, 0), transfer(5, 0), transfer(1, 0)];
+        for transaction in [&heavy, &light[0], &light[1], &light[2]] {
+            mempool.add_transaction(transaction.clone()).unwrap();
+        }
+        assert_eq!(
+            mempool.add_transaction(transfer(100, 100)),
+            Err(TxError::TooHeavy {
+                weight: 500,
+                max_block_weight: 400
+            })
+        );
+
+        // The heavy transaction pays a higher fee, but a lower fee per unit of weight, and it does
+        // not fit after the better ones.
+        let selected = mempool.select_transactions(&blockchain);
+        assert_eq!(selected, light.to_vec());
+
+        // A block heavier than the limit is invalid.
+        let too_heavy = vec![heavy, light[0].clone(), light[1].clone()];
+        assert!(blockchain
+            .append_block(mine(&blockchain, too_heavy))
+            .is_err());
+        blockchain
+            .append_block(mine(&blockchain, selected))
+            .unwrap();
+    }
+
+    #[test]
+    fn admit_future_transactions() {
+        let miner = Address::new(1);
+        let receiver = Address::new(2);
+        let mut blockchain = BlockChain::with_difficulty(0);
+        let mut mempool = MemPool::new(&blockchain);
+        let block = attempt_mining_block_with_difficulty(
+            blockchain.last_hash().clone(),
+            miner,
+            vec![],
+            0..1,
+            0,
+        )
+        .unwrap();
+
+        // A transaction on top of a block that has not been received yet is held.
+        let transaction = BlockTransaction::new(
+            block.hash(),
+            Transaction::new(miner, receiver, Amount::from_coins(10)),
+        );
+        assert!(mempool.add_transaction(transaction.clone()).is_err());
+        assert!(mempool.add_future_transaction(transaction.clone()).is_ok());
+        assert!(mempool.add_future_transaction(transaction.clone()).is_err());
+        assert_eq!((mempool.len(), mempool.future_len()), (0, 1));
+
+        // Each sender can only have a limited number of held transactions.
+        let unknown_block = Block::new(vec![], block.hash(), miner, 0);
+        let on_unknown_block = |coins| {
+            BlockTransaction::new(
+                unknown_block.hash(),
+                Transaction::new(miner, receiver, Amount::from_coins(coins)),
+            )
+        };
+        for coins in 1..MEMPOOL_MAX_FUTURE_TRANSACTIONS_PER_SENDER as u64 {
+            assert!(mempool
+                .add_future_transaction(on_unknown_block(coins))
+                .is_ok());
+        }
+        assert!(mempool.add_future_transaction(on_unknown_block(0)).is_err());
+
+        // A held transaction on top of a block that becomes an ancestor of the last one is dropped.
+        let stale = BlockTransaction::new(
+            blockchain.last_hash().clone(),
+            Transaction::new(receiver, miner, Amount::from_coins(1)),
+        );
+        assert!(mempool.add_future_transaction(stale).is_ok());
+
+        // The held transaction is admitted once the blockchain reaches its prefix.
+        blockchain.append_block(block).unwrap();
+        mempool.reset(&blockchain);
+        assert_eq!(mempool.transactions(), &vec![transaction]);
+        assert_eq!(
+            mempool.future_len(),
+            MEMPOOL_MAX_FUTURE_TRANSACTIONS_PER_SENDER - 1
+        );
+    }
+
+    #[test]
+    fn limit_and_expire_future_transactions() {
+        let miner = Address::new(1);
+        let mut blockchain = BlockChain::with_difficulty(0);
+        let mut mempool = MemPool::new(&blockchain);
+        let unknown_block = Block::new(vec![], blockchain.last_hash().clone(), miner, 1);
+        let on_unknown_block = |sender| {
+            BlockTransaction::new(
+                unknown_block.hash(),
+                Transaction::new(sender, miner, Amount::from_coins(1)),
+            )
+        };
+
+        // A transaction rejected because too many are held leaves no trace of its sender.
+        let senders = MEMPOOL_MAX_FUTURE_TRANSACTIONS as u64;
+        for sender in 2..senders + 2 {
+            assert!(mempool
+                .add_future_transaction(on_unknown_block(Address::new(sender)))
+                .is_ok());
+        }
+        let late_sender = Address::new(senders + 2);
+        assert_eq!(
+            mempool.add_future_transaction(on_unknown_block(late_sender)),
+            Err(TxError::TooManyHeld)
+        );
+        assert!(!mempool.future.contains_key(&late_sender));
+        assert_eq!(mempool.future.len(), MEMPOOL_MAX_FUTURE_TRANSACTIONS);
+
+        // The held transactions are dropped once enough blocks are appended without their block.
+        for _ in 0..MEMPOOL_FUTURE_TRANSACTION_EXPIRY_BLOCKS {
+            assert_eq!(mempool.future_len(), MEMPOOL_MAX_FUTURE_TRANSACTIONS);
+            let block = attempt_mining_block_with_difficulty(
+                blockchain.last_hash().clone(),
+                miner,
+                vec![],
+                0..1,
+                0,
+            )
+            .unwrap();
+            blockchain.append_block(block).unwrap();
+            mempool.reset(&blockchain);
+        }
+        assert_eq!(mempool.future_len(), 0);
+        assert!(mempool
+            .add_future_transaction(on_unknown_block(late_sender))
+            .is_ok());
+    }
+
+    #[test]
+    fn reject_identical_transfers_on_the_same_block() {
+        let (miner, alice) = (Address::new(1), Address::new(2));
+        let mut blockchain = BlockChain::with_difficulty(0);
+        let block = attempt_mining_block_with_difficulty(
+            blockchain.last_hash().clone(),
+            miner,
+            vec![],
+            0..1,
+            0,
+        )
+        .unwrap();
+        blockchain.append_block(block).unwrap();
+        let mut mempool = MemPool::new(&blockchain);
+        let transfer = Transaction::new(miner, alice, Amount::from_coins(5));
+        let on_tip = |transfer: &Transaction| {
+            BlockTransaction::new(blockchain.last_hash().clone(), transfer.clone())
+        };
+
+        // The same transfer on top of the same block is the same transaction.
+        assert_eq!(on_tip(&transfer).id, on_tip(&transfer).id);
+        mempool.add_transaction(on_tip(&transfer)).unwrap();
+        assert_eq!(
+            mempool.add_transaction(on_tip(&transfer)),
+            Err(TxError::Duplicate)
+        );
+
+        // A different memo makes it another transaction, as does another block.
+        let memo = Memo::try_from("second".to_string()).unwrap();
+        let again = transfer.clone().with_memo(memo);
+        mempool.add_transaction(on_tip(&again)).unwrap();
+        let on_genesis = BlockTransaction::new(Block::genesis().hash(), transfer.clone());
+        assert_ne!(on_genesis.id, on_tip(&transfer).id);
+        assert_eq!(mempool.len(), 2);
     }
 }
@@ -1,100 +1,1089 @@
+use crate::capture;
+use crate::encoding::Encode;
 use crate::prelude::*;
-use tokio::sync::OnceCell;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use serde::{Serialize, Serializer};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
 
-/// The network interface that a node uses to communicate with other nodes.
-pub trait NetworkInterface {
-    /// Broadcast a new mined block to all other nodes.
-    fn broadcast_block(
-        &self,
-        block: &Block,
+mod tcp;
+
+pub use tcp::{TcpNetwork, TCP_NETWORK_BACKEND};
+
+/// A future returned by a network interface.
+pub type NetworkFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// The network interface that carries the messages between the nodes, on behalf of any source.
+/// Nodes send their messages through their own `NetworkHandle` instead. Downstream crates can
+/// implement their own backend and register it with `register_network_backend`.
+pub trait NetworkInterface: Send + Sync {
+    /// Broadcast a new mined block to all other nodes, with the hash of the protocol parameters of
+    /// the source.
+    fn broadcast_block<'a>(
+        &'a self,
+        block: &'a Block,
         blockchain_length: usize,
+        params_hash: &'a BlockHash,
         source: Address,
-    ) -> impl std::future::Future<Output = ()> + Send;
+    ) -> NetworkFuture<'a, ()>;
 
-    /// Broadcast a new pending transaction to all nodes.
-    fn broadcast_transaction(
-        &self,
-        transaction: &BlockTransaction,
+    /// Broadcast a new pending transaction to all nodes, returning the number of peers it has been
+    /// sent to. Each peer sends a receipt to the source, telling whether it accepted the
+    /// transaction.
+    fn broadcast_transaction<'a>(
+        &'a self,
+        transaction: &'a BlockTransaction,
         source: Address,
-    ) -> impl std::future::Future<Output = ()> + Send;
+    ) -> NetworkFuture<'a, usize>;
 
-    /// Query a block from a specific node.
-    fn query_block(
+    /// Announce the last block of the local blockchain of `source` to the other nodes, with the
+    /// length and the cumulative work of the blockchain.
+    fn broadcast_heartbeat<'a>(
+        &'a self,
+        blockchain_length: usize,
+        last_block_hash: &'a BlockHash,
+        cumulative_work: u128,
+        source: Address,
+    ) -> NetworkFuture<'a, ()>;
+
+    /// Tell the node that broadcast a transaction whether `source` accepted it.
+    fn send_transaction_receipt<'a>(
+        &'a self,
+        transaction_id: TransactionId,
+        accepted: bool,
+        source: Address,
+        destination: Address,
+    ) -> NetworkFuture<'a, ()>;
+
+    /// Send a message to a specific node, e.g. to answer its request.
+    fn send_message<'a>(
+        &'a self,
+        message: Message,
+        source: Address,
+        destination: Address,
+    ) -> NetworkFuture<'a, ()>;
+
+    /// Query a block from a specific node, on behalf of `source`.
+    fn query_block<'a>(
+        &'a self,
+        block_hash: &'a BlockHash,
+        source: Address,
+        destination: Address,
+    ) -> NetworkFuture<'a, Option<Block>>;
+
+    /// Query the last block and the length of the local blockchain of a specific node, on behalf
+    /// of `source`.
+    fn query_tip<'a>(
+        &'a self,
+        source: Address,
+        destination: Address,
+    ) -> NetworkFuture<'a, Option<(Block, usize)>>;
+
+    /// The traffic of the network since the start of the simulation.
+    fn stats(&self) -> NetworkStats;
+}
+
+/// Creates a network backend, for a new world. The network delivers the messages to the nodes of
+/// that world.
+pub type NetworkBackend = fn(&'static World) -> Arc<dyn NetworkInterface>;
+
+/// The name of the default network backend, which simulates the network in the process.
+pub const DEFAULT_NETWORK_BACKEND: &str = "simulated";
+
+/// The network backends that the worlds can use, by name.
+static BACKENDS: Mutex<BTreeMap<String, NetworkBackend>> = Mutex::new(BTreeMap::new());
+
+/// Register a network backend, which the settings can then select by name with
+/// `network_backend`. A backend with the same name is replaced.
+pub fn register_network_backend(name: &str, backend: NetworkBackend) {
+    BACKENDS.lock().unwrap().insert(name.to_string(), backend);
+}
+
+/// Create the network of a new world with the backend called `name`, if there is one.
+pub fn create_network(name: &str, world: &'static World) -> Option<Arc<dyn NetworkInterface>> {
+    if name == DEFAULT_NETWORK_BACKEND {
+        return Some(Arc::new(FakeNetwork::new(world)));
+    }
+    if name == TCP_NETWORK_BACKEND {
+        return Some(TcpNetwork::start(world));
+    }
+    let backend = *BACKENDS.lock().unwrap().get(name)?;
+    Some(backend(world))
+}
+
+/// Whether there is a network backend called `name`.
+pub fn network_backend_exists(name: &str) -> bool {
+    name == DEFAULT_NETWORK_BACKEND
+        || name == TCP_NETWORK_BACKEND
+        || BACKENDS.lock().unwrap().contains_key(name)
+}
+
+/// The traffic of the network since the start of the simulation.
+pub async fn network_stats() -> NetworkStats {
+    world().await.network().stats()
+}
+
+/// The network interface of a single node, which sends all the messages on behalf of the node, so
+/// that nodes cannot spoof the source of their messages by mistake. Sending on behalf of another
+/// node requires the explicit `NetworkHandle::impersonate`, e.g. in an attack experiment.
+#[derive(Clone, Copy, Debug)]
+pub struct NetworkHandle {
+    address: Address,
+    /// The world through whose network the messages are sent, or the world of the simulation if
+    /// the node has not been started in one.
+    world: Option<&'static World>,
+}
+
+impl NetworkHandle {
+    /// The network interface of a node.
+    pub fn of(node: &Node) -> Self {
+        NetworkHandle {
+            address: node.address(),
+            world: node.world(),
+        }
+    }
+
+    /// A network interface that sends messages on behalf of any address, including the one of
+    /// another node, through the network of the world of the simulation. Only meant for attacks.
+    pub fn impersonate(address: Address) -> Self {
+        warn!("Impersonating node {address} on the network");
+        NetworkHandle {
+            address,
+            world: None,
+        }
+    }
+
+    /// The network of the world of the node.
+    async fn network(&self) -> Arc<dyn NetworkInterface> {
+        match self.world {
+            Some(world) => world.network(),
+            None => world().await.network(),
+        }
+    }
+
+    /// The address on behalf of which the messages are sent.
+    pub fn address(&self) -> Address {
+        self.address
+    }
+
+    /// The world through whose network the messages are sent, if not the world of the simulation.
+    pub fn world(&self) -> Option<&'static World> {
+        self.world
+    }
+
+    /// Broadcast a new mined block to all other nodes, with the hash of the protocol parameters of
+    /// the node.
+    pub async fn broadcast_block(
         &self,
-        block_hash: &BlockHash,
+        block: &Block,
+        blockchain_length: usize,
+        params_hash: &BlockHash,
+    ) {
+        self.network()
+            .await
+            .broadcast_block(block, blockchain_length, params_hash, self.address)
+            .await;
+    }
+
+    /// Broadcast a new pending transaction to all nodes, returning the number of peers it has been
+    /// sent to.
+    pub async fn broadcast_transaction(&self, transaction: &BlockTransaction) -> usize {
+        self.network()
+            .await
+            .broadcast_transaction(transaction, self.address)
+            .await
+    }
+
+    /// Announce the last block of the local blockchain of the node to the other nodes.
+    pub async fn broadcast_heartbeat(&self, blockchain: &BlockChain) {
+        self.network()
+            .await
+            .broadcast_heartbeat(
+                blockchain.len(),
+                blockchain.last_hash(),
+                blockchain.cumulative_work(),
+                self.address,
+            )
+            .await;
+    }
+
+    /// Tell the node that broadcast a transaction whether this node accepted it.
+    pub async fn send_transaction_receipt(
+        &self,
+        transaction_id: TransactionId,
+        accepted: bool,
         destination: Address,
-    ) -> impl std::future::Future<Output = Option<Block>> + Send;
+    ) {
+        self.network()
+            .await
+            .send_transaction_receipt(transaction_id, accepted, self.address, destination)
+            .await;
+    }
+
+    /// Send a message to a specific node. Messages that name another source are not sent.
+    pub async fn send_message(&self, message: Message, destination: Address) {
+        if message.source() != self.address {
+            error!(
+                "Node {}: Refusing to send a message of type {} on behalf of node {}",
+                self.address,
+                message.kind(),
+                message.source()
+            );
+            return;
+        }
+        self.network()
+            .await
+            .send_message(message, self.address, destination)
+            .await;
+    }
+
+    /// Query a block from a specific node.
+    pub async fn query_block(&self, block_hash: &BlockHash, destination: Address) -> Option<Block> {
+        self.network()
+            .await
+            .query_block(block_hash, self.address, destination)
+            .await
+    }
+
+    /// Query the last block and the length of the local blockchain of a specific node.
+    pub async fn query_tip(&self, destination: Address) -> Option<(Block, usize)> {
+        self.network()
+            .await
+            .query_tip(self.address, destination)
+            .await
+    }
+}
+
+/// The peers among `addresses` to which `source` sends a block or a transaction: a random sample
+/// of `gossip_fanout` nodes in gossip mode, all the other nodes otherwise.
+fn gossip_peers(mut addresses: Vec<Address>, source: Address) -> Vec<Address> {
+    addresses.retain(|address| *address != source);
+    let fanout = config().gossip_fanout;
+    if fanout > 0 && fanout < addresses.len() {
+        addresses = addresses
+            .choose_multiple(&mut rand::thread_rng(), fanout)
+            .copied()
+            .collect();
+    }
+    addresses
 }
 
-/// Get the singleton of the network interface.
-pub async fn network() -> &'static impl NetworkInterface {
-    NETWORK.get_or_init(|| async { FakeNetwork::new() }).await
+/// The message that announces a new block to the peers: its hash in inventory mode, the block
+/// itself otherwise.
+fn block_announcement(
+    block: &Block,
+    blockchain_length: usize,
+    params_hash: &BlockHash,
+    source: Address,
+) -> Message {
+    if config().inventory {
+        Message::Inventory {
+            items: vec![Item::Block(block.hash())],
+            source,
+        }
+    } else {
+        Message::NewBlock {
+            block: block.clone(),
+            blockchain_length,
+            source,
+            params_hash: Some(params_hash.clone()),
+        }
+    }
 }
 
-static NETWORK: OnceCell<FakeNetwork> = OnceCell::const_new();
+/// The message that announces a new pending transaction to the peers: its identifier in inventory
+/// mode, the transaction itself otherwise.
+fn transaction_announcement(transaction: &BlockTransaction, source: Address) -> Message {
+    if config().inventory {
+        Message::Inventory {
+            items: vec![Item::Transaction(transaction.id)],
+            source,
+        }
+    } else {
+        Message::Transaction {
+            transaction: transaction.clone(),
+            source,
+        }
+    }
+}
 
-/// A fake network that simulates the communication between nodes.
-struct FakeNetwork;
+/// A fake network that simulates the communication between the nodes of a world.
+struct FakeNetwork {
+    world: &'static World,
+    links: Mutex<Links>,
+    stats: Mutex<NetworkStats>,
+    seen: Mutex<Seen>,
+}
 
 impl FakeNetwork {
-    pub fn new() -> Self {
-        FakeNetwork
+    pub fn new(world: &'static World) -> Self {
+        FakeNetwork {
+            world,
+            links: Mutex::default(),
+            stats: Mutex::default(),
+            seen: Mutex::default(),
+        }
+    }
+
+    /// The peers to which `source` sends a block or a transaction.
+    async fn peers(&self, source: Address) -> Vec<Address> {
+        gossip_peers(self.world.get_node_addresses().await, source)
+    }
+
+    /// Transmit `size` bytes from `source` to `destination`, returning when they arrive. If the
+    /// bandwidth of the link is limited, the bytes are transmitted after the ones previously sent
+    /// on the same link. They arrive once they have been transmitted and they have traveled for
+    /// the latency of the link, which depends on the regions of the two nodes.
+    async fn transmit(
+        &self,
+        source: Address,
+        destination: Address,
+        size: usize,
+        now: Instant,
+    ) -> Instant {
+        let source_region = self.world.get_region(source).await;
+        let destination_region = self.world.get_region(destination).await;
+        let link = config().link(source_region.as_deref(), destination_region.as_deref());
+        let transmitted = match link.bandwidth() {
+            Some(bandwidth) => {
+                self.links
+                    .lock()
+                    .unwrap()
+                    .schedule(source, destination, size, bandwidth, now)
+            }
+            None => now,
+        };
+        transmitted + link.latency()
+    }
+
+    /// Send the block that answers a query from `source` to `destination`, waiting for it to
+    /// arrive.
+    async fn reply(
+        &self,
+        kind: &'static str,
+        block: &Block,
+        source: Address,
+        destination: Address,
+    ) {
+        capture::record(|| capture::Record::Reply {
+            timestamp: now_millis(),
+            source,
+            block: block.clone(),
+        });
+        let size = block.encode().len();
+        let now = Instant::now();
+        let delivery = self.transmit(source, destination, size, now).await;
+        self.stats
+            .lock()
+            .unwrap()
+            .record(kind, source, destination, size, delivery - now, false);
+        tokio::time::sleep_until(delivery).await;
+    }
+
+    fn record_drop(&self, kind: &'static str, source: Address, destination: Address) {
+        self.stats
+            .lock()
+            .unwrap()
+            .record_drop(kind, source, destination);
+    }
+
+    /// Send a message from `source` to the inbox of `destination`, which receives it once it has
+    /// been transmitted on their link.
+    async fn send(
+        &self,
+        inbox: Arc<Inbox>,
+        source: Address,
+        destination: Address,
+        message: Message,
+    ) {
+        let size = message.size();
+        let now = Instant::now();
+        let mut delivery = self.transmit(source, destination, size, now).await;
+
+        // Count the deliveries of blocks and transactions that the destination already received.
+        let duplicate = !self.seen.lock().unwrap().insert(&message, destination);
+        self.stats.lock().unwrap().record_items(&message);
+
+        // Inject the faults of the network.
+        let config = config();
+        let mut rng = rand::thread_rng();
+        if rng.gen_bool(config.reorder_probability) {
+            delivery += NETWORK_FAULT_MAX_DELAY.mul_f64(rng.gen());
+        }
+        if rng.gen_bool(config.duplicate_probability) {
+            let duplicate_delivery = delivery + NETWORK_FAULT_MAX_DELAY.mul_f64(rng.gen());
+            let duplicate = !self.seen.lock().unwrap().insert(&message, destination);
+            self.stats.lock().unwrap().record(
+                message.kind(),
+                source,
+                destination,
+                size,
+                duplicate_delivery - now,
+                duplicate,
+            );
+            deliver_at(
+                inbox.clone(),
+                destination,
+                message.clone(),
+                duplicate_delivery,
+            );
+        }
+
+        self.stats.lock().unwrap().record(
+            message.kind(),
+            source,
+            destination,
+            size,
+            delivery - now,
+            duplicate,
+        );
+        deliver_at(inbox, destination, message, delivery);
     }
 }
 
 impl NetworkInterface for FakeNetwork {
-    async fn broadcast_block(&self, block: &Block, blockchain_length: usize, source: Address) {
-        debug!("Node {source} broadcasts block {block}");
-        let mut addresses = world().await.get_node_addresses().await;
-        for address in addresses.drain(..) {
-            if address == source {
-                continue;
+    fn broadcast_block<'a>(
+        &'a self,
+        block: &'a Block,
+        blockchain_length: usize,
+        params_hash: &'a BlockHash,
+        source: Address,
+    ) -> NetworkFuture<'a, ()> {
+        Box::pin(async move {
+            debug!("Node {source} broadcasts block {block}");
+            let message = block_announcement(block, blockchain_length, params_hash, source);
+            for address in self.peers(source).await {
+                let Some(inbox) = self.world.get_inbox(address).await else {
+                    warn!("Cannot find node {address} to broadcast block {block}");
+                    self.record_drop(message.kind(), source, address);
+                    continue;
+                };
+                self.send(inbox, source, address, message.clone()).await;
             }
-            let cloned_block = block.clone();
-            tokio::spawn(async move {
-                let Some(node) = world().await.get_node(address).await else {
-                    warn!("Cannot find node {address} to broadcast block {cloned_block}");
-                    return;
+        })
+    }
+
+    fn broadcast_transaction<'a>(
+        &'a self,
+        transaction: &'a BlockTransaction,
+        source: Address,
+    ) -> NetworkFuture<'a, usize> {
+        Box::pin(async move {
+            debug!("Node {source} broadcasts transaction {transaction}");
+            let message = transaction_announcement(transaction, source);
+            let mut peers = 0;
+            for address in self.peers(source).await {
+                let Some(inbox) = self.world.get_inbox(address).await else {
+                    warn!("Cannot find node {address} to broadcast transaction {transaction}");
+                    self.record_drop(message.kind(), source, address);
+                    continue;
                 };
-                node.write()
-                    .await
-                    .receive_new_block(cloned_block, blockchain_length, source)
-                    .await;
-            });
-        }
+                self.send(inbox, source, address, message.clone()).await;
+                peers += 1;
+            }
+            peers
+        })
     }
 
-    async fn broadcast_transaction(&self, transaction: &BlockTransaction, source: Address) {
-        debug!("Node {source} broadcasts transaction {transaction}");
-        let mut addresses = world().await.get_node_addresses().await;
-        for address in addresses.drain(..) {
-            if address == source {
-                continue;
+    fn broadcast_heartbeat<'a>(
+        &'a self,
+        blockchain_length: usize,
+        last_block_hash: &'a BlockHash,
+        cumulative_work: u128,
+        source: Address,
+    ) -> NetworkFuture<'a, ()> {
+        Box::pin(async move {
+            debug!("Node {source} announces its tip {last_block_hash}");
+            let message = Message::Heartbeat {
+                blockchain_length,
+                last_block_hash: last_block_hash.clone(),
+                cumulative_work,
+                source,
+            };
+            for address in self.peers(source).await {
+                let Some(inbox) = self.world.get_inbox(address).await else {
+                    debug!("Cannot find node {address} to announce the tip of {source}");
+                    self.record_drop(message.kind(), source, address);
+                    continue;
+                };
+                self.send(inbox, source, address, message.clone()).await;
             }
-            let cloned_transaction = transaction.clone();
-            tokio::spawn(async move {
-                let Some(node) = world().await.get_node(address).await else {
-                    warn!(
-                        "Cannot find node {address} to broadcast transaction {cloned_transaction}"
-                    );
-                    return;
+        })
+    }
+
+    fn send_message<'a>(
+        &'a self,
+        message: Message,
+        source: Address,
+        destination: Address,
+    ) -> NetworkFuture<'a, ()> {
+        Box::pin(async move {
+            let Some(inbox) = self.world.get_inbox(destination).await else {
+                debug!(
+                    "Cannot find node {destination} to send a message of type {}",
+                    message.kind()
+                );
+                self.record_drop(message.kind(), source, destination);
+                return;
+            };
+            self.send(inbox, source, destination, message).await;
+        })
+    }
+
+    fn send_transaction_receipt<'a>(
+        &'a self,
+        transaction_id: TransactionId,
+        accepted: bool,
+        source: Address,
+        destination: Address,
+    ) -> NetworkFuture<'a, ()> {
+        Box::pin(async move {
+            let Some(inbox) = self.world.get_inbox(destination).await else {
+                debug!("Cannot find node {destination} to deliver the receipt of {transaction_id}");
+                self.record_drop("transaction_receipt", source, destination);
+                return;
+            };
+            self.send(
+                inbox,
+                source,
+                destination,
+                Message::TransactionReceipt {
+                    transaction_id,
+                    peer: source,
+                    accepted,
+                },
+            )
+            .await;
+        })
+    }
+
+    fn query_block<'a>(
+        &'a self,
+        block_hash: &'a BlockHash,
+        source: Address,
+        destination: Address,
+    ) -> NetworkFuture<'a, Option<Block>> {
+        Box::pin(async move {
+            debug!("Node {source} queries block {block_hash} from {destination}");
+            let Some(node) = self.world.get_node(destination).await else {
+                if let Some(block) = capture::replayed_block(block_hash) {
+                    return Some(block);
+                }
+                warn!("Cannot find node {destination} to query block {block_hash}");
+                self.record_drop("query_block", destination, source);
+                return None;
+            };
+            let Ok(readable_node) = tokio::time::timeout(NETWORK_QUERY_TIMEOUT, node.read()).await
+            else {
+                warn!(
+                    "Node {destination} is busy and cannot answer the query of block {block_hash}"
+                );
+                self.record_drop("query_block", destination, source);
+                return None;
+            };
+            if readable_node.is_banned(source) {
+                debug!("Node {destination} ignores the query of block {block_hash} from {source}");
+                self.record_drop("query_block", destination, source);
+                return None;
+            }
+            let Some(block) = readable_node.get_block(block_hash) else {
+                warn!("Node {destination} does not have block {block_hash}");
+                self.record_drop("query_block", destination, source);
+                return None;
+            };
+            drop(readable_node);
+            self.reply("query_block", &block, destination, source).await;
+            Some(block)
+        })
+    }
+
+    fn query_tip<'a>(
+        &'a self,
+        source: Address,
+        destination: Address,
+    ) -> NetworkFuture<'a, Option<(Block, usize)>> {
+        Box::pin(async move {
+            debug!("Node {source} queries the tip of {destination}");
+            let Some(node) = self.world.get_node(destination).await else {
+                warn!("Cannot find node {destination} to query its tip");
+                self.record_drop("query_tip", destination, source);
+                return None;
+            };
+            let (block, length) = {
+                let Ok(readable_node) =
+                    tokio::time::timeout(NETWORK_QUERY_TIMEOUT, node.read()).await
+                else {
+                    warn!("Node {destination} is busy and cannot answer the query of its tip");
+                    self.record_drop("query_tip", destination, source);
+                    return None;
                 };
-                node.write().await.add_transaction(cloned_transaction);
-            });
+                if readable_node.is_banned(source) {
+                    debug!("Node {destination} ignores the query of its tip from {source}");
+                    self.record_drop("query_tip", destination, source);
+                    return None;
+                }
+                let blockchain = readable_node.blockchain();
+                (blockchain.last_block().clone(), blockchain.len())
+            };
+            self.reply("query_tip", &block, destination, source).await;
+            Some((block, length))
+        })
+    }
+
+    fn stats(&self) -> NetworkStats {
+        self.stats.lock().unwrap().clone()
+    }
+}
+
+/// The traffic of the network, by type of message and by link. The answers to the queries count
+/// as messages of type `query_block` or `query_tip`, sent by the queried node.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct NetworkStats {
+    /// How many blocks and transactions have been announced in inventories, to each peer.
+    pub announced_items: u64,
+    /// How many of the announced blocks and transactions have been requested by the peers. The
+    /// others did not need to be sent.
+    pub requested_items: u64,
+    pub message_types: BTreeMap<&'static str, Traffic>,
+    /// The traffic of each link, from a source to a destination.
+    #[serde(serialize_with = "serialize_links")]
+    pub links: BTreeMap<(Address, Address), Traffic>,
+}
+
+impl NetworkStats {
+    fn record(
+        &mut self,
+        kind: &'static str,
+        source: Address,
+        destination: Address,
+        size: usize,
+        delay: Duration,
+        duplicate: bool,
+    ) {
+        self.message_types
+            .entry(kind)
+            .or_default()
+            .record(size, delay, duplicate);
+        self.links
+            .entry((source, destination))
+            .or_default()
+            .record(size, delay, duplicate);
+    }
+
+    /// Count the blocks and transactions announced or requested by a message.
+    fn record_items(&mut self, message: &Message) {
+        match message {
+            Message::Inventory { items, .. } => self.announced_items += items.len() as u64,
+            Message::GetData { items, .. } => self.requested_items += items.len() as u64,
+            _ => {}
+        }
+    }
+
+    fn record_drop(&mut self, kind: &'static str, source: Address, destination: Address) {
+        self.message_types.entry(kind).or_default().drops += 1;
+        self.links.entry((source, destination)).or_default().drops += 1;
+    }
+}
+
+/// Serialize the links as a list, because JSON objects only have string keys.
+fn serialize_links<S: Serializer>(
+    links: &BTreeMap<(Address, Address), Traffic>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    #[derive(Serialize)]
+    struct LinkTraffic<'a> {
+        source: Address,
+        destination: Address,
+        #[serde(flatten)]
+        traffic: &'a Traffic,
+    }
+    serializer.collect_seq(
+        links
+            .iter()
+            .map(|(&(source, destination), traffic)| LinkTraffic {
+                source,
+                destination,
+                traffic,
+            }),
+    )
+}
+
+/// The counters of the messages sent on a link or of a certain type.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Traffic {
+    pub messages: u64,
+    pub bytes: u64,
+    /// How many messages could not be delivered, e.g. because their destination does not exist.
+    pub drops: u64,
+    /// The average time between sending and delivering a message, in milliseconds.
+    pub average_delay_millis: f64,
+    /// How many messages carried a block or a transaction that their destination had already
+    /// received.
+    pub duplicates: u64,
+    /// How many times, on average, a node received the same block or transaction again.
+    pub redundancy: f64,
+}
+
+impl Traffic {
+    fn record(&mut self, size: usize, delay: Duration, duplicate: bool) {
+        self.messages += 1;
+        self.bytes += size as u64;
+        let delay_millis = delay.as_secs_f64() * 1000.0;
+        self.average_delay_millis +=
+            (delay_millis - self.average_delay_millis) / self.messages as f64;
+        if duplicate {
+            self.duplicates += 1;
+        }
+        let firsts = self.messages - self.duplicates;
+        if firsts > 0 {
+            self.redundancy = self.duplicates as f64 / firsts as f64;
         }
     }
+}
 
-    async fn query_block(&self, block_hash: &BlockHash, destination: Address) -> Option<Block> {
-        debug!("Querying block {block_hash} from {destination}");
-        let Some(node) = world().await.get_node(destination).await else {
-            warn!("Cannot find node {destination} to query block {block_hash}");
-            return None;
+/// The blocks and the transactions that each node has been sent, among the
+/// `NETWORK_SEEN_CAPACITY` most recent deliveries.
+#[derive(Debug, Default)]
+struct Seen {
+    items: HashSet<(Item, Address)>,
+    order: VecDeque<(Item, Address)>,
+}
+
+impl Seen {
+    /// Record that a message is sent to `destination`, returning whether it is the first time
+    /// that it receives the block or the transaction of the message, if any. The oldest delivery
+    /// is forgotten when the set is full.
+    fn insert(&mut self, message: &Message, destination: Address) -> bool {
+        let Some(item) = message.item() else {
+            return true;
         };
-        let readable_node = node.read().await;
-        let Some(block) = readable_node.get_block(block_hash) else {
-            warn!("Node {destination} does not have block {block_hash}");
-            return None;
+        if !self.items.insert((item.clone(), destination)) {
+            return false;
+        }
+        self.order.push_back((item, destination));
+        if self.order.len() > NETWORK_SEEN_CAPACITY {
+            if let Some(oldest) = self.order.pop_front() {
+                self.items.remove(&oldest);
+            }
+        }
+        true
+    }
+}
+
+/// When each link, from a source to a destination, finishes transmitting the messages sent on it.
+#[derive(Debug, Default)]
+struct Links {
+    busy_until: HashMap<(Address, Address), Instant>,
+}
+
+impl Links {
+    /// Schedule the transmission of `size` bytes on a link, returning when they are delivered.
+    fn schedule(
+        &mut self,
+        source: Address,
+        destination: Address,
+        size: usize,
+        bandwidth: u64,
+        now: Instant,
+    ) -> Instant {
+        let busy_until = self.busy_until.entry((source, destination)).or_insert(now);
+        *busy_until = (*busy_until).max(now) + transmission_time(size, bandwidth);
+        *busy_until
+    }
+}
+
+/// How long it takes to transmit `size` bytes on a link with the given bandwidth.
+fn transmission_time(size: usize, bandwidth: u64) -> Duration {
+    Duration::from_secs_f64(size as f64 / bandwidth as f64)
+}
+
+/// Push a message to the inbox of a node at the given instant, or immediately if it has passed.
+fn deliver_at(inbox: Arc<Inbox>, destination: Address, message: Message, delivery: Instant) {
+    if delivery <= Instant::now() {
+        deliver(&inbox, destination, message);
+        return;
+    }
+    tokio::spawn(async move {
+        tokio::time::sleep_until(delivery).await;
+        deliver(&inbox, destination, message);
+    });
+}
+
+/// Push a message to the inbox of a node, recording it if the network is being captured.
+fn deliver(inbox: &Inbox, destination: Address, message: Message) {
+    capture::record(|| capture::Record::Delivered {
+        timestamp: now_millis(),
+        destination,
+        message: message.clone(),
+    });
+    inbox.push(message);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn queue_messages_on_busy_links() {
+        let (alice, bob, carol) = (Address::new(1), Address::new(2), Address::new(3));
+        let mut links = Links::default();
+        let now = Instant::now();
+        let second = Duration::from_secs(1);
+
+        // A message of 1000 bytes takes 1 second on a link of 1000 bytes/s, and delays the next
+        // messages on the same link, but not on the other links.
+        assert_eq!(links.schedule(alice, bob, 1000, 1000, now), now + second);
+        assert_eq!(
+            links.schedule(alice, bob, 500, 1000, now),
+            now + second * 3 / 2
+        );
+        assert_eq!(
+            links.schedule(alice, carol, 500, 1000, now),
+            now + second / 2
+        );
+        assert_eq!(links.schedule(bob, alice, 500, 1000, now), now + second / 2);
+
+        // An idle link transmits immediately.
+        let later = now + second * 10;
+        assert_eq!(
+            links.schedule(alice, bob, 1000, 1000, later),
+            later + second
+        );
+    }
+
+    #[test]
+    fn count_traffic_by_type_and_link() {
+        let (alice, bob) = (Address::new(1), Address::new(2));
+        let mut stats = NetworkStats::default();
+        stats.record(
+            "new_block",
+            alice,
+            bob,
+            100,
+            Duration::from_millis(10),
+            false,
+        );
+        stats.record(
+            "new_block",
+            alice,
+            bob,
+            300,
+            Duration::from_millis(30),
+            false,
+        );
+        stats.record("transaction", bob, alice, 50, Duration::ZERO, false);
+        stats.record_drop("transaction", alice, bob);
+
+        let blocks = &stats.message_types["new_block"];
+        assert_eq!((blocks.messages, blocks.bytes, blocks.drops), (2, 400, 0));
+        assert_eq!(blocks.average_delay_millis, 20.0);
+        let link = &stats.links[&(alice, bob)];
+        assert_eq!((link.messages, link.bytes, link.drops), (2, 400, 1));
+
+        let json = serde_json::to_value(&stats).unwrap();
+        assert_eq!(json["links"][0]["source"], alice.to_string());
+        assert_eq!(json["links"][0]["drops"], 1);
+        assert_eq!(json["message_types"]["transaction"]["drops"], 1);
+
+        // A block sent again to the same node is redundant.
+        let block = Block::genesis();
+        let new_block = Message::NewBlock {
+            block,
+            blockchain_length: 1,
+            source: alice,
+            params_hash: None,
+        };
+        let mut seen = Seen::default();
+        assert!(seen.insert(&new_block, bob));
+        assert!(seen.insert(&new_block, alice));
+        assert!(!seen.insert(&new_block, bob));
+        // The oldest deliveries are forgotten once the set is full.
+        for n in 0..NETWORK_SEEN_CAPACITY as u64 {
+            let transaction = Message::Transaction {
+                transaction: BlockTransaction::new(
+                    Block::genesis().hash(),
+                    Transaction::new(alice, bob, Amount::from_units(n + 1)),
+                ),
+                source: alice,
+            };
+            assert!(seen.insert(&transaction, bob));
+        }
+        assert_eq!(seen.items.len(), NETWORK_SEEN_CAPACITY);
+        assert!(seen.insert(&new_block, bob));
+        stats.record("new_block", bob, alice, 100, Duration::ZERO, true);
+        let blocks = &stats.message_types["new_block"];
+        assert_eq!((blocks.duplicates, blocks.redundancy), (1, 0.5));
+    }
+
+    #[tokio::test]
+    async fn select_registered_backends() {
+        let config = Config {
+            network_backend: Some("custom".to_string()),
+            ..Config::default()
+        };
+        let world = world().await;
+        assert!(create_network("custom", world).is_none());
+        assert_eq!(config.validate(), Err(()));
+
+        register_network_backend("custom", |world| Arc::new(FakeNetwork::new(world)));
+        assert!(create_network("custom", world).is_some());
+        assert_eq!(config.validate(), Ok(()));
+        assert!(create_network(DEFAULT_NETWORK_BACKEND, world).is_some());
+    }
+
+    /// Add to the world a node that does not mine.
+    async fn add_idle_node() -> Address {
+        world()
+            .await
+            .add_node_with_config(NodeConfig {
+                hashrate: 0.0,
+                ..NodeConfig::default()
+            })
+            .await
+            .unwrap()
+    }
+
+    /// A node cannot send a message on behalf of another node through its own handle.
+    #[tokio::test]
+    async fn refuse_spoofed_sources() {
+        let (alice, bob) = (add_idle_node().await, add_idle_node().await);
+        let node = world().await.get_node(alice).await.unwrap();
+        let network = node.read().await.network();
+        let link = || network_stats_link(alice, bob);
+
+        network
+            .send_message(Message::Departure { source: bob }, bob)
+            .await;
+        assert_eq!(link().await, 0);
+        network
+            .send_message(Message::Departure { source: alice }, bob)
+            .await;
+        assert_eq!(link().await, 1);
+
+        // Impersonating another node must be explicit
+        NetworkHandle::impersonate(bob)
+            .send_message(Message::Departure { source: bob }, alice)
+            .await;
+        assert_eq!(network_stats_link(bob, alice).await, 1);
+        world().await.delete_node(alice).await;
+        world().await.delete_node(bob).await;
+    }
+
+    /// How many messages were sent from a source to a destination.
+    async fn network_stats_link(source: Address, destination: Address) -> u64 {
+        network_stats()
+            .await
+            .links
+            .get(&(source, destination))
+            .map_or(0, |traffic| traffic.messages)
+    }
+
+    /// A new world whose nodes are paused, so that the tests control what runs and the time of
+    /// the runtime advances as soon as it is idle.
+    async fn new_paused_world() -> &'static World {
+        let world: &'static World = Box::leak(Box::new(World::new()));
+        world.pause().await;
+        world
+    }
+
+    /// Add to a world a node that does not mine.
+    async fn add_idle_node_to(world: &'static World) -> Address {
+        world
+            .add_node_with_config(NodeConfig {
+                hashrate: 0.0,
+                ..NodeConfig::default()
+            })
+            .await
+            .unwrap()
+    }
+
+    /// Two nodes that query each other while holding their own lock, e.g. because each one is
+    /// adopting a blockchain announced by the other, must not wait for each other forever.
+    #[tokio::test(start_paused = true)]
+    async fn crossed_queries_do_not_deadlock() {
+        let world = new_paused_world().await;
+        let (alice, bob) = (add_idle_node_to(world).await, add_idle_node_to(world).await);
+        let genesis_hash = Block::genesis().hash();
+        let both_locked = Arc::new(tokio::sync::Barrier::new(2));
+        let query = |source: Address, destination: Address| {
+            let (genesis_hash, both_locked) = (genesis_hash.clone(), both_locked.clone());
+            tokio::spawn(async move {
+                let node = world.get_node(source).await.unwrap();
+                let _writable_node = node.write().await;
+                both_locked.wait().await;
+                world
+                    .network()
+                    .query_block(&genesis_hash, source, destination)
+                    .await
+            })
         };
-        Some(block)
+        let started_at = tokio::time::Instant::now();
+        let queries = async { tokio::join!(query(alice, bob), query(bob, alice)) };
+        let (to_bob, to_alice) = tokio::time::timeout(NETWORK_QUERY_TIMEOUT * 5, queries)
+            .await
+            .expect("The nodes are deadlocked");
+        // Both queries time out at once: the first one to give up releases the lock of its node,
+        // which lets the other one succeed.
+        assert!(to_bob.unwrap().is_none() != to_alice.unwrap().is_none());
+        assert!(started_at.elapsed() >= NETWORK_QUERY_TIMEOUT);
+
+        // Once the locks are released, the queries succeed again.
+        let block = world.network().query_block(&genesis_hash, alice, bob).await;
+        assert_eq!(block, Some(Block::genesis()));
+    }
+
+    /// Nodes can be added and removed while the others broadcast blocks and transactions. The
+    /// tasks are interleaved at each `yield_now`, and a deadlock fails the test as soon as the
+    /// runtime is idle, because the time is paused.
+    #[tokio::test(start_paused = true)]
+    async fn churn_while_broadcasting() {
+        let world = new_paused_world().await;
+        let source = add_idle_node_to(world).await;
+        let churn = tokio::spawn(async move {
+            for _ in 0..20 {
+                let address = add_idle_node_to(world).await;
+                tokio::task::yield_now().await;
+                world.delete_node(address).await;
+            }
+        });
+        let broadcasts = tokio::spawn(async move {
+            let genesis_hash = Block::genesis().hash();
+            let params_hash = BlockChain::new().params().hash();
+            for _ in 0..50 {
+                let block = attempt_mining_block_with_difficulty(
+                    genesis_hash.clone(),
+                    source,
+                    vec![],
+                    0..1,
+                    0,
+                )
+                .unwrap();
+                world
+                    .network()
+                    .broadcast_block(&block, 2, &params_hash, source)
+                    .await;
+                let transaction = BlockTransaction::new(
+                    genesis_hash.clone(),
+                    Transaction::new(source, Address::new(1), Amount::from_coins(1)),
+                );
+                world
+                    .network()
+                    .broadcast_transaction(&transaction, source)
+                    .await;
+                tokio::task::yield_now().await;
+            }
+        });
+        let all = async { tokio::join!(churn, broadcasts) };
+        let (churn, broadcasts) = tokio::time::timeout(Duration::from_secs(30), all)
+            .await
+            .expect("The nodes are deadlocked");
+        churn.unwrap();
+        broadcasts.unwrap();
+        assert_eq!(world.get_node_addresses().await, vec![source]);
     }
 }
@@ -0,0 +1,1804 @@
+//! A network backend that carries the messages between the nodes of separate smallchain
+//! processes, e.g. on different machines, over TCP.
+//!
+//! Each process accepts connections on `tcp_listen`, if set, and connects to the processes in
+//! `tcp_peers` and to all the addresses of the seeds in `peers`, retrying the connections that fail
+//! or break with an exponential backoff. The two ends of a connection are
+//! symmetric: each one periodically tells the other which nodes it hosts, and routes the messages
+//! and the queries for those nodes through the connection. Messages between the nodes of the same
+//! process are delivered directly. The simulated latency, bandwidth and faults of the links do not
+//! apply, and the traffic of each process only counts the messages sent by its own nodes, without
+//! their delay.
+//!
+//! The processes also periodically tell each other the port on which they accept connections, and
+//! the addresses of the other processes they are connected to, which they then connect to as
+//! well. So a process that only knows a bootnode, from `bootnodes`, joins the whole network: it
+//! connects to the bootnodes while it is not connected to any other process, and to the
+//! processes that they tell it about.
+//!
+//! A connection carries frames: a `u32` little-endian length, followed by that many bytes, which
+//! are the binary encoding of the frame:
+//!
+//! ```text
+//! Frame:
+//!     type:         u8, followed by the fields of the type
+//!
+//!     Hello (0):      version: u8 (= TCP_PROTOCOL_VERSION)
+//!                     nodes: u32 (count), followed by that many u64
+//!     Message (1):    destination: u64, message: Message
+//!     QueryBlock (2): query: u64, source: u64, destination: u64, block_hash: BlockHash
+//!     QueryTip (3):   query: u64, source: u64, destination: u64
+//!     Block (4):      query: u64, found: u8, followed by a Block if `found` is 1
+//!     Tip (5):        query: u64, found: u8, followed by a Block and a u64 length if `found` is 1
+//!     Peers (6):      process: u64 (random, to notice the connections of a process to itself)
+//!                     listen_port: u32 (0 if the process does not accept connections)
+//!                     peers: u32 (count), followed by that many `ip:port` socket addresses,
+//!                     each a u8 length followed by that many bytes
+//! ```
+//!
+//! With `tcp_encryption`, each connection starts with a handshake that authenticates the other
+//! process by its static key, and the frames are then encrypted, as described in `encryption`.
+//!
+//! The answers to the queries (`Block` and `Tip`) carry the number chosen by the querying process
+//! for the query, on the connection on which the query arrived, and are ignored on other
+//! connections. Messages and queries are only
+//! accepted on behalf of the nodes that the sending process announced in its last `Hello`, and a
+//! node announced by several processes is routed to the first one, while it is connected.
+
+use self::encryption::{Cipher, StaticKeys};
+use super::{block_announcement, transaction_announcement};
+use super::{deliver, gossip_peers, NetworkFuture, NetworkInterface, NetworkStats};
+use crate::encoding::{Decode, DecodeError, Decoder, Encode, Encoder};
+use crate::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, oneshot, Semaphore};
+
+mod encryption;
+
+/// The name of the network backend that connects separate processes over TCP.
+pub const TCP_NETWORK_BACKEND: &str = "tcp";
+
+/// The version of the protocol spoken on the connections. Processes with another version are
+/// disconnected.
+const TCP_PROTOCOL_VERSION: u8 = 2;
+
+/// A network that delivers the messages to the nodes of this process directly, and to the nodes
+/// of the other processes through TCP connections.
+pub struct TcpNetwork {
+    /// The world whose nodes are hosted by this process.
+    world: &'static World,
+    /// The open connections, accepted or established.
+    connections: Mutex<Vec<Arc<Connection>>>,
+    /// The connection that leads to each node of another process.
+    routes: Mutex<HashMap<Address, Arc<Connection>>>,
+    next_query: AtomicU64,
+    stats: Mutex<NetworkStats>,
+    /// A random number that identifies this process among the ones it is connected to.
+    process: u64,
+    /// The port on which this process accepts connections, if it does.
+    listen_port: Mutex<Option<u16>>,
+    /// The socket addresses of the processes that the other processes told about, to connect to,
+    /// at most `NETWORK_TCP_MAX_DISCOVERED_PEERS`.
+    discovered: Mutex<HashSet<SocketAddr>>,
+    /// The socket addresses at which this process connected to itself, which are not dialed again.
+    own_addresses: Mutex<HashSet<SocketAddr>>,
+    /// The keys that authenticate the processes and encrypt the connections, if they are.
+    keys: Option<StaticKeys>,
+}
+
+/// A connection to another process.
+struct Connection {
+    /// The socket address of the other process.
+    peer: SocketAddr,
+    /// The frames waiting to be written on the connection, at most
+    /// `NETWORK_TCP_MAX_QUEUED_FRAMES`.
+    outgoing: mpsc::Sender<Frame>,
+    /// The permits to answer the queries received on the connection, at most
+    /// `NETWORK_TCP_MAX_CONCURRENT_QUERIES` at the same time.
+    answering: Arc<Semaphore>,
+    /// Whether the connection has been closed, after failing to read from it.
+    closed: AtomicBool,
+    /// The nodes of this process last announced on the connection.
+    announced: Mutex<HashSet<Address>>,
+    /// The queries sent on the connection that wait for their answer, by number. Answers are
+    /// only accepted on the connection of their query.
+    queries: Mutex<HashMap<u64, oneshot::Sender<Frame>>>,
+    /// The socket address at which the other process accepts connections, as told in its last
+    /// `Peers`.
+    listening: Mutex<Option<SocketAddr>>,
+}
+
+impl Connection {
+    /// Queue a frame to be written on the connection. Frames sent after the connection has been
+    /// closed, or while too many frames are queued, are lost.
+    fn send(&self, frame: Frame) {
+        match self.outgoing.try_send(frame) {
+            Ok(()) => {}
+            Err(mpsc::error::TrySendError::Full(_)) => warn!(
+                "Dropping a frame to process {}, which does not keep up with the queued ones",
+                self.peer
+            ),
+            Err(mpsc::error::TrySendError::Closed(_)) => debug!(
+                "Cannot send a frame to process {}, which is disconnected",
+                self.peer
+            ),
+        }
+    }
+
+    fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Relaxed)
+    }
+
+    /// Whether the other process knows that a node is hosted by this process.
+    fn announces(&self, node: Address) -> bool {
+        self.announced.lock().unwrap().contains(&node)
+    }
+}
+
+impl TcpNetwork {
+    /// Create the network of a world, accepting and establishing the connections set by
+    /// `tcp_listen`, `tcp_peers`, `peers` and `bootnodes`. Must be called within a Tokio runtime.
+    pub fn start(world: &'static World) -> Arc<Self> {
+        let config = config();
+        let keys = config.tcp_encryption.as_ref().map(|encryption| StaticKeys {
+            own: encryption.key().expect("Invalid key of tcp_encryption"),
+            peers: encryption.peer_keys(),
+        });
+        let network = Self::new(world, keys);
+        if let Some(address) = config.tcp_listen {
+            tokio::spawn(network.clone().listen(address));
+        }
+        tokio::spawn(network.clone().maintain(
+            config.tcp_peers,
+            config.peers.seeds,
+            config.bootnodes,
+        ));
+        network
+    }
+
+    /// Create the network of a world, without any connection, encrypting the future ones with
+    /// `keys`, if any.
+    fn new(world: &'static World, keys: Option<StaticKeys>) -> Arc<Self> {
+        Arc::new(TcpNetwork {
+            world,
+            connections: Mutex::default(),
+            routes: Mutex::default(),
+            next_query: AtomicU64::new(0),
+            stats: Mutex::default(),
+            process: rand::random(),
+            listen_port: Mutex::default(),
+            discovered: Mutex::default(),
+            own_addresses: Mutex::default(),
+            keys,
+        })
+    }
+
+    /// Accept the connections of the other processes.
+    async fn listen(self: Arc<Self>, address: String) {
+        let listener = match TcpListener::bind(&address).await {
+            Ok(listener) => listener,
+            Err(err) => {
+                error!("Failed to listen for other processes on {address}: {err}");
+                return;
+            }
+        };
+        info!("Listening for other processes on {address}");
+        self.accept(listener).await;
+    }
+
+    /// Accept the connections of the other processes on a bound socket.
+    async fn accept(self: Arc<Self>, listener: TcpListener) {
+        *self.listen_port.lock().unwrap() =
+            listener.local_addr().ok().map(|address| address.port());
+        loop {
+            match listener.accept().await {
+                Ok((mut stream, peer)) => {
+                    // The handshake of a slow process does not delay the other connections.
+                    let network = self.clone();
+                    tokio::spawn(async move {
+                        let ciphers = match network.handshake(&mut stream).await {
+                            Ok(ciphers) => ciphers,
+                            Err(err) => {
+                                warn!(
+                                    "Disconnecting process {peer}, which failed the handshake: \
+                                     {err}"
+                                );
+                                return;
+                            }
+                        };
+                        info!("Process {peer} connected");
+                        let connection = network.open(stream, peer, ciphers);
+                        network.greet(&connection).await;
+                    });
+                }
+                Err(err) => warn!("Failed to accept the connection of a process: {err}"),
+            }
+        }
+    }
+
+    /// Every `NETWORK_TCP_HELLO_INTERVAL`, connect to the peers, the seeds and the discovered
+    /// processes that are not connected, and to the bootnodes while no process is connected, then
+    /// tell all the connected processes which nodes this process hosts, which may have changed.
+    /// Every `NETWORK_TCP_PEER_EXCHANGE_INTERVAL`, also tell them which processes it is connected
+    /// to. The peers, seeds and bootnodes that fail are retried with an exponential backoff.
+    async fn maintain(
+        self: Arc<Self>,
+        peers: Vec<String>,
+        seeds: Vec<String>,
+        bootnodes: Vec<String>,
+    ) {
+        let mut dialed: HashMap<String, Arc<Connection>> = HashMap::new();
+        let mut backoff = Backoff::default();
+        let mut seed_addresses: HashMap<&str, Vec<SocketAddr>> = HashMap::new();
+        let mut last_exchange = tokio::time::Instant::now();
+        loop {
+            for seed in &seeds {
+                if !backoff.is_due(seed) {
+                    continue;
+                }
+                match resolve(seed).await {
+                    Some(addresses) => {
+                        seed_addresses.insert(seed, addresses);
+                        backoff.wait(seed, NETWORK_TCP_SEED_REFRESH_INTERVAL);
+                    }
+                    None => backoff.fail(seed),
+                }
+            }
+            let bootnodes = if self.connections.lock().unwrap().is_empty() {
+                &bootnodes[..]
+            } else {
+                &[]
+            };
+            let seeds = seed_addresses
+                .values()
+                .flatten()
+                .filter(|address| !self.is_connected_to(**address))
+                .map(|address| address.to_string());
+            let targets: Vec<String> = peers
+                .iter()
+                .chain(bootnodes)
+                .cloned()
+                .chain(seeds)
+                .collect();
+            for peer in targets {
+                if dialed
+                    .get(&peer)
+                    .is_some_and(|connection| !connection.is_closed())
+                    || !backoff.is_due(&peer)
+                {
+                    continue;
+                }
+                match self.dial(&peer).await {
+                    Some(connection) => {
+                        backoff.reset(&peer);
+                        dialed.insert(peer, connection);
+                    }
+                    None => backoff.fail(&peer),
+                }
+            }
+            for address in self.undialed_peers() {
+                if self.dial(&address.to_string()).await.is_none() {
+                    // The other processes tell about it again if it still accepts connections.
+                    self.discovered.lock().unwrap().remove(&address);
+                }
+            }
+            let exchange = last_exchange.elapsed() >= NETWORK_TCP_PEER_EXCHANGE_INTERVAL;
+            if exchange {
+                last_exchange = tokio::time::Instant::now();
+            }
+            let connections = self.connections.lock().unwrap().clone();
+            for connection in connections {
+                self.hello(&connection).await;
+                if exchange {
+                    self.exchange_peers(&connection);
+                }
+            }
+            tokio::time::sleep(NETWORK_TCP_HELLO_INTERVAL).await;
+        }
+    }
+
+    /// Connect to another process, at a `host:port` address, within `NETWORK_TCP_CONNECT_TIMEOUT`.
+    async fn dial(self: &Arc<Self>, peer: &str) -> Option<Arc<Connection>> {
+        let connect = tokio::time::timeout(NETWORK_TCP_CONNECT_TIMEOUT, TcpStream::connect(peer));
+        let mut stream = match connect.await {
+            Ok(Ok(stream)) => stream,
+            Ok(Err(err)) => {
+                debug!("Failed to connect to process {peer}: {err}");
+                return None;
+            }
+            Err(_) => {
+                debug!(
+                    "Failed to connect to process {peer} within {NETWORK_TCP_CONNECT_TIMEOUT:?}"
+                );
+                return None;
+            }
+        };
+        let address = stream
+            .peer_addr()
+            .unwrap_or_else(|_| SocketAddr::from(([0, 0, 0, 0], 0)));
+        let ciphers = match self.handshake(&mut stream).await {
+            Ok(ciphers) => ciphers,
+            Err(err) => {
+                warn!(
+                    "Disconnecting process {peer} ({address}), which failed the handshake: {err}"
+                );
+                return None;
+            }
+        };
+        info!("Connected to process {peer} ({address})");
+        let connection = self.open(stream, address, ciphers);
+        self.greet(&connection).await;
+        Some(connection)
+    }
+
+    /// Whether this process is connected to the process that accepts connections at a socket
+    /// address.
+    fn is_connected_to(&self, address: SocketAddr) -> bool {
+        self.connections.lock().unwrap().iter().any(|connection| {
+            connection.peer == address || *connection.listening.lock().unwrap() == Some(address)
+        })
+    }
+
+    /// The discovered processes to which this process is not connected.
+    fn undialed_peers(&self) -> Vec<SocketAddr> {
+        let discovered = self.discovered.lock().unwrap().clone();
+        discovered
+            .into_iter()
+            .filter(|address| !self.is_connected_to(*address))
+            .collect()
+    }
+
+    /// Authenticate the process at the other end of a new connection, if this process has keys,
+    /// within `NETWORK_TCP_HANDSHAKE_TIMEOUT`. Returns the ciphers of the frames sent and received
+    /// on the connection, if they are encrypted.
+    async fn handshake(&self, stream: &mut TcpStream) -> std::io::Result<Option<(Cipher, Cipher)>> {
+        let Some(keys) = &self.keys else {
+            return Ok(None);
+        };
+        tokio::time::timeout(
+            NETWORK_TCP_HANDSHAKE_TIMEOUT,
+            encryption::handshake(stream, keys),
+        )
+        .await
+        .map_err(|_| {
+            std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                format!("no handshake within {NETWORK_TCP_HANDSHAKE_TIMEOUT:?}"),
+            )
+        })?
+        .map(Some)
+    }
+
+    /// Start reading and writing the frames of a new connection, encrypted with `ciphers`, if
+    /// any.
+    fn open(
+        self: &Arc<Self>,
+        stream: TcpStream,
+        peer: SocketAddr,
+        ciphers: Option<(Cipher, Cipher)>,
+    ) -> Arc<Connection> {
+        if let Err(err) = stream.set_nodelay(true) {
+            debug!("Cannot disable the buffering of the connection to {peer}: {err}");
+        }
+        let (reader, writer) = stream.into_split();
+        let (outgoing, frames) = mpsc::channel(NETWORK_TCP_MAX_QUEUED_FRAMES);
+        let connection = Arc::new(Connection {
+            peer,
+            outgoing,
+            answering: Arc::new(Semaphore::new(NETWORK_TCP_MAX_CONCURRENT_QUERIES)),
+            closed: AtomicBool::new(false),
+            announced: Mutex::default(),
+            queries: Mutex::default(),
+            listening: Mutex::default(),
+        });
+        self.connections.lock().unwrap().push(connection.clone());
+        let (sender, receiver) = ciphers.unzip();
+        tokio::spawn(write_frames(writer, frames, peer, sender));
+        tokio::spawn(
+            self.clone()
+                .read_frames(reader, connection.clone(), receiver),
+        );
+        connection
+    }
+
+    /// Tell the process at the other end of a new connection which nodes this process hosts, and
+    /// which processes it is connected to.
+    async fn greet(&self, connection: &Arc<Connection>) {
+        self.hello(connection).await;
+        self.exchange_peers(connection);
+    }
+
+    /// Tell the process at the other end of a connection on which port this process accepts
+    /// connections, and at which socket addresses the other connected processes do.
+    fn exchange_peers(&self, connection: &Arc<Connection>) {
+        let peers = self
+            .connections
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|other| !Arc::ptr_eq(other, connection))
+            .filter_map(|other| *other.listening.lock().unwrap())
+            .take(NETWORK_TCP_MAX_EXCHANGED_PEERS)
+            .collect();
+        connection.send(Frame::Peers {
+            process: self.process,
+            listen_port: *self.listen_port.lock().unwrap(),
+            peers,
+        });
+    }
+
+    /// Tell the process at the other end of a connection which nodes this process hosts.
+    async fn hello(&self, connection: &Connection) {
+        let nodes = self.world.get_node_addresses().await;
+        *connection.announced.lock().unwrap() = nodes.iter().copied().collect();
+        connection.send(Frame::Hello {
+            version: TCP_PROTOCOL_VERSION,
+            nodes,
+        });
+    }
+
+    /// Handle the frames received on a connection, decrypted with `cipher`, if any, until it
+    /// breaks, then forget it.
+    async fn read_frames(
+        self: Arc<Self>,
+        mut reader: impl AsyncRead + Unpin,
+        connection: Arc<Connection>,
+        mut cipher: Option<Cipher>,
+    ) {
+        let peer = connection.peer;
+        loop {
+            let bytes = match read_frame(&mut reader).await {
+                Ok(bytes) => bytes,
+                Err(err) => {
+                    info!("Process {peer} disconnected: {err}");
+                    break;
+                }
+            };
+            let bytes = match &mut cipher {
+                Some(cipher) => match cipher.open(&bytes) {
+                    Ok(bytes) => bytes,
+                    Err(err) => {
+                        warn!(
+                            "Disconnecting process {peer}, which sent a frame that cannot be \
+                             decrypted: {err}"
+                        );
+                        break;
+                    }
+                },
+                None => bytes,
+            };
+            let frame = match Frame::decode(&bytes) {
+                Ok(frame) => frame,
+                Err(err) => {
+                    warn!("Disconnecting process {peer}, which sent an invalid frame: {err}");
+                    break;
+                }
+            };
+            if self.receive(frame, &connection).await.is_err() {
+                break;
+            }
+        }
+        connection.closed.store(true, Ordering::Relaxed);
+        self.connections
+            .lock()
+            .unwrap()
+            .retain(|other| !Arc::ptr_eq(other, &connection));
+        self.routes
+            .lock()
+            .unwrap()
+            .retain(|_, other| !Arc::ptr_eq(other, &connection));
+    }
+
+    /// Handle a frame received on a connection. Fails if the connection must be closed.
+    async fn receive(
+        self: &Arc<Self>,
+        frame: Frame,
+        connection: &Arc<Connection>,
+    ) -> Result<(), ()> {
+        let peer = connection.peer;
+        match frame {
+            Frame::Hello { version, nodes } => {
+                if version != TCP_PROTOCOL_VERSION {
+                    warn!(
+                        "Disconnecting process {peer}, which speaks version {version} of the \
+                         protocol instead of {TCP_PROTOCOL_VERSION}"
+                    );
+                    return Err(());
+                }
+                let local_nodes = self.world.get_node_addresses().await;
+                let mut routes = self.routes.lock().unwrap();
+                routes.retain(|_, other| !Arc::ptr_eq(other, connection));
+                for node in nodes {
+                    if local_nodes.contains(&node) {
+                        continue;
+                    }
+                    // A process cannot take over the nodes announced by another connected one,
+                    // e.g. to receive their messages, until that connection closes. The same
+                    // process may also be connected twice, and keeps the first route.
+                    if let Some(other) = routes.get(&node).filter(|other| !other.is_closed()) {
+                        debug!(
+                            "Ignoring node {node} announced by process {peer}, which is reached \
+                             through process {}",
+                            other.peer
+                        );
+                        continue;
+                    }
+                    routes.insert(node, connection.clone());
+                }
+            }
+            Frame::Message {
+                destination,
+                message,
+            } => {
+                // A process can only send messages on behalf of the nodes that it hosts.
+                let source = message.source();
+                if !self.hosts(connection, source) {
+                    warn!(
+                        "Dropping a message of type {} from process {peer} on behalf of node \
+                         {source}, which it does not host",
+                        message.kind()
+                    );
+                    return Ok(());
+                }
+                let Some(inbox) = self.world.get_inbox(destination).await else {
+                    debug!(
+                        "Cannot find node {destination} to deliver a message of type {} from \
+                         process {peer}",
+                        message.kind()
+                    );
+                    return Ok(());
+                };
+                deliver(&inbox, destination, message);
+            }
+            Frame::QueryBlock {
+                query,
+                source,
+                destination,
+                block_hash,
+            } => {
+                // Like messages, queries can only be sent on behalf of the hosted nodes, so that
+                // the queried node knows who asks, e.g. to ignore its banned peers.
+                if !self.hosts(connection, source) {
+                    warn!(
+                        "Refusing a query of block {block_hash} from process {peer} on behalf of \
+                         node {source}, which it does not host"
+                    );
+                    connection.send(Frame::Block { query, block: None });
+                    return Ok(());
+                }
+                let Ok(permit) = connection.answering.clone().try_acquire_owned() else {
+                    warn!(
+                        "Refusing a query of block {block_hash} from process {peer}, which has \
+                         too many pending queries"
+                    );
+                    connection.send(Frame::Block { query, block: None });
+                    return Ok(());
+                };
+                let (network, connection) = (self.clone(), connection.clone());
+                tokio::spawn(async move {
+                    let block = network.local_block(&block_hash, source, destination).await;
+                    connection.send(Frame::Block { query, block });
+                    drop(permit);
+                });
+            }
+            Frame::QueryTip {
+                query,
+                source,
+                destination,
+            } => {
+                if !self.hosts(connection, source) {
+                    warn!(
+                        "Refusing a query of the tip of {destination} from process {peer} on \
+                         behalf of node {source}, which it does not host"
+                    );
+                    connection.send(Frame::Tip { query, tip: None });
+                    return Ok(());
+                }
+                let Ok(permit) = connection.answering.clone().try_acquire_owned() else {
+                    warn!(
+                        "Refusing a query of the tip of {destination} from process {peer}, which \
+                         has too many pending queries"
+                    );
+                    connection.send(Frame::Tip { query, tip: None });
+                    return Ok(());
+                };
+                let (network, connection) = (self.clone(), connection.clone());
+                tokio::spawn(async move {
+                    let tip = network.local_tip(source, destination).await;
+                    connection.send(Frame::Tip { query, tip });
+                    drop(permit);
+                });
+            }
+            Frame::Peers {
+                process,
+                listen_port,
+                peers,
+            } => {
+                if process == self.process {
+                    info!("Disconnecting process {peer}, which is this process itself");
+                    self.own_addresses.lock().unwrap().insert(peer);
+                    self.discovered.lock().unwrap().remove(&peer);
+                    return Err(());
+                }
+                let listening = listen_port.map(|port| SocketAddr::new(peer.ip(), port));
+                let previous =
+                    std::mem::replace(&mut *connection.listening.lock().unwrap(), listening);
+                if listening.is_some() && listening != previous {
+                    // Tell the other processes about the new one right away, so that the ones
+                    // that joined through the same bootnode connect to each other.
+                    let connections = self.connections.lock().unwrap().clone();
+                    for other in connections {
+                        if !Arc::ptr_eq(&other, connection) {
+                            self.exchange_peers(&other);
+                        }
+                    }
+                }
+                let own_addresses = self.own_addresses.lock().unwrap().clone();
+                let mut discovered = self.discovered.lock().unwrap();
+                for address in peers {
+                    if discovered.len() >= NETWORK_TCP_MAX_DISCOVERED_PEERS {
+                        break;
+                    }
+                    if !(address.ip().is_unspecified()
+                        || address.port() == 0
+                        || own_addresses.contains(&address))
+                    {
+                        discovered.insert(address);
+                    }
+                }
+            }
+            Frame::Block { query, .. } | Frame::Tip { query, .. } => {
+                // The querying node may have given up waiting.
+                match connection.queries.lock().unwrap().remove(&query) {
+                    Some(answer) => {
+                        let _ = answer.send(frame);
+                    }
+                    None => {
+                        debug!("Ignoring an answer from process {peer} to unknown query {query}")
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The connection that leads to a node of another process, if any.
+    fn route(&self, destination: Address) -> Option<Arc<Connection>> {
+        self.routes.lock().unwrap().get(&destination).cloned()
+    }
+
+    /// Whether a node is hosted by the process at the other end of a connection, as announced by
+    /// the process.
+    fn hosts(&self, connection: &Arc<Connection>, node: Address) -> bool {
+        self.route(node)
+            .is_some_and(|other| Arc::ptr_eq(&other, connection))
+    }
+
+    /// The peers to which `source` sends a block or a transaction, among the nodes of all the
+    /// connected processes.
+    async fn peers(&self, source: Address) -> Vec<Address> {
+        let mut addresses = self.world.get_node_addresses().await;
+        addresses.extend(self.routes.lock().unwrap().keys());
+        gossip_peers(addresses, source)
+    }
+
+    /// Send a message from `source` to `destination`, in this process or in another one,
+    /// returning whether there is such a node.
+    async fn send(&self, message: Message, source: Address, destination: Address) -> bool {
+        let kind = message.kind();
+        let size = message.size();
+        if let Some(inbox) = self.world.get_inbox(destination).await {
+            self.record(&message, source, destination, size);
+            deliver(&inbox, destination, message);
+            return true;
+        }
+        let Some(connection) = self.route(destination) else {
+            debug!("Cannot find node {destination} to send a message of type {kind}");
+            self.stats
+                .lock()
+                .unwrap()
+                .record_drop(kind, source, destination);
+            return false;
+        };
+        // The other process drops the messages of the nodes that it does not know yet, e.g. the
+        // ones created since the last announcement.
+        if !connection.announces(source) {
+            self.hello(&connection).await;
+        }
+        self.record(&message, source, destination, size);
+        connection.send(Frame::Message {
+            destination,
+            message,
+        });
+        true
+    }
+
+    fn record(&self, message: &Message, source: Address, destination: Address, size: usize) {
+        let mut stats = self.stats.lock().unwrap();
+        stats.record_items(message);
+        stats.record(
+            message.kind(),
+            source,
+            destination,
+            size,
+            Duration::ZERO,
+            false,
+        );
+    }
+
+    /// Send a query to a node of another process, returning its answer unless it does not arrive
+    /// within `NETWORK_TCP_QUERY_TIMEOUT`.
+    async fn query(
+        &self,
+        source: Address,
+        destination: Address,
+        frame: impl FnOnce(u64) -> Frame,
+    ) -> Option<Frame> {
+        let connection = self.route(destination)?;
+        // The other process refuses the queries of the nodes that it does not know yet.
+        if !connection.announces(source) {
+            self.hello(&connection).await;
+        }
+        let query = self.next_query.fetch_add(1, Ordering::Relaxed);
+        let (sender, answer) = oneshot::channel();
+        connection.queries.lock().unwrap().insert(query, sender);
+        connection.send(frame(query));
+        let answer = tokio::time::timeout(NETWORK_TCP_QUERY_TIMEOUT, answer).await;
+        connection.queries.lock().unwrap().remove(&query);
+        answer.ok()?.ok()
+    }
+
+    /// Answer the query of `source` for a block of a node of this process.
+    async fn local_block(
+        &self,
+        block_hash: &BlockHash,
+        source: Address,
+        destination: Address,
+    ) -> Option<Block> {
+        let node = self.world.get_node(destination).await?;
+        let Ok(readable_node) = tokio::time::timeout(NETWORK_QUERY_TIMEOUT, node.read()).await
+        else {
+            warn!("Node {destination} is busy and cannot answer the query of block {block_hash}");
+            return None;
+        };
+        if readable_node.is_banned(source) {
+            debug!("Node {destination} ignores the query of block {block_hash} from {source}");
+            return None;
+        }
+        let block = readable_node.get_block(block_hash)?;
+        drop(readable_node);
+        self.record_reply("query_block", &block, destination, source);
+        Some(block)
+    }
+
+    /// Answer the query of `source` for the tip of a node of this process.
+    async fn local_tip(&self, source: Address, destination: Address) -> Option<(Block, usize)> {
+        let node = self.world.get_node(destination).await?;
+        let Ok(readable_node) = tokio::time::timeout(NETWORK_QUERY_TIMEOUT, node.read()).await
+        else {
+            warn!("Node {destination} is busy and cannot answer the query of its tip");
+            return None;
+        };
+        if readable_node.is_banned(source) {
+            debug!("Node {destination} ignores the query of its tip from {source}");
+            return None;
+        }
+        let blockchain = readable_node.blockchain();
+        let tip = (blockchain.last_block().clone(), blockchain.len());
+        drop(readable_node);
+        self.record_reply("query_tip", &tip.0, destination, source);
+        Some(tip)
+    }
+
+    fn record_reply(
+        &self,
+        kind: &'static str,
+        block: &Block,
+        source: Address,
+        destination: Address,
+    ) {
+        let size = block.encode().len();
+        self.stats
+            .lock()
+            .unwrap()
+            .record(kind, source, destination, size, Duration::ZERO, false);
+    }
+}
+
+impl NetworkInterface for TcpNetwork {
+    fn broadcast_block<'a>(
+        &'a self,
+        block: &'a Block,
+        blockchain_length: usize,
+        params_hash: &'a BlockHash,
+        source: Address,
+    ) -> NetworkFuture<'a, ()> {
+        Box::pin(async move {
+            debug!("Node {source} broadcasts block {block}");
+            let message = block_announcement(block, blockchain_length, params_hash, source);
+            for address in self.peers(source).await {
+                self.send(message.clone(), source, address).await;
+            }
+        })
+    }
+
+    fn broadcast_transaction<'a>(
+        &'a self,
+        transaction: &'a BlockTransaction,
+        source: Address,
+    ) -> NetworkFuture<'a, usize> {
+        Box::pin(async move {
+            debug!("Node {source} broadcasts transaction {transaction}");
+            let message = transaction_announcement(transaction, source);
+            let mut peers = 0;
+            for address in self.peers(source).await {
+                if self.send(message.clone(), source, address).await {
+                    peers += 1;
+                }
+            }
+            peers
+        })
+    }
+
+    fn broadcast_heartbeat<'a>(
+        &'a self,
+        blockchain_length: usize,
+        last_block_hash: &'a BlockHash,
+        cumulative_work: u128,
+        source: Address,
+    ) -> NetworkFuture<'a, ()> {
+        Box::pin(async move {
+            let message = Message::Heartbeat {
+                blockchain_length,
+                last_block_hash: last_block_hash.clone(),
+                cumulative_work,
+                source,
+            };
+            for address in self.peers(source).await {
+                self.send(message.clone(), source, address).await;
+            }
+        })
+    }
+
+    fn send_transaction_receipt<'a>(
+        &'a self,
+        transaction_id: TransactionId,
+        accepted: bool,
+        source: Address,
+        destination: Address,
+    ) -> NetworkFuture<'a, ()> {
+        Box::pin(async move {
+            let message = Message::TransactionReceipt {
+                transaction_id,
+                peer: source,
+                accepted,
+            };
+            self.send(message, source, destination).await;
+        })
+    }
+
+    fn send_message<'a>(
+        &'a self,
+        message: Message,
+        source: Address,
+        destination: Address,
+    ) -> NetworkFuture<'a, ()> {
+        Box::pin(async move {
+            self.send(message, source, destination).await;
+        })
+    }
+
+    fn query_block<'a>(
+        &'a self,
+        block_hash: &'a BlockHash,
+        source: Address,
+        destination: Address,
+    ) -> NetworkFuture<'a, Option<Block>> {
+        Box::pin(async move {
+            debug!("Node {source} queries block {block_hash} from {destination}");
+            if self.world.get_node(destination).await.is_some() {
+                return self.local_block(block_hash, source, destination).await;
+            }
+            let answer = self
+                .query(source, destination, |query| Frame::QueryBlock {
+                    query,
+                    source,
+                    destination,
+                    block_hash: block_hash.clone(),
+                })
+                .await;
+            match answer {
+                Some(Frame::Block { block, .. }) => block,
+                _ => {
+                    warn!("Node {destination} did not answer the query of block {block_hash}");
+                    self.stats
+                        .lock()
+                        .unwrap()
+                        .record_drop("query_block", destination, source);
+                    None
+                }
+            }
+        })
+    }
+
+    fn query_tip<'a>(
+        &'a self,
+        source: Address,
+        destination: Address,
+    ) -> NetworkFuture<'a, Option<(Block, usize)>> {
+        Box::pin(async move {
+            debug!("Node {source} queries the tip of {destination}");
+            if self.world.get_node(destination).await.is_some() {
+                return self.local_tip(source, destination).await;
+            }
+            let answer = self
+                .query(source, destination, |query| Frame::QueryTip {
+                    query,
+                    source,
+                    destination,
+                })
+                .await;
+            match answer {
+                Some(Frame::Tip { tip, .. }) => tip,
+                _ => {
+                    warn!("Node {destination} did not answer the query of its tip");
+                    self.stats
+                        .lock()
+                        .unwrap()
+                        .record_drop("query_tip", destination, source);
+                    None
+                }
+            }
+        })
+    }
+
+    fn stats(&self) -> NetworkStats {
+        self.stats.lock().unwrap().clone()
+    }
+}
+
+/// The delays before connecting again to the processes, or resolving again the seeds, that
+/// failed, by `host:port` address.
+#[derive(Default)]
+struct Backoff {
+    /// The number of consecutive failures, and when to try again.
+    retries: HashMap<String, (u32, tokio::time::Instant)>,
+}
+
+impl Backoff {
+    /// Whether it is time to try to reach a process.
+    fn is_due(&self, peer: &str) -> bool {
+        self.retries
+            .get(peer)
+            .is_none_or(|(_, next)| *next <= tokio::time::Instant::now())
+    }
+
+    /// Record a failure to reach a process, doubling the delay before the next attempt, from
+    /// `NETWORK_TCP_RECONNECT_MIN_DELAY` up to `NETWORK_TCP_RECONNECT_MAX_DELAY`.
+    fn fail(&mut self, peer: &str) {
+        let failures = self.retries.get(peer).map_or(0, |(failures, _)| *failures);
+        let delay = NETWORK_TCP_RECONNECT_MIN_DELAY
+            .saturating_mul(1 << failures.min(16))
+            .min(NETWORK_TCP_RECONNECT_MAX_DELAY);
+        let next = tokio::time::Instant::now() + delay;
+        self.retries.insert(peer.to_string(), (failures + 1, next));
+    }
+
+    /// Wait before reaching a process again, e.g. after success, without counting a failure.
+    fn wait(&mut self, peer: &str, delay: Duration) {
+        let next = tokio::time::Instant::now() + delay;
+        self.retries.insert(peer.to_string(), (0, next));
+    }
+
+    /// Forget the failures to reach a process, after reaching it.
+    fn reset(&mut self, peer: &str) {
+        self.retries.remove(peer);
+    }
+}
+
+/// The socket addresses of a `host:port` address, resolved within `NETWORK_TCP_CONNECT_TIMEOUT`.
+async fn resolve(peer: &str) -> Option<Vec<SocketAddr>> {
+    let lookup = tokio::time::timeout(NETWORK_TCP_CONNECT_TIMEOUT, tokio::net::lookup_host(peer));
+    match lookup.await {
+        Ok(Ok(addresses)) => Some(addresses.collect()),
+        Ok(Err(err)) => {
+            debug!("Failed to resolve the addresses of seed {peer}: {err}");
+            None
+        }
+        Err(_) => {
+            debug!(
+                "Failed to resolve the addresses of seed {peer} within \
+                 {NETWORK_TCP_CONNECT_TIMEOUT:?}"
+            );
+            None
+        }
+    }
+}
+
+/// A unit of the protocol spoken on the connections between processes.
+#[derive(Debug)]
+enum Frame {
+    /// The nodes hosted by the sending process, which replace the ones it previously announced.
+    Hello { version: u8, nodes: Vec<Address> },
+    /// A message for a node of the receiving process.
+    Message {
+        destination: Address,
+        message: Message,
+    },
+    /// A query for a block of a node of the receiving process, on behalf of `source`.
+    QueryBlock {
+        query: u64,
+        source: Address,
+        destination: Address,
+        block_hash: BlockHash,
+    },
+    /// A query for the tip of a node of the receiving process, on behalf of `source`.
+    QueryTip {
+        query: u64,
+        source: Address,
+        destination: Address,
+    },
+    /// The answer to a `QueryBlock`, if the node has the block.
+    Block { query: u64, block: Option<Block> },
+    /// The answer to a `QueryTip`: the last block and the length of the blockchain of the node,
+    /// if it could answer.
+    Tip {
+        query: u64,
+        tip: Option<(Block, usize)>,
+    },
+    /// The port on which the sending process accepts connections, if it does, and the socket
+    /// addresses at which the processes it is connected to do.
+    Peers {
+        process: u64,
+        listen_port: Option<u16>,
+        peers: Vec<SocketAddr>,
+    },
+}
+
+impl Encode for Frame {
+    fn encode_into(&self, encoder: &mut Encoder) {
+        match self {
+            Frame::Hello { version, nodes } => {
+                encoder.u8(0);
+                encoder.u8(*version);
+                let count = u32::try_from(nodes.len()).expect("Too many nodes");
+                encoder.u32(count);
+                for node in nodes {
+                    node.encode_into(encoder);
+                }
+            }
+            Frame::Message {
+                destination,
+                message,
+            } => {
+                encoder.u8(1);
+                destination.encode_into(encoder);
+                message.encode_into(encoder);
+            }
+            Frame::QueryBlock {
+                query,
+                source,
+                destination,
+                block_hash,
+            } => {
+                encoder.u8(2);
+                encoder.u64(*query);
+                source.encode_into(encoder);
+                destination.encode_into(encoder);
+                block_hash.encode_into(encoder);
+            }
+            Frame::QueryTip {
+                query,
+                source,
+                destination,
+            } => {
+                encoder.u8(3);
+                encoder.u64(*query);
+                source.encode_into(encoder);
+                destination.encode_into(encoder);
+            }
+            Frame::Block { query, block } => {
+                encoder.u8(4);
+                encoder.u64(*query);
+                match block {
+                    Some(block) => {
+                        encoder.u8(1);
+                        block.encode_into(encoder);
+                    }
+                    None => encoder.u8(0),
+                }
+            }
+            Frame::Tip { query, tip } => {
+                encoder.u8(5);
+                encoder.u64(*query);
+                match tip {
+                    Some((block, length)) => {
+                        encoder.u8(1);
+                        block.encode_into(encoder);
+                        encoder.u64(*length as u64);
+                    }
+                    None => encoder.u8(0),
+                }
+            }
+            Frame::Peers {
+                process,
+                listen_port,
+                peers,
+            } => {
+                encoder.u8(6);
+                encoder.u64(*process);
+                encoder.u32(listen_port.unwrap_or(0).into());
+                let count = u32::try_from(peers.len()).expect("Too many peers");
+                encoder.u32(count);
+                for peer in peers {
+                    encoder.short_bytes(peer.to_string().as_bytes());
+                }
+            }
+        }
+    }
+}
+
+impl Decode for Frame {
+    fn decode_from(decoder: &mut Decoder) -> Result<Self, DecodeError> {
+        let found = |decoder: &mut Decoder| match decoder.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(DecodeError::InvalidField("found")),
+        };
+        let frame = match decoder.u8()? {
+            0 => {
+                let version = decoder.u8()?;
+                let count = decoder.u32()? as usize;
+                // Don't trust `count` to preallocate memory.
+                let mut nodes = Vec::with_capacity(count.min(decoder.remaining() / 8));
+                for _ in 0..count {
+                    nodes.push(Address::decode_from(decoder)?);
+                }
+                Frame::Hello { version, nodes }
+            }
+            1 => Frame::Message {
+                destination: Address::decode_from(decoder)?,
+                message: Message::decode_from(decoder)?,
+            },
+            2 => Frame::QueryBlock {
+                query: decoder.u64()?,
+                source: Address::decode_from(decoder)?,
+                destination: Address::decode_from(decoder)?,
+                block_hash: BlockHash::decode_from(decoder)?,
+            },
+            3 => Frame::QueryTip {
+                query: decoder.u64()?,
+                source: Address::decode_from(decoder)?,
+                destination: Address::decode_from(decoder)?,
+            },
+            4 => {
+                let query = decoder.u64()?;
+                let block = if found(decoder)? {
+                    Some(Block::decode_from(decoder)?)
+                } else {
+                    None
+                };
+                Frame::Block { query, block }
+            }
+            5 => {
+                let query = decoder.u64()?;
+                let tip = if found(decoder)? {
+                    let block = Block::decode_from(decoder)?;
+                    let length = usize::try_from(decoder.u64()?)
+                        .map_err(|_| DecodeError::InvalidField("length"))?;
+                    Some((block, length))
+                } else {
+                    None
+                };
+                Frame::Tip { query, tip }
+            }
+            6 => {
+                let process = decoder.u64()?;
+                let listen_port = u16::try_from(decoder.u32()?)
+                    .map_err(|_| DecodeError::InvalidField("listen_port"))?;
+                let count = decoder.u32()? as usize;
+                if count > NETWORK_TCP_MAX_EXCHANGED_PEERS {
+                    return Err(DecodeError::InvalidField("peers"));
+                }
+                let mut peers = Vec::with_capacity(count);
+                for _ in 0..count {
+                    let peer = std::str::from_utf8(decoder.short_bytes()?)
+                        .ok()
+                        .and_then(|peer| peer.parse().ok())
+                        .ok_or(DecodeError::InvalidField("peer"))?;
+                    peers.push(peer);
+                }
+                Frame::Peers {
+                    process,
+                    listen_port: (listen_port > 0).then_some(listen_port),
+                    peers,
+                }
+            }
+            _ => return Err(DecodeError::InvalidField("frame type")),
+        };
+        Ok(frame)
+    }
+}
+
+/// Write the frames queued for a connection, encrypted with `cipher`, if any, until the connection
+/// breaks or is forgotten.
+async fn write_frames(
+    mut writer: impl AsyncWrite + Unpin,
+    mut frames: mpsc::Receiver<Frame>,
+    peer: SocketAddr,
+    mut cipher: Option<Cipher>,
+) {
+    while let Some(frame) = frames.recv().await {
+        let bytes = match &mut cipher {
+            Some(cipher) => cipher.seal(&frame.encode()),
+            None => frame.encode(),
+        };
+        if let Err(err) = write_frame(&mut writer, &bytes).await {
+            debug!("Failed to write to process {peer}: {err}");
+            return;
+        }
+    }
+}
+
+/// Write the bytes of a frame, prefixed by their length.
+async fn write_frame(writer: &mut (impl AsyncWrite + Unpin), bytes: &[u8]) -> std::io::Result<()> {
+    let length = u32::try_from(bytes.len())
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "frame too large"))?;
+    writer.write_all(&length.to_le_bytes()).await?;
+    writer.write_all(bytes).await?;
+    writer.flush().await
+}
+
+/// Read the bytes of a frame, prefixed by their length, which must be at most
+/// `NETWORK_TCP_MAX_FRAME_BYTES`. Fails if the whole frame does not arrive within
+/// `NETWORK_TCP_READ_TIMEOUT`.
+async fn read_frame(reader: &mut (impl AsyncRead + Unpin)) -> std::io::Result<Vec<u8>> {
+    tokio::time::timeout(NETWORK_TCP_READ_TIMEOUT, read_frame_bytes(reader))
+        .await
+        .map_err(|_| {
+            std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                format!("no frame within {NETWORK_TCP_READ_TIMEOUT:?}"),
+            )
+        })?
+}
+
+async fn read_frame_bytes(reader: &mut (impl AsyncRead + Unpin)) -> std::io::Result<Vec<u8>> {
+    let mut length = [0; 4];
+    reader.read_exact(&mut length).await?;
+    let length = u32::from_le_bytes(length) as usize;
+    if length > NETWORK_TCP_MAX_FRAME_BYTES {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("frame of {length} bytes, more than {NETWORK_TCP_MAX_FRAME_BYTES}"),
+        ));
+    }
+    // Don't trust `length` to preallocate memory.
+    let mut bytes = Vec::with_capacity(length.min(NETWORK_TCP_READ_CHUNK_BYTES));
+    reader.take(length as u64).read_to_end(&mut bytes).await?;
+    if bytes.len() < length {
+        return Err(std::io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_and_decode_frames() {
+        let (alice, bob) = (Address::new(1), Address::new(2));
+        let genesis = Block::genesis();
+        let frames = vec![
+            Frame::Hello {
+                version: TCP_PROTOCOL_VERSION,
+                nodes: vec![alice, bob],
+            },
+            Frame::Message {
+                destination: bob,
+                message: Message::Departure { source: alice },
+            },
+            Frame::QueryBlock {
+                query: 7,
+                source: alice,
+                destination: bob,
+                block_hash: genesis.hash(),
+            },
+            Frame::QueryTip {
+                query: 8,
+                source: alice,
+                destination: bob,
+            },
+            Frame::Block {
+                query: 7,
+                block: Some(genesis.clone()),
+            },
+            Frame::Block {
+                query: 7,
+                block: None,
+            },
+            Frame::Tip {
+                query: 8,
+                tip: Some((genesis, 1)),
+            },
+            Frame::Tip {
+                query: 8,
+                tip: None,
+            },
+            Frame::Peers {
+                process: 9,
+                listen_port: Some(7000),
+                peers: vec![
+                    SocketAddr::from(([10, 0, 0, 2], 7000)),
+                    "[::1]:7001".parse().unwrap(),
+                ],
+            },
+            Frame::Peers {
+                process: 9,
+                listen_port: None,
+                peers: vec![],
+            },
+        ];
+        for frame in frames {
+            let decoded = Frame::decode(&frame.encode()).unwrap();
+            assert_eq!(format!("{decoded:?}"), format!("{frame:?}"));
+        }
+        assert_eq!(
+            Frame::decode(&[7]).unwrap_err(),
+            DecodeError::InvalidField("frame type")
+        );
+    }
+
+    #[tokio::test]
+    async fn read_length_prefixed_frames() {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        write_frame(&mut client, b"first").await.unwrap();
+        write_frame(&mut client, b"").await.unwrap();
+        assert_eq!(read_frame(&mut server).await.unwrap(), b"first");
+        assert_eq!(read_frame(&mut server).await.unwrap(), b"");
+
+        // Frames that are too large close the connection before being read.
+        let length = NETWORK_TCP_MAX_FRAME_BYTES as u32 + 1;
+        client.write_all(&length.to_le_bytes()).await.unwrap();
+        let err = read_frame(&mut server).await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+
+        // A connection that breaks in the middle of a frame fails to read it.
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        client.write_all(&10u32.to_le_bytes()).await.unwrap();
+        client.write_all(b"short").await.unwrap();
+        drop(client);
+        let err = read_frame(&mut server).await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn time_out_stalled_frames() {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        client.write_all(&10u32.to_le_bytes()).await.unwrap();
+        client.write_all(b"short").await.unwrap();
+        let err = read_frame(&mut server).await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+
+        // An idle connection times out too.
+        let err = read_frame(&mut server).await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+        drop(client);
+    }
+
+    /// Connect the networks of two worlds over loopback, and wait until each one knows the nodes
+    /// of the other.
+    async fn connect(a: &Arc<TcpNetwork>, b: &Arc<TcpNetwork>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let (client, server) = tokio::join!(TcpStream::connect(address), listener.accept());
+        let client = client.unwrap();
+        let (server, peer) = server.unwrap();
+        a.greet(&a.open(server, peer, None)).await;
+        b.greet(&b.open(client, address, None)).await;
+        let (a_nodes, b_nodes) = (
+            a.world.get_node_addresses().await,
+            b.world.get_node_addresses().await,
+        );
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while !(a_nodes.iter().all(|node| b.route(*node).is_some())
+                && b_nodes.iter().all(|node| a.route(*node).is_some()))
+            {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("The processes did not exchange their nodes");
+    }
+
+    fn idle_node() -> Node {
+        let mut node = Node::new();
+        node.set_config(NodeConfig {
+            hashrate: 0.0,
+            ..NodeConfig::default()
+        });
+        node
+    }
+
+    #[tokio::test]
+    async fn exchange_messages_and_queries_over_loopback() {
+        let (world_a, world_b) = (
+            Box::leak(Box::new(World::new())),
+            Box::leak(Box::new(World::new())),
+        );
+        let alice = world_a.insert_node(idle_node()).await.unwrap();
+        let bob = world_b.insert_node(idle_node()).await.unwrap();
+        let (a, b) = (
+            TcpNetwork::new(world_a, None),
+            TcpNetwork::new(world_b, None),
+        );
+        connect(&a, &b).await;
+
+        // A message from a node of one process reaches the node of the other process.
+        let genesis = Block::genesis();
+        let new_block = |block: &Block, source| Message::NewBlock {
+            block: block.clone(),
+            blockchain_length: 1,
+            source,
+            params_hash: None,
+        };
+        let inbox = world_a.get_inbox(alice).await.unwrap();
+        assert!(b.send(new_block(&genesis, bob), bob, alice).await);
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while !inbox.has_seen(&Item::Block(genesis.hash())) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("The message was not delivered");
+
+        // Queries are answered by the node of the other process.
+        let block = b.query_block(&genesis.hash(), bob, alice).await;
+        assert_eq!(block.map(|block| block.hash()), Some(genesis.hash()));
+        let tip = b.query_tip(bob, alice).await;
+        assert_eq!(
+            tip.map(|(block, length)| (block.hash(), length)),
+            Some((genesis.hash(), 1))
+        );
+        let tip = a.query_tip(alice, bob).await;
+        assert_eq!(tip.map(|(block, _)| block.hash()), Some(genesis.hash()));
+    }
+
+    #[tokio::test]
+    async fn refuse_spoofed_sources() {
+        let (world_a, world_b) = (
+            Box::leak(Box::new(World::new())),
+            Box::leak(Box::new(World::new())),
+        );
+        let alice = world_a.insert_node(idle_node()).await.unwrap();
+        let bob = world_b.insert_node(idle_node()).await.unwrap();
+        let (a, b) = (
+            TcpNetwork::new(world_a, None),
+            TcpNetwork::new(world_b, None),
+        );
+        connect(&a, &b).await;
+        // Carol is not hosted by the process of Bob, which claims to speak on her behalf.
+        let carol = Address::new_random();
+
+        let answer = b
+            .query(carol, alice, |query| Frame::QueryTip {
+                query,
+                source: carol,
+                destination: alice,
+            })
+            .await;
+        assert!(matches!(answer, Some(Frame::Tip { tip: None, .. })));
+        let genesis = Block::genesis();
+        let answer = b
+            .query(carol, alice, |query| Frame::QueryBlock {
+                query,
+                source: carol,
+                destination: alice,
+                block_hash: genesis.hash(),
+            })
+            .await;
+        assert!(matches!(answer, Some(Frame::Block { block: None, .. })));
+
+        // The frames of a connection are handled in order, so the spoofed message has been
+        // dropped once the genuine one is delivered.
+        let block =
+            attempt_mining_block_with_difficulty(genesis.hash(), carol, vec![], 0..1, 0).unwrap();
+        let new_block = |block: &Block, source| Message::NewBlock {
+            block: block.clone(),
+            blockchain_length: 2,
+            source,
+            params_hash: None,
+        };
+        let (spoofed, genuine) = (new_block(&block, carol), new_block(&genesis, bob));
+        let (spoofed_item, genuine_item) = (spoofed.item().unwrap(), genuine.item().unwrap());
+        let connection = b.route(alice).unwrap();
+        for message in [spoofed, genuine] {
+            connection.send(Frame::Message {
+                destination: alice,
+                message,
+            });
+        }
+        let inbox = world_a.get_inbox(alice).await.unwrap();
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while !inbox.has_seen(&genuine_item) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("The genuine message was not delivered");
+        assert!(!inbox.has_seen(&spoofed_item));
+    }
+
+    #[tokio::test]
+    async fn ignore_banned_peers_over_loopback() {
+        let (world_a, world_b) = (
+            Box::leak(Box::new(World::new())),
+            Box::leak(Box::new(World::new())),
+        );
+        let mut node = Node::restore(
+            Address::new_random(),
+            BlockChain::with_difficulty(0),
+            vec![],
+        );
+        node.set_config(idle_node().config().clone());
+        let alice = world_a.insert_node(node).await.unwrap();
+        let bob = world_b.insert_node(idle_node()).await.unwrap();
+        let (a, b) = (
+            TcpNetwork::new(world_a, None),
+            TcpNetwork::new(world_b, None),
+        );
+        connect(&a, &b).await;
+        let node = world_a.get_node(alice).await.unwrap();
+        node.write().await.ban_peer(bob);
+
+        // The queries of a banned peer are not answered.
+        let genesis = Block::genesis();
+        assert!(b.query_block(&genesis.hash(), bob, alice).await.is_none());
+        assert!(b.query_tip(bob, alice).await.is_none());
+
+        // The blocks of a banned peer are ignored, the ones sent after unbanning it are not.
+        let new_block = |miner| {
+            let block =
+                attempt_mining_block_with_difficulty(genesis.hash(), miner, vec![], 0..1, 0)
+                    .unwrap();
+            Message::NewBlock {
+                block,
+                blockchain_length: 2,
+                source: bob,
+                params_hash: None,
+            }
+        };
+        let (ignored, adopted) = (new_block(bob), new_block(Address::new_random()));
+        let Message::NewBlock { block, .. } = &adopted else {
+            unreachable!()
+        };
+        let adopted_hash = block.hash();
+        let ignored_item = ignored.item().unwrap();
+        assert!(b.send(ignored, bob, alice).await);
+        let inbox = world_a.get_inbox(alice).await.unwrap();
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while !inbox.has_seen(&ignored_item) || !inbox.is_empty() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("The message of the banned peer was not processed");
+        node.write().await.unban_peer(bob);
+        assert!(b.send(adopted, bob, alice).await);
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while node.read().await.blockchain().last_hash() != &adopted_hash {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("The block of the unbanned peer was not adopted");
+        assert_eq!(node.read().await.blockchain().len(), 2);
+        assert!(b.query_tip(bob, alice).await.is_some());
+    }
+
+    /// Accept the connections of other processes on a loopback port, returning its address.
+    async fn listen_on_loopback(network: &Arc<TcpNetwork>) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(network.clone().accept(listener));
+        address
+    }
+
+    #[tokio::test]
+    async fn discover_processes_from_a_bootnode() {
+        let worlds = [(); 3].map(|()| &*Box::leak(Box::new(World::new())));
+        let mut nodes = Vec::new();
+        for world in worlds {
+            nodes.push(world.insert_node(idle_node()).await.unwrap());
+        }
+        let [bootnode, b, c] = worlds.map(|world| TcpNetwork::new(world, None));
+        let address = listen_on_loopback(&bootnode).await;
+        tokio::spawn(bootnode.clone().maintain(vec![], vec![], vec![]));
+        for network in [&b, &c] {
+            listen_on_loopback(network).await;
+            tokio::spawn(
+                network
+                    .clone()
+                    .maintain(vec![], vec![], vec![address.to_string()]),
+            );
+        }
+
+        // The processes that joined through the bootnode connect to each other: the nodes are
+        // only announced to the processes that are directly connected.
+        tokio::time::timeout(Duration::from_secs(20), async {
+            while !(b.route(nodes[2]).is_some() && c.route(nodes[1]).is_some()) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("The processes did not discover each other");
+        assert!(bootnode.route(nodes[1]).is_some() && bootnode.route(nodes[2]).is_some());
+    }
+
+    #[tokio::test]
+    async fn forget_connections_to_itself() {
+        let network = TcpNetwork::new(Box::leak(Box::new(World::new())), None);
+        let address = listen_on_loopback(&network).await;
+        network.discovered.lock().unwrap().insert(address);
+        assert_eq!(network.undialed_peers(), vec![address]);
+        network.dial(&address.to_string()).await.unwrap();
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while !network.connections.lock().unwrap().is_empty() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("The connection to itself was not closed");
+        assert!(network.own_addresses.lock().unwrap().contains(&address));
+        assert!(network.undialed_peers().is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn back_off_exponentially() {
+        let mut backoff = Backoff::default();
+        assert!(backoff.is_due("seed:7000"));
+        for delay in [1, 2, 4, 8, 16, 32, 60, 60] {
+            backoff.fail("seed:7000");
+            tokio::time::advance(Duration::from_secs(delay) - Duration::from_millis(1)).await;
+            assert!(!backoff.is_due("seed:7000"));
+            tokio::time::advance(Duration::from_millis(1)).await;
+            assert!(backoff.is_due("seed:7000"));
+        }
+        assert!(backoff.is_due("other:7000"));
+
+        // Reaching the process forgets its failures.
+        backoff.reset("seed:7000");
+        backoff.fail("seed:7000");
+        tokio::time::advance(Duration::from_secs(1)).await;
+        assert!(backoff.is_due("seed:7000"));
+        backoff.wait("seed:7000", Duration::from_secs(300));
+        tokio::time::advance(Duration::from_secs(299)).await;
+        assert!(!backoff.is_due("seed:7000"));
+    }
+
+    #[tokio::test]
+    async fn connect_to_seeds() {
+        let (world_a, world_b) = (
+            Box::leak(Box::new(World::new())),
+            Box::leak(Box::new(World::new())),
+        );
+        let alice = world_a.insert_node(idle_node()).await.unwrap();
+        world_b.insert_node(idle_node()).await.unwrap();
+        let (a, b) = (
+            TcpNetwork::new(world_a, None),
+            TcpNetwork::new(world_b, None),
+        );
+        let address = listen_on_loopback(&a).await;
+        // The host name of the seed may also resolve to addresses on which nothing listens, e.g.
+        // the IPv6 loopback, which are retried.
+        let seed = format!("localhost:{}", address.port());
+        tokio::spawn(b.clone().maintain(vec![], vec![seed], vec![]));
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while b.route(alice).is_none() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("The process did not connect to the seed");
+        assert!(b.is_connected_to(address));
+    }
+
+    #[tokio::test]
+    async fn encrypt_connections_between_known_keys() {
+        let worlds = [(); 3].map(|()| &*Box::leak(Box::new(World::new())));
+        let mut nodes = Vec::new();
+        for world in worlds {
+            nodes.push(world.insert_node(idle_node()).await.unwrap());
+        }
+        let keys = [
+            [1; encryption::KEY_BYTES],
+            [2; encryption::KEY_BYTES],
+            [3; encryption::KEY_BYTES],
+        ];
+        // The process of C knows the key of the one of A, which does not know its key.
+        let [a, b, c] = [(0, 1), (1, 0), (2, 0)].map(|(own, peer)| {
+            let keys = StaticKeys {
+                own: keys[own],
+                peers: vec![keys[peer]],
+            };
+            TcpNetwork::new(worlds[own], Some(keys))
+        });
+        let address = listen_on_loopback(&a).await;
+
+        // The processes that know each other's keys exchange messages.
+        b.dial(&address.to_string()).await.unwrap();
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while !(a.route(nodes[1]).is_some() && b.route(nodes[0]).is_some()) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("The processes did not exchange their nodes");
+        let genesis = Block::genesis();
+        let block = b.query_block(&genesis.hash(), nodes[1], nodes[0]).await;
+        assert_eq!(block.map(|block| block.hash()), Some(genesis.hash()));
+
+        // A process whose key is not known is disconnected, and so is a process without keys.
+        assert!(c.dial(&address.to_string()).await.is_none());
+        let plain = TcpNetwork::new(worlds[2], None);
+        plain.dial(&address.to_string()).await.unwrap();
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while !plain.connections.lock().unwrap().is_empty() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("The process without keys was not disconnected");
+        assert!(a.route(nodes[2]).is_none());
+    }
+
+    #[tokio::test]
+    async fn keep_the_routes_of_nodes_announced_by_other_processes() {
+        let worlds = [(); 3].map(|()| &*Box::leak(Box::new(World::new())));
+        let mut nodes = Vec::new();
+        for world in worlds {
+            nodes.push(world.insert_node(idle_node()).await.unwrap());
+        }
+        let [a, b, c] = worlds.map(|world| TcpNetwork::new(world, None));
+        connect(&a, &b).await;
+        connect(&a, &c).await;
+        let (alice, bob, carol) = (nodes[0], nodes[1], nodes[2]);
+        let route = a.route(bob).unwrap();
+
+        // The process of Carol claims to also host Bob, then sends a message, which is handled
+        // after its claim.
+        let connection = c.route(alice).unwrap();
+        connection.send(Frame::Hello {
+            version: TCP_PROTOCOL_VERSION,
+            nodes: vec![carol, bob],
+        });
+        let message = Message::NewBlock {
+            block: Block::genesis(),
+            blockchain_length: 1,
+            source: carol,
+            params_hash: None,
+        };
+        let item = message.item().unwrap();
+        connection.send(Frame::Message {
+            destination: alice,
+            message,
+        });
+        let inbox = worlds[0].get_inbox(alice).await.unwrap();
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while !inbox.has_seen(&item) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("The message was not delivered");
+        assert!(Arc::ptr_eq(&a.route(bob).unwrap(), &route));
+        assert!(!Arc::ptr_eq(&a.route(carol).unwrap(), &route));
+    }
+}
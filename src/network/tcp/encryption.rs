@@ -0,0 +1,241 @@
+//! The optional encryption of the connections of the TCP network backend, authenticated by static
+//! keys.
+//!
+//! Each process has a secret key of 32 bytes, and knows the keys of the processes that may connect
+//! to it. On a new connection, each end sends a random nonce, then proves that it knows one of the
+//! keys known by the other end, with the HMAC-SHA256 of both nonces:
+//!
+//! ```text
+//! Handshake, each a frame:
+//!     nonce:        32 random bytes
+//!     proof:        HMAC-SHA256(key of the sender, nonce of the sender || nonce of the receiver)
+//!     confirmation: an empty encrypted frame
+//! ```
+//!
+//! Both ends then derive a secret from their two keys and nonces, and from it the keys that encrypt
+//! the frames in each direction, with ChaCha20, and authenticate them, with a truncated
+//! HMAC-SHA256 of the number of the frame and of its encrypted bytes. So frames that are altered,
+//! replayed or reordered close the connection. The lengths of the frames are not encrypted.
+//!
+//! This is neither Noise nor TLS, but a small protocol to show the principles: the keys are shared
+//! secrets rather than key pairs, so a process can impersonate the ones whose keys it knows, and
+//! there is no forward secrecy, so whoever learns both keys can decrypt the recorded connections
+//! between their processes.
+
+use super::{read_frame, write_frame};
+use rand_chacha::rand_core::{RngCore, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use sha2::{Digest, Sha256};
+use std::io::{Error, ErrorKind};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// The size of the keys and of the nonces.
+pub const KEY_BYTES: usize = 32;
+
+/// The size of the authentication tag that follows the encrypted bytes of each frame.
+const TAG_BYTES: usize = 16;
+
+/// The key of a process, and the ones of the processes that may connect to it.
+#[derive(Clone)]
+pub struct StaticKeys {
+    pub own: [u8; KEY_BYTES],
+    pub peers: Vec<[u8; KEY_BYTES]>,
+}
+
+/// The keys that encrypt and authenticate the frames sent in one direction of a connection.
+pub struct Cipher {
+    encryption_key: [u8; KEY_BYTES],
+    authentication_key: [u8; KEY_BYTES],
+    /// The number of the next frame, which is also the nonce of its encryption.
+    counter: u64,
+}
+
+impl Cipher {
+    /// The cipher of the frames sent by the end of a connection that chose `nonce`.
+    fn new(secret: &[u8; KEY_BYTES], nonce: &[u8; KEY_BYTES]) -> Self {
+        Cipher {
+            encryption_key: hmac(secret, &[b"encryption", nonce]),
+            authentication_key: hmac(secret, &[b"authentication", nonce]),
+            counter: 0,
+        }
+    }
+
+    /// Encrypt the bytes of the next frame, followed by their authentication tag.
+    pub fn seal(&mut self, bytes: &[u8]) -> Vec<u8> {
+        let mut sealed = bytes.to_vec();
+        self.apply_keystream(&mut sealed);
+        let tag = self.tag(&sealed);
+        sealed.extend_from_slice(&tag);
+        self.counter += 1;
+        sealed
+    }
+
+    /// Check the authentication tag of the next frame, and decrypt its bytes.
+    pub fn open(&mut self, sealed: &[u8]) -> std::io::Result<Vec<u8>> {
+        let invalid = || Error::new(ErrorKind::InvalidData, "frame with an invalid tag");
+        let length = sealed.len().checked_sub(TAG_BYTES).ok_or_else(invalid)?;
+        let (encrypted, tag) = sealed.split_at(length);
+        if !constant_time_eq(&self.tag(encrypted), tag) {
+            return Err(invalid());
+        }
+        let mut bytes = encrypted.to_vec();
+        self.apply_keystream(&mut bytes);
+        self.counter += 1;
+        Ok(bytes)
+    }
+
+    fn apply_keystream(&self, bytes: &mut [u8]) {
+        let mut rng = ChaCha20Rng::from_seed(self.encryption_key);
+        rng.set_stream(self.counter);
+        let mut keystream = vec![0; bytes.len()];
+        rng.fill_bytes(&mut keystream);
+        for (byte, key) in bytes.iter_mut().zip(keystream) {
+            *byte ^= key;
+        }
+    }
+
+    fn tag(&self, encrypted: &[u8]) -> [u8; TAG_BYTES] {
+        let tag = hmac(
+            &self.authentication_key,
+            &[&self.counter.to_le_bytes(), encrypted],
+        );
+        tag[..TAG_BYTES].try_into().unwrap()
+    }
+}
+
+/// Authenticate the process at the other end of a new connection, returning the ciphers of the
+/// frames sent and received on the connection. Fails if the other process does not know one of
+/// the keys of `keys.peers`, other than the one of this process, or does not know this one.
+pub async fn handshake(
+    stream: &mut (impl AsyncRead + AsyncWrite + Unpin),
+    keys: &StaticKeys,
+) -> std::io::Result<(Cipher, Cipher)> {
+    let nonce: [u8; KEY_BYTES] = rand::random();
+    write_frame(stream, &nonce).await?;
+    let peer_nonce: [u8; KEY_BYTES] = read_frame(stream)
+        .await?
+        .try_into()
+        .map_err(|_| Error::new(ErrorKind::InvalidData, "invalid nonce"))?;
+
+    write_frame(stream, &proof(&keys.own, &nonce, &peer_nonce)).await?;
+    let peer_proof = read_frame(stream).await?;
+    // A process never accepts its own key, so that the proof of a connection cannot be replayed
+    // on another connection to the same process.
+    let peer_key = keys
+        .peers
+        .iter()
+        .filter(|key| **key != keys.own)
+        .find(|key| constant_time_eq(&proof(key, &peer_nonce, &nonce), &peer_proof))
+        .ok_or_else(|| Error::new(ErrorKind::PermissionDenied, "unknown key"))?;
+
+    let secret = if nonce < peer_nonce {
+        hmac(&[keys.own, *peer_key].concat(), &[&nonce, &peer_nonce])
+    } else {
+        hmac(&[*peer_key, keys.own].concat(), &[&peer_nonce, &nonce])
+    };
+    let (mut sender, mut receiver) = (
+        Cipher::new(&secret, &nonce),
+        Cipher::new(&secret, &peer_nonce),
+    );
+
+    // The other process may not know the key of this one, in which case it closes the connection.
+    write_frame(stream, &sender.seal(&[])).await?;
+    receiver.open(&read_frame(stream).await?)?;
+    Ok((sender, receiver))
+}
+
+fn proof(key: &[u8; KEY_BYTES], nonce: &[u8], peer_nonce: &[u8]) -> [u8; 32] {
+    hmac(key, &[b"proof", nonce, peer_nonce])
+}
+
+/// The HMAC-SHA256 of the concatenation of some parts, with a key of at most 64 bytes.
+fn hmac(key: &[u8], parts: &[&[u8]]) -> [u8; 32] {
+    let mut block = [0; 64];
+    block[..key.len()].copy_from_slice(key);
+    let mut inner = Sha256::new();
+    inner.update(block.map(|byte| byte ^ 0x36));
+    for part in parts {
+        inner.update(part);
+    }
+    let mut outer = Sha256::new();
+    outer.update(block.map(|byte| byte ^ 0x5c));
+    outer.update(inner.finalize());
+    outer.finalize().into()
+}
+
+/// Whether two sequences of bytes are equal, in a time that does not depend on where they differ.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hmac_sha256() {
+        // The second test case of RFC 4231.
+        let mac = hmac(b"Jefe", &[b"what do ya want ", b"for nothing?"]);
+        let hex: String = mac.iter().map(|byte| format!("{byte:02x}")).collect();
+        assert_eq!(
+            hex,
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn seal_and_open_frames() {
+        let (secret, nonce) = ([1; KEY_BYTES], [2; KEY_BYTES]);
+        let (mut sender, mut receiver) =
+            (Cipher::new(&secret, &nonce), Cipher::new(&secret, &nonce));
+        let first = sender.seal(b"first frame");
+        let second = sender.seal(b"first frame");
+        assert_eq!(first.len(), b"first frame".len() + TAG_BYTES);
+        assert!(!first.windows(5).any(|window| window == b"first"));
+        // The same bytes are encrypted differently in each frame.
+        assert_ne!(first, second);
+        assert_eq!(receiver.open(&first).unwrap(), b"first frame");
+
+        // Frames that are altered, replayed or truncated are refused.
+        let mut altered = second.clone();
+        altered[0] ^= 1;
+        for sealed in [&altered, &first, &second[..TAG_BYTES - 1].to_vec()] {
+            let err = receiver.open(sealed).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+        }
+        assert_eq!(receiver.open(&second).unwrap(), b"first frame");
+
+        // The other direction of the connection has other keys.
+        let mut other = Cipher::new(&secret, &[3; KEY_BYTES]);
+        assert!(other.open(&sender.seal(b"")).is_err());
+    }
+
+    #[tokio::test]
+    async fn authenticate_processes_by_their_keys() {
+        let keys = [[1; KEY_BYTES], [2; KEY_BYTES], [3; KEY_BYTES]];
+        let trusting = |own: usize, peers: &[usize]| StaticKeys {
+            own: keys[own],
+            peers: peers.iter().map(|peer| keys[*peer]).collect(),
+        };
+        // Each end closes the connection when its handshake fails.
+        let end = |mut stream: tokio::io::DuplexStream, keys: StaticKeys| async move {
+            handshake(&mut stream, &keys).await
+        };
+        let connect = |a: StaticKeys, b: StaticKeys| async move {
+            let (client, server) = tokio::io::duplex(1024);
+            tokio::join!(end(client, a), end(server, b))
+        };
+
+        let (a, b) = connect(trusting(0, &[1]), trusting(1, &[0, 2])).await;
+        let ((mut a_sender, _), (_, mut b_receiver)) = (a.unwrap(), b.unwrap());
+        assert_eq!(b_receiver.open(&a_sender.seal(b"hello")).unwrap(), b"hello");
+
+        // Each process refuses the keys it does not know, and the other one notices.
+        let (a, b) = connect(trusting(0, &[1]), trusting(2, &[0])).await;
+        assert_eq!(a.err().unwrap().kind(), ErrorKind::PermissionDenied);
+        assert!(b.is_err());
+        // A process does not accept its own key.
+        let (a, b) = connect(trusting(0, &[0]), trusting(0, &[0])).await;
+        assert!(a.is_err() && b.is_err());
+    }
+}
@@ -1,32 +1,173 @@
 use crate::prelude::*;
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::RwLock;
 
+/// The builder of the blocks that the nodes mine, if not the default one.
+static TEMPLATE_BUILDER: std::sync::RwLock<Option<Arc<dyn BlockTemplateBuilder>>> =
+    std::sync::RwLock::new(None);
+
+/// Replace the builder of the blocks that all the nodes mine, from their next mining attempt.
+pub fn set_block_template_builder(builder: impl BlockTemplateBuilder + 'static) {
+    *TEMPLATE_BUILDER.write().unwrap() = Some(Arc::new(builder));
+}
+
+/// Build the block that a node mines, with the current builder.
+fn build_block_template(blockchain: &BlockChain, mempool: &MemPool, miner: Address) -> Block {
+    match TEMPLATE_BUILDER.read().unwrap().as_ref() {
+        Some(builder) => builder.build(blockchain, mempool, miner),
+        None => DefaultTemplateBuilder.build(blockchain, mempool, miner),
+    }
+}
+
 /// Run a node in the blockchain network. This function will run until the node is stopped.
 pub async fn run_node(node: Arc<RwLock<Node>>) {
+    initial_block_download(&node).await;
+
+    let world = world_of(&*node.read().await).await;
     loop {
+        let running = world.running().await;
         if !node.read().await.alive {
             break;
         }
 
         let mut writable_node = node.write().await;
+        writable_node.process_messages().await;
         writable_node.achieve_consensus().await;
+        writable_node.rebroadcast_transactions().await;
+        writable_node.announce_tip().await;
         if writable_node.mining() {
-            network()
-                .await
-                .broadcast_block(writable_node.blockchain().last_block(), writable_node.blockchain.len(), writable_node.address())
-                .await;
+            writable_node.announce_mined_block().await;
         }
+        writable_node.persist_blocks();
 
         // It's important to release all lock before yielding, to avoid deadlocks.
         drop(writable_node);
+        drop(running);
 
         // Cooperative preemption.
         tokio::task::yield_now().await;
     }
 }
 
+/// Download the longest blockchain available among the peers, before starting to mine.
+/// The lock of the node is only held for short periods, so that the progress can be observed.
+async fn initial_block_download(node: &Arc<RwLock<Node>>) {
+    let network = NetworkHandle::of(&*node.read().await);
+    let address = network.address();
+    let banned_peers = node.read().await.banned_peers().clone();
+    let world = world_of(&*node.read().await).await;
+    let running = world.running().await;
+
+    // Find the peer with the longest blockchain
+    let mut best_tip: Option<(Block, usize, Address)> = None;
+    for peer in world.get_node_addresses().await {
+        if peer == address || banned_peers.contains(&peer) {
+            continue;
+        }
+        let Some((block, length)) = network.query_tip(peer).await else {
+            continue;
+        };
+        if best_tip
+            .as_ref()
+            .is_none_or(|(_, best_length, _)| length > *best_length)
+        {
+            best_tip = Some((block, length, peer));
+        }
+    }
+    let Some((tip, length, source)) = best_tip else {
+        return;
+    };
+    if length <= node.read().await.blockchain().len() {
+        return;
+    }
+
+    info!(
+        "Node {address}: Starting the initial block download of a blockchain of length {length} \
+        from {source}"
+    );
+    node.write().await.sync.start(length);
+
+    let mut last_common_hash = tip.prefix_hash.clone();
+    let mut new_blocks = vec![tip];
+    node.write().await.sync.record_download();
+    drop(running);
+    while !node.read().await.blockchain().contains(&last_common_hash) {
+        // The world can be paused between two downloads.
+        let _running = world.running().await;
+        let Some(block) = network.query_block(&last_common_hash, source).await else {
+            error!("Node {address}: Failed to fetch block {last_common_hash} from the network");
+            let mut writable_node = node.write().await;
+            let length = writable_node.blockchain.len();
+            writable_node.sync.reset_best_known_length(length);
+            writable_node.sync.finish();
+            return;
+        };
+        last_common_hash = block.prefix_hash.clone();
+        new_blocks.push(block);
+        node.write().await.sync.record_download();
+    }
+
+    let _running = world.running().await;
+    let mut writable_node = node.write().await;
+    if writable_node.blockchain.len() < length {
+        let _ = writable_node
+            .adopt_blocks(&last_common_hash, new_blocks, length, source)
+            .await;
+    }
+    writable_node.sync.finish();
+}
+
+/// The world in which a node was started, or the global one if it was not started.
+async fn world_of(node: &Node) -> &'static World {
+    match node.world() {
+        Some(world) => world,
+        None => world().await,
+    }
+}
+
+/// The messages with which a drained node leaves the network.
+pub struct Departure {
+    network: NetworkHandle,
+    /// The pending transactions of the node, to hand over to the peers.
+    transactions: Vec<BlockTransaction>,
+}
+
+impl Departure {
+    /// Send the pending transactions of the node to all its peers, then announce its departure.
+    /// Returns how many transactions were handed over.
+    pub async fn send(self) -> usize {
+        let source = self.network.address();
+        let world = match self.network.world() {
+            Some(world) => world,
+            None => world().await,
+        };
+        let peers: Vec<Address> = world
+            .get_node_addresses()
+            .await
+            .into_iter()
+            .filter(|peer| *peer != source)
+            .collect();
+        for transaction in &self.transactions {
+            for &peer in &peers {
+                let message = Message::Transaction {
+                    transaction: transaction.clone(),
+                    source,
+                };
+                self.network.send_message(message, peer).await;
+            }
+        }
+        for &peer in &peers {
+            self.network
+                .send_message(Message::Departure { source }, peer)
+                .await;
+        }
+        self.transactions.len()
+    }
+}
+
 /// A node in the blockchain network.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Node {
@@ -42,11 +183,82 @@ pub struct Node {
     mempool: MemPool,
     /// A better blockchain proposed by the network.
     better_blockchain: Option<BetterBlockChain>,
+    /// The progress of the initial block download.
+    sync: SyncStatus,
+    /// The propagation of the most recent transactions received from clients.
+    propagation: HashMap<TransactionId, TransactionPropagation>,
+    /// The transactions in `propagation`, from the oldest to the most recent.
+    propagation_order: VecDeque<TransactionId>,
+    /// Whether the most recent transactions received from clients were accepted, from the oldest
+    /// to the most recent.
+    #[serde(default)]
+    submissions: VecDeque<Submission>,
+    /// The messages delivered by the network, waiting to be processed.
+    #[serde(skip)]
+    inbox: Arc<Inbox>,
+    /// The statistics of the mining attempts, including the current nonce budget.
+    mining_stats: MiningStats,
+    /// How many blocks the node relayed or refused to relay, in gossip mode.
+    relay_stats: RelayStats,
+    /// How many blocks and transactions the node rejected, by validation rule.
+    #[serde(default)]
+    validation_stats: ValidationStats,
+    /// The reward (coins per mined block plus fees) of each block mined by the node, including
+    /// the ones that later became orphans.
+    #[serde(default)]
+    mined_rewards: HashMap<BlockHash, Amount>,
+    /// How many blocks each peer sent with the hash of other protocol parameters.
+    #[serde(default)]
+    param_mismatches: BTreeMap<Address, u64>,
+    /// The peers excluded by the operator, whose messages and queries the node ignores.
+    #[serde(default)]
+    banned_peers: BTreeSet<Address>,
+    /// The most recent blocks prepared for external miners, from the oldest to the most recent.
+    #[serde(default)]
+    work: VecDeque<Work>,
+    /// The identifier of the last work prepared for external miners.
+    #[serde(default)]
+    next_work_id: u64,
+    /// The settings of the node.
+    config: NodeConfig,
+    /// The consensus engine of the world in which the node was started.
+    #[serde(skip, default = "default_consensus_engine")]
+    consensus: Arc<dyn ConsensusEngine>,
+    /// When each pending transaction was last broadcast, as far as the node knows.
+    #[serde(skip)]
+    broadcast_at: HashMap<TransactionId, Instant>,
+    /// When the node last looked for pending transactions to broadcast again.
+    #[serde(skip)]
+    last_rebroadcast_check: Option<Instant>,
+    /// When the node last announced its tip to its peers.
+    #[serde(skip)]
+    last_heartbeat: Option<Instant>,
+    /// The missing blocks and transactions announced by each peer, to request from it after
+    /// processing a batch of messages.
+    #[serde(skip)]
+    requests: HashMap<Address, Vec<Item>>,
+    /// Where the node stores its blocks as soon as they change, if it does.
+    #[serde(skip)]
+    block_store: Option<BlockStoreWriter>,
+    /// The world in which the node was started, through whose network it communicates.
+    #[serde(skip)]
+    world: Option<&'static World>,
+}
+
+fn default_consensus_engine() -> Arc<dyn ConsensusEngine> {
+    Arc::new(ProofOfWork)
+}
+
+impl Default for Node {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Node {
     pub fn new() -> Self {
-        let blockchain = BlockChain::new();
+        let mut blockchain = BlockChain::new();
+        blockchain.set_rules(config().transaction_rules);
         let mempool = MemPool::new(&blockchain);
         Node {
             alive: true,
@@ -55,18 +267,188 @@ impl Node {
             next_nonce: 0,
             mempool,
             better_blockchain: None,
+            sync: SyncStatus::default(),
+            propagation: HashMap::new(),
+            propagation_order: VecDeque::new(),
+            submissions: VecDeque::new(),
+            inbox: Arc::default(),
+            mining_stats: MiningStats::default(),
+            relay_stats: RelayStats::default(),
+            validation_stats: ValidationStats::default(),
+            mined_rewards: HashMap::new(),
+            param_mismatches: BTreeMap::new(),
+            banned_peers: BTreeSet::new(),
+            work: VecDeque::new(),
+            next_work_id: 0,
+            config: NodeConfig::default(),
+            consensus: default_consensus_engine(),
+            broadcast_at: HashMap::new(),
+            last_rebroadcast_check: None,
+            last_heartbeat: None,
+            requests: HashMap::new(),
+            block_store: None,
+            world: None,
+        }
+    }
+
+    /// Create a node with a specific address, e.g. to replay the messages delivered to it.
+    pub fn with_address(address: Address) -> Self {
+        Node {
+            address,
+            ..Node::new()
+        }
+    }
+
+    /// Create a node from a saved blockchain and the pending transactions of its mempool.
+    /// Transactions that are no longer valid on top of the last block are dropped.
+    pub fn restore(
+        address: Address,
+        blockchain: BlockChain,
+        transactions: Vec<BlockTransaction>,
+    ) -> Self {
+        let mut mempool = MemPool::new(&blockchain);
+        for transaction in transactions {
+            if mempool.add_transaction(transaction.clone()).is_err() {
+                warn!("Node {address}: Dropping saved transaction {transaction}");
+            }
+        }
+        info!(
+            "Node {address}: Restored a blockchain of length {} and {} pending transactions",
+            blockchain.len(),
+            mempool.len()
+        );
+        Node {
+            address,
+            blockchain,
+            mempool,
+            ..Node::new()
+        }
+    }
+
+    /// Create a node with a copy of the blockchain, the mempool and the settings of this node, but
+    /// with a new random address and none of its other state.
+    pub fn fork(&self) -> Self {
+        let mut node = Node::new();
+        node.blockchain = self.blockchain.clone();
+        node.mempool = self.mempool.clone();
+        node.set_config(self.config.clone());
+        node.consensus = self.consensus.clone();
+        node
+    }
+
+    /// Create a fresh node from the blocks and the pending transactions of this node, validated
+    /// again, with the same address, inbox and settings. Used to restart a node whose task failed,
+    /// whose other state may be inconsistent. If the blocks are not valid anymore, the node starts
+    /// again from the genesis block.
+    pub fn recover(&self) -> Self {
+        let mut blockchain = BlockChain::with_difficulty(self.blockchain.difficulty());
+        blockchain.set_rules(*self.blockchain.rules());
+        let blocks = self.blockchain.blocks().skip(1).cloned();
+        if blockchain.append_blocks(blocks).is_err() {
+            warn!("Node {self}: Dropping the invalid blockchain of the failed node");
+            blockchain = BlockChain::with_difficulty(self.blockchain.difficulty());
+            blockchain.set_rules(*self.blockchain.rules());
         }
+        let mut node = Node::restore(
+            self.address,
+            blockchain,
+            self.mempool.transactions().clone(),
+        );
+        node.inbox = self.inbox.clone();
+        node.banned_peers = self.banned_peers.clone();
+        node.set_config(self.config.clone());
+        node.consensus = self.consensus.clone();
+        node
+    }
+
+    /// The settings of the node.
+    pub fn config(&self) -> &NodeConfig {
+        &self.config
+    }
+
+    /// Change the settings of the node.
+    pub fn set_config(&mut self, config: NodeConfig) {
+        self.mempool.set_capacity(config.max_mempool_transactions);
+        self.config = config;
     }
 
     pub fn address(&self) -> Address {
         self.address
     }
 
+    /// The consensus engine that the node follows.
+    pub fn consensus_engine(&self) -> &Arc<dyn ConsensusEngine> {
+        &self.consensus
+    }
+
+    /// Replace the consensus engine that the node follows.
+    pub fn set_consensus_engine(&mut self, consensus: Arc<dyn ConsensusEngine>) {
+        self.consensus = consensus;
+    }
+
+    /// Whether the node runs, i.e. it has not been stopped.
+    pub fn is_alive(&self) -> bool {
+        self.alive
+    }
+
     /// Stop the node.
     pub fn stop(&mut self) {
         self.alive = false;
     }
 
+    /// Leave the network gracefully: stop running and collect the messages that hand the pending
+    /// transactions over to all the peers and announce the departure. They are sent by
+    /// `Departure::send`, once the lock of the node is released, so that the node does not block
+    /// the peers that query it meanwhile.
+    pub fn drain(&mut self) -> Departure {
+        self.stop();
+        Departure {
+            network: self.network(),
+            transactions: self.mempool.transactions().clone(),
+        }
+    }
+
+    /// Store the blocks of the node as soon as they change, from the next iteration of its run
+    /// loop, replacing the blocks already in the store. Must be called within a Tokio runtime.
+    pub fn set_block_store(&mut self, store: Box<dyn BlockStore>) {
+        self.block_store = Some(BlockStoreWriter::start(self.address, store));
+    }
+
+    /// Send the blocks that changed to the store of the blocks, if any, which writes them in the
+    /// background.
+    fn persist_blocks(&mut self) {
+        if let Some(writer) = &mut self.block_store {
+            writer.update(&self.blockchain);
+        }
+    }
+
+    /// Send the blocks that changed to the store of the blocks, if any, and wait until they are
+    /// all written, or failed to be, without holding the lock of the node.
+    pub fn flush_blocks(&mut self) -> impl std::future::Future<Output = ()> {
+        self.persist_blocks();
+        let flushed = self.block_store.as_ref().map(BlockStoreWriter::flushed);
+        async {
+            if let Some(flushed) = flushed {
+                flushed.await;
+            }
+        }
+    }
+
+    /// The world in which the node was started, if any.
+    pub fn world(&self) -> Option<&'static World> {
+        self.world
+    }
+
+    /// Start the node in a world, whose network it uses from now on.
+    pub(crate) fn set_world(&mut self, world: &'static World) {
+        self.world = Some(world);
+    }
+
+    /// The network interface of the node, which sends the messages on its behalf.
+    pub fn network(&self) -> NetworkHandle {
+        NetworkHandle::of(self)
+    }
+
     pub fn get_block(&self, block: &BlockHash) -> Option<Block> {
         self.blockchain.get_block(block).cloned()
     }
@@ -79,25 +461,554 @@ impl Node {
         &self.mempool
     }
 
+    pub fn sync_status(&self) -> &SyncStatus {
+        &self.sync
+    }
+
+    /// The messages delivered by the network, waiting to be processed.
+    pub fn inbox(&self) -> &Arc<Inbox> {
+        &self.inbox
+    }
+
+    pub fn mining_stats(&self) -> &MiningStats {
+        &self.mining_stats
+    }
+
+    pub fn relay_stats(&self) -> &RelayStats {
+        &self.relay_stats
+    }
+
+    pub fn validation_stats(&self) -> &ValidationStats {
+        &self.validation_stats
+    }
+
+    /// How many of the blocks mined by the node are in its blockchain, and how many became
+    /// orphans after a reorganization, with their rewards.
+    pub fn miner_stats(&self) -> MinerStats {
+        let mut miner_stats = MinerStats::default();
+        for (block_hash, reward) in &self.mined_rewards {
+            if self.blockchain.contains(block_hash) {
+                miner_stats.canonical_blocks += 1;
+                miner_stats.earned_rewards = miner_stats.earned_rewards.saturating_add(*reward);
+            } else {
+                miner_stats.orphaned_blocks += 1;
+                miner_stats.lost_rewards = miner_stats.lost_rewards.saturating_add(*reward);
+            }
+        }
+        miner_stats
+    }
+
+    /// Process the messages delivered by the network, up to `NODE_INBOX_BATCH` of them, and adapt
+    /// the nonce budget of the next mining attempts to how many messages were waiting.
+    async fn process_messages(&mut self) {
+        let inbox_depth = self.inbox.len();
+        for _ in 0..NODE_INBOX_BATCH {
+            let Some(message) = self.inbox.pop() else {
+                break;
+            };
+            self.process_message(message).await;
+        }
+        self.request_data().await;
+        self.mining_stats.adapt_nonce_budget(inbox_depth);
+    }
+
+    async fn process_message(&mut self, message: Message) {
+        if self.is_banned(message.source()) {
+            debug!(
+                "Node {self}: Ignoring a message of type {} from banned peer {}",
+                message.kind(),
+                message.source()
+            );
+            return;
+        }
+        match message {
+            Message::NewBlock {
+                block,
+                blockchain_length,
+                source,
+                params_hash,
+            } => {
+                let own_params_hash = self.blockchain.params().hash();
+                if params_hash.is_some_and(|params_hash| params_hash != own_params_hash) {
+                    error!(
+                        "Node {self}: Rejecting block {block} from {source}, \
+                        which follows other protocol parameters"
+                    );
+                    *self.param_mismatches.entry(source).or_default() += 1;
+                    return;
+                }
+                // Check the block before receiving it, which makes it the better blockchain.
+                let can_relay = config()
+                    .should_forward()
+                    .then(|| self.can_relay(&block, blockchain_length));
+                let is_new = self
+                    .receive_new_block(block.clone(), blockchain_length, source)
+                    .await;
+                match can_relay {
+                    Some(true) if is_new => {
+                        self.relay_stats.relayed_blocks += 1;
+                        self.network()
+                            .broadcast_block(&block, blockchain_length, &own_params_hash)
+                            .await;
+                    }
+                    Some(false) if is_new => {
+                        self.relay_stats.suppressed_blocks += 1;
+                    }
+                    _ => {}
+                }
+            }
+            Message::Transaction {
+                transaction,
+                source,
+            } => {
+                let transaction_id = transaction.id;
+                let is_new = !self.mempool.contains(&transaction_id);
+                let accepted = if self.config.peer_policy == PeerPolicy::BlocksOnly {
+                    debug!("Node {self}: Rejecting transaction {transaction} from {source}");
+                    false
+                } else {
+                    self.add_transaction(transaction.clone()).is_ok()
+                };
+                if accepted && is_new && config().should_forward() {
+                    self.network().broadcast_transaction(&transaction).await;
+                }
+                self.network()
+                    .send_transaction_receipt(transaction_id, accepted, source)
+                    .await;
+            }
+            Message::TransactionReceipt {
+                transaction_id,
+                peer,
+                accepted,
+            } => self.receive_transaction_receipt(transaction_id, peer, accepted),
+            Message::Inventory { items, source } => self.receive_inventory(items, source),
+            Message::GetData { items, source } => self.send_data(items, source).await,
+            Message::Departure { source } => self.forget_peer(source),
+            Message::Heartbeat {
+                blockchain_length,
+                last_block_hash,
+                cumulative_work,
+                source,
+            } => {
+                self.receive_heartbeat(blockchain_length, last_block_hash, cumulative_work, source)
+            }
+        }
+    }
+
+    /// Request the last block announced by a peer whose blockchain has more work, as if the peer
+    /// announced it in an inventory, so that the node catches up even if it missed the block.
+    fn receive_heartbeat(
+        &mut self,
+        blockchain_length: usize,
+        last_block_hash: BlockHash,
+        cumulative_work: u128,
+        source: Address,
+    ) {
+        if cumulative_work <= self.blockchain.cumulative_work() {
+            return;
+        }
+        debug!(
+            "Node {self}: Peer {source} announced a blockchain of length {blockchain_length} with \
+            more work"
+        );
+        self.receive_inventory(vec![Item::Block(last_block_hash)], source);
+    }
+
+    /// Stop waiting for a peer that left the network: drop the requests to send to it and the
+    /// better blockchain that it proposed, which can no longer be downloaded.
+    fn forget_peer(&mut self, peer: Address) {
+        info!("Node {self}: Peer {peer} left the network");
+        self.forget_requests(peer);
+    }
+
+    /// Drop the requests to send to a peer and the better blockchain that it proposed.
+    fn forget_requests(&mut self, peer: Address) {
+        self.requests.remove(&peer);
+        if self
+            .better_blockchain
+            .as_ref()
+            .is_some_and(|better| better.source == peer)
+        {
+            self.better_blockchain = None;
+        }
+    }
+
+    /// The peers excluded by the operator.
+    pub fn banned_peers(&self) -> &BTreeSet<Address> {
+        &self.banned_peers
+    }
+
+    /// Whether the operator excluded a peer.
+    pub fn is_banned(&self, peer: Address) -> bool {
+        self.banned_peers.contains(&peer)
+    }
+
+    /// Exclude a peer: from now on, the node ignores its messages and its queries, and does not
+    /// download blocks from it. Returns whether the peer was not banned yet.
+    pub fn ban_peer(&mut self, peer: Address) -> bool {
+        if !self.banned_peers.insert(peer) {
+            return false;
+        }
+        info!("Node {self}: Banned peer {peer}");
+        self.forget_requests(peer);
+        true
+    }
+
+    /// Stop excluding a peer. Returns whether the peer was banned.
+    pub fn unban_peer(&mut self, peer: Address) -> bool {
+        if !self.banned_peers.remove(&peer) {
+            return false;
+        }
+        info!("Node {self}: Unbanned peer {peer}");
+        true
+    }
+
+    /// The length of the blockchain that ends with a block, if the node knows the block: either in
+    /// its blockchain or as the last block of the better blockchain proposed by the network.
+    fn known_length(&self, block_hash: &BlockHash) -> Option<usize> {
+        self.blockchain
+            .hashes()
+            .position(|hash| hash == block_hash)
+            .map(|index| index + 1)
+            .or_else(|| {
+                self.better_blockchain
+                    .as_ref()
+                    .filter(|better| &better.last_block.hash() == block_hash)
+                    .map(|better| better.length)
+            })
+    }
+
+    /// Remember to request the announced blocks and transactions that the node does not know and
+    /// that it has not requested yet.
+    fn receive_inventory(&mut self, items: Vec<Item>, source: Address) {
+        for item in items {
+            let known = match &item {
+                Item::Block(block_hash) => self.known_length(block_hash).is_some(),
+                Item::Transaction(transaction_id) => {
+                    self.config.peer_policy == PeerPolicy::BlocksOnly
+                        || self.mempool.contains(transaction_id)
+                }
+            };
+            let requested = self.requests.values().flatten().any(|other| other == &item);
+            if known || requested || self.inbox.has_seen(&item) {
+                continue;
+            }
+            self.requests.entry(source).or_default().push(item);
+        }
+    }
+
+    /// Request the missing blocks and transactions from the peers that announced them, with one
+    /// message for each peer.
+    async fn request_data(&mut self) {
+        for (peer, items) in std::mem::take(&mut self.requests) {
+            debug!("Node {self}: Requesting {} items from {peer}", items.len());
+            let message = Message::GetData {
+                items,
+                source: self.address,
+            };
+            self.network().send_message(message, peer).await;
+        }
+    }
+
+    /// Send to a peer the requested blocks and transactions that the node knows.
+    async fn send_data(&self, items: Vec<Item>, peer: Address) {
+        for item in items {
+            let message = match &item {
+                Item::Block(block_hash) => self.known_length(block_hash).map(|blockchain_length| {
+                    let block = self.get_block(block_hash).unwrap_or_else(|| {
+                        self.better_blockchain.as_ref().unwrap().last_block.clone()
+                    });
+                    Message::NewBlock {
+                        block,
+                        blockchain_length,
+                        source: self.address,
+                        params_hash: Some(self.blockchain.params().hash()),
+                    }
+                }),
+                Item::Transaction(transaction_id) => self
+                    .mempool
+                    .transactions()
+                    .iter()
+                    .find(|transaction| &transaction.id == transaction_id)
+                    .map(|transaction| Message::Transaction {
+                        transaction: transaction.clone(),
+                        source: self.address,
+                    }),
+            };
+            let Some(message) = message else {
+                debug!("Node {self}: Cannot send {item:?} requested by {peer}");
+                continue;
+            };
+            self.network().send_message(message, peer).await;
+        }
+    }
+
+    /// Check that a block can be relayed: it must have a valid proof of work and it must extend a
+    /// block that the node knows, at the announced length. The transactions of the block are only
+    /// checked when the node adopts it.
+    fn can_relay(&self, block: &Block, blockchain_length: usize) -> bool {
+        if !block.meets_difficulty(self.blockchain.difficulty()) {
+            warn!("Node {self}: Not relaying block {block}, whose proof of work is invalid");
+            return false;
+        }
+        match self.known_length(&block.prefix_hash) {
+            Some(length) if length + 1 == blockchain_length => true,
+            Some(length) => {
+                warn!(
+                    "Node {self}: Not relaying block {block}, which is announced at length \
+                    {blockchain_length} instead of {}",
+                    length + 1
+                );
+                false
+            }
+            None => {
+                debug!("Node {self}: Not relaying block {block}, whose previous block is unknown");
+                false
+            }
+        }
+    }
+
+    /// Process a single message and switch to the better blockchain that it announces, if any,
+    /// without mining. Used to replay captured messages deterministically.
+    pub async fn replay_message(&mut self, message: Message) {
+        self.process_message(message).await;
+        self.request_data().await;
+        self.achieve_consensus().await;
+    }
+
+    /// Receive a block submitted by a client, e.g. a crafted one, as if a peer announced it on top
+    /// of its prefix, and switch to it if it is valid. Returns the height of the block in the
+    /// blockchain of the node.
+    pub async fn submit_block(&mut self, block: Block) -> Result<usize, ()> {
+        let Some(prefix_height) = self
+            .blockchain
+            .hashes()
+            .position(|hash| hash == &block.prefix_hash)
+        else {
+            warn!(
+                "Node {self}: Rejecting block {block} on top of unknown block {}",
+                block.prefix_hash
+            );
+            return Err(());
+        };
+        let block_hash = block.hash();
+        self.process_message(Message::NewBlock {
+            block,
+            blockchain_length: prefix_height + 2,
+            source: self.address,
+            params_hash: None,
+        })
+        .await;
+        self.achieve_consensus().await;
+        let Some(height) = self
+            .blockchain
+            .hashes()
+            .position(|hash| hash == &block_hash)
+        else {
+            warn!("Node {self}: The submitted block {block_hash} was not adopted");
+            return Err(());
+        };
+        Ok(height)
+    }
+
+    /// Prepare a block for an external miner, with the transactions that the node would mine on
+    /// top of its last block, and remember it until the miner submits a nonce for it.
+    pub fn new_work(&mut self, miner: Address) -> Work {
+        if self.work.len() >= NODE_MAX_WORK {
+            self.work.pop_front();
+        }
+        self.next_work_id += 1;
+        let block = build_block_template(&self.blockchain, &self.mempool, miner);
+        let work = Work::new(
+            self.next_work_id,
+            self.blockchain.len(),
+            self.blockchain.difficulty(),
+            block,
+        );
+        self.work.push_back(work.clone());
+        work
+    }
+
+    /// Complete the block of some work with the nonce found by an external miner, and append it
+    /// to the blockchain. Fails if the work is unknown, if the hash of the block does not satisfy
+    /// the mining difficulty, or if the work is stale because another block was appended in the
+    /// meantime. Returns the height of the block.
+    pub fn submit_work(&mut self, work_id: u64, nonce: u64) -> Result<usize, ()> {
+        let Some(work) = self.work.iter().find(|work| work.id == work_id) else {
+            warn!("Node {self}: Cannot find work {work_id}");
+            return Err(());
+        };
+        let block = Block {
+            nonce,
+            ..work.block.clone()
+        };
+        if !block.meets_difficulty(work.difficulty) {
+            warn!("Node {self}: The nonce {nonce} does not solve work {work_id}");
+            return Err(());
+        }
+        if &block.prefix_hash != self.blockchain.last_hash() {
+            warn!("Node {self}: Rejecting block {block} of stale work {work_id}");
+            return Err(());
+        }
+        if self
+            .consensus
+            .validate_block(&self.blockchain, &block)
+            .is_err()
+        {
+            warn!("Node {self}: The block of work {work_id} breaks the consensus rules");
+            self.validation_stats.record_block(CONSENSUS_RULE);
+            return Err(());
+        }
+        info!("Node {self}: Block {block} was mined by {}", block.miner);
+        if let Err(rule) = self.blockchain.append_block(block) {
+            error!("Node {self}: The block of work {work_id} is invalid");
+            self.validation_stats.record_block(rule);
+            return Err(());
+        }
+        self.next_nonce = 0;
+        self.mempool.reset(&self.blockchain);
+        Ok(self.blockchain.height())
+    }
+
+    /// Record and publish the last block, just mined by the node or by an external miner, and
+    /// broadcast it to the peers.
+    pub async fn announce_mined_block(&self) {
+        let block = self.blockchain.last_block();
+        let height = self.blockchain.height();
+        stats()
+            .await
+            .record_mined_block(block, height, self.blockchain.difficulty())
+            .await;
+        events().await.publish(Event::BlockMined {
+            node: self.address,
+            height,
+            block: block.clone(),
+        });
+        let params_hash = self.blockchain.params().hash();
+        self.network()
+            .broadcast_block(block, self.blockchain.len(), &params_hash)
+            .await;
+    }
+
+    /// The propagation to the peers of a transaction received from a client.
+    pub fn transaction_propagation(
+        &self,
+        transaction_id: TransactionId,
+    ) -> Option<&TransactionPropagation> {
+        self.propagation.get(&transaction_id)
+    }
+
+    /// How many blocks each peer sent with the hash of other protocol parameters, which the node
+    /// rejected.
+    pub fn param_mismatches(&self) -> &BTreeMap<Address, u64> {
+        &self.param_mismatches
+    }
+
+    /// The outcomes of the most recent transactions received from clients, from the oldest to the
+    /// most recent.
+    pub fn submissions(&self) -> &VecDeque<Submission> {
+        &self.submissions
+    }
+
+    /// Broadcast again the pending transactions that have not been broadcast for a while, so that
+    /// they eventually reach the miners after a network disruption. The mempool is checked at
+    /// most once every `NODE_REBROADCAST_CHECK_INTERVAL`.
+    async fn rebroadcast_transactions(&mut self) {
+        let Some(age) = config().rebroadcast_after() else {
+            return;
+        };
+        let now = Instant::now();
+        if self
+            .last_rebroadcast_check
+            .is_some_and(|last| now.duration_since(last) < NODE_REBROADCAST_CHECK_INTERVAL)
+        {
+            return;
+        }
+        self.last_rebroadcast_check = Some(now);
+        for transaction in self.take_stale_transactions(now, age) {
+            info!("Node {self}: Broadcasting again transaction {transaction}");
+            self.network().broadcast_transaction(&transaction).await;
+        }
+    }
+
+    /// Announce the tip of the node to its peers, if the heartbeat interval has elapsed since the
+    /// last announcement.
+    async fn announce_tip(&mut self) {
+        let Some(interval) = config().heartbeat_interval() else {
+            return;
+        };
+        let now = Instant::now();
+        if self
+            .last_heartbeat
+            .is_some_and(|last| now.duration_since(last) < interval)
+        {
+            return;
+        }
+        self.last_heartbeat = Some(now);
+        self.network().broadcast_heartbeat(&self.blockchain).await;
+    }
+
+    /// The pending transactions that were last broadcast at least `age` before `now`, which are
+    /// then considered broadcast at `now`. Transactions seen for the first time are considered
+    /// broadcast at `now`, and the ones that left the mempool are forgotten.
+    fn take_stale_transactions(&mut self, now: Instant, age: Duration) -> Vec<BlockTransaction> {
+        let mut stale = vec![];
+        let mut broadcast_at = HashMap::with_capacity(self.mempool.len());
+        for transaction in self.mempool.transactions() {
+            let last = self
+                .broadcast_at
+                .get(&transaction.id)
+                .copied()
+                .unwrap_or(now);
+            if now.duration_since(last) >= age {
+                stale.push(transaction.clone());
+                broadcast_at.insert(transaction.id, now);
+            } else {
+                broadcast_at.insert(transaction.id, last);
+            }
+        }
+        self.broadcast_at = broadcast_at;
+        stale
+    }
+
     /// Attempt to mine a new block. If successful, the method returns true.
     fn mining(&mut self) -> bool {
-        let last_nonce = self.next_nonce + NODE_MINING_NONCE_STEP;
-        let opt_block = attempt_mining_block(
-            self.blockchain.last_hash().clone(),
-            self.address,
-            // TODO: Cloning these transactions is not necessary to compute the hash of a block.
-            self.mempool.transactions().clone(),
+        let nonce_budget = (self.mining_stats.nonce_budget as f64 * self.config.hashrate) as u64;
+        if nonce_budget == 0 {
+            return false;
+        }
+        // Nodes created by library users may have any hashrate.
+        let last_nonce = self.next_nonce.saturating_add(nonce_budget);
+        // TODO: The template could be cached until the mempool or the blockchain change.
+        let template = build_block_template(&self.blockchain, &self.mempool, self.address);
+        let opt_block = self.consensus.produce_block(
+            &self.blockchain,
+            template,
             self.next_nonce..last_nonce,
+            self.config.hasher.hasher(),
         );
         if let Some(block) = opt_block {
             info!("Node {self}: Mined block {block}");
-            if self.blockchain.append_block(block.clone()).is_err() {
-                unreachable!("Node {self}: The mined block is invalid");
+            self.mining_stats.hashes += block.nonce + 1 - self.next_nonce;
+            let block_hash = block.hash();
+            if let Err(rule) = self.blockchain.append_block(block.clone()) {
+                error!("Node {self}: Dropping the mined block {block_hash}, which is invalid");
+                self.validation_stats.record_block(rule);
+                self.next_nonce = 0;
+                return false;
             }
+            self.mining_stats.mined_blocks += 1;
+            // The block is valid, so its reward does not overflow.
+            let base_fee = self.blockchain.base_fee_at(self.blockchain.height());
+            let reward = block
+                .reward_with_base_fee(base_fee)
+                .unwrap_or(COINS_PER_MINED_BLOCK);
+            self.mined_rewards.insert(block_hash, reward);
             self.next_nonce = 0;
             self.mempool.reset(&self.blockchain);
             true
         } else {
+            self.mining_stats.hashes += last_nonce - self.next_nonce;
             self.next_nonce = last_nonce;
             false
         }
@@ -105,15 +1016,17 @@ impl Node {
 
     /// Receive a new block from the network, without checking its validity.
     /// If the received blockchain is better than the local one, it is stored for later consensus.
+    /// Returns whether the block announces a better blockchain than the ones known so far.
     pub async fn receive_new_block(
         &mut self,
         block: Block,
         blockchain_length: usize,
         source: Address,
-    ) {
+    ) -> bool {
         if blockchain_length <= self.blockchain.len() {
-            return;
+            return false;
         }
+        self.sync.observe_length(blockchain_length);
 
         // Check if self.better_blockchain is already better than the received one
         if let Some(better_blockchain) = self.better_blockchain.as_ref() {
@@ -123,7 +1036,7 @@ impl Node {
                     {source} because we already have a better one of length {} from {}",
                     better_blockchain.length, better_blockchain.source
                 );
-                return;
+                return false;
             }
         }
 
@@ -132,6 +1045,7 @@ impl Node {
             last_block: block.clone(),
             source,
         });
+        true
     }
 
     /// Switch to a better (i.e., longer) blockchain if one is available.
@@ -152,7 +1066,7 @@ impl Node {
             last_common_hash = better_blockchain.last_block.prefix_hash.clone();
             new_blocks.push(better_blockchain.last_block);
             while !self.blockchain.contains(&last_common_hash) {
-                let block = network().await.query_block(&last_common_hash, source).await;
+                let block = self.network().query_block(&last_common_hash, source).await;
                 if let Some(block) = block {
                     last_common_hash = block.prefix_hash.clone();
                     new_blocks.push(block);
@@ -160,31 +1074,75 @@ impl Node {
                     error!(
                         "Node {self}: Failed to fetch block {last_common_hash} from the network"
                     );
+                    self.sync.reset_best_known_length(self.blockchain.len());
                     return;
                 }
             }
         }
 
+        let _ = self
+            .adopt_blocks(
+                &last_common_hash,
+                new_blocks,
+                better_blockchain.length,
+                source,
+            )
+            .await;
+    }
+
+    /// Switch to the blockchain obtained by popping all blocks after `last_common_hash` and
+    /// appending `new_blocks`, which are given in reverse order (i.e., the last block first).
+    /// Returns an error, leaving the local blockchain untouched, if the new blockchain is invalid
+    /// or if it does not have the expected length.
+    async fn adopt_blocks(
+        &mut self,
+        last_common_hash: &BlockHash,
+        new_blocks: Vec<Block>,
+        expected_length: usize,
+        source: Address,
+    ) -> Result<(), ()> {
         // Check if the proposed blockchain is valid.
         // TODO: It is possible to do this more efficiently, without cloning and traversing the
         // full blockchain, by just checking the difference between the two blockchains.
+        let new_hashes: Vec<BlockHash> = new_blocks.iter().map(Block::hash).collect();
         let mut new_blockchain = self.blockchain.clone();
-        new_blockchain.pop_until(&last_common_hash);
-        if new_blockchain
-            .append_blocks(new_blocks.into_iter().rev())
-            .is_err()
-        {
+        if new_blockchain.pop_until(last_common_hash).is_err() {
+            error!("Node {self}: Cannot pop the local blockchain until {last_common_hash}");
+            return Err(());
+        }
+        let reorg_depth = self.blockchain.len() - new_blockchain.len();
+        let valid = new_blocks.into_iter().rev().all(|block| {
+            let result = match self.consensus.validate_block(&new_blockchain, &block) {
+                Ok(()) => new_blockchain.append_block(block),
+                Err(()) => Err(CONSENSUS_RULE),
+            };
+            if let Err(rule) = result {
+                self.validation_stats.record_block(rule);
+            }
+            result.is_ok()
+        });
+        if !valid {
             error!("Node {self}: The proposed better blockchain is invalid");
-            return;
+            self.sync.reset_best_known_length(self.blockchain.len());
+            return Err(());
         }
 
-        if new_blockchain.len() != better_blockchain.length {
+        if new_blockchain.len() != expected_length {
             error!(
                 "Node {self}: The proposed better blockchain has an invalid length ({} != {})",
                 new_blockchain.len(),
-                better_blockchain.length
+                expected_length
             );
-            return;
+            self.sync.reset_best_known_length(self.blockchain.len());
+            return Err(());
+        }
+
+        if !self.consensus.prefers(&self.blockchain, &new_blockchain) {
+            info!(
+                "Node {self}: Keeping its blockchain instead of the one of length {} from {source}",
+                new_blockchain.len()
+            );
+            return Err(());
         }
 
         info!(
@@ -195,38 +1153,132 @@ impl Node {
         self.blockchain = new_blockchain;
         self.next_nonce = 0;
         self.mempool.reset(&self.blockchain);
+        stats()
+            .await
+            .record_adopted_blocks(self.address, &new_hashes)
+            .await;
+        if reorg_depth > 0 {
+            stats().await.record_reorg(self.address, reorg_depth).await;
+        }
+        events().await.publish(Event::BlockchainAdopted {
+            node: self.address,
+            height: self.blockchain.height(),
+            last_block_hash: self.blockchain.last_hash().clone(),
+            reorg_depth,
+        });
+        Ok(())
     }
 
     /// Add a transaction send from a client to the mempool and broadcast it to the network.
-    /// Returns an error if the transaction is invalid.
-    pub async fn add_client_transaction(&mut self, transaction: Transaction) -> Result<(), ()> {
-        let block_transaction = BlockTransaction::new_with_random_id(
-            self.blockchain.last_hash().clone(),
-            transaction.clone(),
-        );
+    /// Returns the identifier of the transaction, or why the transaction was rejected. Sending
+    /// again a transaction that is still pending returns its identifier without broadcasting it.
+    pub async fn add_client_transaction(
+        &mut self,
+        transaction: Transaction,
+    ) -> Result<TransactionId, TxError> {
+        let block_transaction =
+            BlockTransaction::new(self.blockchain.last_hash().clone(), transaction.clone());
         info!("Node {self}: Received transaction {block_transaction} from a client");
-        if self
-            .mempool
-            .add_transaction(block_transaction.clone())
-            .is_err()
-        {
-            error!("Node {self}: Rejecting transaction {block_transaction}");
-            return Err(());
+        // The same transfer on top of the same block has the same identifier
+        if self.mempool.contains_pending(&block_transaction.id) {
+            info!("Node {self}: Transaction {block_transaction} is already pending");
+            return Ok(block_transaction.id);
+        }
+        self.mempool.set_policy(config().transaction_policy);
+        if let Err((rule, err)) = self.mempool.admit_transaction(block_transaction.clone()) {
+            error!("Node {self}: Rejecting transaction {block_transaction}: {err}");
+            self.validation_stats.record_transaction(rule);
+            self.record_submission(block_transaction.id, Some(&err));
+            return Err(err);
         };
         info!("Node {self}: Accepted transaction {block_transaction}");
-        network()
-            .await
-            .broadcast_transaction(&block_transaction, self.address)
+        self.record_submission(block_transaction.id, None);
+        events().await.publish(Event::TransactionAccepted {
+            node: self.address,
+            transaction: block_transaction.clone(),
+        });
+        let peers = self
+            .network()
+            .broadcast_transaction(&block_transaction)
             .await;
-        Ok(())
+        self.track_propagation(block_transaction.id, peers);
+        Ok(block_transaction.id)
+    }
+
+    /// Remember whether a transaction received from a client was accepted, forgetting the oldest
+    /// outcome if there are too many.
+    fn record_submission(&mut self, transaction_id: TransactionId, error: Option<&TxError>) {
+        if self.submissions.len() >= NODE_MAX_SUBMISSIONS {
+            self.submissions.pop_front();
+        }
+        self.submissions.push_back(Submission {
+            transaction_id,
+            accepted: error.is_none(),
+            code: error.map(|err| err.code().to_string()),
+            reason: error.map(|err| err.to_string()),
+            timestamp_millis: now_millis(),
+        });
+    }
+
+    /// Start tracking the propagation of a transaction, forgetting the oldest tracked one if there
+    /// are too many.
+    fn track_propagation(&mut self, transaction_id: TransactionId, peers: usize) {
+        if self.propagation_order.len() >= NODE_MAX_TRACKED_PROPAGATIONS {
+            if let Some(oldest) = self.propagation_order.pop_front() {
+                self.propagation.remove(&oldest);
+            }
+        }
+        self.propagation_order.push_back(transaction_id);
+        self.propagation
+            .insert(transaction_id, TransactionPropagation::new(peers));
     }
 
     /// Add a transaction received from the network to the mempool.
-    /// Invalid transactions are logged and discarded.
-    pub fn add_transaction(&mut self, transaction: BlockTransaction) {
+    /// Invalid transactions are logged and discarded, returning an error.
+    /// Transactions that the node already knows, for example because they have been broadcast
+    /// again, are ignored without an error.
+    pub fn add_transaction(&mut self, transaction: BlockTransaction) -> Result<(), TxError> {
+        if self.mempool.contains(&transaction.id) {
+            debug!("Node {self}: Ignoring already known transaction {transaction}");
+            return Ok(());
+        }
         info!("Node {self}: Received transaction {transaction} from the network");
-        if self.mempool.add_transaction(transaction.clone()).is_err() {
-            warn!("Node {self}: Ignoring invalid transaction {transaction}");
+        if !self.blockchain.contains(&transaction.prefix_hash) {
+            info!("Node {self}: Holding transaction {transaction} until its prefix block arrives");
+            if let Err(err) = self.mempool.add_future_transaction(transaction.clone()) {
+                warn!("Node {self}: Ignoring transaction {transaction}: {err}");
+                return Err(err);
+            }
+            return Ok(());
+        }
+        self.mempool.set_policy(config().transaction_policy);
+        if let Err((rule, err)) = self.mempool.admit_transaction(transaction.clone()) {
+            warn!("Node {self}: Ignoring invalid transaction {transaction}: {err}");
+            self.validation_stats.record_transaction(rule);
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    /// Record whether a peer accepted a transaction that this node broadcast.
+    pub fn receive_transaction_receipt(
+        &mut self,
+        transaction_id: TransactionId,
+        peer: Address,
+        accepted: bool,
+    ) {
+        let Some(propagation) = self.propagation.get_mut(&transaction_id) else {
+            debug!("Node {self}: Ignoring the receipt of untracked transaction {transaction_id}");
+            return;
+        };
+        if propagation.acknowledged.contains(&peer) || propagation.rejected.contains(&peer) {
+            // The peer received the transaction again, after it was broadcast again.
+            return;
+        }
+        if accepted {
+            propagation.acknowledged.push(peer);
+        } else {
+            propagation.rejected.push(peer);
         }
     }
 }
@@ -247,3 +1299,912 @@ struct BetterBlockChain {
     /// The address of the node that proposed the blockchain.
     source: Address,
 }
+
+/// The statistics of the mining attempts of a node.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MiningStats {
+    /// How many nonces the node tries in a row, before processing the messages in its inbox.
+    pub nonce_budget: u64,
+    /// How many messages were waiting in the inbox before the last mining attempt.
+    pub inbox_depth: usize,
+    /// How many hashes have been computed.
+    pub hashes: u64,
+    /// How many blocks have been mined, including the ones that later became orphans.
+    pub mined_blocks: u64,
+}
+
+impl Default for MiningStats {
+    fn default() -> Self {
+        MiningStats {
+            nonce_budget: NODE_MINING_NONCE_STEP,
+            inbox_depth: 0,
+            hashes: 0,
+            mined_blocks: 0,
+        }
+    }
+}
+
+impl MiningStats {
+    /// Halve the nonce budget when many messages are waiting, so that they are processed sooner,
+    /// and double it when the inbox is empty, so that mining is interrupted less often.
+    fn adapt_nonce_budget(&mut self, inbox_depth: usize) {
+        self.inbox_depth = inbox_depth;
+        if inbox_depth >= NODE_INBOX_DEEP_THRESHOLD {
+            self.nonce_budget = (self.nonce_budget / 2).max(NODE_MIN_NONCE_STEP);
+        } else if inbox_depth == 0 {
+            self.nonce_budget = (self.nonce_budget * 2).min(NODE_MAX_NONCE_STEP);
+        }
+    }
+}
+
+/// The outcome of the blocks mined by a node, from the point of view of its blockchain.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct MinerStats {
+    /// How many mined blocks are in the blockchain of the node.
+    pub canonical_blocks: u64,
+    /// How many mined blocks are not in the blockchain of the node anymore.
+    pub orphaned_blocks: u64,
+    /// The rewards of the canonical blocks.
+    pub earned_rewards: Amount,
+    /// The rewards of the orphaned blocks.
+    pub lost_rewards: Amount,
+}
+
+/// The blocks that a node relayed to its peers, in gossip mode.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct RelayStats {
+    /// How many new blocks the node forwarded.
+    pub relayed_blocks: u64,
+    /// How many new blocks the node did not forward, because they could not be checked or were
+    /// invalid.
+    pub suppressed_blocks: u64,
+}
+
+/// How many blocks and transactions a node rejected, by the name of the validation rule that they
+/// broke first. Blocks rejected by the consensus engine are counted under `consensus`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ValidationStats {
+    /// The rejected blocks, received from the network, submitted by external miners or mined by
+    /// the node.
+    pub blocks: BTreeMap<String, u64>,
+    /// The rejected transactions, received from the network or from clients.
+    pub transactions: BTreeMap<String, u64>,
+}
+
+impl ValidationStats {
+    fn record_block(&mut self, rule: &str) {
+        *self.blocks.entry(rule.to_string()).or_default() += 1;
+    }
+
+    fn record_transaction(&mut self, rule: &str) {
+        *self.transactions.entry(rule.to_string()).or_default() += 1;
+    }
+}
+
+/// Whether a node accepted a transaction received from a client.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Submission {
+    /// The identifier assigned to the transaction.
+    pub transaction_id: TransactionId,
+    /// Whether the transaction was added to the mempool.
+    pub accepted: bool,
+    /// The code of the reason why the transaction was rejected, as reported by the REST API.
+    pub code: Option<String>,
+    /// Why the transaction was rejected.
+    pub reason: Option<String>,
+    /// When the node received the transaction, in milliseconds since the Unix epoch.
+    pub timestamp_millis: u64,
+}
+
+/// A block to mine outside of the node: a nonce solves the work if the hash of the block with
+/// that nonce has at least `difficulty` leading zero bits.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Work {
+    pub id: u64,
+    /// The height that the block would have in the blockchain.
+    pub height: usize,
+    pub difficulty: u32,
+    /// The block, with a zero nonce.
+    pub block: Block,
+    /// The binary encoding of the block without its version and its nonce, in hexadecimal. The
+    /// hash of the block is the SHA-256 of these bytes followed by the nonce, as 8 little-endian
+    /// bytes.
+    pub preimage: String,
+}
+
+impl Work {
+    fn new(id: u64, height: usize, difficulty: u32, block: Block) -> Self {
+        let hashed = block.hashed_bytes();
+        let preimage = hashed[..hashed.len() - 8]
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect();
+        Work {
+            id,
+            height,
+            difficulty,
+            block,
+            preimage,
+        }
+    }
+}
+
+/// The delivery receipts of a transaction broadcast to the peers of a node.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TransactionPropagation {
+    /// How many peers the transaction has been sent to.
+    pub peers: usize,
+    /// The peers that accepted the transaction in their mempool.
+    pub acknowledged: Vec<Address>,
+    /// The peers that rejected the transaction.
+    pub rejected: Vec<Address>,
+}
+
+impl TransactionPropagation {
+    fn new(peers: usize) -> Self {
+        TransactionPropagation {
+            peers,
+            acknowledged: vec![],
+            rejected: vec![],
+        }
+    }
+
+    /// How many peers have not delivered a receipt yet. Peers that the transaction reached
+    /// through other nodes, e.g. by gossip, can deliver receipts too, so there may be more
+    /// receipts than peers.
+    pub fn pending(&self) -> usize {
+        self.peers
+            .saturating_sub(self.acknowledged.len() + self.rejected.len())
+    }
+}
+
+/// The progress of the synchronization of a node with the network.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct SyncStatus {
+    /// Whether the node is currently downloading a blockchain.
+    pub syncing: bool,
+    /// The length of the blockchain being downloaded.
+    pub target_length: usize,
+    /// How many blocks have been downloaded so far.
+    pub downloaded_blocks: usize,
+    /// The length of the longest valid blockchain known to be in the network.
+    pub best_known_length: usize,
+    /// When the current (or last) download started.
+    #[serde(skip)]
+    started_at: Option<Instant>,
+    /// When the last download finished.
+    #[serde(skip)]
+    finished_at: Option<Instant>,
+}
+
+impl SyncStatus {
+    fn start(&mut self, target_length: usize) {
+        self.syncing = true;
+        self.target_length = target_length;
+        self.downloaded_blocks = 0;
+        self.observe_length(target_length);
+        self.started_at = Some(Instant::now());
+        self.finished_at = None;
+    }
+
+    fn record_download(&mut self) {
+        self.downloaded_blocks += 1;
+    }
+
+    fn finish(&mut self) {
+        self.syncing = false;
+        self.finished_at = Some(Instant::now());
+    }
+
+    /// Record the length of a blockchain advertised by the network.
+    fn observe_length(&mut self, length: usize) {
+        self.best_known_length = self.best_known_length.max(length);
+    }
+
+    /// Forget about advertised blockchains that turned out to be invalid or unavailable.
+    fn reset_best_known_length(&mut self, length: usize) {
+        self.best_known_length = length;
+    }
+
+    /// How many blocks per second have been downloaded during the current (or last) download.
+    pub fn download_rate(&self) -> f64 {
+        let Some(started_at) = self.started_at else {
+            return 0.0;
+        };
+        let elapsed = self
+            .finished_at
+            .unwrap_or_else(Instant::now)
+            .duration_since(started_at)
+            .as_secs_f64();
+        if elapsed > 0.0 {
+            self.downloaded_blocks as f64 / elapsed
+        } else {
+            0.0
+        }
+    }
+
+    /// How many blocks the node is missing, compared to the best known blockchain.
+    pub fn blocks_remaining(&self, current_length: usize) -> usize {
+        if self.syncing {
+            self.target_length
+                .saturating_sub(current_length)
+                .saturating_sub(self.downloaded_blocks)
+        } else {
+            self.best_known_length.saturating_sub(current_length)
+        }
+    }
+
+    /// Whether the node has caught up with the best known blockchain.
+    pub fn is_synced(&self, current_length: usize) -> bool {
+        !self.syncing && current_length >= self.best_known_length
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn restore_drops_stale_transactions() {
+        let miner = Address::new(1);
+        let receiver = Address::new(2);
+        let mut blockchain = BlockChain::with_difficulty(0);
+        let genesis_hash = blockchain.last_hash().clone();
+        let block =
+            attempt_mining_block_with_difficulty(genesis_hash.clone(), miner, vec![], 0..1, 0)
+                .unwrap();
+        blockchain.append_block(block).unwrap();
+        let tip_hash = blockchain.last_hash().clone();
+
+        let valid = BlockTransaction::new(
+            tip_hash.clone(),
+            Transaction::new(miner, receiver, Amount::from_coins(300)),
+        );
+        let stale = BlockTransaction::new(
+            genesis_hash,
+            Transaction::new(miner, receiver, Amount::from_coins(100)),
+        );
+        let overspending = BlockTransaction::new(
+            tip_hash,
+            Transaction::new(miner, receiver, COINS_PER_MINED_BLOCK),
+        );
+        let node = Node::restore(miner, blockchain, vec![valid.clone(), stale, overspending]);
+        assert_eq!(node.mempool().transactions(), &vec![valid]);
+        assert_eq!(node.blockchain().len(), 2);
+    }
+
+    #[test]
+    fn fork_copies_the_ledger() {
+        let miner = Address::new(1);
+        let mut blockchain = BlockChain::with_difficulty(0);
+        let block = attempt_mining_block_with_difficulty(
+            blockchain.last_hash().clone(),
+            miner,
+            vec![],
+            0..1,
+            0,
+        )
+        .unwrap();
+        blockchain.append_block(block).unwrap();
+        let transaction = BlockTransaction::new(
+            blockchain.last_hash().clone(),
+            Transaction::new(miner, Address::new(2), Amount::from_coins(10)),
+        );
+        let mut node = Node::restore(miner, blockchain, vec![transaction.clone()]);
+
+        let mut fork = node.fork();
+        assert_ne!(fork.address(), node.address());
+        assert_eq!(fork.blockchain().last_hash(), node.blockchain().last_hash());
+        assert_eq!(fork.mempool().transactions(), &vec![transaction]);
+
+        // The two nodes evolve independently.
+        fork.mining_stats.nonce_budget = u64::MAX;
+        fork.next_nonce = 1;
+        assert!(fork.mining());
+        assert!(node.mempool().len() == 1 && fork.mempool().is_empty());
+        assert_eq!(node.blockchain().len() + 1, fork.blockchain().len());
+        node.stop();
+        assert!(fork.alive);
+    }
+
+    #[tokio::test]
+    async fn count_validation_failures() {
+        let mut node = Node::restore(Address::new(1), BlockChain::with_difficulty(0), vec![]);
+        let genesis_hash = node.blockchain().last_hash().clone();
+        let overdraft = BlockTransaction::new(
+            genesis_hash.clone(),
+            Transaction::new(Address::new(2), Address::new(3), Amount::from_coins(1)),
+        );
+        assert_eq!(
+            node.add_transaction(overdraft.clone()),
+            Err(TxError::InsufficientFunds)
+        );
+        let block = attempt_mining_block_with_difficulty(
+            genesis_hash.clone(),
+            Address::new(4),
+            vec![overdraft],
+            0..1,
+            0,
+        )
+        .unwrap();
+        assert!(node
+            .adopt_blocks(&genesis_hash, vec![block], 2, Address::new(4))
+            .await
+            .is_err());
+
+        let validation_stats = node.validation_stats();
+        assert_eq!(validation_stats.transactions["balances"], 1);
+        assert_eq!(validation_stats.blocks["balances"], 1);
+        assert_eq!(validation_stats.blocks.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn apply_node_config() {
+        let mut node = Node::restore(Address::new(1), BlockChain::with_difficulty(0), vec![]);
+        node.set_config(NodeConfig {
+            hashrate: 0.0,
+            max_mempool_transactions: Some(0),
+            peer_policy: PeerPolicy::BlocksOnly,
+            hasher: HasherKind::Midstate,
+            region: None,
+            labels: std::collections::BTreeMap::new(),
+        });
+        assert!(!node.mining());
+        assert_eq!(node.mining_stats().hashes, 0);
+
+        let transaction = BlockTransaction::new(
+            node.blockchain().last_hash().clone(),
+            Transaction::new(Address::new(2), Address::new(3), Amount::from_coins(1)),
+        );
+        node.inbox().push(Message::Transaction {
+            transaction: transaction.clone(),
+            source: Address::new(2),
+        });
+        node.process_messages().await;
+        assert!(node.mempool().is_empty());
+        assert_eq!(node.add_transaction(transaction), Err(TxError::MempoolFull));
+    }
+
+    #[tokio::test]
+    async fn remember_client_submissions() {
+        let mut node = Node::restore(Address::new(1), BlockChain::with_difficulty(0), vec![]);
+        let accepted = Transaction::new(Address::FAUCET, Address::new(2), Amount::from_coins(1));
+        let rejected = Transaction::new(Address::new(3), Address::new(2), Amount::from_coins(1));
+        let accepted_id = node.add_client_transaction(accepted).await.unwrap();
+        let err = node.add_client_transaction(rejected).await.unwrap_err();
+
+        let submissions: Vec<_> = node.submissions().iter().collect();
+        assert_eq!(submissions.len(), 2);
+        assert_eq!(submissions[0].transaction_id, accepted_id);
+        assert!(submissions[0].accepted && submissions[0].code.is_none());
+        assert!(!submissions[1].accepted);
+        assert_eq!(submissions[1].code.as_deref(), Some(err.code()));
+
+        // Only the most recent outcomes are remembered.
+        for _ in 0..NODE_MAX_SUBMISSIONS {
+            node.record_submission(TransactionId::new_random(), None);
+        }
+        assert_eq!(node.submissions().len(), NODE_MAX_SUBMISSIONS);
+        let first = node.submissions().front().unwrap();
+        assert_ne!(first.transaction_id, accepted_id);
+    }
+
+    #[test]
+    fn propagation_receipts() {
+        let mut node = Node::new();
+        let transaction_id = TransactionId::new_random();
+        node.track_propagation(transaction_id, 3);
+        node.receive_transaction_receipt(transaction_id, Address::new(1), true);
+        node.receive_transaction_receipt(transaction_id, Address::new(2), false);
+        let propagation = node.transaction_propagation(transaction_id).unwrap();
+        assert_eq!(propagation.acknowledged, vec![Address::new(1)]);
+        assert_eq!(propagation.rejected, vec![Address::new(2)]);
+        assert_eq!(propagation.pending(), 1);
+
+        // Receipts from peers that the transaction reached through other nodes, and repeated
+        // receipts, do not make the pending peers negative.
+        for peer in [3, 4, 3] {
+            node.receive_transaction_receipt(transaction_id, Address::new(peer), true);
+        }
+        let propagation = node.transaction_propagation(transaction_id).unwrap();
+        assert_eq!(propagation.acknowledged.len(), 3);
+        assert_eq!(propagation.pending(), 0);
+
+        // Only the most recent transactions are tracked.
+        for _ in 0..NODE_MAX_TRACKED_PROPAGATIONS {
+            node.track_propagation(TransactionId::new_random(), 3);
+        }
+        assert!(node.transaction_propagation(transaction_id).is_none());
+        assert_eq!(node.propagation.len(), NODE_MAX_TRACKED_PROPAGATIONS);
+    }
+
+    #[test]
+    fn rebroadcast_stale_transactions() {
+        let miner = Address::new(1);
+        let mut blockchain = BlockChain::with_difficulty(0);
+        let block = attempt_mining_block_with_difficulty(
+            blockchain.last_hash().clone(),
+            miner,
+            vec![],
+            0..1,
+            0,
+        )
+        .unwrap();
+        blockchain.append_block(block).unwrap();
+        let transaction = BlockTransaction::new(
+            blockchain.last_hash().clone(),
+            Transaction::new(miner, Address::new(2), Amount::from_coins(10)),
+        );
+        let mut node = Node::restore(miner, blockchain, vec![transaction.clone()]);
+
+        let age = Duration::from_secs(10);
+        let now = Instant::now();
+        let mut stale_after = |elapsed| node.take_stale_transactions(now + elapsed, age);
+        assert!(stale_after(Duration::ZERO).is_empty());
+        assert!(stale_after(age / 2).is_empty());
+        assert_eq!(stale_after(age), vec![transaction.clone()]);
+        assert!(stale_after(age * 3 / 2).is_empty());
+        assert_eq!(stale_after(age * 2), vec![transaction.clone()]);
+
+        // Receiving the transaction again is not an error, and does not duplicate it.
+        assert!(node.add_transaction(transaction).is_ok());
+        assert_eq!(node.mempool().len(), 1);
+    }
+
+    #[test]
+    fn adaptive_nonce_budget() {
+        let mut mining_stats = MiningStats::default();
+        mining_stats.adapt_nonce_budget(NODE_INBOX_DEEP_THRESHOLD);
+        assert_eq!(mining_stats.nonce_budget, NODE_MINING_NONCE_STEP / 2);
+        for _ in 0..10 {
+            mining_stats.adapt_nonce_budget(NODE_INBOX_DEEP_THRESHOLD * 4);
+        }
+        assert_eq!(mining_stats.nonce_budget, NODE_MIN_NONCE_STEP);
+
+        // A shallow inbox keeps the budget unchanged.
+        mining_stats.adapt_nonce_budget(1);
+        assert_eq!(mining_stats.nonce_budget, NODE_MIN_NONCE_STEP);
+
+        for _ in 0..10 {
+            mining_stats.adapt_nonce_budget(0);
+        }
+        assert_eq!(mining_stats.nonce_budget, NODE_MAX_NONCE_STEP);
+    }
+
+    #[tokio::test]
+    async fn flooded_node_adopts_new_tip() {
+        let mut node = Node::restore(Address::new(1), BlockChain::with_difficulty(0), vec![]);
+        let genesis_hash = node.blockchain().last_hash().clone();
+        let peer = Address::new(2);
+        for coins in 0..10 * NODE_INBOX_BATCH as u64 {
+            let transaction = BlockTransaction::new(
+                genesis_hash.clone(),
+                Transaction::new(peer, Address::new(3), Amount::from_coins(coins + 1)),
+            );
+            node.inbox().push(Message::Transaction {
+                transaction,
+                source: peer,
+            });
+        }
+        let block =
+            attempt_mining_block_with_difficulty(genesis_hash, peer, vec![], 0..1, 0).unwrap();
+        let block_hash = block.hash();
+        node.inbox().push(Message::NewBlock {
+            block,
+            blockchain_length: 2,
+            source: peer,
+            params_hash: Some(node.blockchain().params().hash()),
+        });
+
+        // The block announcement is processed in the first iteration, despite the flood.
+        node.process_messages().await;
+        node.achieve_consensus().await;
+        assert_eq!(node.blockchain().last_hash(), &block_hash);
+        assert_eq!(node.inbox().lane_len(Lane::Consensus), 0);
+        assert_eq!(
+            node.inbox().lane_len(Lane::Transactions),
+            9 * NODE_INBOX_BATCH + 1
+        );
+    }
+
+    #[tokio::test]
+    async fn reject_blocks_with_other_params() {
+        let mut node = Node::restore(Address::new(1), BlockChain::with_difficulty(0), vec![]);
+        let genesis_hash = node.blockchain().last_hash().clone();
+        let peer = Address::new(2);
+        let block =
+            attempt_mining_block_with_difficulty(genesis_hash, peer, vec![], 0..1, 0).unwrap();
+        let new_block = |params: ProtocolParams| Message::NewBlock {
+            block: block.clone(),
+            blockchain_length: 2,
+            source: peer,
+            params_hash: Some(params.hash()),
+        };
+
+        // A peer built with another difficulty
+        let other_params = BlockChain::new().params();
+        assert_eq!(
+            node.blockchain().params().differences(&other_params),
+            vec!["mining_difficulty"]
+        );
+        node.process_message(new_block(other_params)).await;
+        node.achieve_consensus().await;
+        assert_eq!(node.blockchain().len(), 1);
+        assert_eq!(node.param_mismatches()[&peer], 1);
+
+        node.process_message(new_block(node.blockchain().params()))
+            .await;
+        node.achieve_consensus().await;
+        assert_eq!(node.blockchain().len(), 2);
+    }
+
+    #[test]
+    fn mine_external_work() {
+        let mut node = Node::restore(Address::new(1), BlockChain::with_difficulty(8), vec![]);
+        let miner = Address::new(2);
+        let work = node.new_work(miner);
+        assert_eq!(work.height, 1);
+        let solution = (0..)
+            .find(|&nonce| {
+                let mut preimage: Vec<u8> = (0..work.preimage.len())
+                    .step_by(2)
+                    .map(|i| u8::from_str_radix(&work.preimage[i..i + 2], 16).unwrap())
+                    .collect();
+                preimage.extend_from_slice(&u64::to_le_bytes(nonce));
+                BlockHash::digest(&preimage).leading_zero_bits() >= work.difficulty
+            })
+            .unwrap();
+        let wrong_nonce = (0..)
+            .find(|&nonce| {
+                let block = Block {
+                    nonce,
+                    ..work.block.clone()
+                };
+                !block.meets_difficulty(work.difficulty)
+            })
+            .unwrap();
+
+        assert_eq!(node.submit_work(work.id + 1, solution), Err(()));
+        assert_eq!(node.submit_work(work.id, wrong_nonce), Err(()));
+        assert_eq!(node.submit_work(work.id, solution), Ok(1));
+        assert_eq!(node.blockchain().last_block().miner, miner);
+        // The work is stale once its block is appended
+        assert_eq!(node.submit_work(work.id, solution), Err(()));
+    }
+
+    #[test]
+    fn relay_only_checked_blocks() {
+        let mut node = Node::restore(Address::new(1), BlockChain::with_difficulty(8), vec![]);
+        let genesis_hash = node.blockchain().last_hash().clone();
+        let peer = Address::new(2);
+        let mine = |prefix_hash: &BlockHash, difficulty| {
+            attempt_mining_block_with_difficulty(
+                prefix_hash.clone(),
+                peer,
+                vec![],
+                0..100_000,
+                difficulty,
+            )
+            .unwrap()
+        };
+        let block = mine(&genesis_hash, 8);
+        assert!(node.can_relay(&block, 2));
+        assert!(!node.can_relay(&block, 3));
+
+        // A block without enough proof of work.
+        let weak_block = (0..)
+            .map(|nonce| Block::new(vec![], genesis_hash.clone(), peer, nonce))
+            .find(|block| !block.meets_difficulty(8))
+            .unwrap();
+        assert!(!node.can_relay(&weak_block, 2));
+
+        // A block on top of a block that is not known yet, then known as the better blockchain.
+        let next_block = mine(&block.hash(), 8);
+        assert!(!node.can_relay(&next_block, 3));
+        node.better_blockchain = Some(BetterBlockChain {
+            length: 2,
+            last_block: block,
+            source: peer,
+        });
+        assert!(node.can_relay(&next_block, 3));
+    }
+
+    #[tokio::test]
+    async fn submit_only_valid_blocks() {
+        let mut node = Node::restore(Address::new(1), BlockChain::with_difficulty(8), vec![]);
+        let genesis_hash = node.blockchain().last_hash().clone();
+        let miner = Address::new(2);
+        let block = attempt_mining_block_with_difficulty(
+            genesis_hash.clone(),
+            miner,
+            vec![],
+            0..100_000,
+            8,
+        )
+        .unwrap();
+        let weak_block = (0..)
+            .map(|nonce| Block::new(vec![], genesis_hash.clone(), miner, nonce))
+            .find(|block| !block.meets_difficulty(8))
+            .unwrap();
+        let orphan_block = Block::new(vec![], block.hash(), miner, 0);
+
+        assert_eq!(node.submit_block(weak_block).await, Err(()));
+        assert_eq!(node.submit_block(orphan_block).await, Err(()));
+        assert_eq!(node.submit_block(block.clone()).await, Ok(1));
+        assert_eq!(node.blockchain().last_hash(), &block.hash());
+    }
+
+    #[tokio::test]
+    async fn follow_custom_consensus_engine() {
+        // Only one address can produce blocks.
+        struct ProofOfAuthority(Address);
+        impl ConsensusEngine for ProofOfAuthority {
+            fn name(&self) -> &str {
+                "proof_of_authority"
+            }
+            fn validate_block(&self, _: &BlockChain, block: &Block) -> Result<(), ()> {
+                (block.miner == self.0).then_some(()).ok_or(())
+            }
+            fn prefers(&self, current: &BlockChain, candidate: &BlockChain) -> bool {
+                candidate.len() > current.len()
+            }
+            fn produce_block(
+                &self,
+                blockchain: &BlockChain,
+                template: Block,
+                nonces: std::ops::Range<u64>,
+                hasher: &dyn Hasher,
+            ) -> Option<Block> {
+                (template.miner == self.0)
+                    .then(|| ProofOfWork.produce_block(blockchain, template, nonces, hasher))?
+            }
+        }
+
+        let authority = Address::new(2);
+        let mut node = Node::restore(Address::new(1), BlockChain::with_difficulty(0), vec![]);
+        node.set_consensus_engine(Arc::new(ProofOfAuthority(authority)));
+        assert!(!node.mining());
+        let genesis_hash = node.blockchain().last_hash().clone();
+        let intruder_block = Block::new(vec![], genesis_hash.clone(), Address::new(3), 0);
+        let authority_block = Block::new(vec![], genesis_hash, authority, 0);
+        assert_eq!(node.submit_block(intruder_block).await, Err(()));
+        assert_eq!(node.submit_block(authority_block).await, Ok(1));
+    }
+
+    #[test]
+    fn count_orphaned_blocks() {
+        let mut node = Node::restore(Address::new(1), BlockChain::with_difficulty(0), vec![]);
+        let genesis_hash = node.blockchain().last_hash().clone();
+        let mine = |node: &mut Node, nonce| {
+            let block = attempt_mining_block_with_difficulty(
+                genesis_hash.clone(),
+                node.address(),
+                vec![],
+                nonce..nonce + 1,
+                0,
+            )
+            .unwrap();
+            node.mined_rewards
+                .insert(block.hash(), COINS_PER_MINED_BLOCK);
+            node.blockchain.append_block(block).unwrap();
+        };
+        mine(&mut node, 0);
+        // A reorganization drops the mined block, then the node mines another one.
+        node.blockchain.pop_block().unwrap();
+        mine(&mut node, 1);
+        assert_eq!(
+            node.miner_stats(),
+            MinerStats {
+                canonical_blocks: 1,
+                orphaned_blocks: 1,
+                earned_rewards: COINS_PER_MINED_BLOCK,
+                lost_rewards: COINS_PER_MINED_BLOCK,
+            }
+        );
+    }
+
+    #[test]
+    fn request_only_missing_items() {
+        let mut node = Node::restore(Address::new(1), BlockChain::with_difficulty(0), vec![]);
+        let genesis_hash = node.blockchain().last_hash().clone();
+        let (alice, bob) = (Address::new(2), Address::new(3));
+        let block =
+            attempt_mining_block_with_difficulty(genesis_hash.clone(), alice, vec![], 0..1, 0)
+                .unwrap();
+        let transaction_id = TransactionId::new_random();
+        let items = vec![
+            Item::Block(genesis_hash),
+            Item::Block(block.hash()),
+            Item::Transaction(transaction_id),
+        ];
+
+        // Only the unknown items are requested, and only from the first peer that announces them.
+        node.receive_inventory(items.clone(), alice);
+        node.receive_inventory(items, bob);
+        assert_eq!(
+            node.requests,
+            HashMap::from([(
+                alice,
+                vec![Item::Block(block.hash()), Item::Transaction(transaction_id)]
+            )])
+        );
+    }
+
+    #[tokio::test]
+    async fn tolerate_duplicated_and_reordered_messages() {
+        let mut node = Node::restore(Address::new(1), BlockChain::with_difficulty(0), vec![]);
+        let genesis_hash = node.blockchain().last_hash().clone();
+        let peer = Address::new(2);
+        let block =
+            attempt_mining_block_with_difficulty(genesis_hash, peer, vec![], 0..1, 0).unwrap();
+        let block_hash = block.hash();
+        let transaction = BlockTransaction::new(
+            block_hash.clone(),
+            Transaction::new(peer, Address::new(3), Amount::from_coins(10)),
+        );
+        let new_block = Message::NewBlock {
+            block,
+            blockchain_length: 2,
+            source: peer,
+            params_hash: None,
+        };
+        let new_transaction = Message::Transaction {
+            transaction: transaction.clone(),
+            source: peer,
+        };
+
+        // The transaction arrives before the block it is on top of, and everything arrives twice.
+        for message in [
+            new_transaction.clone(),
+            new_transaction.clone(),
+            new_block.clone(),
+            new_block.clone(),
+        ] {
+            node.replay_message(message).await;
+        }
+        assert_eq!(node.blockchain().last_hash(), &block_hash);
+        assert_eq!(node.mempool().transactions(), &vec![transaction]);
+        assert_eq!(node.mempool().future_len(), 0);
+
+        // Late duplicates do not change anything.
+        node.replay_message(new_transaction).await;
+        node.replay_message(new_block).await;
+        assert_eq!(node.blockchain().len(), 2);
+        assert_eq!(node.mempool().len(), 1);
+
+        // Only the first receipt of each peer counts.
+        let transaction_id = TransactionId::new_random();
+        node.track_propagation(transaction_id, 2);
+        for accepted in [true, true, false] {
+            node.replay_message(Message::TransactionReceipt {
+                transaction_id,
+                peer,
+                accepted,
+            })
+            .await;
+        }
+        let propagation = node.transaction_propagation(transaction_id).unwrap();
+        assert_eq!(propagation.acknowledged, vec![peer]);
+        assert!(propagation.rejected.is_empty());
+        assert_eq!(propagation.pending(), 1);
+    }
+
+    #[tokio::test]
+    async fn forget_departed_peers() {
+        let mut node = Node::restore(Address::new(1), BlockChain::with_difficulty(0), vec![]);
+        let genesis_hash = node.blockchain().last_hash().clone();
+        let (peer, other_peer) = (Address::new(2), Address::new(3));
+        // A block on top of a blockchain that the node does not know.
+        let block = attempt_mining_block_with_difficulty(genesis_hash, peer, vec![], 0..1, 0)
+            .and_then(|block| {
+                attempt_mining_block_with_difficulty(block.hash(), peer, vec![], 0..1, 0)
+            })
+            .unwrap();
+        node.process_message(Message::NewBlock {
+            block,
+            blockchain_length: 3,
+            source: peer,
+            params_hash: None,
+        })
+        .await;
+        assert!(node.better_blockchain.is_some());
+        let transaction = Item::Transaction(TransactionId::new_random());
+        node.receive_inventory(vec![transaction.clone()], peer);
+
+        node.process_message(Message::Departure { source: other_peer })
+            .await;
+        assert!(node.better_blockchain.is_some());
+        assert_eq!(node.requests[&peer], vec![transaction]);
+
+        node.process_message(Message::Departure { source: peer })
+            .await;
+        assert!(node.better_blockchain.is_none());
+        assert!(node.requests.is_empty());
+    }
+
+    #[tokio::test]
+    async fn ignore_banned_peers() {
+        let mut node = Node::restore(Address::new(1), BlockChain::with_difficulty(0), vec![]);
+        let genesis_hash = node.blockchain().last_hash().clone();
+        let peer = Address::new(2);
+        let block = |miner| {
+            attempt_mining_block_with_difficulty(genesis_hash.clone(), miner, vec![], 0..1, 0)
+                .unwrap()
+        };
+        let inventory = |block: &Block| Message::Inventory {
+            items: vec![Item::Block(block.hash())],
+            source: peer,
+        };
+
+        // Banning a peer drops the requests to send to it.
+        let first_block = block(Address::new(3));
+        node.process_message(inventory(&first_block)).await;
+        assert_eq!(node.requests[&peer].len(), 1);
+        assert!(node.ban_peer(peer));
+        assert!(!node.ban_peer(peer));
+        assert!(node.requests.is_empty());
+
+        // The messages of a banned peer are ignored until it is unbanned.
+        let second_block = block(Address::new(4));
+        node.process_message(inventory(&second_block)).await;
+        assert!(node.requests.is_empty());
+        assert!(node.unban_peer(peer));
+        assert!(!node.unban_peer(peer));
+        node.process_message(inventory(&second_block)).await;
+        assert_eq!(node.requests[&peer].len(), 1);
+        assert!(node.banned_peers().is_empty());
+    }
+
+    #[tokio::test]
+    async fn request_tip_of_heartbeats_with_more_work() {
+        let mut node = Node::restore(Address::new(1), BlockChain::with_difficulty(0), vec![]);
+        let genesis_hash = node.blockchain().last_hash().clone();
+        let peer = Address::new(2);
+        let heartbeat = |blockchain_length, last_block_hash, cumulative_work| Message::Heartbeat {
+            blockchain_length,
+            last_block_hash,
+            cumulative_work,
+            source: peer,
+        };
+
+        node.process_message(heartbeat(1, genesis_hash.clone(), 0))
+            .await;
+        assert!(node.requests.is_empty());
+
+        let tip = attempt_mining_block_with_difficulty(genesis_hash, peer, vec![], 0..1, 0)
+            .unwrap()
+            .hash();
+        node.process_message(heartbeat(2, tip.clone(), 1)).await;
+        assert_eq!(node.requests[&peer], vec![Item::Block(tip)]);
+    }
+
+    #[tokio::test]
+    async fn recover_a_failed_node() {
+        let mut node = Node::restore(Address::new(1), BlockChain::with_difficulty(0), vec![]);
+        node.set_config(NodeConfig {
+            hashrate: 0.0,
+            ..NodeConfig::default()
+        });
+        let genesis_hash = node.blockchain().last_hash().clone();
+        let block =
+            attempt_mining_block_with_difficulty(genesis_hash, node.address(), vec![], 0..1, 0)
+                .unwrap();
+        assert_eq!(node.submit_block(block).await, Ok(1));
+        node.requests.insert(
+            Address::new(2),
+            vec![Item::Transaction(TransactionId::new_random())],
+        );
+        node.ban_peer(Address::new(3));
+
+        let recovered = node.recover();
+        assert_eq!(recovered.address(), node.address());
+        assert_eq!(
+            recovered.blockchain().last_hash(),
+            node.blockchain().last_hash()
+        );
+        assert!(Arc::ptr_eq(recovered.inbox(), node.inbox()));
+        assert_eq!(recovered.config(), node.config());
+        assert_eq!(recovered.banned_peers(), node.banned_peers());
+        assert!(recovered.requests.is_empty());
+    }
+}
@@ -0,0 +1,818 @@
+use crate::prelude::*;
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+/// The file, in the directory of a node, that contains the blocks of its blockchain.
+const BLOCKCHAIN_FILE: &str = "blockchain.json";
+
+/// The file, in the directory of a node, to which its blocks are appended as soon as they change.
+/// When present, it supersedes the blocks of `BLOCKCHAIN_FILE`, which are only saved when the
+/// server is stopped.
+const BLOCKS_FILE: &str = "blocks.dat";
+
+/// The file, in the directory of a node, that contains the pending transactions of its mempool.
+const MEMPOOL_FILE: &str = "mempool.json";
+
+/// The file, in the directory of a node, that contains the peers banned by the operator.
+const BANNED_PEERS_FILE: &str = "banned_peers.json";
+
+/// The file, in the data directory, that contains the protocol parameters of the saved nodes.
+const PARAMS_FILE: &str = "params.json";
+
+/// The file, in the data directory, that contains the sampled tips of the nodes.
+const TIPS_FILE: &str = "tips.json";
+
+/// The directory, in the data directory, that contains the periodic snapshots of the nodes.
+const SNAPSHOTS_DIR: &str = "snapshots";
+
+/// The marker added to the name of the directory of a node that cannot be restored, which is set
+/// aside for inspection.
+const QUARANTINE_MARKER: &str = ".invalid-";
+
+/// The suffix of the directory in which `reindex` rebuilds a node, before replacing the directory
+/// of the node with it.
+const REINDEX_TEMPORARY_SUFFIX: &str = ".reindexing";
+
+/// The marker added to the name of the directory of a node replaced by `reindex`, which is kept
+/// if blocks were dropped.
+const REINDEX_BACKUP_MARKER: &str = ".before-reindex-";
+
+/// How many blocks `reindex` replays between two progress reports.
+const REINDEX_PROGRESS_BLOCKS: usize = 1000;
+
+/// The data directory in which the nodes store their blocks and their banned peers as soon as they
+/// change, if they do.
+static STORE_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// Let the nodes started from now on store their blocks in their directory of `data_dir` as soon
+/// as they change, so that they resume from their last stored block even if the process is killed.
+/// Fails if the nodes in `data_dir` were saved with other protocol parameters.
+pub fn store_blocks(data_dir: &Path) -> std::io::Result<()> {
+    let params = ProtocolParams::with_rules(config().transaction_rules);
+    if data_dir.join(PARAMS_FILE).exists() {
+        check_params(data_dir, &params)?;
+    }
+    std::fs::create_dir_all(data_dir)?;
+    write_json(&data_dir.join(PARAMS_FILE), &params)?;
+    if STORE_DIR.set(data_dir.to_path_buf()).is_err() {
+        warn!("The nodes already store their blocks in another directory");
+    }
+    Ok(())
+}
+
+/// Open the store of the blocks of a node, if the nodes store their blocks.
+pub fn open_block_store(address: Address) -> Option<Box<dyn BlockStore>> {
+    let node_dir = STORE_DIR.get()?.join(address.to_string());
+    let store = std::fs::create_dir_all(&node_dir)
+        .and_then(|()| FileBlockStore::open(&node_dir.join(BLOCKS_FILE)));
+    match store {
+        Ok(store) => Some(Box::new(store)),
+        Err(err) => {
+            error!("Node {address}: Failed to open the store of its blocks: {err}");
+            None
+        }
+    }
+}
+
+/// Store the banned peers of a node as soon as they change, if the nodes store their blocks, so
+/// that the bans survive a crash.
+pub fn store_banned_peers(address: Address, banned_peers: &BTreeSet<Address>) {
+    let Some(data_dir) = STORE_DIR.get() else {
+        return;
+    };
+    let node_dir = data_dir.join(address.to_string());
+    let stored = std::fs::create_dir_all(&node_dir)
+        .and_then(|()| write_json(&node_dir.join(BANNED_PEERS_FILE), banned_peers));
+    if let Err(err) = stored {
+        error!("Node {address}: Failed to store its banned peers: {err}");
+    }
+}
+
+/// Save the blockchain, the mempool and the banned peers of every node to `data_dir`, in one
+/// directory per node.
+/// The protocol parameters are saved too, so that a restart with different ones is detected, as
+/// well as the sampled tips of the nodes.
+pub async fn save(data_dir: &Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(data_dir)?;
+    write_json(
+        &data_dir.join(PARAMS_FILE),
+        &ProtocolParams::with_rules(config().transaction_rules),
+    )?;
+    write_json(
+        &data_dir.join(TIPS_FILE),
+        &stats().await.tips(None, None).await,
+    )?;
+    let world = world().await;
+    for address in world.get_node_addresses().await {
+        let Some(node) = world.get_node(address).await else {
+            continue;
+        };
+        // The stored blocks supersede the saved ones when restoring, so they must be up to date.
+        let flushed = node.write().await.flush_blocks();
+        flushed.await;
+        let readable_node = node.read().await;
+        write_node(&data_dir.join(address.to_string()), &readable_node)?;
+        debug!(
+            "Saved node {address} with {} blocks and {} pending transactions",
+            readable_node.blockchain().len(),
+            readable_node.mempool().len()
+        );
+    }
+    Ok(())
+}
+
+/// Save the blockchain, the mempool and the banned peers of a node to its directory.
+fn write_node(node_dir: &Path, node: &Node) -> std::io::Result<()> {
+    std::fs::create_dir_all(node_dir)?;
+    let blocks: Vec<&Block> = node.blockchain().blocks().collect();
+    write_json(&node_dir.join(BLOCKCHAIN_FILE), &blocks)?;
+    write_json(&node_dir.join(BANNED_PEERS_FILE), node.banned_peers())?;
+    write_json(&node_dir.join(MEMPOOL_FILE), node.mempool().transactions())
+}
+
+/// Restore the nodes saved in `data_dir`, starting their execution.
+/// Blocks are validated again while rebuilding the blockchain, and checked against the stored
+/// balances, and pending transactions that no longer apply to the restored last block are
+/// dropped. The nodes whose blocks cannot be read or are invalid are not restored, and their
+/// directory is set aside, so that nothing overwrites it. Returns how many nodes were restored.
+/// Fails without restoring anything if the nodes were saved with other protocol parameters.
+pub async fn restore(data_dir: &Path) -> std::io::Result<usize> {
+    let params = ProtocolParams::with_rules(config().transaction_rules);
+    restore_into(world().await, data_dir, &params).await
+}
+
+/// Restore the nodes saved in `data_dir` into a world, given the current protocol parameters.
+async fn restore_into(
+    world: &'static World,
+    data_dir: &Path,
+    params: &ProtocolParams,
+) -> std::io::Result<usize> {
+    if !data_dir.exists() {
+        return Ok(0);
+    }
+    check_params(data_dir, params)?;
+    let tips_path = data_dir.join(TIPS_FILE);
+    if tips_path.exists() {
+        match read_json(&tips_path) {
+            Ok(tips) => stats().await.restore_tips(tips).await,
+            Err(err) => error!("Ignoring the saved tips of the nodes, which cannot be read: {err}"),
+        }
+    }
+    let mut restored = 0;
+    for (address, node_dir) in node_dirs(data_dir)? {
+        let blocks = match read_blocks(&node_dir) {
+            Ok(blocks) => blocks,
+            Err(err) => {
+                error!("Ignoring node {address}, whose saved blocks cannot be read: {err}");
+                quarantine(&node_dir);
+                continue;
+            }
+        };
+        let transactions = match read_transactions(&node_dir) {
+            Ok(transactions) => transactions,
+            Err(err) => {
+                error!("Ignoring node {address}, whose saved transactions cannot be read: {err}");
+                quarantine(&node_dir);
+                continue;
+            }
+        };
+        let banned_peers = match read_banned_peers(&node_dir) {
+            Ok(banned_peers) => banned_peers,
+            Err(err) => {
+                error!("Ignoring node {address}, whose banned peers cannot be read: {err}");
+                quarantine(&node_dir);
+                continue;
+            }
+        };
+        let mut blockchain = new_blockchain(params);
+        if blockchain.append_blocks(blocks).is_err() {
+            error!("Ignoring node {address}, whose saved blockchain is invalid");
+            quarantine(&node_dir);
+            continue;
+        }
+        match read_balances(&node_dir) {
+            Ok(Some(stored)) if stored.hash == *blockchain.last_hash() => {
+                if stored != blockchain.stored_balances() {
+                    error!(
+                        "Ignoring node {address}, whose blocks do not match its stored balances"
+                    );
+                    quarantine(&node_dir);
+                    continue;
+                }
+            }
+            Ok(Some(stored)) => warn!(
+                "Node {address}: Ignoring its outdated stored balances, at height {}",
+                stored.height
+            ),
+            Ok(None) => {}
+            Err(err) => {
+                error!("Ignoring node {address}, whose stored balances cannot be read: {err}");
+                quarantine(&node_dir);
+                continue;
+            }
+        }
+        let mut node = Node::restore(address, blockchain, transactions);
+        for peer in banned_peers {
+            node.ban_peer(peer);
+        }
+        if let Err(err) = world.insert_node(node).await {
+            error!("Ignoring node {address}: {err}");
+            continue;
+        }
+        restored += 1;
+    }
+    Ok(restored)
+}
+
+/// Set aside the directory of a node that cannot be restored, renaming it so that it is not
+/// restored again and so that the node does not store its blocks in it if it is created again.
+fn quarantine(node_dir: &Path) {
+    let mut name = node_dir.file_name().unwrap_or_default().to_os_string();
+    name.push(format!("{QUARANTINE_MARKER}{}", now_millis()));
+    let quarantined = node_dir.with_file_name(name);
+    match std::fs::rename(node_dir, &quarantined) {
+        Ok(()) => warn!("Moved {} to {}", node_dir.display(), quarantined.display()),
+        Err(err) => error!("Failed to set aside {}: {err}", node_dir.display()),
+    }
+}
+
+/// An empty blockchain with the given protocol parameters.
+fn new_blockchain(params: &ProtocolParams) -> BlockChain {
+    let mut blockchain = BlockChain::with_difficulty(params.mining_difficulty);
+    blockchain.set_rules(params.transaction_rules);
+    blockchain
+}
+
+/// Rebuild the nodes saved in `data_dir` offline, by replaying their blocks, and save them again,
+/// reporting the progress in the logs. Unlike `restore`, which ignores the nodes whose blockchain
+/// is invalid, a blockchain is cut before its first invalid block. Pending transactions that no
+/// longer apply to the last block are dropped. Returns how many nodes were rebuilt.
+/// Each node is rebuilt in a directory of its own, which then replaces the directory of the node,
+/// so that an interrupted reindex leaves the node as it was. If blocks were dropped, the replaced
+/// directory is kept next to it.
+pub fn reindex(data_dir: &Path) -> std::io::Result<usize> {
+    reindex_with(
+        data_dir,
+        &ProtocolParams::with_rules(config().transaction_rules),
+    )
+}
+
+/// Rebuild the nodes saved in `data_dir` offline, given the current protocol parameters.
+fn reindex_with(data_dir: &Path, params: &ProtocolParams) -> std::io::Result<usize> {
+    check_params(data_dir, params)?;
+    let mut reindexed = 0;
+    for (address, node_dir) in node_dirs(data_dir)? {
+        let blocks = read_blocks(&node_dir)?;
+        let transactions = read_transactions(&node_dir)?;
+        let saved_blocks = blocks.len() + 1;
+        let mut blockchain = new_blockchain(params);
+        for (height, block) in (1..).zip(blocks) {
+            if blockchain.append_block(block).is_err() {
+                warn!(
+                    "Node {address}: Dropping the blocks from the invalid one at height {height}"
+                );
+                break;
+            }
+            if height % REINDEX_PROGRESS_BLOCKS == 0 {
+                info!("Node {address}: Replayed {height} of {saved_blocks} blocks");
+            }
+        }
+        let mut node = Node::restore(address, blockchain, transactions);
+        for peer in read_banned_peers(&node_dir)? {
+            node.ban_peer(peer);
+        }
+
+        let rebuilt_dir = node_dir.with_file_name(format!("{address}{REINDEX_TEMPORARY_SUFFIX}"));
+        if rebuilt_dir.exists() {
+            // Left by an interrupted reindex.
+            std::fs::remove_dir_all(&rebuilt_dir)?;
+        }
+        write_node(&rebuilt_dir, &node)?;
+        if node_dir.join(BLOCKS_FILE).exists() {
+            node.blockchain()
+                .persist(&mut FileBlockStore::open(&rebuilt_dir.join(BLOCKS_FILE))?)?;
+        }
+        let backup_dir =
+            node_dir.with_file_name(format!("{address}{REINDEX_BACKUP_MARKER}{}", now_millis()));
+        std::fs::rename(&node_dir, &backup_dir)?;
+        std::fs::rename(&rebuilt_dir, &node_dir)?;
+        if node.blockchain().len() < saved_blocks {
+            warn!(
+                "Node {address}: Dropped {} of {saved_blocks} blocks, which are kept in {}",
+                saved_blocks - node.blockchain().len(),
+                backup_dir.display()
+            );
+        } else {
+            std::fs::remove_dir_all(&backup_dir)?;
+        }
+        info!(
+            "Node {address}: Rebuilt {} of {saved_blocks} blocks and {} pending transactions",
+            node.blockchain().len(),
+            node.mempool().len()
+        );
+        reindexed += 1;
+    }
+    Ok(reindexed)
+}
+
+/// Which snapshots survive a compaction.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct SnapshotRetention {
+    /// How many of the most recent snapshots to keep. The most recent one is always kept, so that
+    /// the nodes can be restored, even if this is zero.
+    pub keep_last: usize,
+    /// Also keep the snapshots whose number is a multiple of this, or none of them if zero.
+    pub keep_every: u64,
+}
+
+impl SnapshotRetention {
+    /// Whether to keep the snapshot `number`, given the numbers of all the snapshots.
+    fn keeps(&self, number: u64, numbers: &[u64]) -> bool {
+        let newer = numbers.iter().filter(|&&other| other > number).count();
+        newer < self.keep_last.max(1)
+            || (self.keep_every > 0 && number.is_multiple_of(self.keep_every))
+    }
+}
+
+/// The periodic snapshots of the nodes, saved in numbered directories under `data_dir`.
+#[derive(Clone, Debug)]
+pub struct Snapshots {
+    pub data_dir: PathBuf,
+    pub retention: SnapshotRetention,
+}
+
+/// The result of a compaction of the snapshots.
+#[derive(Debug, Serialize)]
+pub struct Compaction {
+    pub kept: Vec<u64>,
+    pub removed: Vec<u64>,
+}
+
+impl Snapshots {
+    fn dir(&self) -> PathBuf {
+        self.data_dir.join(SNAPSHOTS_DIR)
+    }
+
+    /// The numbers of the snapshots, in increasing order.
+    fn numbers(&self) -> std::io::Result<Vec<u64>> {
+        let dir = self.dir();
+        if !dir.exists() {
+            return Ok(vec![]);
+        }
+        let mut numbers = vec![];
+        for entry in std::fs::read_dir(dir)? {
+            let name = entry?.file_name();
+            if let Ok(number) = name.to_string_lossy().parse() {
+                numbers.push(number);
+            }
+        }
+        numbers.sort_unstable();
+        Ok(numbers)
+    }
+
+    /// Save the nodes to a new snapshot, numbered after the last one, and return its number.
+    /// The snapshot is written to a temporary directory first, so that an interrupted snapshot is
+    /// never mistaken for a complete one.
+    pub async fn take(&self) -> std::io::Result<u64> {
+        let number = self.numbers()?.last().map_or(1, |last| last + 1);
+        let partial_dir = self.dir().join(format!(".{number}.partial"));
+        if partial_dir.exists() {
+            std::fs::remove_dir_all(&partial_dir)?;
+        }
+        save(&partial_dir).await?;
+        std::fs::rename(&partial_dir, self.dir().join(format!("{number:06}")))?;
+        Ok(number)
+    }
+
+    /// Delete the snapshots that the retention policy does not keep.
+    pub fn compact(&self) -> std::io::Result<Compaction> {
+        let numbers = self.numbers()?;
+        let (kept, removed): (Vec<u64>, Vec<u64>) = numbers
+            .iter()
+            .partition(|&&number| self.retention.keeps(number, &numbers));
+        for number in &removed {
+            std::fs::remove_dir_all(self.dir().join(format!("{number:06}")))?;
+        }
+        Ok(Compaction { kept, removed })
+    }
+}
+
+/// Take a snapshot of the nodes every `interval`, deleting the old snapshots after each one.
+pub async fn take_snapshots(snapshots: Snapshots, interval: Duration) {
+    loop {
+        tokio::time::sleep(interval).await;
+        match snapshots.take().await {
+            Ok(number) => debug!("Took snapshot {number} of the nodes"),
+            Err(err) => error!("Failed to take a snapshot of the nodes: {err}"),
+        }
+        match snapshots.compact() {
+            Ok(compaction) if !compaction.removed.is_empty() => {
+                debug!("Removed the snapshots {:?}", compaction.removed)
+            }
+            Ok(_) => {}
+            Err(err) => error!("Failed to remove the old snapshots: {err}"),
+        }
+    }
+}
+
+/// Check that the nodes in `data_dir` were saved with the current protocol parameters.
+fn check_params(data_dir: &Path, params: &ProtocolParams) -> std::io::Result<()> {
+    let params_path = data_dir.join(PARAMS_FILE);
+    if !params_path.exists() {
+        warn!(
+            "Cannot find {}, the protocol parameters of the nodes are not checked",
+            params_path.display()
+        );
+        return Ok(());
+    }
+    let saved_params: ProtocolParams = read_json(&params_path)?;
+    let mut differences = saved_params.differences(params);
+    // The blocks encoded with an older version are still decoded.
+    if saved_params.encoding_version <= params.encoding_version {
+        differences.retain(|difference| *difference != "encoding_version");
+    }
+    if !differences.is_empty() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!(
+                "The nodes were saved with other protocol parameters: {}",
+                differences.join(", ")
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// The blocks after the genesis block saved in the directory of a node: the stored ones if the
+/// node stored its blocks, the ones saved when the server was stopped otherwise.
+fn read_blocks(node_dir: &Path) -> std::io::Result<Vec<Block>> {
+    let blocks_path = node_dir.join(BLOCKS_FILE);
+    if blocks_path.exists() {
+        return FileBlockStore::open(&blocks_path)?.blocks();
+    }
+    let blocks: Vec<Block> = read_json(&node_dir.join(BLOCKCHAIN_FILE))?;
+    Ok(blocks.into_iter().skip(1).collect())
+}
+
+/// The balances stored with the blocks of a node, if the node stored its blocks.
+fn read_balances(node_dir: &Path) -> std::io::Result<Option<StoredBalances>> {
+    let blocks_path = node_dir.join(BLOCKS_FILE);
+    if !blocks_path.exists() {
+        return Ok(None);
+    }
+    FileBlockStore::open(&blocks_path)?.balances()
+}
+
+/// The pending transactions saved in the directory of a node, if the server was stopped since the
+/// node was created.
+fn read_transactions(node_dir: &Path) -> std::io::Result<Vec<BlockTransaction>> {
+    let mempool_path = node_dir.join(MEMPOOL_FILE);
+    if !mempool_path.exists() {
+        return Ok(vec![]);
+    }
+    read_json(&mempool_path)
+}
+
+/// The peers banned by the operator saved in the directory of a node, if any.
+fn read_banned_peers(node_dir: &Path) -> std::io::Result<BTreeSet<Address>> {
+    let banned_peers_path = node_dir.join(BANNED_PEERS_FILE);
+    if !banned_peers_path.exists() {
+        return Ok(BTreeSet::new());
+    }
+    read_json(&banned_peers_path)
+}
+
+/// The directories of the nodes saved in `data_dir`, with their addresses.
+fn node_dirs(data_dir: &Path) -> std::io::Result<Vec<(Address, PathBuf)>> {
+    let mut node_dirs = vec![];
+    for entry in std::fs::read_dir(data_dir)? {
+        let node_dir = entry?.path();
+        if !node_dir.is_dir() || node_dir.ends_with(SNAPSHOTS_DIR) {
+            continue;
+        }
+        let raw_address = node_dir.file_name().unwrap_or_default().to_string_lossy();
+        // The directories set aside by `restore` and `reindex`.
+        if raw_address.contains(QUARANTINE_MARKER)
+            || raw_address.contains(REINDEX_BACKUP_MARKER)
+            || raw_address.ends_with(REINDEX_TEMPORARY_SUFFIX)
+        {
+            continue;
+        }
+        let Ok(address) = raw_address.parse::<Address>() else {
+            warn!(
+                "Ignoring directory {} of an unknown node",
+                node_dir.display()
+            );
+            continue;
+        };
+        node_dirs.push((address, node_dir));
+    }
+    Ok(node_dirs)
+}
+
+fn write_json(path: &Path, value: &impl serde::Serialize) -> std::io::Result<()> {
+    let json = serde_json::to_string_pretty(value).map_err(std::io::Error::other)?;
+    std::fs::write(path, json)
+}
+
+fn read_json<T: serde::de::DeserializeOwned>(path: &Path) -> std::io::Result<T> {
+    let json = std::fs::read_to_string(path)?;
+    serde_json::from_str(&json).map_err(|err| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("{}: {err}", path.display()),
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retain_last_and_every_nth_snapshot() {
+        let retention = SnapshotRetention {
+            keep_last: 3,
+            keep_every: 4,
+        };
+        let numbers: Vec<u64> = (1..=10).collect();
+        let kept: Vec<u64> = numbers
+            .iter()
+            .copied()
+            .filter(|&number| retention.keeps(number, &numbers))
+            .collect();
+        assert_eq!(kept, vec![4, 8, 9, 10]);
+    }
+
+    #[test]
+    fn retain_snapshots_in_edge_cases() {
+        let kept = |keep_last, keep_every, numbers: &[u64]| -> Vec<u64> {
+            let retention = SnapshotRetention {
+                keep_last,
+                keep_every,
+            };
+            numbers
+                .iter()
+                .copied()
+                .filter(|&number| retention.keeps(number, numbers))
+                .collect()
+        };
+        let numbers: Vec<u64> = (1..=5).collect();
+        // The most recent snapshot survives even a retention that keeps nothing.
+        assert_eq!(kept(0, 0, &numbers), vec![5]);
+        assert_eq!(kept(1, 0, &numbers), vec![5]);
+        assert_eq!(kept(0, 2, &numbers), vec![2, 4, 5]);
+        assert_eq!(kept(0, 1, &numbers), numbers);
+        assert_eq!(kept(10, 0, &numbers), numbers);
+        assert_eq!(kept(0, 0, &[]), Vec::<u64>::new());
+        // The numbers of the snapshots may have gaps, after earlier compactions.
+        assert_eq!(kept(2, 0, &[3, 7, 8, 20]), vec![8, 20]);
+    }
+
+    /// Mine a chain of `count` blocks with difficulty zero.
+    fn mine_blocks(count: usize) -> BlockChain {
+        let mut blockchain = BlockChain::with_difficulty(0);
+        for _ in 0..count {
+            let block = attempt_mining_block_with_difficulty(
+                blockchain.last_hash().clone(),
+                Address::new(1),
+                vec![],
+                0..1,
+                0,
+            )
+            .unwrap();
+            blockchain.append_block(block).unwrap();
+        }
+        blockchain
+    }
+
+    #[tokio::test]
+    async fn restore_killed_nodes_from_their_stored_blocks() {
+        let data_dir =
+            std::env::temp_dir().join(format!("smallchain-restore-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&data_dir);
+        let params = ProtocolParams {
+            mining_difficulty: 0,
+            ..ProtocolParams::with_rules(config().transaction_rules)
+        };
+        std::fs::create_dir_all(&data_dir).unwrap();
+        write_json(&data_dir.join(PARAMS_FILE), &params).unwrap();
+
+        // A node killed while appending its fourth block, which is cut short.
+        let valid = Address::new_random();
+        let blockchain = mine_blocks(3);
+        let valid_dir = data_dir.join(valid.to_string());
+        std::fs::create_dir_all(&valid_dir).unwrap();
+        blockchain
+            .persist(&mut FileBlockStore::open(&valid_dir.join(BLOCKS_FILE)).unwrap())
+            .unwrap();
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(valid_dir.join(BLOCKS_FILE))
+            .unwrap();
+        std::io::Write::write_all(&mut file, &[100, 0, 0, 0, 4]).unwrap();
+
+        // A node whose stored blocks cannot be decoded.
+        let corrupt = Address::new_random();
+        let corrupt_dir = data_dir.join(corrupt.to_string());
+        std::fs::create_dir_all(&corrupt_dir).unwrap();
+        std::fs::write(corrupt_dir.join(BLOCKS_FILE), [3, 0, 0, 0, 1, 2, 3]).unwrap();
+
+        // A node whose stored blocks are decodable but do not extend the genesis block.
+        let invalid = Address::new_random();
+        let invalid_dir = data_dir.join(invalid.to_string());
+        std::fs::create_dir_all(&invalid_dir).unwrap();
+        let orphan = attempt_mining_block_with_difficulty(
+            blockchain.last_hash().clone(),
+            Address::new(2),
+            vec![],
+            0..1,
+            0,
+        )
+        .unwrap();
+        FileBlockStore::open(&invalid_dir.join(BLOCKS_FILE))
+            .unwrap()
+            .append(&orphan)
+            .unwrap();
+
+        // A node whose stored balances do not match its stored blocks.
+        let tampered = Address::new_random();
+        let tampered_dir = data_dir.join(tampered.to_string());
+        std::fs::create_dir_all(&tampered_dir).unwrap();
+        let mut store = FileBlockStore::open(&tampered_dir.join(BLOCKS_FILE)).unwrap();
+        blockchain.persist(&mut store).unwrap();
+        let mut balances = blockchain.stored_balances();
+        balances
+            .balances
+            .insert(Address::new(3), Amount::from_coins(1));
+        store.store_balances(&balances).unwrap();
+
+        // Nodes whose saved transactions or banned peers cannot be parsed.
+        let unreadable_mempool = Address::new_random();
+        let unreadable_mempool_dir = data_dir.join(unreadable_mempool.to_string());
+        std::fs::create_dir_all(&unreadable_mempool_dir).unwrap();
+        blockchain
+            .persist(&mut FileBlockStore::open(&unreadable_mempool_dir.join(BLOCKS_FILE)).unwrap())
+            .unwrap();
+        std::fs::write(unreadable_mempool_dir.join(MEMPOOL_FILE), "[{").unwrap();
+        let unreadable_bans = Address::new_random();
+        let unreadable_bans_dir = data_dir.join(unreadable_bans.to_string());
+        std::fs::create_dir_all(&unreadable_bans_dir).unwrap();
+        blockchain
+            .persist(&mut FileBlockStore::open(&unreadable_bans_dir.join(BLOCKS_FILE)).unwrap())
+            .unwrap();
+        std::fs::write(unreadable_bans_dir.join(BANNED_PEERS_FILE), "{").unwrap();
+
+        let world: &'static World = Box::leak(Box::new(World::new()));
+        world.pause().await;
+        assert_eq!(restore_into(world, &data_dir, &params).await.unwrap(), 1);
+
+        let node = world.get_node(valid).await.unwrap();
+        assert_eq!(
+            node.read().await.blockchain().hash_at(3),
+            Some(blockchain.last_hash())
+        );
+        assert!(world.get_node(corrupt).await.is_none());
+        assert!(world.get_node(invalid).await.is_none());
+        assert!(world.get_node(tampered).await.is_none());
+        assert!(world.get_node(unreadable_mempool).await.is_none());
+        assert!(world.get_node(unreadable_bans).await.is_none());
+        // The directories of the nodes that were not restored are set aside.
+        assert!(!corrupt_dir.exists());
+        assert!(!invalid_dir.exists());
+        assert!(!tampered_dir.exists());
+        assert!(!unreadable_mempool_dir.exists());
+        assert!(!unreadable_bans_dir.exists());
+        let quarantined = std::fs::read_dir(&data_dir)
+            .unwrap()
+            .filter(|entry| {
+                let name = entry.as_ref().unwrap().file_name();
+                name.to_string_lossy().contains(QUARANTINE_MARKER)
+            })
+            .count();
+        assert_eq!(quarantined, 5);
+        assert_eq!(node_dirs(&data_dir).unwrap().len(), 1);
+
+        std::fs::remove_dir_all(&data_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn restore_the_banned_peers() {
+        let data_dir = std::env::temp_dir().join(format!("smallchain-bans-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&data_dir);
+        let params = ProtocolParams {
+            mining_difficulty: 0,
+            ..ProtocolParams::with_rules(config().transaction_rules)
+        };
+        std::fs::create_dir_all(&data_dir).unwrap();
+        write_json(&data_dir.join(PARAMS_FILE), &params).unwrap();
+        let address = Address::new_random();
+        let peer = Address::new_random();
+        let mut node = Node::restore(address, mine_blocks(2), vec![]);
+        node.ban_peer(peer);
+        write_node(&data_dir.join(address.to_string()), &node).unwrap();
+
+        let world: &'static World = Box::leak(Box::new(World::new()));
+        world.pause().await;
+        assert_eq!(restore_into(world, &data_dir, &params).await.unwrap(), 1);
+        let node = world.get_node(address).await.unwrap();
+        assert_eq!(*node.read().await.banned_peers(), BTreeSet::from([peer]));
+
+        std::fs::remove_dir_all(&data_dir).unwrap();
+    }
+
+    #[test]
+    fn refuse_nodes_saved_with_random_transaction_ids() {
+        let data_dir =
+            std::env::temp_dir().join(format!("smallchain-params-{}", std::process::id()));
+        std::fs::create_dir_all(&data_dir).unwrap();
+        let params = ProtocolParams::with_rules(config().transaction_rules);
+        let mut saved = serde_json::to_value(&params).unwrap();
+        saved
+            .as_object_mut()
+            .unwrap()
+            .remove("transaction_id_version");
+        write_json(&data_dir.join(PARAMS_FILE), &saved).unwrap();
+
+        let err = check_params(&data_dir, &params).unwrap_err();
+        assert!(err.to_string().ends_with(": transaction_id_version"));
+        let saved: ProtocolParams = read_json(&data_dir.join(PARAMS_FILE)).unwrap();
+        assert_ne!(saved.hash(), params.hash());
+
+        // The blocks of an older encoding are still decoded, unlike the ones of a newer one.
+        for (encoding_version, accepted) in [(1, true), (params.encoding_version + 1, false)] {
+            let saved = ProtocolParams {
+                encoding_version,
+                ..params.clone()
+            };
+            write_json(&data_dir.join(PARAMS_FILE), &saved).unwrap();
+            assert_eq!(check_params(&data_dir, &params).is_ok(), accepted);
+        }
+
+        std::fs::remove_dir_all(&data_dir).unwrap();
+    }
+
+    #[test]
+    fn reindex_the_saved_nodes() {
+        let data_dir =
+            std::env::temp_dir().join(format!("smallchain-reindex-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&data_dir);
+        let params = ProtocolParams {
+            mining_difficulty: 0,
+            ..ProtocolParams::with_rules(config().transaction_rules)
+        };
+        std::fs::create_dir_all(&data_dir).unwrap();
+        write_json(&data_dir.join(PARAMS_FILE), &params).unwrap();
+        let blockchain = mine_blocks(3);
+
+        // A node that stored its blocks, the last of which is invalid.
+        let stored = Address::new_random();
+        let stored_dir = data_dir.join(stored.to_string());
+        std::fs::create_dir_all(&stored_dir).unwrap();
+        let mut store = FileBlockStore::open(&stored_dir.join(BLOCKS_FILE)).unwrap();
+        blockchain.persist(&mut store).unwrap();
+        let orphan = attempt_mining_block_with_difficulty(
+            Block::genesis().hash(),
+            Address::new(2),
+            vec![],
+            0..1,
+            0,
+        )
+        .unwrap();
+        store.append(&orphan).unwrap();
+
+        // A node saved when the server was stopped, whose blocks are all valid.
+        let saved = Address::new_random();
+        let saved_dir = data_dir.join(saved.to_string());
+        write_node(&saved_dir, &Node::restore(saved, mine_blocks(2), vec![])).unwrap();
+
+        assert_eq!(reindex_with(&data_dir, &params).unwrap(), 2);
+        let store = FileBlockStore::open(&stored_dir.join(BLOCKS_FILE)).unwrap();
+        assert_eq!(store.len(), 3);
+        assert_eq!(store.hash_at(3), Some(blockchain.last_hash()));
+        assert_eq!(
+            store.balances().unwrap(),
+            Some(blockchain.stored_balances())
+        );
+        assert_eq!(read_blocks(&saved_dir).unwrap().len(), 2);
+        // The blocks dropped from the first node are kept aside, and no directory is left over.
+        let entries: Vec<String> = std::fs::read_dir(&data_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|name| name != PARAMS_FILE)
+            .collect();
+        assert_eq!(entries.len(), 3);
+        let backup = entries
+            .iter()
+            .find(|name| name.starts_with(&format!("{stored}{REINDEX_BACKUP_MARKER}")))
+            .unwrap();
+        let kept = FileBlockStore::open(&data_dir.join(backup).join(BLOCKS_FILE)).unwrap();
+        assert_eq!(kept.len(), 4);
+        assert_eq!(node_dirs(&data_dir).unwrap().len(), 2);
+
+        std::fs::remove_dir_all(&data_dir).unwrap();
+    }
+}
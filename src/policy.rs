@@ -0,0 +1,301 @@
+use crate::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// The rules that every transaction must satisfy, both to be admitted in a mempool and to be
+/// included in a block of the blockchain.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TransactionRules {
+    /// Whether a transaction can move zero coins.
+    pub allow_zero_amount: bool,
+    /// Whether a transaction can move coins from an address to the same address.
+    pub allow_self_transfer: bool,
+    /// How the fees of the transactions are shared between the miners and the burned coins.
+    pub fee_policy: FeePolicy,
+    /// The maximum total weight of the transactions of a block.
+    pub max_block_weight: u64,
+    /// The validation rules that the nodes skip, by name, e.g. `["balances"]`.
+    pub skipped: SkippedRules,
+}
+
+impl Default for TransactionRules {
+    fn default() -> Self {
+        TransactionRules::DEFAULT
+    }
+}
+
+impl TransactionRules {
+    pub const DEFAULT: TransactionRules = TransactionRules {
+        allow_zero_amount: false,
+        allow_self_transfer: false,
+        fee_policy: FeePolicy::MinerTakesAll,
+        max_block_weight: DEFAULT_MAX_BLOCK_WEIGHT,
+        skipped: SkippedRules::NONE,
+    };
+
+    pub fn check(&self, transaction: &Transaction) -> Result<(), TxError> {
+        if transaction.amount == Amount::ZERO && !self.allow_zero_amount {
+            return Err(TxError::ZeroAmount);
+        }
+        if transaction.receiver == Some(transaction.sender) && !self.allow_self_transfer {
+            return Err(TxError::SelfTransfer);
+        }
+        if transaction.weight() > self.max_block_weight {
+            return Err(TxError::TooHeavy {
+                weight: transaction.weight(),
+                max_block_weight: self.max_block_weight,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// How the fees of the transactions of a block are paid.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FeePolicy {
+    /// The miner of the block earns the whole fees.
+    #[default]
+    MinerTakesAll,
+    /// Like EIP-1559, each block has a base fee, which every transaction must pay and which is
+    /// burned: the miner only earns the rest of the fees (the tips). The base fee rises after the
+    /// blocks with more than `BASE_FEE_TARGET_TRANSACTIONS` transactions and falls after the ones
+    /// with fewer, and blocks have at most `BASE_FEE_MAX_TRANSACTIONS` transactions.
+    BaseFee,
+}
+
+impl FeePolicy {
+    /// The base fee of a block whose parent paid `parent_base_fee` and included
+    /// `parent_transactions` transactions. It changes by at most 1/8 (see
+    /// `BASE_FEE_MAX_CHANGE_DENOMINATOR`), and is never less than one unit, so that it can always
+    /// rise again.
+    pub fn next_base_fee(parent_base_fee: Amount, parent_transactions: usize) -> Amount {
+        let base_fee = parent_base_fee.units() as u128;
+        let target = BASE_FEE_TARGET_TRANSACTIONS as u128;
+        let used = parent_transactions as u128;
+        let next = if used > target {
+            let delta = base_fee * (used - target) / target / BASE_FEE_MAX_CHANGE_DENOMINATOR;
+            base_fee + delta.max(1)
+        } else {
+            base_fee - base_fee * (target - used) / target / BASE_FEE_MAX_CHANGE_DENOMINATOR
+        };
+        Amount::from_units(next.clamp(1, u64::MAX as u128) as u64)
+    }
+}
+
+/// The rules, stricter than the validity of blocks, that a node applies before admitting a
+/// transaction in its mempool.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TransactionPolicy {
+    /// The smallest positive amount that a transaction can move. Smaller amounts are dust.
+    /// Whether zero amounts are allowed is decided by the `TransactionRules`.
+    pub min_amount: Amount,
+    /// If set, a transaction is dust when its fee exceeds its amount multiplied by this factor.
+    pub max_fee_multiple: Option<u64>,
+}
+
+impl Default for TransactionPolicy {
+    fn default() -> Self {
+        TransactionPolicy::DEFAULT
+    }
+}
+
+impl TransactionPolicy {
+    pub const DEFAULT: TransactionPolicy = TransactionPolicy {
+        min_amount: MEMPOOL_DEFAULT_MIN_AMOUNT,
+        max_fee_multiple: None,
+    };
+
+    /// Check that a transaction is not dust.
+    pub fn check(&self, transaction: &Transaction) -> Result<(), TxError> {
+        if transaction.amount > Amount::ZERO && transaction.amount < self.min_amount {
+            return Err(TxError::BelowMinimumAmount {
+                amount: transaction.amount,
+                min_amount: self.min_amount,
+            });
+        }
+        if let Some(max_fee_multiple) = self.max_fee_multiple {
+            if transaction.fee > transaction.amount.saturating_mul(max_fee_multiple) {
+                return Err(TxError::ExcessiveFee {
+                    fee: transaction.fee,
+                    amount: transaction.amount,
+                    max_fee_multiple,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The reasons why a transaction is not admitted in a mempool.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum TxError {
+    /// The transaction is not on top of the last block of the mempool.
+    InvalidPrefix,
+    /// The identifier is not the one derived from the content of the transaction.
+    InvalidId,
+    /// The transaction moves zero coins, which the rules forbid.
+    ZeroAmount,
+    /// The transaction moves coins from an address to itself, which the rules forbid.
+    SelfTransfer,
+    /// The transaction is already in the mempool.
+    Duplicate,
+    /// The amount plus the fee does not fit in 64 bits.
+    CostOverflow,
+    /// The sender cannot afford the amount plus the fee.
+    InsufficientFunds,
+    /// The balance of the receiver would overflow.
+    BalanceOverflow,
+    /// Too many transactions on top of unknown blocks are held, in total or for the sender.
+    TooManyHeld,
+    /// The mempool has reached its maximum number of transactions.
+    MempoolFull,
+    /// The amount is smaller than the minimum of the policy.
+    BelowMinimumAmount { amount: Amount, min_amount: Amount },
+    /// The fee exceeds the amount multiplied by the maximum factor of the policy.
+    ExcessiveFee {
+        fee: Amount,
+        amount: Amount,
+        max_fee_multiple: u64,
+    },
+    /// With the base-fee policy, the fee does not cover the base fee of the next block.
+    BelowBaseFee { fee: Amount, base_fee: Amount },
+    /// The transaction is heavier than a whole block.
+    TooHeavy { weight: u64, max_block_weight: u64 },
+    /// A client tried to send coins from the faucet, which only the faucet endpoint does.
+    FaucetSender,
+}
+
+impl TxError {
+    /// A stable identifier of the error, reported by the REST API.
+    pub fn code(&self) -> &'static str {
+        match self {
+            TxError::InvalidPrefix => "invalid_prefix",
+            TxError::InvalidId => "invalid_id",
+            TxError::ZeroAmount => "zero_amount",
+            TxError::SelfTransfer => "self_transfer",
+            TxError::Duplicate => "duplicate",
+            TxError::CostOverflow => "cost_overflow",
+            TxError::InsufficientFunds => "insufficient_funds",
+            TxError::BalanceOverflow => "balance_overflow",
+            TxError::TooManyHeld => "too_many_held",
+            TxError::MempoolFull => "mempool_full",
+            TxError::BelowMinimumAmount { .. } => "below_minimum_amount",
+            TxError::ExcessiveFee { .. } => "excessive_fee",
+            TxError::BelowBaseFee { .. } => "below_base_fee",
+            TxError::TooHeavy { .. } => "too_heavy",
+            TxError::FaucetSender => "faucet_sender",
+        }
+    }
+}
+
+impl std::fmt::Display for TxError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            TxError::InvalidPrefix => write!(f, "not on top of the last block"),
+            TxError::InvalidId => write!(f, "the identifier does not match the content"),
+            TxError::ZeroAmount => write!(f, "the amount must be positive"),
+            TxError::SelfTransfer => write!(f, "the sender and the receiver must differ"),
+            TxError::Duplicate => write!(f, "already known"),
+            TxError::CostOverflow => write!(f, "the amount plus the fee overflows"),
+            TxError::InsufficientFunds => write!(f, "insufficient funds"),
+            TxError::BalanceOverflow => write!(f, "the balance of the receiver overflows"),
+            TxError::TooManyHeld => write!(f, "too many transactions on top of unknown blocks"),
+            TxError::MempoolFull => write!(f, "the mempool is full"),
+            TxError::BelowMinimumAmount { amount, min_amount } => {
+                write!(
+                    f,
+                    "the amount {amount} is below the minimum of {min_amount}"
+                )
+            }
+            TxError::ExcessiveFee {
+                fee,
+                amount,
+                max_fee_multiple,
+            } => write!(
+                f,
+                "the fee {fee} exceeds {max_fee_multiple} times the amount {amount}"
+            ),
+            TxError::BelowBaseFee { fee, base_fee } => {
+                write!(f, "the fee {fee} is below the base fee of {base_fee}")
+            }
+            TxError::TooHeavy {
+                weight,
+                max_block_weight,
+            } => write!(
+                f,
+                "the weight {weight} exceeds the maximum weight of a block, {max_block_weight}"
+            ),
+            TxError::FaucetSender => write!(f, "only the faucet sends coins from the faucet"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reject_dust() {
+        let transfer = |amount, fee| {
+            Transaction::new(Address::new(1), Address::new(2), Amount::from_coins(amount))
+                .with_fee(Amount::from_coins(fee))
+        };
+        let policy = TransactionPolicy {
+            min_amount: Amount::from_coins(10),
+            max_fee_multiple: Some(2),
+        };
+        assert_eq!(policy.check(&transfer(10, 20)), Ok(()));
+        assert_eq!(
+            policy.check(&transfer(9, 0)).map_err(|err| err.code()),
+            Err("below_minimum_amount")
+        );
+        assert_eq!(
+            policy.check(&transfer(10, 21)).map_err(|err| err.code()),
+            Err("excessive_fee")
+        );
+        // Zero amounts are not dust; they are allowed or forbidden by the rules.
+        assert_eq!(policy.check(&transfer(0, 0)), Ok(()));
+    }
+
+    #[test]
+    fn adjust_the_base_fee() {
+        let base_fee = Amount::from_coins(8);
+        let next = |transactions| FeePolicy::next_base_fee(base_fee, transactions);
+        assert_eq!(next(BASE_FEE_TARGET_TRANSACTIONS), base_fee);
+        assert_eq!(next(BASE_FEE_MAX_TRANSACTIONS), Amount::from_coins(9));
+        assert_eq!(next(0), Amount::from_coins(7));
+        assert!(next(BASE_FEE_TARGET_TRANSACTIONS + 1) > base_fee);
+        // The base fee never reaches zero, and it can always rise again
+        let min = Amount::from_units(1);
+        assert_eq!(FeePolicy::next_base_fee(min, 0), min);
+        assert!(FeePolicy::next_base_fee(min, BASE_FEE_TARGET_TRANSACTIONS + 1) > min);
+    }
+
+    #[test]
+    fn forbid_zero_amounts_and_self_transfers() {
+        let (alice, bob) = (Address::new(1), Address::new(2));
+        let coins = Amount::from_coins;
+        let rules = TransactionRules::default();
+        assert_eq!(rules.check(&Transaction::new(alice, bob, coins(1))), Ok(()));
+        assert_eq!(
+            rules.check(&Transaction::new(alice, bob, coins(0))),
+            Err(TxError::ZeroAmount)
+        );
+        assert_eq!(
+            rules.check(&Transaction::new(alice, alice, coins(1))),
+            Err(TxError::SelfTransfer)
+        );
+
+        let permissive = TransactionRules {
+            allow_zero_amount: true,
+            allow_self_transfer: true,
+            ..TransactionRules::DEFAULT
+        };
+        assert_eq!(
+            permissive.check(&Transaction::new(alice, alice, coins(0))),
+            Ok(())
+        );
+    }
+}
@@ -1,8 +1,27 @@
+pub use crate::amount::*;
+pub use crate::balance::*;
 pub use crate::block::*;
 pub use crate::blockchain::*;
+#[cfg(feature = "runtime")]
+pub use crate::config::*;
+pub use crate::consensus::*;
 pub use crate::constants::*;
+#[cfg(feature = "runtime")]
+pub use crate::events::*;
+#[cfg(feature = "runtime")]
+pub use crate::inbox::*;
 pub use crate::mempool::*;
+#[cfg(feature = "runtime")]
 pub use crate::network::*;
+#[cfg(feature = "runtime")]
 pub use crate::node::*;
+pub use crate::policy::*;
+#[cfg(feature = "runtime")]
+pub use crate::stats::*;
+pub use crate::store::*;
+pub use crate::validation::*;
+#[cfg(feature = "runtime")]
+pub use crate::wallet::*;
+#[cfg(feature = "runtime")]
 pub use crate::world::*;
 pub use tracing::{debug, error, info, warn};
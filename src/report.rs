@@ -0,0 +1,207 @@
+use crate::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::Path;
+
+/// A summary of a run of the simulation.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Report {
+    /// The parameters of the run.
+    pub parameters: Parameters,
+    /// How long the simulation ran, in seconds.
+    pub duration_secs: f64,
+    /// The longest blockchain among the nodes at the end of the run.
+    pub chain: ChainSummary,
+    /// The forks observed during the run.
+    pub forks: ForkSummary,
+    /// How quickly the mined blocks reached the other nodes.
+    pub propagation: PropagationSummary,
+    /// The rate at which blocks and transactions have been added to the longest blockchain.
+    pub throughput: Throughput,
+}
+
+/// The parameters of a run of the simulation.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Parameters {
+    pub demo: bool,
+    pub nodes: usize,
+    pub mining_difficulty: u32,
+    pub coins_per_mined_block: Amount,
+    pub node_mining_nonce_step: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChainSummary {
+    pub height: usize,
+    pub last_block_hash: String,
+    pub transactions: usize,
+    /// Whether all the nodes share the same last block.
+    pub converged: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ForkSummary {
+    /// How many blocks have been mined, including orphans.
+    pub mined_blocks: usize,
+    /// How many mined blocks are not part of the longest blockchain.
+    pub orphaned_blocks: usize,
+    /// How many times a node replaced some blocks of its local blockchain.
+    pub reorgs: usize,
+    /// The maximum number of blocks replaced by a reorganization.
+    pub max_reorg_depth: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PropagationSummary {
+    /// The average, over the mined blocks, of the time it took for 95% of the nodes that adopted
+    /// a block to do so, in milliseconds.
+    pub mean_p95_millis: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Throughput {
+    pub blocks_per_minute: f64,
+    pub transactions_per_second: f64,
+}
+
+impl Report {
+    /// Summarize the current state of the simulation.
+    pub async fn collect(demo: bool) -> Self {
+        let stats = stats().await;
+        let duration_secs = stats.uptime().as_secs_f64();
+
+        // Find the longest blockchain among the nodes
+        let addresses = world().await.get_node_addresses().await;
+        let mut longest_blockchain: Option<BlockChain> = None;
+        for &address in &addresses {
+            let Some(node) = world().await.get_node(address).await else {
+                continue;
+            };
+            let readable_node = node.read().await;
+            let blockchain = readable_node.blockchain();
+            if longest_blockchain
+                .as_ref()
+                .is_none_or(|longest| blockchain.len() > longest.len())
+            {
+                longest_blockchain = Some(blockchain.clone());
+            }
+        }
+        let longest_blockchain = longest_blockchain.unwrap_or_default();
+        let transactions: usize = longest_blockchain
+            .blocks()
+            .map(|block| block.transactions.len())
+            .sum();
+
+        let mined_blocks = stats.mined_block_hashes().await;
+        let canonical: HashSet<&BlockHash> = longest_blockchain.hashes().collect();
+        let orphaned_blocks = mined_blocks
+            .iter()
+            .filter(|hash| !canonical.contains(hash))
+            .count();
+        let reorgs = stats.reorgs().await;
+        let propagation_delays: Vec<u64> = stats
+            .block_rows()
+            .await
+            .iter()
+            .filter_map(|row| row.propagation_p95_millis)
+            .collect();
+        let mean_p95_millis = if propagation_delays.is_empty() {
+            0.0
+        } else {
+            propagation_delays.iter().sum::<u64>() as f64 / propagation_delays.len() as f64
+        };
+
+        let rate = |count: usize, unit_secs: f64| {
+            if duration_secs > 0.0 {
+                count as f64 * unit_secs / duration_secs
+            } else {
+                0.0
+            }
+        };
+
+        Report {
+            parameters: Parameters {
+                demo,
+                nodes: addresses.len(),
+                mining_difficulty: MINING_DIFFICULTY,
+                coins_per_mined_block: COINS_PER_MINED_BLOCK,
+                node_mining_nonce_step: NODE_MINING_NONCE_STEP,
+            },
+            duration_secs,
+            chain: ChainSummary {
+                height: longest_blockchain.height(),
+                last_block_hash: longest_blockchain.last_hash().to_string(),
+                transactions,
+                converged: world().await.convergence().await.converged,
+            },
+            forks: ForkSummary {
+                mined_blocks: mined_blocks.len(),
+                orphaned_blocks,
+                reorgs: reorgs.len(),
+                max_reorg_depth: reorgs.iter().map(|reorg| reorg.depth).max().unwrap_or(0),
+            },
+            propagation: PropagationSummary { mean_p95_millis },
+            throughput: Throughput {
+                blocks_per_minute: rate(longest_blockchain.height(), 60.0),
+                transactions_per_second: rate(transactions, 1.0),
+            },
+        }
+    }
+
+    /// Write the report in `report.json` and `report.txt`, creating the directory if needed.
+    pub fn write(&self, results_dir: &Path) -> std::io::Result<()> {
+        std::fs::create_dir_all(results_dir)?;
+        let json = serde_json::to_string_pretty(self).map_err(std::io::Error::other)?;
+        std::fs::write(results_dir.join("report.json"), json)?;
+        std::fs::write(results_dir.join("report.txt"), self.to_string())?;
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        fn field(
+            f: &mut std::fmt::Formatter,
+            label: &str,
+            value: impl std::fmt::Display,
+        ) -> std::fmt::Result {
+            writeln!(f, "  {label:<26}{value}")
+        }
+
+        writeln!(f, "SmallChain run report")?;
+        writeln!(f, "\nParameters:")?;
+        field(f, "demo mode:", self.parameters.demo)?;
+        field(f, "nodes:", self.parameters.nodes)?;
+        field(f, "mining difficulty:", self.parameters.mining_difficulty)?;
+        field(
+            f,
+            "coins per mined block:",
+            self.parameters.coins_per_mined_block,
+        )?;
+        field(
+            f,
+            "nonces per iteration:",
+            self.parameters.node_mining_nonce_step,
+        )?;
+        field(f, "duration (s):", format!("{:.1}", self.duration_secs))?;
+        writeln!(f, "\nLongest blockchain:")?;
+        field(f, "height:", self.chain.height)?;
+        field(f, "last block:", &self.chain.last_block_hash)?;
+        field(f, "transactions:", self.chain.transactions)?;
+        field(f, "converged:", self.chain.converged)?;
+        writeln!(f, "\nForks:")?;
+        field(f, "mined blocks:", self.forks.mined_blocks)?;
+        field(f, "orphaned blocks:", self.forks.orphaned_blocks)?;
+        field(f, "reorganizations:", self.forks.reorgs)?;
+        field(f, "max reorganization depth:", self.forks.max_reorg_depth)?;
+        writeln!(f, "\nPropagation:")?;
+        let mean_p95_millis = format!("{:.1}", self.propagation.mean_p95_millis);
+        field(f, "mean p95 delay (ms):", mean_p95_millis)?;
+        writeln!(f, "\nThroughput:")?;
+        let blocks_per_minute = format!("{:.2}", self.throughput.blocks_per_minute);
+        field(f, "blocks per minute:", blocks_per_minute)?;
+        let transactions_per_second = format!("{:.2}", self.throughput.transactions_per_second);
+        field(f, "transactions per second:", transactions_per_second)?;
+        Ok(())
+    }
+}
@@ -1,44 +1,604 @@
+use crate::encoding::{Decode, DecodeError, Encode};
+use crate::persistence::{self, Snapshots};
 use crate::prelude::*;
-use std::collections::HashMap;
-use warp::http::StatusCode;
+use futures_util::{SinkExt, StreamExt};
+use rand::seq::SliceRandom;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{oneshot, watch, RwLock};
+use warp::filters::path::FullPath;
+use warp::filters::BoxedFilter;
+use warp::http::{HeaderValue, StatusCode};
 use warp::reject::Rejection;
 use warp::reply::json;
-use warp::reply::Reply;
+use warp::reply::{Reply, Response};
 use warp::Filter;
 
-pub async fn serve(port: Option<u16>) {
-    let root = warp::path!().map(|| "Ok".to_string());
-    let list_nodes = warp::path!("nodes").and_then(handle_list_nodes);
-    let add_node = warp::path!("node").and_then(handle_add_node);
-    let show_node = warp::path!("node" / String).and_then(handle_show_node);
-    let show_node_block = warp::path!("node" / String / "block" / String)
-        .and_then(handle_show_node_block);
-    let show_node_blockchain_balance = warp::path!("node" / String / "blockchain_balance")
-        .and_then(handle_show_node_blockchain_balance);
-    let show_node_mempool_balance =
-        warp::path!("node" / String / "mempool_balance").and_then(handle_show_node_mempool_balance);
-    let delete_node = warp::path!("node" / String).and_then(handle_delete_node);
-    let send_transaction =
-        warp::path!("node" / String / "send" / "from" / String / "to" / String / "amount" / String)
-            .and_then(handle_send_transaction);
-
-    let get_routes = warp::get().and(
-        root.or(list_nodes)
-            .or(show_node)
-            .or(show_node_block)
-            .or(show_node_blockchain_balance)
-            .or(show_node_mempool_balance),
-    );
-    let post_routes = warp::post().and(add_node.or(send_transaction));
-    let del_routes = warp::post().and(delete_node);
-    let routes = get_routes
+/// How the administration endpoints, which change the world, are served.
+pub struct AdminOptions {
+    /// The token that the requests must carry, as `Authorization: Bearer {token}`.
+    pub token: String,
+    /// The port on which to serve them, if not the one of the public endpoints.
+    pub port: Option<u16>,
+    /// Reads the settings again, if they come from a configuration file that can be reloaded.
+    pub config_loader: Option<ConfigLoader>,
+    /// The snapshots of the nodes, if they are saved to a data directory.
+    pub snapshots: Option<Snapshots>,
+}
+
+/// The current version of the REST API, which prefixes its routes, e.g. `/v1/nodes`.
+pub const API_VERSION: &str = "v1";
+
+/// The routes of a server, which reply with a response or reject the request.
+type Routes = BoxedFilter<(Response,)>;
+
+/// Serve the REST API until an admin requests a shutdown with `POST /admin/shutdown`.
+pub async fn serve(port: Option<u16>, max_body_size: u64, read_only: bool, admin: AdminOptions) {
+    let (shutdown, shutdown_requested) = watch::channel(false);
+    let public_routes = versioned(public_routes(max_body_size, read_only));
+    let admin_routes = versioned(admin_routes(
+        admin.token,
+        admin.config_loader,
+        admin.snapshots,
+        Arc::new(shutdown),
+        max_body_size,
+    ));
+    let stopped = move || {
+        let mut shutdown_requested = shutdown_requested.clone();
+        async move {
+            let _ = shutdown_requested.wait_for(|&requested| requested).await;
+        }
+    };
+
+    let address = [127, 0, 0, 1];
+    let servers = async {
+        match admin.port {
+            Some(admin_port) => {
+                info!("Serving the admin endpoints on port {admin_port}");
+                tokio::join!(
+                    warp::serve(public_routes.recover(handle_rejection))
+                        .bind_with_graceful_shutdown((address, port.unwrap_or(0)), stopped())
+                        .1,
+                    warp::serve(admin_routes.recover(handle_rejection))
+                        .bind_with_graceful_shutdown((address, admin_port), stopped())
+                        .1,
+                );
+            }
+            None => {
+                let routes = admin_routes.or(public_routes).recover(handle_rejection);
+                warp::serve(routes)
+                    .bind_with_graceful_shutdown((address, port.unwrap_or(0)), stopped())
+                    .1
+                    .await;
+            }
+        }
+    };
+    // The servers finish the open requests, but the streams of events would never end
+    let timeout = async {
+        stopped().await;
+        tokio::time::sleep(SERVER_SHUTDOWN_TIMEOUT).await;
+    };
+    tokio::select! {
+        _ = servers => {}
+        _ = timeout => {}
+    }
+}
+
+/// Serve the routes under the prefix of the current version of the API, and without a prefix
+/// for the scripts written before the versioning. The unprefixed routes are deprecated and will
+/// be removed in the next release: their responses carry a `Deprecation` header and a link to
+/// the versioned route.
+fn versioned(routes: Routes) -> Routes {
+    let current = warp::path(API_VERSION).and(routes.clone());
+    let legacy = warp::path::full()
+        .and(routes)
+        .map(|path: FullPath, reply: Response| {
+            deprecate(reply, Some(&format!("/{API_VERSION}{}", path.as_str())))
+        });
+    current.or(legacy).unify().boxed()
+}
+
+/// Mark the response of a deprecated route with a `Deprecation` header and, if the route has a
+/// successor that is not linked yet, a link to it.
+fn deprecate(mut reply: Response, successor: Option<&str>) -> Response {
+    let headers = reply.headers_mut();
+    headers.insert("deprecation", HeaderValue::from_static("true"));
+    if let Some(successor) = successor {
+        let successor = format!("<{successor}>; rel=\"successor-version\"");
+        if let Ok(successor) = HeaderValue::from_str(&successor) {
+            headers.entry("link").or_insert(successor);
+        }
+    }
+    reply
+}
+
+/// The endpoints that everyone can query. In read-only mode, the ones that send transactions
+/// are rejected.
+fn public_routes(max_body_size: u64, read_only: bool) -> Routes {
+    // Rejects the requests to the endpoints that change the simulation, in read-only mode.
+    let writable = warp::any()
+        .and_then(move || async move {
+            if read_only {
+                Err(warp::reject::custom(ReadOnly))
+            } else {
+                Ok(())
+            }
+        })
+        .untuple_one();
+    let root = warp::path!().and(warp::get()).map(|| "Ok".to_string());
+    let list_nodes = warp::path!("nodes")
+        .and(warp::get())
+        .and(paginated())
+        .and_then(handle_list_nodes);
+    let world_converged = warp::path!("world" / "converged")
+        .and(warp::get())
+        .and_then(handle_world_converged);
+    let world_converged_wait = warp::path!("world" / "converged" / "wait")
+        .and(warp::get())
+        .and(warp::query::<WaitQuery>())
+        .and_then(handle_world_converged_wait);
+    let world_timeline = warp::path!("world" / "timeline")
+        .and(warp::get())
+        .and(paginated())
+        .and_then(handle_world_timeline);
+    let world_node_ports = warp::path!("world" / "node_ports")
+        .and(warp::get())
+        .and_then(handle_world_node_ports);
+    let world_blocktree = warp::path!("world" / "blocktree")
+        .and(warp::get())
+        .and(paginated())
+        .and_then(handle_world_blocktree);
+    let world_params = warp::path!("world" / "params")
+        .and(warp::get())
+        .and_then(handle_world_params);
+    let network_stats = warp::path!("network" / "stats")
+        .and(warp::get())
+        .and_then(handle_network_stats);
+    let stats_block_times = warp::path!("stats" / "block_times")
+        .and(warp::get())
+        .and(warp::query::<BlockTimesQuery>())
+        .and_then(handle_stats_block_times);
+    let stats_tips = warp::path!("stats" / "tips")
+        .and(warp::get())
+        .and(warp::query::<TipsQuery>())
+        .and_then(handle_stats_tips);
+    let stats_export = warp::path!("stats" / "export")
+        .and(warp::get())
+        .and(warp::query::<ExportQuery>())
+        .and_then(handle_stats_export);
+    let events_ws = warp::path!("events")
+        .and(warp::get())
+        .and(warp::ws())
+        .map(|ws: warp::ws::Ws| ws.on_upgrade(stream_events));
+    let events_sse = warp::path!("events" / "sse")
+        .and(warp::get())
+        .and(warp::header::optional::<u64>("last-event-id"))
+        .and_then(handle_events_sse);
+    let show_node = warp::path::end()
+        .and(warp::get())
+        .map(|| node_handler(handle_show_node));
+    let show_node_block = warp::path("block")
+        .and(with_parsed("block hash"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(if_none_match())
+        .map(|hash, if_none_match| {
+            node_handler(move |node| handle_show_node_block(node, hash, if_none_match))
+        });
+    let show_node_blockchain_balance = warp::path!("blockchain_balance")
+        .and(warp::get())
+        .and(if_none_match())
+        .map(|if_none_match| {
+            node_handler(move |node| handle_show_node_blockchain_balance(node, if_none_match))
+        });
+    let show_node_mempool_balance = warp::path!("mempool_balance")
+        .and(warp::get())
+        .map(|| node_handler(handle_show_node_mempool_balance));
+    let show_node_sync_status = warp::path!("sync_status")
+        .and(warp::get())
+        .map(|| node_handler(handle_show_node_sync_status));
+    let show_node_mining_stats = warp::path!("mining_stats")
+        .and(warp::get())
+        .map(|| node_handler(handle_show_node_mining_stats));
+    let show_node_transaction_propagation = warp::path("transaction")
+        .and(with_parsed("transaction identifier"))
+        .and(warp::path!("propagation"))
+        .and(warp::get())
+        .map(|transaction_id| {
+            node_handler(move |node| handle_show_node_transaction_propagation(node, transaction_id))
+        });
+    let show_node_receipt = warp::path("receipt")
+        .and(with_parsed("transaction identifier"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .map(|transaction_id| {
+            node_handler(move |node| handle_show_node_receipt(node, transaction_id))
+        });
+    let show_node_miner_stats = warp::path!("miner_stats")
+        .and(warp::get())
+        .map(|| node_handler(handle_show_node_miner_stats));
+    let show_node_validation_stats = warp::path!("validation_stats")
+        .and(warp::get())
+        .map(|| node_handler(handle_show_node_validation_stats));
+    let stats_wealth = warp::path!("stats" / "wealth")
+        .and(warp::get())
+        .and(warp::query::<WealthQuery>())
+        .and_then(handle_stats_wealth);
+    let stats_miners = warp::path!("stats" / "miners")
+        .and(warp::get())
+        .and_then(handle_stats_miners);
+    let stats_confirmations = warp::path!("stats" / "confirmations")
+        .and(warp::get())
+        .and_then(handle_stats_confirmations);
+    let show_node_audit = warp::path!("audit")
+        .and(warp::get())
+        .map(|| node_handler(handle_show_node_audit));
+    let show_node_config = warp::path!("config")
+        .and(warp::get())
+        .map(|| node_handler(handle_show_node_config));
+    let show_node_banned_peers = warp::path!("banned_peers")
+        .and(warp::get())
+        .map(|| node_handler(handle_show_node_banned_peers));
+    let show_node_fee_market = warp::path!("fee_market")
+        .and(warp::get())
+        .map(|| node_handler(handle_show_node_fee_market));
+    let show_node_blocks = warp::path!("blocks")
+        .and(warp::get())
+        .and(paginated())
+        .and(if_none_match())
+        .map(|search, page, if_none_match| {
+            node_handler(move |node| handle_show_node_blocks(node, search, page, if_none_match))
+        });
+    let show_node_blocks_search = warp::path!("blocks" / "search")
+        .and(warp::get())
+        .and(paginated())
+        .and(if_none_match())
+        .map(|search, page, if_none_match| {
+            node_handler(move |node| handle_show_node_blocks(node, search, page, if_none_match))
+        });
+    let show_node_mempool = warp::path!("mempool")
+        .and(warp::get())
+        .and(paginated())
+        .map(|filters, page| {
+            node_handler(move |node| handle_show_node_mempool(node, filters, page))
+        });
+    let show_node_submissions = warp::path!("submissions")
+        .and(warp::get())
+        .and(paginated())
+        .map(|filters, page| {
+            node_handler(move |node| handle_show_node_submissions(node, filters, page))
+        });
+    let show_node_wait_for_block = warp::path("node")
+        .and(with_parsed("address"))
+        .and(warp::path!("wait_for_block"))
+        .and(warp::get())
+        .and(warp::query::<BlockWaitQuery>())
+        .and_then(handle_show_node_wait_for_block);
+    let show_node_transaction_wait = warp::path("node")
+        .and(with_parsed("address"))
+        .and(warp::path("transaction"))
+        .and(with_parsed("transaction identifier"))
+        .and(warp::path!("wait"))
+        .and(warp::get())
+        .and(warp::query::<ConfirmationsQuery>())
+        .and_then(handle_show_node_transaction_wait);
+    let send_transaction = warp::path!("send" / "from" / ..)
+        .and(with_parsed("sender address"))
+        .and(warp::path("to"))
+        .and(with_parsed("recipient address"))
+        .and(warp::path("amount"))
+        .and(with_parsed("amount"))
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(writable)
+        .map(|sender, recipient, amount| {
+            node_handler(move |node| handle_send_transaction(node, sender, recipient, amount))
+        });
+    let submit_transaction = warp::path!("transaction")
+        .and(warp::post())
+        .and(writable)
+        .and(warp::body::content_length_limit(max_body_size))
+        .and(warp::body::json())
+        .map(|body| node_handler(move |node| handle_submit_transaction(node, body)));
+    let submit_raw_transaction = warp::path!("transaction" / "raw")
+        .and(warp::post())
+        .and(writable)
+        .and(warp::body::content_length_limit(max_body_size))
+        .and(warp::body::bytes())
+        .map(|bytes| node_handler(move |node| handle_submit_raw_transaction(node, bytes)));
+    let show_node_work = warp::path!("work")
+        .and(warp::get())
+        .and(writable)
+        .and(warp::query::<WorkQuery>())
+        .map(|query| node_handler(move |node| handle_show_node_work(node, query)));
+    let submit_work = warp::path!("submit_work")
+        .and(warp::post())
+        .and(writable)
+        .and(warp::body::content_length_limit(max_body_size))
+        .and(warp::body::json())
+        .map(|solution| node_handler(move |node| handle_submit_work(node, solution)));
+    let faucet = warp::path("faucet")
+        .and(with_parsed("address"))
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(warp::query::<FaucetQuery>())
+        .and(writable)
+        .and_then(handle_faucet);
+    let list_wallets = warp::path!("wallets")
+        .and(warp::get())
+        .and_then(handle_list_wallets);
+    let add_wallet = warp::path!("wallet")
+        .and(warp::post())
+        .and(warp::body::content_length_limit(max_body_size))
+        .and(warp::body::json())
+        .and_then(handle_add_wallet);
+    let wallet = warp::path("wallet").and(warp::path::param::<String>());
+    let show_wallet = wallet
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(warp::query::<WalletQuery>())
+        .and_then(handle_show_wallet);
+    let show_wallet_transactions = wallet
+        .and(warp::path!("transactions"))
+        .and(warp::get())
+        .and(paginated())
+        .and_then(handle_show_wallet_transactions);
+    let delete_wallet = wallet
+        .and(warp::path::end())
+        .and(warp::delete())
+        .and_then(handle_delete_wallet);
+    let build_transaction = warp::path!("transaction" / "build")
+        .and(warp::post())
+        .and(warp::body::content_length_limit(max_body_size))
+        .and(warp::body::json())
+        .and_then(handle_build_transaction);
+
+    let stats_routes = stats_block_times
+        .or(stats_tips)
+        .or(stats_export)
+        .or(stats_miners)
+        .or(stats_confirmations)
+        .or(stats_wealth)
+        .map(Reply::into_response)
+        .boxed();
+    let node_routes = with_node(
+        warp::path("node").and(with_parsed("node address")).boxed(),
+        vec![
+            show_node.boxed(),
+            show_node_block.boxed(),
+            show_node_blockchain_balance.boxed(),
+            show_node_mempool_balance.boxed(),
+            show_node_sync_status.boxed(),
+            show_node_mining_stats.boxed(),
+            show_node_transaction_propagation.boxed(),
+            show_node_receipt.boxed(),
+            show_node_blocks.boxed(),
+            show_node_blocks_search.boxed(),
+            show_node_mempool.boxed(),
+            show_node_submissions.boxed(),
+            show_node_work.boxed(),
+            show_node_audit.boxed(),
+            show_node_config.boxed(),
+            show_node_banned_peers.boxed(),
+            show_node_fee_market.boxed(),
+            show_node_miner_stats.boxed(),
+            show_node_validation_stats.boxed(),
+            send_transaction.boxed(),
+            submit_transaction.boxed(),
+            submit_raw_transaction.boxed(),
+            submit_work.boxed(),
+        ],
+    )
+    .or(show_node_wait_for_block)
+    .or(show_node_transaction_wait)
+    .map(Reply::into_response)
+    .boxed();
+    let get_routes = root
+        .or(list_nodes)
+        .or(world_converged)
+        .or(world_converged_wait)
+        .or(world_timeline)
+        .or(world_node_ports)
+        .or(world_params)
+        .or(world_blocktree)
+        .or(network_stats)
+        .or(stats_routes)
+        .or(events_ws)
+        .or(events_sse)
+        .or(node_routes)
+        .or(list_wallets)
+        .or(show_wallet)
+        .or(show_wallet_transactions);
+    let post_routes = faucet.or(add_wallet).or(build_transaction);
+    get_routes
         .or(post_routes)
-        .or(del_routes)
-        .recover(handle_rejection);
+        .or(delete_wallet)
+        .map(Reply::into_response)
+        .boxed()
+}
+
+/// The administration endpoints, under `/admin`, which are not affected by the read-only mode.
+fn admin_routes(
+    admin_token: String,
+    config_loader: Option<ConfigLoader>,
+    snapshots: Option<Snapshots>,
+    shutdown: Arc<watch::Sender<bool>>,
+    max_body_size: u64,
+) -> Routes {
+    let authorized = warp::header::optional::<String>("authorization")
+        .and_then(move |authorization: Option<String>| {
+            let authorized = authorization
+                .as_deref()
+                .and_then(|authorization| authorization.strip_prefix("Bearer "))
+                == Some(admin_token.as_str());
+            async move {
+                if authorized {
+                    Ok(())
+                } else {
+                    Err(warp::reject::custom(Unauthorized))
+                }
+            }
+        })
+        .untuple_one();
+    let add_node = warp::path!("node")
+        .and(warp::post())
+        .and_then(handle_add_node);
+    let add_nodes = warp::path!("nodes")
+        .and(warp::post())
+        .and(warp::query::<AddNodesQuery>())
+        .and_then(handle_add_nodes);
+    let node_address = warp::path("node")
+        .and(with_parsed("address"))
+        .and(warp::path::end());
+    let delete_node = node_address
+        .and(warp::delete())
+        .and(warp::query::<DeleteQuery>())
+        .and_then(handle_delete_node)
+        .map(Reply::into_response);
+    // Nodes used to be deleted with `POST`, which is kept until the next release.
+    let delete_node_with_post = node_address
+        .and(warp::post())
+        .and(warp::query::<DeleteQuery>())
+        .and_then(handle_delete_node)
+        .map(|reply| deprecate(Reply::into_response(reply), None));
+    let clone_node = warp::path("node")
+        .and(with_parsed("address"))
+        .and(warp::path!("clone"))
+        .and(warp::post())
+        .and_then(handle_clone_node);
+    let world_pause = warp::path!("world" / "pause")
+        .and(warp::post())
+        .and_then(handle_world_pause);
+    let world_resume = warp::path!("world" / "resume")
+        .and(warp::post())
+        .and_then(handle_world_resume);
+    let reload_config = warp::path!("world" / "config" / "reload")
+        .and(warp::post())
+        .map(move || config_loader.clone())
+        .and_then(handle_reload_config);
+    let compact_snapshots = warp::path!("snapshots" / "compact")
+        .and(warp::post())
+        .map(move || snapshots.clone())
+        .and_then(handle_compact_snapshots);
+    let shutdown = warp::path!("shutdown")
+        .and(warp::post())
+        .map(move || shutdown.clone())
+        .and_then(handle_shutdown);
+    let set_node_config = warp::path("node")
+        .and(with_parsed("address"))
+        .and(warp::path!("config"))
+        .and(warp::put())
+        .and(warp::body::content_length_limit(max_body_size))
+        .and(warp::body::json())
+        .and_then(handle_set_node_config);
+    let submit_block = warp::path!("block")
+        .and(warp::post())
+        .and(warp::body::content_length_limit(max_body_size))
+        .and(warp::body::json())
+        .map(|block| node_handler(move |node| handle_submit_block(node, block)));
+    let node_ban = warp::path("ban")
+        .and(with_parsed("peer address"))
+        .and(warp::path::end());
+    let ban_peer = node_ban
+        .and(warp::post())
+        .map(|peer| node_handler(move |node| handle_ban_peer(node, peer)));
+    let unban_peer = node_ban
+        .and(warp::delete())
+        .map(|peer| node_handler(move |node| handle_unban_peer(node, peer)));
+    let node_routes = with_node(
+        warp::path("node").and(with_parsed("node address")).boxed(),
+        vec![submit_block.boxed(), ban_peer.boxed(), unban_peer.boxed()],
+    );
+    warp::path("admin")
+        .and(authorized)
+        .and(
+            add_node
+                .or(add_nodes)
+                .or(clone_node)
+                .or(node_routes)
+                .or(world_pause)
+                .or(world_resume)
+                .or(reload_config)
+                .or(compact_snapshots)
+                .or(shutdown)
+                .or(set_node_config)
+                .or(delete_node)
+                .or(delete_node_with_post),
+        )
+        .map(Reply::into_response)
+        .boxed()
+}
 
-    warp::serve(routes)
-        .run(([127, 0, 0, 1], port.unwrap_or(0)))
-        .await;
+/// The HTTP listener on which a single node serves its read-only endpoints. The listener stops
+/// when dropped.
+pub struct NodeListener {
+    /// The port of the listener, on the local interface.
+    pub port: u16,
+    _shutdown: oneshot::Sender<()>,
+}
+
+/// Serve the read-only endpoints of a node on a new ephemeral port, as `GET /`, `GET /block/{hash}`
+/// and so on, like the endpoints under `/node/{address}` of the main server.
+pub fn serve_node(address: Address) -> Result<NodeListener, ()> {
+    let show_node = warp::path::end().map(|| node_handler(handle_show_node));
+    let show_node_block = warp::path("block")
+        .and(with_parsed("block hash"))
+        .and(warp::path::end())
+        .and(if_none_match())
+        .map(|hash, if_none_match| {
+            node_handler(move |node| handle_show_node_block(node, hash, if_none_match))
+        });
+    let show_node_blockchain_balance =
+        warp::path!("blockchain_balance")
+            .and(if_none_match())
+            .map(|if_none_match| {
+                node_handler(move |node| handle_show_node_blockchain_balance(node, if_none_match))
+            });
+    let show_node_mempool_balance =
+        warp::path!("mempool_balance").map(|| node_handler(handle_show_node_mempool_balance));
+    let show_node_sync_status =
+        warp::path!("sync_status").map(|| node_handler(handle_show_node_sync_status));
+    let show_node_mining_stats =
+        warp::path!("mining_stats").map(|| node_handler(handle_show_node_mining_stats));
+    let show_node_transaction_propagation = warp::path("transaction")
+        .and(with_parsed("transaction identifier"))
+        .and(warp::path!("propagation"))
+        .map(|transaction_id| {
+            node_handler(move |node| handle_show_node_transaction_propagation(node, transaction_id))
+        });
+    let routes = warp::get()
+        .and(with_node(
+            warp::any().map(move || address).boxed(),
+            vec![
+                show_node.boxed(),
+                show_node_block.boxed(),
+                show_node_blockchain_balance.boxed(),
+                show_node_mempool_balance.boxed(),
+                show_node_sync_status.boxed(),
+                show_node_mining_stats.boxed(),
+                show_node_transaction_propagation.boxed(),
+            ],
+        ))
+        .boxed();
+    let routes = versioned(routes).recover(handle_rejection);
+
+    let (shutdown, shutdown_signal) = oneshot::channel::<()>();
+    let (socket_address, server) = warp::serve(routes)
+        .try_bind_with_graceful_shutdown(([127, 0, 0, 1], 0), async {
+            let _ = shutdown_signal.await;
+        })
+        .map_err(|err| error!("Failed to start the listener of node {address}: {err}"))?;
+    tokio::spawn(server);
+    Ok(NodeListener {
+        port: socket_address.port(),
+        _shutdown: shutdown,
+    })
 }
 
 #[derive(Debug)]
@@ -46,149 +606,2074 @@ struct InvalidParameter;
 
 impl warp::reject::Reject for InvalidParameter {}
 
+/// A transaction that the node did not admit in its mempool.
 #[derive(Debug)]
-struct InvalidTransaction;
+struct InvalidTransaction(TxError);
 
 impl warp::reject::Reject for InvalidTransaction {}
 
-/// List the nodes in the world.
-async fn handle_list_nodes() -> Result<impl Reply, Rejection> {
-    let addresses: Vec<Address> = world().await.get_node_addresses().await;
-    Ok(json(&addresses))
+/// A well-formed request with a transaction that breaks the rules of the blockchain.
+#[derive(Debug)]
+struct InvalidField(TxError);
+
+impl warp::reject::Reject for InvalidField {}
+
+/// A request to an admin endpoint without the admin token.
+#[derive(Debug)]
+struct Unauthorized;
+
+impl warp::reject::Reject for Unauthorized {}
+
+/// A request to change the simulation while the server is read-only.
+#[derive(Debug)]
+struct ReadOnly;
+
+impl warp::reject::Reject for ReadOnly {}
+
+/// A raw transaction that cannot be decoded.
+#[derive(Debug)]
+struct InvalidEncoding(DecodeError);
+
+impl warp::reject::Reject for InvalidEncoding {}
+
+/// A submitted block that the node did not adopt.
+#[derive(Debug)]
+struct InvalidBlock;
+
+impl warp::reject::Reject for InvalidBlock {}
+
+/// A nonce submitted by an external miner that does not complete a block of the node.
+#[derive(Debug)]
+struct InvalidWork;
+
+impl warp::reject::Reject for InvalidWork {}
+
+/// A request to the faucet for an address that got coins from it too recently. Holds how long
+/// the address still has to wait.
+#[derive(Debug)]
+struct FaucetCooldown(Duration);
+
+impl warp::reject::Reject for FaucetCooldown {}
+
+/// A request that needs a node, while the world has none.
+#[derive(Debug)]
+struct NoNodes;
+
+impl warp::reject::Reject for NoNodes {}
+
+/// A request to reload the settings, while they do not come from a configuration file.
+#[derive(Debug)]
+struct NoConfigFile;
+
+impl warp::reject::Reject for NoConfigFile {}
+
+/// A request to compact the snapshots, while the server has no data directory.
+#[derive(Debug)]
+struct NoDataDir;
+
+impl warp::reject::Reject for NoDataDir {}
+
+/// A request about a watch-only wallet that does not exist.
+#[derive(Debug)]
+struct UnknownWallet;
+
+impl warp::reject::Reject for UnknownWallet {}
+
+/// A request for the receipt of a transaction that is not in the blockchain of the node.
+#[derive(Debug)]
+struct UnknownReceipt;
+
+impl warp::reject::Reject for UnknownReceipt {}
+
+/// Settings that could not replace the current ones.
+#[derive(Debug)]
+struct InvalidConfig(ReloadError);
+
+impl warp::reject::Reject for InvalidConfig {}
+
+/// A request to add nodes beyond the capacity of the world.
+#[derive(Debug)]
+struct OverCapacity(CapacityError);
+
+impl warp::reject::Reject for OverCapacity {}
+
+/// The query parameters shared by the list endpoints, which split the lists in pages.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PageQuery {
+    /// How many items to return at most.
+    limit: Option<usize>,
+    /// How many items to skip.
+    offset: Option<usize>,
+    #[serde(default)]
+    sort: SortOrder,
 }
 
-/// Add a node to the world.
-async fn handle_add_node() -> Result<impl Reply, Rejection> {
-    let address = world().await.add_node().await;
-    Ok(json(&address))
+/// The names of the fields of `PageQuery`.
+const PAGE_PARAMETERS: [&str; 3] = ["limit", "offset", "sort"];
+
+#[derive(Copy, Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum SortOrder {
+    /// The natural order of the list, e.g. from the oldest block to the newest one.
+    #[default]
+    Asc,
+    /// The reverse order.
+    Desc,
 }
 
-/// Show the details of a node.
-async fn handle_show_node(raw_address: String) -> Result<impl Reply, Rejection> {
-    let address = Address::from_str(&raw_address).map_err(|err| {
-        warn!("Failed to parse address {raw_address:?}: {err:?}");
+/// A page of a list, requested by a client.
+struct Page {
+    limit: usize,
+    offset: usize,
+    sort: SortOrder,
+    /// The path of the request, to link to the next page.
+    path: String,
+    /// The query parameters of the request other than the ones of the page, e.g. the filters.
+    parameters: Vec<(String, String)>,
+}
+
+/// Extract the page of a list endpoint, from the `limit`, `offset` and `sort` query parameters,
+/// and the filters of the endpoint, from the other query parameters. Invalid query parameters are
+/// rejected.
+fn paginated<F: DeserializeOwned + Send + 'static>(
+) -> impl Filter<Extract = (F, Page), Error = Rejection> + Clone {
+    warp::path::full()
+        .and(warp::query::<Vec<(String, String)>>())
+        .and_then(
+            |path: FullPath, parameters: Vec<(String, String)>| async move {
+                let (page_parameters, parameters): (Vec<_>, Vec<_>) = parameters
+                    .into_iter()
+                    .partition(|(name, _)| PAGE_PARAMETERS.contains(&name.as_str()));
+                let query: PageQuery = parse_query(&page_parameters)?;
+                let filters: F = parse_query(&parameters)?;
+                let limit = query.limit.unwrap_or(SERVER_DEFAULT_PAGE_LIMIT);
+                if limit == 0 || limit > SERVER_MAX_PAGE_LIMIT {
+                    warn!("The limit of a page must be between 1 and {SERVER_MAX_PAGE_LIMIT}");
+                    return Err(warp::reject::custom(InvalidParameter));
+                }
+                let page = Page {
+                    limit,
+                    offset: query.offset.unwrap_or(0),
+                    sort: query.sort,
+                    path: path.as_str().to_string(),
+                    parameters,
+                };
+                Ok((filters, page))
+            },
+        )
+        .untuple_one()
+}
+
+/// Deserialize some query parameters.
+fn parse_query<T: DeserializeOwned>(parameters: &[(String, String)]) -> Result<T, Rejection> {
+    let query = serde_urlencoded::to_string(parameters).unwrap_or_default();
+    serde_urlencoded::from_str(&query).map_err(|err| {
+        warn!("Invalid query parameters {query:?}: {err}");
         warp::reject::custom(InvalidParameter)
-    })?;
+    })
+}
+
+impl Page {
+    /// Select the items of the page, from the full list in its natural order.
+    fn select<T>(&self, mut items: Vec<T>) -> Vec<T> {
+        if let SortOrder::Desc = self.sort {
+            items.reverse();
+        }
+        items
+            .into_iter()
+            .skip(self.offset)
+            .take(self.limit)
+            .collect()
+    }
+
+    /// Reply with the items of the page, out of `total` ones, with the total in the
+    /// `X-Total-Count` header and, if there are more items, a link to the next page in the `Link`
+    /// header.
+    fn reply<T: Serialize>(self, items: &[T], total: usize) -> Response {
+        let mut response = json(&items).into_response();
+        let headers = response.headers_mut();
+        headers.insert("x-total-count", HeaderValue::from(total));
+        let next_offset = self.offset.saturating_add(self.limit);
+        if next_offset < total {
+            let mut parameters = self.parameters;
+            parameters.push(("limit".to_string(), self.limit.to_string()));
+            parameters.push(("offset".to_string(), next_offset.to_string()));
+            if let SortOrder::Desc = self.sort {
+                parameters.push(("sort".to_string(), "desc".to_string()));
+            }
+            let query = serde_urlencoded::to_string(&parameters).unwrap_or_default();
+            let next = format!("<{}?{query}>; rel=\"next\"", self.path);
+            if let Ok(next) = HeaderValue::from_str(&next) {
+                headers.append("link", next);
+            }
+        }
+        response
+    }
+
+    /// Reply with the page of a full list, in its natural order.
+    fn reply_with_list<T: Serialize>(self, items: Vec<T>) -> Response {
+        let total = items.len();
+        let items = self.select(items);
+        self.reply(&items, total)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NodeFilters {
+    /// The status of the nodes.
+    status: Option<NodeStatus>,
+}
+
+/// List the nodes in the world, with an overview of each one, sorted by address.
+async fn handle_list_nodes(filters: NodeFilters, page: Page) -> Result<impl Reply, Rejection> {
+    let mut nodes = world().await.node_summaries().await;
+    nodes.retain(|node| filters.status.is_none_or(|status| node.status == status));
+    Ok(page.reply_with_list(nodes))
+}
+
+#[derive(Deserialize)]
+struct WaitQuery {
+    /// How many seconds to wait at most.
+    #[serde(default, deserialize_with = "deserialize_seconds")]
+    timeout: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct BlockWaitQuery {
+    /// The block after which to wait for a new one; by default, the current last block.
+    after: Option<String>,
+    /// How many seconds to wait at most.
+    #[serde(default, deserialize_with = "deserialize_seconds")]
+    timeout: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct ConfirmationsQuery {
+    /// How many confirmations to wait for.
+    confirmations: Option<usize>,
+    /// How many seconds to wait at most.
+    #[serde(default, deserialize_with = "deserialize_seconds")]
+    timeout: Option<u64>,
+}
+
+/// Deserialize a number of seconds, with an optional `s` suffix, e.g. `30` or `30s`, which must be
+/// at most `SERVER_LONG_POLL_MAX_TIMEOUT_SECS`.
+fn deserialize_seconds<'de, D>(deserializer: D) -> Result<Option<u64>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let Some(raw) = Option::<String>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+    let digits = raw.strip_suffix('s').unwrap_or(&raw);
+    let seconds: u64 = digits.parse().map_err(serde::de::Error::custom)?;
+    if seconds > SERVER_LONG_POLL_MAX_TIMEOUT_SECS {
+        return Err(serde::de::Error::custom(format!(
+            "the timeout is more than {SERVER_LONG_POLL_MAX_TIMEOUT_SECS} seconds"
+        )));
+    }
+    Ok(Some(seconds))
+}
+
+/// Report whether all nodes share the same last block.
+async fn handle_world_converged() -> Result<impl Reply, Rejection> {
+    Ok(json(&world().await.convergence().await))
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BlockTreeFilters {
+    /// Only the blocks at this height or above; by default, all blocks.
+    min_height: Option<usize>,
+}
+
+/// Show the tree of the blocks in the local blockchains of all nodes, with the nodes on each tip.
+async fn handle_world_blocktree(
+    filters: BlockTreeFilters,
+    page: Page,
+) -> Result<impl Reply, Rejection> {
+    let min_height = filters.min_height.unwrap_or(0);
+    Ok(page.reply_with_list(world().await.block_tree(min_height).await))
+}
+
+/// Freeze all the nodes, until the world is resumed.
+async fn handle_world_pause() -> Result<impl Reply, Rejection> {
+    world().await.pause().await;
+    Ok(StatusCode::OK)
+}
+
+/// Stop the server after replying, which then stops the simulation like Ctrl+C: the nodes are
+/// saved and the report of the run is written.
+async fn handle_shutdown(shutdown: Arc<watch::Sender<bool>>) -> Result<impl Reply, Rejection> {
+    info!("Shutting down at the request of an admin");
+    shutdown.send_replace(true);
+    Ok(StatusCode::ACCEPTED)
+}
+
+/// Let the nodes run again.
+async fn handle_world_resume() -> Result<impl Reply, Rejection> {
+    world().await.resume().await;
+    Ok(StatusCode::OK)
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TimelineFilters {
+    /// The time from which to list the changes, in milliseconds since the Unix epoch.
+    since: Option<u64>,
+}
+
+/// List the changes to the world, in the order in which they happened.
+async fn handle_world_timeline(
+    filters: TimelineFilters,
+    page: Page,
+) -> Result<impl Reply, Rejection> {
+    let since = filters.since.unwrap_or(0);
+    let mut timeline = stats().await.timeline().await;
+    timeline.retain(|entry| entry.timestamp >= since);
+    Ok(page.reply_with_list(timeline))
+}
+
+/// Show the traffic of the network, by type of message and by link.
+async fn handle_network_stats() -> Result<impl Reply, Rejection> {
+    Ok(json(&network_stats().await))
+}
+
+/// List the ports on which the nodes serve their own endpoints, for the nodes that have one.
+async fn handle_world_node_ports() -> Result<impl Reply, Rejection> {
+    Ok(json(&world().await.get_node_ports().await))
+}
+
+/// Show the protocol parameters of new nodes and their hash, which nodes send with their blocks.
+async fn handle_world_params() -> Result<impl Reply, Rejection> {
+    let params = ProtocolParams::with_rules(config().transaction_rules);
+    Ok(json(&ParamsReply {
+        hash: params.hash(),
+        params,
+    }))
+}
+
+#[derive(Serialize)]
+struct ParamsReply {
+    hash: BlockHash,
+    params: ProtocolParams,
+}
+
+/// Wait until all nodes share the same last block, or until the timeout expires.
+async fn handle_world_converged_wait(query: WaitQuery) -> Result<impl Reply, Rejection> {
+    let timeout = Duration::from_secs(
+        query
+            .timeout
+            .unwrap_or(SERVER_LONG_POLL_DEFAULT_TIMEOUT_SECS),
+    );
+    Ok(json(&world().await.wait_for_convergence(timeout).await))
+}
+
+/// A block found by a search, without its transactions.
+#[derive(Serialize)]
+struct BlockSummary {
+    height: usize,
+    hash: String,
+    miner: Address,
+    transactions: usize,
+    weight: u64,
+}
+
+/// List the blocks of the local blockchain of a node that match a search.
+async fn handle_show_node_blocks(
+    node: Arc<RwLock<Node>>,
+    search: BlockSearch,
+    page: Page,
+    if_none_match: Option<String>,
+) -> Result<impl Reply, Rejection> {
+    let readable_node = node.read().await;
+    let blockchain = readable_node.blockchain();
+    let tag = blockchain.last_hash().to_string();
+    let list_blocks = || {
+        let heights = blockchain.search_blocks(&search);
+        let total = heights.len();
+        let blocks: Vec<BlockSummary> = page
+            .select(heights)
+            .into_iter()
+            .filter_map(|height| {
+                let block_hash = blockchain.hash_at(height)?;
+                let block = blockchain.get_block(block_hash)?;
+                Some(BlockSummary {
+                    height,
+                    hash: block_hash.to_string(),
+                    miner: block.miner,
+                    transactions: block.transactions.len(),
+                    weight: block.weight(),
+                })
+            })
+            .collect();
+        page.reply(&blocks, total)
+    };
+    Ok(conditional(
+        if_none_match,
+        &tag,
+        Caching::Revalidate,
+        list_blocks,
+    ))
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MempoolFilters {
+    /// The address that sends the transactions.
+    sender: Option<Address>,
+    /// The address that receives the transactions.
+    receiver: Option<Address>,
+}
+
+/// List the pending transactions of a node, in the order in which it admitted them.
+async fn handle_show_node_mempool(
+    node: Arc<RwLock<Node>>,
+    filters: MempoolFilters,
+    page: Page,
+) -> Result<impl Reply, Rejection> {
+    let readable_node = node.read().await;
+    let transactions: Vec<&BlockTransaction> = readable_node
+        .mempool()
+        .transactions()
+        .iter()
+        .filter(|transaction| {
+            let info = &transaction.info;
+            filters.sender.is_none_or(|sender| info.sender == sender)
+                && filters
+                    .receiver
+                    .is_none_or(|receiver| info.receiver == Some(receiver))
+        })
+        .collect();
+    Ok(page.reply_with_list(transactions))
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SubmissionFilters {
+    /// Whether the transactions were accepted.
+    accepted: Option<bool>,
+}
+
+/// List whether a node accepted the transactions that it most recently received from clients, in
+/// the order in which it received them.
+async fn handle_show_node_submissions(
+    node: Arc<RwLock<Node>>,
+    filters: SubmissionFilters,
+    page: Page,
+) -> Result<impl Reply, Rejection> {
+    let readable_node = node.read().await;
+    let submissions: Vec<&Submission> = readable_node
+        .submissions()
+        .iter()
+        .filter(|submission| {
+            filters
+                .accepted
+                .is_none_or(|accepted| submission.accepted == accepted)
+        })
+        .collect();
+    Ok(page.reply_with_list(submissions))
+}
+
+/// Wait until the last block of a node changes, or until the timeout expires.
+async fn handle_show_node_wait_for_block(
+    address: Address,
+    query: BlockWaitQuery,
+) -> Result<impl Reply, Rejection> {
+    let after = query
+        .after
+        .map(|raw_hash| parse(&raw_hash, "block hash"))
+        .transpose()?;
+    let timeout = Duration::from_secs(
+        query
+            .timeout
+            .unwrap_or(SERVER_LONG_POLL_DEFAULT_TIMEOUT_SECS),
+    );
+    let Some(tip) = world().await.wait_for_block(address, after, timeout).await else {
+        warn!("Cannot find node {address}");
+        return Err(warp::reject::custom(InvalidParameter));
+    };
+    Ok(json(&tip))
+}
+
+/// Wait until a transaction has enough confirmations in the blockchain of a node, or until the
+/// timeout expires.
+async fn handle_show_node_transaction_wait(
+    address: Address,
+    transaction_id: TransactionId,
+    query: ConfirmationsQuery,
+) -> Result<impl Reply, Rejection> {
+    let confirmations = query.confirmations.unwrap_or(SERVER_DEFAULT_CONFIRMATIONS);
+    let timeout = Duration::from_secs(
+        query
+            .timeout
+            .unwrap_or(SERVER_LONG_POLL_DEFAULT_TIMEOUT_SECS),
+    );
+    let Some(status) = world()
+        .await
+        .wait_for_confirmations(address, transaction_id, confirmations, timeout)
+        .await
+    else {
+        warn!("Cannot find node {address}");
+        return Err(warp::reject::custom(InvalidParameter));
+    };
+    Ok(json(&status))
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TipsQuery {
+    /// Only the samples of this node.
+    node: Option<Address>,
+    /// Only the samples taken since this time, in milliseconds since the Unix epoch, plus the
+    /// previous sample of each node, which tells its tip at that time.
+    since: Option<u64>,
+}
+
+/// Show when the tip of each node changed, as sampled every `--tip-sample-interval`.
+async fn handle_stats_tips(query: TipsQuery) -> Result<impl Reply, Rejection> {
+    let tips = stats().await.tips(query.node, query.since).await;
+    Ok(json(&tips))
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BlockTimesQuery {
+    /// The width of the buckets of the histogram, in milliseconds.
+    bucket_ms: Option<u64>,
+}
+
+/// Show the histogram of the intervals between mined blocks and the difficulty over time.
+async fn handle_stats_block_times(query: BlockTimesQuery) -> Result<impl Reply, Rejection> {
+    let bucket_millis = query.bucket_ms.unwrap_or(STATS_DEFAULT_BUCKET_MILLIS);
+    let Some(block_times) = stats().await.block_times(bucket_millis).await else {
+        warn!("Buckets of {bucket_millis} ms are too narrow for the histogram of block intervals");
+        return Err(warp::reject::custom(InvalidParameter));
+    };
+    Ok(json(&block_times))
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct WealthQuery {
+    /// The node whose blockchain is analyzed; by default, one with the longest blockchain.
+    node: Option<Address>,
+    /// The width of the buckets of the histogram of balances, in coins.
+    bucket: Option<u64>,
+    /// How many blocks apart the distribution is sampled.
+    every: Option<usize>,
+}
+
+/// Show how the coins are distributed among the addresses in the blockchain of a node.
+async fn handle_stats_wealth(query: WealthQuery) -> Result<impl Reply, Rejection> {
+    let node = find_node_or_best(query.node).await?;
+    let bucket_coins = query.bucket.unwrap_or(STATS_DEFAULT_WEALTH_BUCKET_COINS);
+    let Some(distribution) = WealthDistribution::new(
+        node.read().await.blockchain(),
+        bucket_coins,
+        query.every.unwrap_or(STATS_DEFAULT_WEALTH_SAMPLE_BLOCKS),
+    ) else {
+        warn!("Buckets of {bucket_coins} coins are too narrow for the histogram of balances");
+        return Err(warp::reject::custom(InvalidParameter));
+    };
+    Ok(json(&distribution))
+}
+
+/// Find a node or, by default, one with the longest blockchain.
+async fn find_node_or_best(address: Option<Address>) -> Result<Arc<RwLock<Node>>, Rejection> {
+    let address = match address {
+        Some(address) => address,
+        None => {
+            let convergence = world().await.convergence().await;
+            let Some(address) = convergence
+                .tips
+                .first()
+                .and_then(|tip| tip.supporters.first().copied())
+            else {
+                warn!("There are no nodes to analyze");
+                return Err(warp::reject::custom(InvalidParameter));
+            };
+            address
+        }
+    };
     let Some(node) = world().await.get_node(address).await else {
         warn!("Cannot find node {address}");
         return Err(warp::reject::custom(InvalidParameter));
     };
+    Ok(node)
+}
+
+/// List the watch-only wallets, sorted by name.
+async fn handle_list_wallets() -> Result<impl Reply, Rejection> {
+    Ok(json(&wallets().await.list().await))
+}
+
+/// Start watching the addresses of a new wallet.
+async fn handle_add_wallet(wallet: Wallet) -> Result<impl Reply, Rejection> {
+    if wallets().await.add(wallet.clone()).await.is_err() {
+        return Err(warp::reject::custom(InvalidParameter));
+    }
+    Ok(json(&wallet))
+}
+
+/// Stop watching the addresses of a wallet.
+async fn handle_delete_wallet(name: String) -> Result<impl Reply, Rejection> {
+    if wallets().await.remove(&name).await.is_err() {
+        return Err(warp::reject::custom(UnknownWallet));
+    }
+    Ok(json(&name))
+}
+
+async fn find_wallet(name: &str) -> Result<Wallet, Rejection> {
+    let Some(wallet) = wallets().await.get(name).await else {
+        warn!("Cannot find wallet {name}");
+        return Err(warp::reject::custom(UnknownWallet));
+    };
+    Ok(wallet)
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct WalletQuery {
+    /// The node whose blockchain and mempool to query; by default, one with the longest
+    /// blockchain.
+    node: Option<Address>,
+}
+
+/// Show the balances of the addresses of a wallet.
+async fn handle_show_wallet(name: String, query: WalletQuery) -> Result<impl Reply, Rejection> {
+    let wallet = find_wallet(&name).await?;
+    let node = find_node_or_best(query.node).await?;
+    let balance = wallet.balance(&*node.read().await);
+    Ok(json(&balance))
+}
+
+/// List the transactions of the addresses of a wallet, the confirmed ones first.
+async fn handle_show_wallet_transactions(
+    name: String,
+    query: WalletQuery,
+    page: Page,
+) -> Result<impl Reply, Rejection> {
+    let wallet = find_wallet(&name).await?;
+    let node = find_node_or_best(query.node).await?;
+    let transactions = wallet.transactions(&*node.read().await);
+    Ok(page.reply_with_list(transactions))
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ExportQuery {
+    /// The exported table: `blocks` (default) or `nodes`.
+    table: Option<String>,
+    /// The format of the export: `csv` (default) or `json`.
+    format: Option<String>,
+}
+
+/// Export the statistics of the simulation, with one row per block or per node.
+async fn handle_stats_export(query: ExportQuery) -> Result<impl Reply, Rejection> {
+    let table = query.table.as_deref().unwrap_or("blocks");
+    let format = query.format.as_deref().unwrap_or("csv");
+    let stats = stats().await;
+    let response = match (table, format) {
+        ("blocks", "csv") => csv(to_csv(&stats.block_rows().await)),
+        ("blocks", "json") => json(&stats.block_rows().await).into_response(),
+        ("nodes", "csv") => csv(to_csv(&stats.node_rows().await)),
+        ("nodes", "json") => json(&stats.node_rows().await).into_response(),
+        _ => {
+            warn!("Cannot export table {table:?} in format {format:?}");
+            return Err(warp::reject::custom(InvalidParameter));
+        }
+    };
+    Ok(response)
+}
+
+/// Reply with comma-separated values.
+fn csv(body: String) -> warp::reply::Response {
+    warp::reply::with_header(body, "content-type", "text/csv").into_response()
+}
+
+/// Stream the events of the simulation over a WebSocket. Clients can send a JSON `EventFilter`
+/// at any time to replace their subscription; by default, all events are sent.
+async fn stream_events(socket: warp::ws::WebSocket) {
+    let (mut socket_tx, mut socket_rx) = socket.split();
+    let mut receiver = events().await.subscribe();
+    let mut filter = EventFilter::default();
+    loop {
+        tokio::select! {
+            message = socket_rx.next() => {
+                let Some(Ok(message)) = message else {
+                    break;
+                };
+                if message.is_close() {
+                    break;
+                }
+                let Ok(text) = message.to_str() else {
+                    continue;
+                };
+                match serde_json::from_str::<EventFilter>(text) {
+                    Ok(new_filter) => filter = new_filter,
+                    Err(err) => {
+                        warn!("Ignoring invalid subscription {text:?}: {err}");
+                        let reply = format!("invalid subscription: {err}");
+                        if socket_tx.send(warp::ws::Message::text(reply)).await.is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+            event = receiver.recv() => {
+                let record = match event {
+                    Ok(record) => record,
+                    Err(tokio::sync::broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!("An event subscriber fell behind, skipping {skipped} events");
+                        continue;
+                    }
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                };
+                if !filter.matches(&record.event) {
+                    continue;
+                }
+                let Ok(text) = serde_json::to_string(&record) else {
+                    error!("Failed to serialize event {record:?}");
+                    continue;
+                };
+                if socket_tx.send(warp::ws::Message::text(text)).await.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Stream the events of the simulation as server-sent events. Clients that reconnect with a
+/// `Last-Event-ID` header first receive the events that they missed.
+async fn handle_events_sse(last_event_id: Option<u64>) -> Result<impl Reply, Rejection> {
+    let (missed, receiver) = match last_event_id {
+        Some(last_id) => events().await.subscribe_after(last_id),
+        None => (vec![], events().await.subscribe()),
+    };
+    let upcoming = futures_util::stream::unfold(receiver, |mut receiver| async move {
+        loop {
+            match receiver.recv().await {
+                Ok(record) => return Some((record, receiver)),
+                Err(tokio::sync::broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("An event subscriber fell behind, skipping {skipped} events");
+                }
+                Err(tokio::sync::broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    });
+    let stream = futures_util::stream::iter(missed)
+        .chain(upcoming)
+        .map(|record| {
+            warp::sse::Event::default()
+                .id(record.id.to_string())
+                .event(record.event.kind())
+                .json_data(&record.event)
+        });
+    Ok(warp::sse::reply(warp::sse::keep_alive().stream(stream)))
+}
+
+/// Add a node to the world.
+async fn handle_add_node() -> Result<impl Reply, Rejection> {
+    let address = world()
+        .await
+        .add_node()
+        .await
+        .map_err(|err| warp::reject::custom(OverCapacity(err)))?;
+    Ok(json(&address))
+}
+
+#[derive(Deserialize)]
+struct AddNodesQuery {
+    /// How many nodes to create.
+    #[serde(default = "default_node_count")]
+    count: usize,
+    /// The name of the template, in the configuration, from which to create the nodes.
+    template: Option<String>,
+}
+
+fn default_node_count() -> usize {
+    1
+}
+
+/// Create several nodes at once, from a template, and return their addresses.
+async fn handle_add_nodes(query: AddNodesQuery) -> Result<impl Reply, Rejection> {
+    let node_config = match &query.template {
+        Some(template) => {
+            let Some(node_config) = config().node_templates.get(template).cloned() else {
+                warn!("Cannot find node template {template:?}");
+                return Err(warp::reject::custom(InvalidParameter));
+            };
+            node_config
+        }
+        None => NodeConfig::default(),
+    };
+    let addresses = world()
+        .await
+        .add_nodes_with_config(node_config, query.count)
+        .await
+        .map_err(|err| warp::reject::custom(OverCapacity(err)))?;
+    Ok(json(&addresses))
+}
+
+/// Show the details of a node.
+async fn handle_show_node(node: Arc<RwLock<Node>>) -> Result<impl Reply, Rejection> {
     let readable_node = node.read().await;
-    let details: HashMap<String, String> = HashMap::from_iter(vec![
+    let port = world().await.get_node_port(readable_node.address()).await;
+    let mut details: HashMap<String, String> = HashMap::from_iter(vec![
         ("blockchain_length".to_string(), readable_node.blockchain().len().to_string()),
         ("last_block_hash".to_string(), readable_node.blockchain().last_hash().to_string()),
+        ("cumulative_work".to_string(), readable_node.blockchain().cumulative_work().to_string()),
         ("mempool_length".to_string(), readable_node.mempool().len().to_string()),
+        (
+            "inbox_duplicates".to_string(),
+            readable_node.inbox().duplicates().to_string(),
+        ),
+        (
+            "relayed_blocks".to_string(),
+            readable_node.relay_stats().relayed_blocks.to_string(),
+        ),
+        (
+            "suppressed_blocks".to_string(),
+            readable_node.relay_stats().suppressed_blocks.to_string(),
+        ),
+        (
+            "mempool_future_length".to_string(),
+            readable_node.mempool().future_len().to_string(),
+        ),
+        (
+            "is_synced".to_string(),
+            readable_node
+                .sync_status()
+                .is_synced(readable_node.blockchain().len())
+                .to_string(),
+        ),
     ]);
+    let params_hash = readable_node.blockchain().params().hash();
+    details.insert("params_hash".to_string(), params_hash.to_string());
+    let param_mismatches: u64 = readable_node.param_mismatches().values().sum();
+    details.insert("param_mismatches".to_string(), param_mismatches.to_string());
+    details.insert(
+        "consensus".to_string(),
+        readable_node.consensus_engine().name().to_string(),
+    );
+    if let Some(port) = port {
+        details.insert("port".to_string(), port.to_string());
+    }
+    if let Some(region) = &readable_node.config().region {
+        details.insert("region".to_string(), region.clone());
+    }
+    match world().await.get_failure(readable_node.address()).await {
+        Some(failure) => {
+            details.insert("status".to_string(), "failed".to_string());
+            details.insert("failure".to_string(), failure);
+        }
+        None => {
+            details.insert("status".to_string(), "running".to_string());
+        }
+    }
     Ok(json(&details))
 }
 
 /// Show a block in the local blockchain of a node.
-async fn handle_show_node_block(raw_address: String, raw_hash: String) -> Result<impl Reply, Rejection> {
-    let address = Address::from_str(&raw_address).map_err(|err| {
-        warn!("Failed to parse address {raw_address:?}: {err:?}");
-        warp::reject::custom(InvalidParameter)
-    })?;
-    let Some(node) = world().await.get_node(address).await else {
-        warn!("Cannot find node {address}");
-        return Err(warp::reject::custom(InvalidParameter));
-    };
-    let hash = BlockHash::from_str(&raw_hash).map_err(|err| {
-        warn!("Failed to parse block hash {raw_hash:?}: {err:?}");
-        warp::reject::custom(InvalidParameter)
-    })?;
-    let Some(block) = node.read().await.get_block(&hash) else {
+async fn handle_show_node_block(
+    node: Arc<RwLock<Node>>,
+    hash: BlockHash,
+    if_none_match: Option<String>,
+) -> Result<impl Reply, Rejection> {
+    let readable_node = node.read().await;
+    let Some(block) = readable_node.get_block(&hash) else {
+        let address = readable_node.address();
         warn!("Cannot find block {hash} in node {address}");
         return Err(warp::reject::custom(InvalidParameter));
     };
-    Ok(json(&block))
+    let tag = hash.to_string();
+    Ok(conditional(if_none_match, &tag, Caching::Immutable, || {
+        json(&block).into_response()
+    }))
 }
 
 /// Show the blockchain balance of a node.
-async fn handle_show_node_blockchain_balance(raw_address: String) -> Result<impl Reply, Rejection> {
-    let address = Address::from_str(&raw_address).map_err(|err| {
-        warn!("Failed to parse address {raw_address:?}: {err:?}");
-        warp::reject::custom(InvalidParameter)
-    })?;
-    let Some(node) = world().await.get_node(address).await else {
-        warn!("Cannot find node {address}");
-        return Err(warp::reject::custom(InvalidParameter));
-    };
+async fn handle_show_node_blockchain_balance(
+    node: Arc<RwLock<Node>>,
+    if_none_match: Option<String>,
+) -> Result<impl Reply, Rejection> {
     let readable_node = node.read().await;
-    let balance = readable_node.blockchain().balance();
-    Ok(json(&balance))
+    let blockchain = readable_node.blockchain();
+    let tag = blockchain.last_hash().to_string();
+    Ok(conditional(
+        if_none_match,
+        &tag,
+        Caching::Revalidate,
+        || json(blockchain.balance()).into_response(),
+    ))
 }
 
 /// Show the mempool balance of a node.
-async fn handle_show_node_mempool_balance(raw_address: String) -> Result<impl Reply, Rejection> {
-    let address = Address::from_str(&raw_address).map_err(|err| {
-        warn!("Failed to parse address {raw_address:?}: {err:?}");
-        warp::reject::custom(InvalidParameter)
-    })?;
-    let Some(node) = world().await.get_node(address).await else {
-        warn!("Cannot find node {address}");
-        return Err(warp::reject::custom(InvalidParameter));
-    };
+async fn handle_show_node_mempool_balance(
+    node: Arc<RwLock<Node>>,
+) -> Result<impl Reply, Rejection> {
     let readable_node = node.read().await;
     let balance = readable_node.mempool().balance();
     Ok(json(&balance))
 }
 
+#[derive(Serialize)]
+struct SyncStatusReply {
+    syncing: bool,
+    is_synced: bool,
+    current_height: usize,
+    best_known_height: usize,
+    blocks_remaining: usize,
+    downloaded_blocks: usize,
+    /// Downloaded blocks per second.
+    download_rate: f64,
+}
+
+/// Show the progress of the synchronization of a node with the network.
+async fn handle_show_node_sync_status(node: Arc<RwLock<Node>>) -> Result<impl Reply, Rejection> {
+    let readable_node = node.read().await;
+    let sync_status = readable_node.sync_status();
+    let blockchain = readable_node.blockchain();
+    Ok(json(&SyncStatusReply {
+        syncing: sync_status.syncing,
+        is_synced: sync_status.is_synced(blockchain.len()),
+        current_height: blockchain.height(),
+        best_known_height: sync_status.best_known_length.max(blockchain.len()) - 1,
+        blocks_remaining: sync_status.blocks_remaining(blockchain.len()),
+        downloaded_blocks: sync_status.downloaded_blocks,
+        download_rate: sync_status.download_rate(),
+    }))
+}
+
+#[derive(Serialize)]
+struct MiningStatsReply {
+    /// How many nonces the node tries in a row, before processing its messages.
+    nonce_budget: u64,
+    /// How many messages are currently waiting to be processed.
+    inbox_depth: usize,
+    hashes: u64,
+    mined_blocks: u64,
+}
+
+/// Show the mining statistics of a node, including its current nonce budget.
+async fn handle_show_node_mining_stats(node: Arc<RwLock<Node>>) -> Result<impl Reply, Rejection> {
+    let readable_node = node.read().await;
+    let mining_stats = readable_node.mining_stats();
+    Ok(json(&MiningStatsReply {
+        nonce_budget: mining_stats.nonce_budget,
+        inbox_depth: readable_node.inbox().len(),
+        hashes: mining_stats.hashes,
+        mined_blocks: mining_stats.mined_blocks,
+    }))
+}
+
+/// Show how many blocks and transactions a node rejected, by validation rule.
+async fn handle_show_node_validation_stats(
+    node: Arc<RwLock<Node>>,
+) -> Result<impl Reply, Rejection> {
+    Ok(json(node.read().await.validation_stats()))
+}
+
+/// Show how many of the blocks mined by a node are canonical or orphaned, with their rewards.
+async fn handle_show_node_miner_stats(node: Arc<RwLock<Node>>) -> Result<impl Reply, Rejection> {
+    let miner_stats = node.read().await.miner_stats();
+    Ok(json(&miner_stats))
+}
+
+/// Check that the balances of the blockchain of a node add up to the issued coins.
+async fn handle_show_node_audit(node: Arc<RwLock<Node>>) -> Result<impl Reply, Rejection> {
+    let audit = node.read().await.blockchain().audit_supply();
+    Ok(json(&audit))
+}
+
+/// Show the settings that a node currently applies.
+async fn handle_show_node_config(node: Arc<RwLock<Node>>) -> Result<impl Reply, Rejection> {
+    let node_config = node.read().await.config().clone();
+    Ok(json(&node_config))
+}
+
+/// Show the peers excluded by the operator of a node.
+async fn handle_show_node_banned_peers(node: Arc<RwLock<Node>>) -> Result<impl Reply, Rejection> {
+    let banned_peers = node.read().await.banned_peers().clone();
+    Ok(json(&banned_peers))
+}
+
+/// Show the fee policy of the blockchain of a node and, with the base-fee policy, the base fees.
+async fn handle_show_node_fee_market(node: Arc<RwLock<Node>>) -> Result<impl Reply, Rejection> {
+    let fee_market = node
+        .read()
+        .await
+        .blockchain()
+        .fee_market(BASE_FEE_RECENT_BLOCKS);
+    Ok(json(&fee_market))
+}
+
+/// Rank the nodes by the rewards of the blocks that they mined.
+async fn handle_stats_miners() -> Result<impl Reply, Rejection> {
+    Ok(json(&world().await.miner_leaderboard().await))
+}
+
+/// Compare how long the normal and the priority transactions of the demo took to be mined.
+async fn handle_stats_confirmations() -> Result<impl Reply, Rejection> {
+    Ok(json(&stats().await.confirmation_latencies().await))
+}
+
+#[derive(Serialize)]
+struct PropagationReply {
+    peers: usize,
+    acknowledged: Vec<Address>,
+    rejected: Vec<Address>,
+    pending: usize,
+}
+
+/// Show the receipt of a transaction included in the blockchain of a node.
+async fn handle_show_node_receipt(
+    node: Arc<RwLock<Node>>,
+    transaction_id: TransactionId,
+) -> Result<impl Reply, Rejection> {
+    let readable_node = node.read().await;
+    let Some(receipt) = readable_node.blockchain().receipt(&transaction_id) else {
+        let address = readable_node.address();
+        warn!("Node {address} has no receipt of transaction {transaction_id}");
+        return Err(warp::reject::custom(UnknownReceipt));
+    };
+    Ok(json(receipt))
+}
+
+/// Show how many peers accepted a transaction that a node received from a client.
+async fn handle_show_node_transaction_propagation(
+    node: Arc<RwLock<Node>>,
+    transaction_id: TransactionId,
+) -> Result<impl Reply, Rejection> {
+    let readable_node = node.read().await;
+    let Some(propagation) = readable_node.transaction_propagation(transaction_id) else {
+        let address = readable_node.address();
+        warn!("Node {address} does not track the propagation of transaction {transaction_id}");
+        return Err(warp::reject::custom(InvalidParameter));
+    };
+    Ok(json(&PropagationReply {
+        peers: propagation.peers,
+        acknowledged: propagation.acknowledged.clone(),
+        rejected: propagation.rejected.clone(),
+        pending: propagation.pending(),
+    }))
+}
+
+#[derive(Deserialize)]
+struct DeleteQuery {
+    #[serde(default)]
+    mode: DeletionMode,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum DeletionMode {
+    /// Stop the node instantly, losing its pending transactions, e.g. to inject a fault.
+    #[default]
+    Kill,
+    /// Let the node hand its pending transactions over and announce its departure first.
+    Drain,
+}
+
 /// Delete a node from the world.
-async fn handle_delete_node(address: String) -> Result<impl Reply, Rejection> {
-    let address = Address::from_str(&address).map_err(|err| {
-        warn!("Failed to parse address {address}: {err:?}");
-        warp::reject::custom(InvalidParameter)
-    })?;
-    world().await.delete_node(address).await;
+async fn handle_delete_node(address: Address, query: DeleteQuery) -> Result<impl Reply, Rejection> {
+    match query.mode {
+        DeletionMode::Kill => world().await.delete_node(address).await,
+        DeletionMode::Drain => world().await.drain_node(address).await,
+    }
     Ok(StatusCode::OK)
 }
 
-/// Send a transaction to a node.
+/// Create a copy of a node, with a new address, and return its address.
+async fn handle_clone_node(address: Address) -> Result<impl Reply, Rejection> {
+    let Some(clone) = world().await.clone_node(address).await else {
+        warn!("Cannot find node {address}");
+        return Err(warp::reject::custom(InvalidParameter));
+    };
+    let clone = clone.map_err(|err| warp::reject::custom(OverCapacity(err)))?;
+    Ok(json(&clone))
+}
+
+/// Read the configuration file again and apply the settings that can change while the simulation
+/// runs, returning the new settings.
+async fn handle_reload_config(
+    config_loader: Option<ConfigLoader>,
+) -> Result<impl Reply, Rejection> {
+    let Some(config_loader) = config_loader else {
+        return Err(warp::reject::custom(NoConfigFile));
+    };
+    let new_config =
+        config_loader().map_err(|()| warp::reject::custom(InvalidConfig(ReloadError::Invalid)))?;
+    reload_config(new_config)
+        .await
+        .map_err(|err| warp::reject::custom(InvalidConfig(err)))?;
+    Ok(json(&config()))
+}
+
+/// Delete the snapshots of the nodes that the retention policy does not keep, returning the kept
+/// and the removed ones.
+async fn handle_compact_snapshots(snapshots: Option<Snapshots>) -> Result<impl Reply, Rejection> {
+    let Some(snapshots) = snapshots else {
+        return Err(warp::reject::custom(NoDataDir));
+    };
+    match snapshots.compact() {
+        Ok(compaction) => Ok(json(&compaction).into_response()),
+        Err(err) => {
+            error!("Failed to remove the old snapshots: {err}");
+            Ok(status_with_message(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to remove the old snapshots",
+            ))
+        }
+    }
+}
+
+/// Replace the settings of a node, which apply from the next iteration of its run loop.
+async fn handle_set_node_config(
+    address: Address,
+    node_config: NodeConfig,
+) -> Result<impl Reply, Rejection> {
+    if node_config.validate().is_err() {
+        return Err(warp::reject::custom(InvalidParameter));
+    }
+    let Some(result) = world()
+        .await
+        .set_node_config(address, node_config.clone())
+        .await
+    else {
+        warn!("Cannot find node {address}");
+        return Err(warp::reject::custom(InvalidParameter));
+    };
+    result.map_err(|err| warp::reject::custom(OverCapacity(err)))?;
+    Ok(json(&node_config))
+}
+
+/// Send a transaction to a node. Deprecated in favor of `POST /node/{address}/transaction`, which
+/// takes the transaction in the body.
 async fn handle_send_transaction(
-    raw_node_address: String,
-    raw_sender: String,
-    raw_recipient: String,
-    raw_amount: String,
+    node: Arc<RwLock<Node>>,
+    sender: Address,
+    recipient: Address,
+    amount: Amount,
 ) -> Result<impl Reply, Rejection> {
-    let node_address = Address::from_str(&raw_node_address).map_err(|err| {
-        warn!("Failed to parse node address {raw_node_address:?}: {err:?}");
-        warp::reject::custom(InvalidParameter)
-    })?;
-    let Some(node) = world().await.get_node(node_address).await else {
-        warn!("Cannot find node {node_address}");
+    let transaction = Transaction::new(sender, recipient, amount);
+    validate_client_transaction(&transaction)?;
+    let mut writable_node = node.write().await;
+    let transaction_id = writable_node
+        .add_client_transaction(transaction)
+        .await
+        .map_err(|err| warp::reject::custom(InvalidTransaction(err)))?;
+    let address = writable_node.address();
+    let successor = format!("/{API_VERSION}/node/{address}/transaction");
+    let reply = json(&transaction_id).into_response();
+    Ok(deprecate(reply, Some(&successor)))
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FaucetQuery {
+    /// How many coins to send; by default, `FAUCET_DEFAULT_AMOUNT`.
+    amount: Option<Amount>,
+}
+
+/// When the faucet last sent coins to each address, to rate-limit it.
+static FAUCET_DRIPS: std::sync::Mutex<BTreeMap<Address, Instant>> =
+    std::sync::Mutex::new(BTreeMap::new());
+
+/// Send coins from the faucet to an address, through a random node, and return the identifier of
+/// the transaction.
+async fn handle_faucet(recipient: Address, query: FaucetQuery) -> Result<impl Reply, Rejection> {
+    let amount = query.amount.unwrap_or(FAUCET_DEFAULT_AMOUNT);
+    if amount > FAUCET_MAX_AMOUNT {
+        warn!("Refusing to send {amount} from the faucet, more than {FAUCET_MAX_AMOUNT}");
         return Err(warp::reject::custom(InvalidParameter));
+    }
+    let transaction = Transaction::new(Address::FAUCET, recipient, amount);
+    validate_transaction(&transaction)?;
+    let addresses = world().await.get_node_addresses().await;
+    let Some(&address) = addresses.choose(&mut rand::thread_rng()) else {
+        return Err(warp::reject::custom(NoNodes));
     };
-    let sender = Address::from_str(&raw_sender).map_err(|err| {
-        warn!("Failed to parse sender address {raw_sender:?}: {err:?}");
-        warp::reject::custom(InvalidParameter)
-    })?;
-    let recipient = Address::from_str(&raw_recipient).map_err(|err| {
-        warn!("Failed to parse recipient address {raw_recipient:?}: {err:?}");
-        warp::reject::custom(InvalidParameter)
-    })?;
-    let amount = raw_amount.parse::<u64>().map_err(|err| {
-        warn!("Failed to parse amount {raw_amount:?}: {err:?}");
-        warp::reject::custom(InvalidParameter)
+    let node = find_node(address).await?;
+    {
+        let mut drips = FAUCET_DRIPS.lock().unwrap();
+        // Forget the addresses whose cooldown is over, so that the map does not grow forever.
+        drips.retain(|_, last_drip| last_drip.elapsed() < FAUCET_COOLDOWN);
+        if let Some(last_drip) = drips.get(&recipient) {
+            let elapsed = last_drip.elapsed();
+            if elapsed < FAUCET_COOLDOWN {
+                return Err(warp::reject::custom(FaucetCooldown(
+                    FAUCET_COOLDOWN - elapsed,
+                )));
+            }
+        }
+        drips.insert(recipient, Instant::now());
+    }
+    let result = node.write().await.add_client_transaction(transaction).await;
+    match result {
+        Ok(transaction_id) => Ok(json(&transaction_id)),
+        Err(err) => {
+            FAUCET_DRIPS.lock().unwrap().remove(&recipient);
+            Err(warp::reject::custom(InvalidTransaction(err)))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TransactionBody {
+    sender: Address,
+    /// Required, but `null` for a burn.
+    #[serde(deserialize_with = "Option::deserialize")]
+    receiver: Option<Address>,
+    amount: Amount,
+    #[serde(default)]
+    fee: Amount,
+    #[serde(default)]
+    memo: Memo,
+}
+
+impl From<TransactionBody> for Transaction {
+    fn from(body: TransactionBody) -> Self {
+        Transaction {
+            sender: body.sender,
+            receiver: body.receiver,
+            amount: body.amount,
+            fee: body.fee,
+            memo: body.memo,
+        }
+    }
+}
+
+/// Send a transaction, described by a JSON body, to a node.
+async fn handle_submit_transaction(
+    node: Arc<RwLock<Node>>,
+    body: TransactionBody,
+) -> Result<impl Reply, Rejection> {
+    let transaction = Transaction::from(body);
+    validate_client_transaction(&transaction)?;
+    let mut writable_node = node.write().await;
+    let transaction_id = writable_node
+        .add_client_transaction(transaction)
+        .await
+        .map_err(|err| warp::reject::custom(InvalidTransaction(err)))?;
+    Ok(json(&transaction_id))
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct WorkQuery {
+    /// The address to which the mining reward is given; by default, the node.
+    miner: Option<Address>,
+}
+
+/// Prepare a block for an external miner, which looks for its nonce out of the process.
+async fn handle_show_node_work(
+    node: Arc<RwLock<Node>>,
+    query: WorkQuery,
+) -> Result<impl Reply, Rejection> {
+    let mut writable_node = node.write().await;
+    let miner = query.miner.unwrap_or(writable_node.address());
+    Ok(json(&writable_node.new_work(miner)))
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct WorkSolution {
+    work_id: u64,
+    nonce: u64,
+}
+
+/// Append the block of some work, completed with the nonce found by an external miner, to the
+/// blockchain of a node and broadcast it. Returns the height of the block.
+async fn handle_submit_work(
+    node: Arc<RwLock<Node>>,
+    solution: WorkSolution,
+) -> Result<impl Reply, Rejection> {
+    let mut writable_node = node.write().await;
+    let height = writable_node
+        .submit_work(solution.work_id, solution.nonce)
+        .map_err(|()| warp::reject::custom(InvalidWork))?;
+    writable_node.announce_mined_block().await;
+    Ok(json(&height))
+}
+
+/// Encode a transaction, described by a JSON body, in the raw format accepted by
+/// `/node/{address}/transaction/raw`.
+async fn handle_build_transaction(body: TransactionBody) -> Result<impl Reply, Rejection> {
+    let transaction = Transaction::from(body);
+    Ok(warp::reply::with_header(
+        transaction.encode(),
+        "content-type",
+        "application/octet-stream",
+    ))
+}
+
+/// Send a transaction, encoded in the raw format, to a node.
+async fn handle_submit_raw_transaction(
+    node: Arc<RwLock<Node>>,
+    bytes: warp::hyper::body::Bytes,
+) -> Result<impl Reply, Rejection> {
+    let transaction = Transaction::decode(&bytes).map_err(|err| {
+        warn!("Failed to decode a raw transaction: {err}");
+        warp::reject::custom(InvalidEncoding(err))
     })?;
-    let transaction = Transaction::new(sender, recipient, amount);
+    validate_client_transaction(&transaction)?;
     let mut writable_node = node.write().await;
-    writable_node
+    let transaction_id = writable_node
         .add_client_transaction(transaction)
         .await
-        .map_err(|_| warp::reject::custom(InvalidTransaction))?;
-    Ok(StatusCode::OK)
+        .map_err(|err| warp::reject::custom(InvalidTransaction(err)))?;
+    Ok(json(&transaction_id))
+}
+
+/// Feed a block, described by a JSON body, to a node as if a peer announced it, and return its
+/// height if the node adopted it.
+async fn handle_submit_block(
+    node: Arc<RwLock<Node>>,
+    block: Block,
+) -> Result<impl Reply, Rejection> {
+    let mut writable_node = node.write().await;
+    let height = writable_node
+        .submit_block(block)
+        .await
+        .map_err(|()| warp::reject::custom(InvalidBlock))?;
+    Ok(json(&height))
+}
+
+/// Exclude a peer of a node, storing the ban in the data directory. Replies with the banned peers.
+async fn handle_ban_peer(node: Arc<RwLock<Node>>, peer: Address) -> Result<impl Reply, Rejection> {
+    let mut writable_node = node.write().await;
+    let address = writable_node.address();
+    if peer == address {
+        warn!("Node {address} cannot ban itself");
+        return Err(warp::reject::custom(InvalidParameter));
+    }
+    if writable_node.ban_peer(peer) {
+        persistence::store_banned_peers(address, writable_node.banned_peers());
+    }
+    Ok(json(writable_node.banned_peers()))
+}
+
+/// Stop excluding a peer of a node, storing the change in the data directory. Replies with the
+/// banned peers.
+async fn handle_unban_peer(
+    node: Arc<RwLock<Node>>,
+    peer: Address,
+) -> Result<impl Reply, Rejection> {
+    let mut writable_node = node.write().await;
+    if writable_node.unban_peer(peer) {
+        persistence::store_banned_peers(writable_node.address(), writable_node.banned_peers());
+    }
+    Ok(json(writable_node.banned_peers()))
+}
+
+/// Check that a transaction submitted by a client satisfies the rules of the blockchain, and
+/// that it does not spend the coins of the faucet, which only `handle_faucet` sends.
+fn validate_client_transaction(transaction: &Transaction) -> Result<(), Rejection> {
+    if transaction.sender == Address::FAUCET {
+        warn!("Rejecting transaction {transaction:?}, which spends the coins of the faucet");
+        return Err(warp::reject::custom(InvalidField(TxError::FaucetSender)));
+    }
+    validate_transaction(transaction)
+}
+
+/// Check that a transaction satisfies the rules of the blockchain.
+fn validate_transaction(transaction: &Transaction) -> Result<(), Rejection> {
+    config()
+        .transaction_rules
+        .check(transaction)
+        .map_err(|err| {
+            warn!("Rejecting transaction {transaction:?}: {err}");
+            warp::reject::custom(InvalidField(err))
+        })
+}
+
+/// Reply with an error status, explaining the error in the body.
+fn status_with_message(
+    status: StatusCode,
+    message: impl std::fmt::Display,
+) -> warp::reply::Response {
+    warp::reply::with_status(message.to_string(), status).into_response()
+}
+
+/// Extract a path parameter, e.g. an address or a hash. Invalid parameters are rejected.
+fn with_parsed<T>(what: &'static str) -> impl Filter<Extract = (T,), Error = Rejection> + Copy
+where
+    T: FromStr + Send + 'static,
+    T::Err: std::fmt::Debug,
+{
+    warp::path::param::<String>()
+        .and_then(move |raw: String| async move { parse_segment(&raw, what) })
+}
+
+/// Parse a segment of the path of a request, which warp does not percent-decode: e.g. the `#`
+/// prefix of a hash, as displayed by the API, is sent as `%23`.
+fn parse_segment<T>(raw: &str, what: &str) -> Result<T, Rejection>
+where
+    T: FromStr,
+    T::Err: std::fmt::Debug,
+{
+    parse(
+        &percent_encoding::percent_decode_str(raw).decode_utf8_lossy(),
+        what,
+    )
+}
+
+/// Parse a parameter of a request, e.g. an address or a hash, rejecting the request if it is
+/// invalid. `what` describes the parameter in the logs.
+fn parse<T>(raw: &str, what: &str) -> Result<T, Rejection>
+where
+    T: FromStr,
+    T::Err: std::fmt::Debug,
+{
+    T::from_str(raw).map_err(|err| {
+        warn!("Failed to parse {what} {raw:?}: {err:?}");
+        warp::reject::custom(InvalidParameter)
+    })
+}
+
+/// The handler of an endpoint of a node, which `with_node` calls with the node.
+type NodeHandler = Box<
+    dyn FnOnce(
+            Arc<RwLock<Node>>,
+        ) -> Pin<Box<dyn Future<Output = Result<Response, Rejection>> + Send>>
+        + Send,
+>;
+
+/// Wrap the handler of an endpoint of a node, which takes the node and the parameters of the
+/// request, into a `NodeHandler`.
+fn node_handler<H, F, R>(handler: H) -> NodeHandler
+where
+    H: FnOnce(Arc<RwLock<Node>>) -> F + Send + 'static,
+    F: Future<Output = Result<R, Rejection>> + Send + 'static,
+    R: Reply,
+{
+    Box::new(|node| Box::pin(async move { handler(node).await.map(Reply::into_response) }))
+}
+
+/// Serve the endpoints of the node whose address `address` extracts. Each of the `routes` matches
+/// the rest of the request to an endpoint and extracts its handler. The node is only looked up
+/// once an endpoint matched, so that each request looks it up once, and requests for a node that
+/// is not in the world are rejected.
+fn with_node(
+    address: BoxedFilter<(Address,)>,
+    routes: Vec<BoxedFilter<(NodeHandler,)>>,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    let routes = routes
+        .into_iter()
+        .reduce(|routes, route| routes.or(route).unify().boxed())
+        .unwrap_or_else(|| {
+            warp::any()
+                .and_then(|| async { Err(warp::reject()) })
+                .boxed()
+        });
+    address
+        .and(routes)
+        .and_then(|address, handler: NodeHandler| async move {
+            handler(find_node(address).await?).await
+        })
+}
+
+/// Find a node in the world, rejecting the request if there is none with that address.
+async fn find_node(address: Address) -> Result<Arc<RwLock<Node>>, Rejection> {
+    world().await.get_node(address).await.ok_or_else(|| {
+        warn!("Cannot find node {address}");
+        warp::reject::custom(InvalidParameter)
+    })
+}
+
+/// Extract the `If-None-Match` header of a conditional request.
+fn if_none_match() -> impl Filter<Extract = (Option<String>,), Error = Rejection> + Copy {
+    warp::header::optional::<String>("if-none-match")
+}
+
+/// How long the clients can cache a reply.
+enum Caching {
+    /// Forever, because the reply never changes, e.g. a block.
+    Immutable,
+    /// Until the local blockchain of the node changes, which the clients must check.
+    Revalidate,
+}
+
+/// Reply to a request for a resource whose version is identified by an entity tag, e.g. the hash
+/// of a block or the hash of the last block of a blockchain. If the client already has that
+/// version, as stated by its `If-None-Match` header, the reply has status 304 and no body.
+/// Otherwise, it is computed by `reply`. In both cases, it has the `ETag` and `Cache-Control`
+/// headers.
+fn conditional(
+    if_none_match: Option<String>,
+    tag: &str,
+    caching: Caching,
+    reply: impl FnOnce() -> Response,
+) -> Response {
+    let etag = format!("\"{tag}\"");
+    let not_modified = if_none_match.is_some_and(|tags| {
+        tags.split(',').any(|client_tag| {
+            let client_tag = client_tag.trim();
+            client_tag == "*" || client_tag.trim_start_matches("W/") == etag
+        })
+    });
+    let mut response = if not_modified {
+        StatusCode::NOT_MODIFIED.into_response()
+    } else {
+        reply()
+    };
+    let cache_control = match caching {
+        Caching::Immutable => "public, max-age=31536000, immutable",
+        Caching::Revalidate => "no-cache",
+    };
+    let headers = response.headers_mut();
+    if let Ok(etag) = HeaderValue::from_str(&etag) {
+        headers.insert("etag", etag);
+    }
+    headers.insert("cache-control", HeaderValue::from_static(cache_control));
+    response
 }
 
-/// Handle errors.
-async fn handle_rejection(err: Rejection) -> Result<impl Reply, Rejection> {
+/// Handle errors. A request rejected by several routes carries the rejections of all of them,
+/// e.g. a segment of its path that another route failed to parse as a parameter, so the rejections
+/// of the handlers, which only run once their route matched, take precedence over the invalid
+/// parameters.
+async fn handle_rejection(err: Rejection) -> Result<warp::reply::Response, Rejection> {
     if err.is_not_found() {
-        Ok(StatusCode::NOT_FOUND)
+        Ok(StatusCode::NOT_FOUND.into_response())
+    } else if let Some(InvalidTransaction(err)) = err.find() {
+        Ok(status_with_message(
+            StatusCode::FORBIDDEN,
+            format!("{}: {err}", err.code()),
+        ))
+    } else if let Some(InvalidField(err)) = err.find() {
+        Ok(status_with_message(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("{}: {err}", err.code()),
+        ))
+    } else if let Some(Unauthorized) = err.find() {
+        Ok(status_with_message(
+            StatusCode::UNAUTHORIZED,
+            "The admin endpoints require the admin token",
+        ))
+    } else if let Some(ReadOnly) = err.find() {
+        Ok(status_with_message(
+            StatusCode::METHOD_NOT_ALLOWED,
+            "The server is read-only",
+        ))
+    } else if let Some(InvalidEncoding(err)) = err.find() {
+        Ok(status_with_message(StatusCode::BAD_REQUEST, err))
+    } else if let Some(InvalidBlock) = err.find() {
+        Ok(status_with_message(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "The node did not adopt the block",
+        ))
+    } else if let Some(InvalidWork) = err.find() {
+        Ok(status_with_message(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "The work is unknown or stale, or the nonce does not solve it",
+        ))
+    } else if let Some(FaucetCooldown(wait)) = err.find() {
+        let mut response = status_with_message(
+            StatusCode::TOO_MANY_REQUESTS,
+            format!("The address got coins from the faucet too recently, retry in {wait:.0?}"),
+        );
+        response.headers_mut().insert(
+            "retry-after",
+            HeaderValue::from(wait.as_secs_f64().ceil() as u64),
+        );
+        Ok(response)
+    } else if let Some(NoNodes) = err.find() {
+        Ok(status_with_message(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "There is no node in the world",
+        ))
+    } else if let Some(OverCapacity(err)) = err.find() {
+        Ok(status_with_message(
+            StatusCode::CONFLICT,
+            format!("{}: {err}", err.code()),
+        ))
+    } else if let Some(NoConfigFile) = err.find() {
+        Ok(status_with_message(
+            StatusCode::CONFLICT,
+            "The server was not started with a configuration file",
+        ))
+    } else if let Some(NoDataDir) = err.find() {
+        Ok(status_with_message(
+            StatusCode::CONFLICT,
+            "The server was not started with a data directory",
+        ))
+    } else if let Some(InvalidConfig(err)) = err.find() {
+        Ok(status_with_message(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("{}: {err}", err.code()),
+        ))
+    } else if let Some(UnknownWallet) = err.find() {
+        Ok(status_with_message(
+            StatusCode::NOT_FOUND,
+            "There is no wallet with this name",
+        ))
+    } else if let Some(UnknownReceipt) = err.find() {
+        Ok(status_with_message(
+            StatusCode::NOT_FOUND,
+            "The transaction is not in the blockchain of the node",
+        ))
     } else if let Some(InvalidParameter) = err.find() {
-        Ok(StatusCode::BAD_REQUEST)
-    } else if let Some(InvalidTransaction) = err.find() {
-        Ok(StatusCode::FORBIDDEN)
+        Ok(StatusCode::BAD_REQUEST.into_response())
+    } else if let Some(err) = err.find::<warp::reject::MethodNotAllowed>() {
+        Ok(status_with_message(StatusCode::METHOD_NOT_ALLOWED, err))
+    } else if let Some(err) = err.find::<warp::reject::PayloadTooLarge>() {
+        Ok(status_with_message(StatusCode::PAYLOAD_TOO_LARGE, err))
+    } else if let Some(err) = err.find::<warp::reject::LengthRequired>() {
+        Ok(status_with_message(StatusCode::LENGTH_REQUIRED, err))
+    } else if let Some(err) = err.find::<warp::reject::UnsupportedMediaType>() {
+        Ok(status_with_message(StatusCode::UNSUPPORTED_MEDIA_TYPE, err))
+    } else if let Some(err) = err.find::<warp::reject::InvalidQuery>() {
+        Ok(status_with_message(StatusCode::BAD_REQUEST, err))
+    } else if let Some(err) = err.find::<warp::filters::body::BodyDeserializeError>() {
+        Ok(status_with_message(StatusCode::BAD_REQUEST, err))
     } else {
         error!("Internal server error: {:?}", err);
-        Ok(StatusCode::INTERNAL_SERVER_ERROR)
+        Ok(StatusCode::INTERNAL_SERVER_ERROR.into_response())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use warp::hyper::body::Bytes;
+
+    /// Filters that reject every query parameter.
+    #[derive(Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct NoFilters {}
+
+    #[tokio::test]
+    async fn paginate_lists() {
+        let numbers = warp::path!("numbers")
+            .and(paginated())
+            .map(|_: NoFilters, page: Page| page.reply_with_list((0..5).collect::<Vec<u32>>()));
+
+        let response = warp::test::request()
+            .path("/numbers?limit=2&offset=1&sort=desc")
+            .reply(&numbers)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.body().as_ref(), b"[3,2]");
+        assert_eq!(response.headers()["x-total-count"], "5");
+        assert_eq!(
+            response.headers()["link"],
+            "</numbers?limit=2&offset=3&sort=desc>; rel=\"next\""
+        );
+
+        let response = warp::test::request()
+            .path("/numbers?offset=3")
+            .reply(&numbers)
+            .await;
+        assert_eq!(response.body().as_ref(), b"[3,4]");
+        assert!(!response.headers().contains_key("link"));
+
+        for path in ["/numbers?limit=0", "/numbers?sort=up", "/numbers?filter=1"] {
+            let rejected = warp::test::request().path(path).filter(&numbers).await;
+            assert!(rejected.is_err(), "{path} was accepted");
+        }
+    }
+
+    #[tokio::test]
+    async fn parse_percent_encoded_segments() {
+        let hashes = warp::path("block")
+            .and(with_parsed::<BlockHash>("block hash"))
+            .map(|hash: BlockHash| hash.to_string());
+        let hash = Block::genesis().hash().to_string();
+        for segment in [format!("%23{}", &hash[1..]), hash[1..].to_uppercase()] {
+            let parsed = warp::test::request()
+                .path(&format!("/block/{segment}"))
+                .filter(&hashes)
+                .await;
+            assert_eq!(parsed.ok(), Some(hash.clone()), "{segment}");
+        }
+        for segment in ["%23abc", "%2", "%23%23ab"] {
+            let rejected = warp::test::request()
+                .path(&format!("/block/{segment}"))
+                .filter(&hashes)
+                .await;
+            assert!(rejected.is_err(), "{segment} was accepted");
+        }
+    }
+
+    #[test]
+    fn reply_conditionally() {
+        let reply = || "block".into_response();
+        let response = conditional(None, "#00ab", Caching::Immutable, reply);
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["etag"], "\"#00ab\"");
+        assert_eq!(
+            response.headers()["cache-control"],
+            "public, max-age=31536000, immutable"
+        );
+
+        for if_none_match in ["\"#00ab\"", "\"#ffff\", W/\"#00ab\"", "*"] {
+            let if_none_match = Some(if_none_match.to_string());
+            let response = conditional(if_none_match, "#00ab", Caching::Revalidate, reply);
+            assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+            assert_eq!(response.headers()["cache-control"], "no-cache");
+        }
+        let if_none_match = Some("\"#ffff\"".to_string());
+        let response = conditional(if_none_match, "#00ab", Caching::Revalidate, reply);
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn reject_unknown_routes_and_methods() {
+        let routes = versioned(public_routes(SERVER_DEFAULT_MAX_BODY_BYTES, false))
+            .recover(handle_rejection);
+        let status = |method: &str, path: &str| {
+            let request = warp::test::request().method(method).path(path);
+            async { request.reply(&routes).await.status() }
+        };
+        assert_eq!(status("GET", "/v1/world/converged").await, StatusCode::OK);
+        assert_eq!(status("GET", "/v1/unknown").await, StatusCode::NOT_FOUND);
+        assert_eq!(status("POST", "/v1/unknown").await, StatusCode::NOT_FOUND);
+        assert_eq!(
+            status("DELETE", "/v1/world/converged").await,
+            StatusCode::METHOD_NOT_ALLOWED
+        );
+        assert_eq!(
+            status("GET", "/v1/transaction/build").await,
+            StatusCode::METHOD_NOT_ALLOWED
+        );
+    }
+
+    #[tokio::test]
+    async fn reject_unknown_query_parameters_of_stats() {
+        async fn accepts<T: DeserializeOwned + Send + 'static>(query: &str) -> bool {
+            let request = warp::test::request().path(&format!("/?{query}"));
+            request.filter(&warp::query::<T>()).await.is_ok()
+        }
+        assert!(accepts::<BlockTimesQuery>("bucket_ms=1000").await);
+        assert!(!accepts::<BlockTimesQuery>("bucket=1000").await);
+        assert!(accepts::<ExportQuery>("table=nodes&format=json").await);
+        assert!(!accepts::<ExportQuery>("table=nodes&fromat=json").await);
+        let node = Address::new_random();
+        assert!(accepts::<WealthQuery>(&format!("node={node}&bucket=10&every=5")).await);
+        assert!(!accepts::<WealthQuery>("node=not-an-address").await);
+        assert!(!accepts::<WealthQuery>("sample=5").await);
+    }
+
+    #[tokio::test]
+    async fn reject_long_poll_timeouts_that_are_too_long() {
+        let routes = versioned(public_routes(SERVER_DEFAULT_MAX_BODY_BYTES, false))
+            .recover(handle_rejection);
+        let status = |path: String| {
+            let request = warp::test::request().path(&path);
+            async { request.reply(&routes).await.status() }
+        };
+        let path = |timeout: &str| format!("/v1/world/converged/wait?timeout={timeout}");
+        assert_eq!(status(path("0s")).await, StatusCode::OK);
+        let too_long = SERVER_LONG_POLL_MAX_TIMEOUT_SECS + 1;
+        for timeout in [too_long.to_string(), u64::MAX.to_string(), "-1".to_string()] {
+            assert_eq!(status(path(&timeout)).await, StatusCode::BAD_REQUEST);
+        }
+        let address = world()
+            .await
+            .add_node_with_config(NodeConfig {
+                hashrate: 0.0,
+                ..NodeConfig::default()
+            })
+            .await
+            .unwrap();
+        let path = format!("/v1/node/{address}/wait_for_block?timeout={too_long}");
+        assert_eq!(status(path).await, StatusCode::BAD_REQUEST);
+        let transaction = TransactionId::new_random();
+        let path = format!("/v1/node/{address}/transaction/{transaction}/wait?timeout={too_long}");
+        assert_eq!(status(path).await, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn rate_limit_the_faucet() {
+        world()
+            .await
+            .add_node_with_config(NodeConfig {
+                hashrate: 0.0,
+                ..NodeConfig::default()
+            })
+            .await
+            .unwrap();
+        let recipient = Address::new_random();
+        let drip = |amount| handle_faucet(recipient, FaucetQuery { amount });
+
+        let too_much = drip(Some(FAUCET_MAX_AMOUNT.saturating_mul(2))).await;
+        assert!(too_much.is_err_and(|err| err.find::<InvalidParameter>().is_some()));
+        // The addresses whose cooldown is over are forgotten at the next drip.
+        let earlier = Address::new_random();
+        let long_ago = Instant::now().checked_sub(FAUCET_COOLDOWN).unwrap();
+        FAUCET_DRIPS.lock().unwrap().insert(earlier, long_ago);
+        assert!(drip(None).await.is_ok());
+        assert!(!FAUCET_DRIPS.lock().unwrap().contains_key(&earlier));
+        let again = drip(None).await;
+        assert!(again.is_err_and(|err| err.find::<FaucetCooldown>().is_some()));
+    }
+
+    #[tokio::test]
+    async fn reject_transactions_from_the_faucet() {
+        let routes = versioned(public_routes(SERVER_DEFAULT_MAX_BODY_BYTES, false))
+            .recover(handle_rejection);
+        let address = world()
+            .await
+            .add_node_with_config(NodeConfig {
+                hashrate: 0.0,
+                ..NodeConfig::default()
+            })
+            .await
+            .unwrap();
+        let recipient = Address::new_random();
+        let response = warp::test::request()
+            .method("POST")
+            .path(&format!(
+                "/v1/node/{address}/send/from/{}/to/{recipient}/amount/1",
+                Address::FAUCET
+            ))
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(response.body().starts_with(b"faucet_sender"));
+        let response = warp::test::request()
+            .method("POST")
+            .path(&format!("/v1/node/{address}/transaction"))
+            .json(&serde_json::json!({
+                "sender": Address::FAUCET,
+                "receiver": recipient,
+                "amount": "1",
+            }))
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        // The raw transactions are rejected in the same way, although other routes fail to parse
+        // `raw` as the identifier of a transaction.
+        let raw = |sender| {
+            warp::test::request()
+                .method("POST")
+                .path(&format!("/v1/node/{address}/transaction/raw"))
+                .body(Transaction::new(sender, recipient, Amount::from_coins(1)).encode())
+                .reply(&routes)
+        };
+        let response = raw(Address::FAUCET).await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(response.body().starts_with(b"faucet_sender"));
+        let response = raw(Address::new_random()).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert!(response.body().starts_with(b"insufficient_funds"));
+    }
+
+    #[tokio::test]
+    async fn look_up_the_node_once_its_endpoint_matched() {
+        let routes = versioned(public_routes(SERVER_DEFAULT_MAX_BODY_BYTES, false))
+            .recover(handle_rejection);
+        let address = world()
+            .await
+            .add_node_with_config(NodeConfig {
+                hashrate: 0.0,
+                ..NodeConfig::default()
+            })
+            .await
+            .unwrap();
+        let status = |path: String| {
+            let request = warp::test::request().path(&path);
+            async { request.reply(&routes).await.status() }
+        };
+
+        let unknown = Address::new_random();
+        assert_eq!(
+            status(format!("/v1/node/{address}/mempool_balance")).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(format!("/v1/node/{unknown}/mempool_balance")).await,
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            status(format!("/v1/node/{unknown}/unknown")).await,
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            status("/v1/node/invalid/mempool_balance".to_string()).await,
+            StatusCode::BAD_REQUEST
+        );
+        // The listener of a single node serves the same handlers.
+        let show_node = warp::path::end().map(|| node_handler(handle_show_node));
+        let node = warp::get().and(with_node(
+            warp::any().map(move || address).boxed(),
+            vec![show_node.boxed()],
+        ));
+        let response = warp::test::request().path("/").reply(&node).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn paginate_the_block_tree() {
+        let routes = versioned(public_routes(SERVER_DEFAULT_MAX_BODY_BYTES, false))
+            .recover(handle_rejection);
+        world()
+            .await
+            .add_node_with_config(NodeConfig {
+                hashrate: 0.0,
+                ..NodeConfig::default()
+            })
+            .await
+            .unwrap();
+        let request = |query: &str| {
+            warp::test::request()
+                .path(&format!("/v1/world/blocktree?{query}"))
+                .reply(&routes)
+        };
+
+        let response = request("min_height=0&limit=1").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let entries: Vec<serde_json::Value> = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0]["height"], 0);
+        assert!(response.headers().contains_key("x-total-count"));
+        for query in ["limit=0", "min_height=x", "max_height=1"] {
+            assert_eq!(request(query).await.status(), StatusCode::BAD_REQUEST);
+        }
+    }
+
+    #[tokio::test]
+    async fn show_the_receipts_of_transactions() {
+        let routes = versioned(public_routes(SERVER_DEFAULT_MAX_BODY_BYTES, false))
+            .recover(handle_rejection);
+        let address = world()
+            .await
+            .add_node_with_config(NodeConfig {
+                hashrate: 0.0,
+                ..NodeConfig::default()
+            })
+            .await
+            .unwrap();
+        let receipt = |transaction_id: TransactionId| {
+            warp::test::request()
+                .path(&format!("/v1/node/{address}/receipt/{transaction_id}"))
+                .reply(&routes)
+        };
+
+        let response = receipt(TransactionId::new_random()).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            response.body().as_ref(),
+            b"The transaction is not in the blockchain of the node"
+        );
+        let response = warp::test::request()
+            .path(&format!("/v1/node/{address}/receipt/invalid"))
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn show_the_sync_status_of_nodes() {
+        let routes = versioned(public_routes(SERVER_DEFAULT_MAX_BODY_BYTES, false))
+            .recover(handle_rejection);
+        let address = world()
+            .await
+            .add_node_with_config(NodeConfig {
+                hashrate: 0.0,
+                ..NodeConfig::default()
+            })
+            .await
+            .unwrap();
+
+        // No peer has a longer blockchain to download.
+        let response = warp::test::request()
+            .path(&format!("/v1/node/{address}/sync_status"))
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let reply: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(
+            reply,
+            serde_json::json!({
+                "syncing": false,
+                "is_synced": true,
+                "current_height": 0,
+                "best_known_height": 0,
+                "blocks_remaining": 0,
+                "downloaded_blocks": 0,
+                "download_rate": 0.0,
+            })
+        );
+        let response = warp::test::request()
+            .path(&format!("/v1/node/{}/sync_status", Address::new_random()))
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn ban_and_unban_peers() {
+        let (shutdown, _) = watch::channel(false);
+        let admin = versioned(admin_routes(
+            "secret".to_string(),
+            None,
+            None,
+            Arc::new(shutdown),
+            SERVER_DEFAULT_MAX_BODY_BYTES,
+        ))
+        .recover(handle_rejection);
+        let public = versioned(public_routes(SERVER_DEFAULT_MAX_BODY_BYTES, false))
+            .recover(handle_rejection);
+        let address = world()
+            .await
+            .add_node_with_config(NodeConfig {
+                hashrate: 0.0,
+                ..NodeConfig::default()
+            })
+            .await
+            .unwrap();
+        let peer = Address::new_random();
+        let request = |method: &str, peer: Address| {
+            warp::test::request()
+                .method(method)
+                .path(&format!("/v1/admin/node/{address}/ban/{peer}"))
+                .header("authorization", "Bearer secret")
+        };
+        let banned_peers = |response: warp::http::Response<Bytes>| -> Vec<Address> {
+            assert_eq!(response.status(), StatusCode::OK);
+            serde_json::from_slice(response.body()).unwrap()
+        };
+        let node = world().await.get_node(address).await.unwrap();
+
+        let response = request("POST", peer).reply(&admin).await;
+        assert_eq!(banned_peers(response), [peer]);
+        // Banning a peer again changes nothing.
+        let response = request("POST", peer).reply(&admin).await;
+        assert_eq!(banned_peers(response), [peer]);
+        let response = warp::test::request()
+            .path(&format!("/v1/node/{address}/banned_peers"))
+            .reply(&public)
+            .await;
+        assert_eq!(banned_peers(response), [peer]);
+        assert!(node.read().await.is_banned(peer));
+
+        let response = request("POST", address).reply(&admin).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let response = warp::test::request()
+            .method("POST")
+            .path(&format!("/v1/admin/node/{address}/ban/{peer}"))
+            .reply(&admin)
+            .await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let unknown = Address::new_random();
+        let response = warp::test::request()
+            .method("POST")
+            .path(&format!("/v1/admin/node/{unknown}/ban/{peer}"))
+            .header("authorization", "Bearer secret")
+            .reply(&admin)
+            .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        assert!(banned_peers(request("DELETE", peer).reply(&admin).await).is_empty());
+        assert!(banned_peers(request("DELETE", peer).reply(&admin).await).is_empty());
+        assert!(!node.read().await.is_banned(peer));
     }
 }
@@ -0,0 +1,1022 @@
+use crate::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::OnceCell;
+use tokio::sync::RwLock;
+
+/// Statistics collected about the whole simulation.
+pub struct Stats {
+    /// The blocks mined in the world, in the order in which they were mined. The oldest ones are
+    /// forgotten beyond `STATS_MAX_MINED_BLOCKS`.
+    mined_blocks: RwLock<VecDeque<MinedBlock>>,
+    /// When each node first added each of the mined blocks to its local blockchain, in
+    /// milliseconds since the Unix epoch.
+    adoptions: RwLock<HashMap<BlockHash, HashMap<Address, u64>>>,
+    /// The reorganizations of the local blockchains of the nodes. The oldest ones are forgotten
+    /// beyond `STATS_MAX_REORGS`.
+    reorgs: RwLock<VecDeque<Reorg>>,
+    /// The changes to the world, in the order in which they happened. The oldest ones are
+    /// forgotten beyond `STATS_MAX_TIMELINE_ENTRIES`.
+    timeline: RwLock<VecDeque<TimelineEntry>>,
+    /// The transactions sent by the demo and not yet included in a mined block, with their lane
+    /// and when they were sent, in milliseconds since the Unix epoch.
+    pending_demo_transactions: RwLock<HashMap<TransactionId, (DemoLane, u64)>>,
+    /// The time between sending each demo transaction and mining the first block that includes
+    /// it, in milliseconds, by lane.
+    confirmation_latencies: RwLock<HashMap<DemoLane, Vec<u64>>>,
+    /// The tip of each node, sampled periodically, only keeping the samples in which it changed.
+    tips: RwLock<BTreeMap<Address, VecDeque<TipSample>>>,
+    /// When the statistics started to be collected.
+    started_at: Instant,
+}
+
+static STATS: OnceCell<Stats> = OnceCell::const_new();
+
+/// Get the singleton of the statistics.
+pub async fn stats() -> &'static Stats {
+    STATS.get_or_init(|| async { Stats::new() }).await
+}
+
+/// A block mined by a node, including blocks that later became orphans.
+#[derive(Clone, Debug, Serialize)]
+pub struct MinedBlock {
+    /// The hash of the block.
+    pub hash: String,
+    /// The height of the block in the blockchain of the miner.
+    pub height: usize,
+    /// The address of the miner.
+    pub miner: Address,
+    /// When the block was mined, in milliseconds since the Unix epoch.
+    pub timestamp: u64,
+    /// The mining difficulty, in leading zero bits.
+    pub difficulty: u32,
+    /// The number of transactions in the block.
+    pub transactions: usize,
+    /// The hash of the block, used to look up its adoptions.
+    #[serde(skip)]
+    block_hash: BlockHash,
+}
+
+impl Stats {
+    fn new() -> Self {
+        Stats {
+            mined_blocks: RwLock::new(VecDeque::new()),
+            adoptions: RwLock::new(HashMap::new()),
+            reorgs: RwLock::new(VecDeque::new()),
+            timeline: RwLock::new(VecDeque::new()),
+            pending_demo_transactions: RwLock::new(HashMap::new()),
+            confirmation_latencies: RwLock::new(HashMap::new()),
+            tips: RwLock::new(BTreeMap::new()),
+            started_at: Instant::now(),
+        }
+    }
+
+    /// Record that a block has just been mined, on a blockchain with the given difficulty.
+    pub async fn record_mined_block(&self, block: &Block, height: usize, difficulty: u32) {
+        let block_hash = block.hash();
+        let timestamp = now_millis();
+        let mut mined_blocks = self.mined_blocks.write().await;
+        let mut adoptions = self.adoptions.write().await;
+        if mined_blocks.len() >= STATS_MAX_MINED_BLOCKS {
+            if let Some(forgotten) = mined_blocks.pop_front() {
+                adoptions.remove(&forgotten.block_hash);
+            }
+        }
+        adoptions.entry(block_hash.clone()).or_default();
+        mined_blocks.push_back(MinedBlock {
+            hash: block_hash.to_string(),
+            height,
+            miner: block.miner,
+            timestamp,
+            difficulty,
+            transactions: block.transactions.len(),
+            block_hash,
+        });
+        drop(adoptions);
+        drop(mined_blocks);
+
+        let mut pending = self.pending_demo_transactions.write().await;
+        if pending.is_empty() {
+            return;
+        }
+        let mut latencies = self.confirmation_latencies.write().await;
+        for transaction in &block.transactions {
+            if let Some((lane, sent_at)) = pending.remove(&transaction.id) {
+                latencies
+                    .entry(lane)
+                    .or_default()
+                    .push(timestamp.saturating_sub(sent_at));
+            }
+        }
+    }
+
+    /// Record that the demo has just sent a transaction in a lane, to measure how long it takes
+    /// to be included in a mined block. The oldest transactions still pending are forgotten
+    /// beyond `STATS_MAX_PENDING_DEMO_TRANSACTIONS`, because they may never be mined.
+    pub async fn record_demo_transaction(&self, transaction_id: TransactionId, lane: DemoLane) {
+        let mut pending = self.pending_demo_transactions.write().await;
+        if pending.len() >= STATS_MAX_PENDING_DEMO_TRANSACTIONS {
+            let oldest = pending
+                .iter()
+                .min_by_key(|(_, (_, sent_at))| *sent_at)
+                .map(|(id, _)| *id);
+            if let Some(oldest) = oldest {
+                pending.remove(&oldest);
+            }
+        }
+        pending.insert(transaction_id, (lane, now_millis()));
+    }
+
+    /// Summarize how long the transactions of each lane of the demo took to be included in a
+    /// mined block.
+    pub async fn confirmation_latencies(&self) -> BTreeMap<DemoLane, LaneLatency> {
+        let pending = self.pending_demo_transactions.read().await;
+        let latencies = self.confirmation_latencies.read().await;
+        [DemoLane::Normal, DemoLane::Priority]
+            .into_iter()
+            .map(|lane| {
+                let pending = pending.values().filter(|(other, _)| *other == lane).count();
+                let mut latencies = latencies.get(&lane).cloned().unwrap_or_default();
+                (lane, LaneLatency::new(&mut latencies, pending))
+            })
+            .collect()
+    }
+
+    /// Record that a node has just added some blocks, received from the network, to its local
+    /// blockchain. Only the adoptions of the mined blocks still remembered are recorded.
+    pub async fn record_adopted_blocks(&self, node: Address, blocks: &[BlockHash]) {
+        let timestamp = now_millis();
+        let mut adoptions = self.adoptions.write().await;
+        for block_hash in blocks {
+            if let Some(nodes) = adoptions.get_mut(block_hash) {
+                nodes.entry(node).or_insert(timestamp);
+            }
+        }
+    }
+
+    /// Record that a node has just replaced some blocks at the end of its local blockchain.
+    pub async fn record_reorg(&self, node: Address, depth: usize) {
+        let mut reorgs = self.reorgs.write().await;
+        if reorgs.len() >= STATS_MAX_REORGS {
+            reorgs.pop_front();
+        }
+        reorgs.push_back(Reorg {
+            node,
+            depth,
+            timestamp: now_millis(),
+        });
+        drop(reorgs);
+        self.record_world_event(WorldEvent::Reorg { node, depth })
+            .await;
+    }
+
+    /// Record that the world has just changed.
+    pub async fn record_world_event(&self, event: WorldEvent) {
+        let mut timeline = self.timeline.write().await;
+        if timeline.len() >= STATS_MAX_TIMELINE_ENTRIES {
+            timeline.pop_front();
+        }
+        timeline.push_back(TimelineEntry {
+            timestamp: now_millis(),
+            event,
+        });
+    }
+
+    /// Record the current tip of some nodes, unless it is the same as in their previous sample.
+    /// The oldest samples of a node are forgotten beyond `STATS_MAX_TIP_SAMPLES`.
+    pub async fn record_tips(&self, tips: Vec<(Address, usize, BlockHash)>) {
+        let timestamp = now_millis();
+        let mut samples = self.tips.write().await;
+        for (node, height, hash) in tips {
+            let node_samples = samples.entry(node).or_default();
+            if node_samples
+                .back()
+                .is_some_and(|last| last.height == height && last.hash == hash)
+            {
+                continue;
+            }
+            if node_samples.len() >= STATS_MAX_TIP_SAMPLES {
+                node_samples.pop_front();
+            }
+            node_samples.push_back(TipSample {
+                timestamp,
+                height,
+                hash,
+            });
+        }
+    }
+
+    /// The changes of the tip of each node, or only of `node`, from the oldest to the most
+    /// recent. With `since`, only the samples taken since then, in milliseconds since the Unix
+    /// epoch, plus the previous sample of each node, which tells its tip at that time.
+    pub async fn tips(
+        &self,
+        node: Option<Address>,
+        since: Option<u64>,
+    ) -> BTreeMap<Address, Vec<TipSample>> {
+        self.tips
+            .read()
+            .await
+            .iter()
+            .filter(|(address, _)| node.is_none_or(|node| **address == node))
+            .map(|(address, samples)| {
+                let later = since.map_or(0, |since| {
+                    samples.partition_point(|sample| sample.timestamp < since)
+                });
+                let samples = samples.range(later.saturating_sub(1)..).cloned().collect();
+                (*address, samples)
+            })
+            .collect()
+    }
+
+    /// Add the changes of the tips recorded by a previous run, e.g. restored from the data
+    /// directory, before the ones recorded so far.
+    pub async fn restore_tips(&self, restored: BTreeMap<Address, VecDeque<TipSample>>) {
+        let mut samples = self.tips.write().await;
+        for (node, mut restored_samples) in restored {
+            let node_samples = samples.entry(node).or_default();
+            restored_samples.append(node_samples);
+            let excess = restored_samples.len().saturating_sub(STATS_MAX_TIP_SAMPLES);
+            restored_samples.drain(..excess);
+            *node_samples = restored_samples;
+        }
+    }
+
+    /// Forget the changes of the tip of a node removed from the world.
+    pub async fn forget_tips(&self, node: Address) {
+        self.tips.write().await.remove(&node);
+    }
+
+    /// The changes to the world so far, in the order in which they happened.
+    pub async fn timeline(&self) -> Vec<TimelineEntry> {
+        self.timeline.read().await.iter().cloned().collect()
+    }
+
+    /// The reorganizations observed so far.
+    pub async fn reorgs(&self) -> Vec<Reorg> {
+        self.reorgs.read().await.iter().cloned().collect()
+    }
+
+    /// The hashes of all the blocks mined so far.
+    pub async fn mined_block_hashes(&self) -> Vec<BlockHash> {
+        self.mined_blocks
+            .read()
+            .await
+            .iter()
+            .map(|block| block.block_hash.clone())
+            .collect()
+    }
+
+    /// How long the statistics have been collected for.
+    pub fn uptime(&self) -> Duration {
+        self.started_at.elapsed()
+    }
+
+    /// One row for each mined block, in the order in which they were mined.
+    pub async fn block_rows(&self) -> Vec<BlockRow> {
+        let mined_blocks = self.mined_blocks.read().await;
+        let adoptions = self.adoptions.read().await;
+        mined_blocks
+            .iter()
+            .map(|block| {
+                let mut delays: Vec<u64> = adoptions
+                    .get(&block.block_hash)
+                    .map(|nodes| {
+                        nodes
+                            .values()
+                            .map(|timestamp| timestamp.saturating_sub(block.timestamp))
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                BlockRow {
+                    height: block.height,
+                    hash: block.hash.clone(),
+                    miner: block.miner,
+                    timestamp: block.timestamp,
+                    transactions: block.transactions,
+                    difficulty: block.difficulty,
+                    propagation_p95_millis: percentile(&mut delays, 95),
+                }
+            })
+            .collect()
+    }
+
+    /// One row for each node currently in the world.
+    pub async fn node_rows(&self) -> Vec<NodeRow> {
+        let mut mined_per_node: HashMap<Address, usize> = HashMap::new();
+        for block in self.mined_blocks.read().await.iter() {
+            *mined_per_node.entry(block.miner).or_default() += 1;
+        }
+
+        let mut rows = vec![];
+        for address in world().await.get_node_addresses().await {
+            let Some(node) = world().await.get_node(address).await else {
+                continue;
+            };
+            let readable_node = node.read().await;
+            let blockchain = readable_node.blockchain();
+            rows.push(NodeRow {
+                address,
+                height: blockchain.height(),
+                last_block_hash: blockchain.last_hash().to_string(),
+                mempool_length: readable_node.mempool().len(),
+                mined_blocks: mined_per_node.get(&address).copied().unwrap_or(0),
+                is_synced: readable_node.sync_status().is_synced(blockchain.len()),
+            });
+        }
+        rows.sort_by_key(|row| row.address);
+        rows
+    }
+
+    /// Summarize the intervals between the mined blocks and the mining difficulty at each height,
+    /// or `None` if the histogram of the intervals would have more than
+    /// `STATS_MAX_HISTOGRAM_BUCKETS` buckets of the given width.
+    pub async fn block_times(&self, bucket_millis: u64) -> Option<BlockTimes> {
+        BlockTimes::new(
+            self.mined_blocks.write().await.make_contiguous(),
+            bucket_millis,
+        )
+    }
+}
+
+/// The lane of a transaction sent by the demo: priority transactions pay higher fees, to show
+/// that they are confirmed sooner.
+#[derive(Copy, Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DemoLane {
+    Normal,
+    Priority,
+}
+
+/// How long the demo transactions of a lane took to be included in a mined block.
+#[derive(Debug, Serialize)]
+pub struct LaneLatency {
+    /// How many transactions were included in a mined block.
+    pub confirmed: usize,
+    /// How many transactions were sent but not included in a mined block yet.
+    pub pending: usize,
+    /// The average latency, in milliseconds, if any transaction was confirmed.
+    pub mean_millis: Option<f64>,
+    pub p50_millis: Option<u64>,
+    pub p95_millis: Option<u64>,
+}
+
+impl LaneLatency {
+    fn new(latencies: &mut [u64], pending: usize) -> Self {
+        let mean_millis = (!latencies.is_empty())
+            .then(|| latencies.iter().sum::<u64>() as f64 / latencies.len() as f64);
+        LaneLatency {
+            confirmed: latencies.len(),
+            pending,
+            mean_millis,
+            p50_millis: percentile(latencies, 50),
+            p95_millis: percentile(latencies, 95),
+        }
+    }
+}
+
+/// The current time, in milliseconds since the Unix epoch.
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// The tip of the local blockchain of a node, from when it was sampled until the next sample.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TipSample {
+    /// When the tip was sampled, in milliseconds since the Unix epoch.
+    pub timestamp: u64,
+    pub height: usize,
+    pub hash: BlockHash,
+}
+
+/// Sample the tip of every node periodically, to reconstruct when the nodes disagreed.
+pub async fn sample_tips(interval: Duration) {
+    loop {
+        tokio::time::sleep(interval).await;
+        let world = world().await;
+        let mut tips = vec![];
+        for address in world.get_node_addresses().await {
+            let Some(node) = world.get_node(address).await else {
+                continue;
+            };
+            let readable_node = node.read().await;
+            let blockchain = readable_node.blockchain();
+            tips.push((address, blockchain.height(), blockchain.last_hash().clone()));
+        }
+        stats().await.record_tips(tips).await;
+    }
+}
+
+/// A reorganization of the local blockchain of a node, which replaced some of its last blocks.
+#[derive(Clone, Debug, Serialize)]
+pub struct Reorg {
+    /// The address of the node.
+    pub node: Address,
+    /// How many blocks were removed from the local blockchain.
+    pub depth: usize,
+    /// When the reorganization happened, in milliseconds since the Unix epoch.
+    pub timestamp: u64,
+}
+
+/// A change to the world, as opposed to the progress of the nodes, which helps to explain the
+/// anomalies observed in the blockchains.
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum WorldEvent {
+    /// A node joined the network.
+    NodeAdded { node: Address },
+    /// A node left the network.
+    NodeRemoved { node: Address },
+    /// The task of a node failed, stopping the node.
+    NodeFailed { node: Address, reason: String },
+    /// A failed node started running again.
+    NodeRestarted { node: Address },
+    /// A node replaced some of the last blocks of its local blockchain.
+    Reorg { node: Address, depth: usize },
+    /// The settings of the simulation changed.
+    ConfigChanged { config: Box<Config> },
+    /// The settings of a node changed.
+    NodeConfigChanged { node: Address, config: NodeConfig },
+    /// The nodes stopped running.
+    Paused,
+    /// The nodes started running again.
+    Resumed,
+}
+
+/// A change to the world, with the time at which it happened.
+#[derive(Clone, Debug, Serialize)]
+pub struct TimelineEntry {
+    /// When the change happened, in milliseconds since the Unix epoch.
+    pub timestamp: u64,
+    #[serde(flatten)]
+    pub event: WorldEvent,
+}
+
+/// Compute the given percentile of some values, using the nearest-rank method.
+pub fn percentile(values: &mut [u64], percentile: usize) -> Option<u64> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_unstable();
+    let rank = (percentile * values.len()).div_ceil(100).max(1);
+    Some(values[rank - 1])
+}
+
+/// A row that can be exported as comma-separated values.
+pub trait CsvRow {
+    /// The names of the columns.
+    fn header() -> &'static [&'static str];
+
+    /// The values of the columns.
+    fn values(&self) -> Vec<String>;
+}
+
+/// Format some rows as comma-separated values, starting with a header line. The fields are
+/// quoted as in RFC 4180 when they contain a comma, a quote or a line break.
+pub fn to_csv<T: CsvRow>(rows: &[T]) -> String {
+    let line = |fields: Vec<String>| {
+        let fields: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+        fields.join(",") + "\n"
+    };
+    let mut csv = line(T::header().iter().map(|name| name.to_string()).collect());
+    for row in rows {
+        csv.push_str(&line(row.values()));
+    }
+    csv
+}
+
+/// Quote a CSV field if needed, doubling the quotes that it contains.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// The exported statistics of a mined block.
+#[derive(Debug, Serialize)]
+pub struct BlockRow {
+    pub height: usize,
+    pub hash: String,
+    pub miner: Address,
+    /// When the block was mined, in milliseconds since the Unix epoch.
+    pub timestamp: u64,
+    pub transactions: usize,
+    pub difficulty: u32,
+    /// How long it took for 95% of the nodes that adopted the block to do so, in milliseconds.
+    pub propagation_p95_millis: Option<u64>,
+}
+
+impl CsvRow for BlockRow {
+    fn header() -> &'static [&'static str] {
+        &[
+            "height",
+            "hash",
+            "miner",
+            "timestamp",
+            "transactions",
+            "difficulty",
+            "propagation_p95_millis",
+        ]
+    }
+
+    fn values(&self) -> Vec<String> {
+        vec![
+            self.height.to_string(),
+            self.hash.clone(),
+            self.miner.to_string(),
+            self.timestamp.to_string(),
+            self.transactions.to_string(),
+            self.difficulty.to_string(),
+            self.propagation_p95_millis
+                .map(|delay| delay.to_string())
+                .unwrap_or_default(),
+        ]
+    }
+}
+
+/// The exported statistics of a node.
+#[derive(Debug, Serialize)]
+pub struct NodeRow {
+    pub address: Address,
+    pub height: usize,
+    pub last_block_hash: String,
+    pub mempool_length: usize,
+    pub mined_blocks: usize,
+    pub is_synced: bool,
+}
+
+impl CsvRow for NodeRow {
+    fn header() -> &'static [&'static str] {
+        &[
+            "address",
+            "height",
+            "last_block_hash",
+            "mempool_length",
+            "mined_blocks",
+            "is_synced",
+        ]
+    }
+
+    fn values(&self) -> Vec<String> {
+        vec![
+            self.address.to_string(),
+            self.height.to_string(),
+            self.last_block_hash.clone(),
+            self.mempool_length.to_string(),
+            self.mined_blocks.to_string(),
+            self.is_synced.to_string(),
+        ]
+    }
+}
+
+/// A summary of the intervals between mined blocks and of the mining difficulty over time.
+#[derive(Debug, Serialize)]
+pub struct BlockTimes {
+    /// How many blocks have been mined.
+    pub mined_blocks: usize,
+    /// The average time between two consecutive mined blocks, in milliseconds.
+    pub mean_interval_millis: f64,
+    /// The histogram of the time between two consecutive mined blocks.
+    pub interval_histogram: Vec<HistogramBucket>,
+    /// The mining difficulty of the first block mined at each height.
+    pub difficulty_by_height: Vec<HeightDifficulty>,
+}
+
+/// A bucket of a histogram, counting the values in `[from, to)`.
+#[derive(Debug, Eq, PartialEq, Serialize)]
+pub struct HistogramBucket {
+    pub from: u64,
+    pub to: u64,
+    pub count: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HeightDifficulty {
+    pub height: usize,
+    pub difficulty: u32,
+}
+
+impl BlockTimes {
+    fn new(mined_blocks: &[MinedBlock], bucket_millis: u64) -> Option<Self> {
+        let intervals: Vec<u64> = mined_blocks
+            .windows(2)
+            .map(|pair| pair[1].timestamp.saturating_sub(pair[0].timestamp))
+            .collect();
+        let mean_interval_millis = if intervals.is_empty() {
+            0.0
+        } else {
+            intervals.iter().sum::<u64>() as f64 / intervals.len() as f64
+        };
+
+        let mut difficulty_by_height = BTreeMap::new();
+        for block in mined_blocks {
+            difficulty_by_height
+                .entry(block.height)
+                .or_insert(block.difficulty);
+        }
+
+        Some(BlockTimes {
+            mined_blocks: mined_blocks.len(),
+            mean_interval_millis,
+            interval_histogram: histogram(&intervals, bucket_millis)?,
+            difficulty_by_height: difficulty_by_height
+                .into_iter()
+                .map(|(height, difficulty)| HeightDifficulty { height, difficulty })
+                .collect(),
+        })
+    }
+}
+
+/// How the coins are distributed among the addresses of a blockchain.
+#[derive(Debug, Serialize)]
+pub struct WealthDistribution {
+    /// The height of the last block of the blockchain.
+    pub height: usize,
+    /// How many addresses ever held coins.
+    pub addresses: usize,
+    /// The Gini coefficient of the balances: 0 when all addresses hold the same amount, close to 1
+    /// when a single address holds everything.
+    pub gini: f64,
+    /// The fraction of the coins held by the richest 10% of the addresses.
+    pub top_10_percent_share: f64,
+    /// The histogram of the balances, in coins.
+    pub balance_histogram: Vec<HistogramBucket>,
+    /// The Gini coefficient and the share of the richest addresses every few blocks, from the
+    /// genesis to the last block.
+    pub samples: Vec<WealthSample>,
+}
+
+/// The concentration of the coins at a height of the blockchain.
+#[derive(Debug, Serialize)]
+pub struct WealthSample {
+    pub height: usize,
+    pub gini: f64,
+    pub top_10_percent_share: f64,
+}
+
+impl WealthDistribution {
+    /// Analyze the balances of a blockchain, replaying its blocks to sample the distribution every
+    /// `sample_blocks` blocks. Returns `None` if the histogram of the balances would have more
+    /// than `STATS_MAX_HISTOGRAM_BUCKETS` buckets of `bucket_coins`.
+    pub fn new(blockchain: &BlockChain, bucket_coins: u64, sample_blocks: usize) -> Option<Self> {
+        let sample_blocks = sample_blocks.max(1);
+        let mut balance: HashMap<Address, Amount> =
+            HashMap::from([(Address::FAUCET, GENESIS_ALLOCATION)]);
+        let mut samples = vec![];
+        for (height, block) in blockchain.blocks().enumerate() {
+            if !block.is_genesis() {
+                for transaction in &block.transactions {
+                    let info = &transaction.info;
+                    let sender = balance.entry(info.sender).or_default();
+                    *sender = sender.saturating_sub(info.cost().unwrap_or(Amount::ZERO));
+                    if let Some(receiver) = info.receiver {
+                        let receiver = balance.entry(receiver).or_default();
+                        *receiver = receiver.saturating_add(info.amount);
+                    }
+                }
+                let miner = balance.entry(block.miner).or_default();
+                let reward = block.reward_with_base_fee(blockchain.base_fee_at(height));
+                *miner = miner.saturating_add(reward.unwrap_or(COINS_PER_MINED_BLOCK));
+            }
+            if height % sample_blocks == 0 || height == blockchain.height() {
+                let balances: Vec<u64> = balance.values().map(Amount::units).collect();
+                samples.push(WealthSample {
+                    height,
+                    gini: gini(&balances),
+                    top_10_percent_share: top_share(&balances, 10),
+                });
+            }
+        }
+        let balances: Vec<u64> = blockchain
+            .balance()
+            .values()
+            .map(|balance| balance.amount().units())
+            .collect();
+        let whole_coins: Vec<u64> = balances
+            .iter()
+            .map(|units| units / UNITS_PER_COIN)
+            .collect();
+        Some(WealthDistribution {
+            height: blockchain.height(),
+            addresses: balances.len(),
+            gini: gini(&balances),
+            top_10_percent_share: top_share(&balances, 10),
+            balance_histogram: histogram(&whole_coins, bucket_coins)?,
+            samples,
+        })
+    }
+}
+
+/// The Gini coefficient of some non-negative values, or zero if they sum to zero.
+pub fn gini(values: &[u64]) -> f64 {
+    let total: f64 = values.iter().map(|&value| value as f64).sum();
+    if total == 0.0 {
+        return 0.0;
+    }
+    let mut sorted = values.to_vec();
+    sorted.sort_unstable();
+    let count = sorted.len() as f64;
+    let weighted: f64 = sorted
+        .iter()
+        .enumerate()
+        .map(|(index, &value)| (index + 1) as f64 * value as f64)
+        .sum();
+    2.0 * weighted / (count * total) - (count + 1.0) / count
+}
+
+/// The fraction of the total held by the largest `percent`% of the values (at least one), or
+/// zero if they sum to zero.
+pub fn top_share(values: &[u64], percent: usize) -> f64 {
+    let total: u64 = values.iter().sum();
+    if total == 0 {
+        return 0.0;
+    }
+    let mut sorted = values.to_vec();
+    sorted.sort_unstable_by(|a, b| b.cmp(a));
+    let top_count = (values.len() * percent).div_ceil(100).max(1);
+    sorted[..top_count].iter().sum::<u64>() as f64 / total as f64
+}
+
+/// Count the values in consecutive buckets of the given width, from zero to the maximum value.
+/// Returns `None` if that takes more than `STATS_MAX_HISTOGRAM_BUCKETS` buckets.
+pub fn histogram(values: &[u64], bucket_width: u64) -> Option<Vec<HistogramBucket>> {
+    let bucket_width = bucket_width.max(1);
+    let Some(&max_value) = values.iter().max() else {
+        return Some(vec![]);
+    };
+    if max_value / bucket_width >= STATS_MAX_HISTOGRAM_BUCKETS {
+        return None;
+    }
+    let mut buckets: Vec<HistogramBucket> = (0..=max_value / bucket_width)
+        .map(|index| HistogramBucket {
+            from: index * bucket_width,
+            to: (index + 1).saturating_mul(bucket_width),
+            count: 0,
+        })
+        .collect();
+    for &value in values {
+        buckets[(value / bucket_width) as usize].count += 1;
+    }
+    Some(buckets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn timeline_records_reorgs() {
+        let stats = Stats::new();
+        let node = Address::new(1);
+        stats
+            .record_world_event(WorldEvent::NodeAdded { node })
+            .await;
+        stats.record_reorg(node, 2).await;
+        let timeline = serde_json::to_value(stats.timeline().await).unwrap();
+        assert_eq!(timeline[0]["type"], "node_added");
+        assert_eq!(timeline[1]["type"], "reorg");
+        assert_eq!(timeline[1]["depth"], 2);
+        assert!(timeline[0]["timestamp"].as_u64() <= timeline[1]["timestamp"].as_u64());
+    }
+
+    #[tokio::test]
+    async fn forget_the_oldest_records() {
+        let stats = Stats::new();
+        let node = Address::new(1);
+        for depth in 0..STATS_MAX_REORGS + 1 {
+            stats.record_reorg(node, depth).await;
+        }
+        let reorgs = stats.reorgs().await;
+        assert_eq!(reorgs.len(), STATS_MAX_REORGS);
+        assert_eq!(reorgs[0].depth, 1);
+        assert_eq!(stats.timeline().await.len(), STATS_MAX_TIMELINE_ENTRIES);
+
+        let block = |nonce| Block::new(vec![], Block::genesis().hash(), node, nonce);
+        for nonce in 0..STATS_MAX_MINED_BLOCKS as u64 + 1 {
+            stats.record_mined_block(&block(nonce), 1, 0).await;
+        }
+        let (first, second) = (block(0).hash(), block(1).hash());
+        stats
+            .record_adopted_blocks(Address::new(2), &[first.clone(), second.clone()])
+            .await;
+        assert_eq!(stats.block_rows().await.len(), STATS_MAX_MINED_BLOCKS);
+        let adoptions = stats.adoptions.read().await;
+        assert_eq!(adoptions.len(), STATS_MAX_MINED_BLOCKS);
+        assert!(!adoptions.contains_key(&first));
+        assert_eq!(adoptions[&second].len(), 1);
+    }
+
+    #[tokio::test]
+    async fn record_tip_changes() {
+        let stats = Stats::new();
+        let (alice, bob) = (Address::new(1), Address::new(2));
+        let genesis_hash = Block::genesis().hash();
+        let fork = Block::new(vec![], genesis_hash.clone(), alice, 0).hash();
+        stats
+            .record_tips(vec![
+                (alice, 0, genesis_hash.clone()),
+                (bob, 0, genesis_hash.clone()),
+            ])
+            .await;
+        stats
+            .record_tips(vec![
+                (alice, 1, fork.clone()),
+                (bob, 0, genesis_hash.clone()),
+            ])
+            .await;
+        let tips = stats.tips(None, None).await;
+        assert_eq!(tips[&alice].len(), 2);
+        assert_eq!(tips[&alice][1].hash, fork);
+        // The tip of bob did not change
+        assert_eq!(tips[&bob].len(), 1);
+        let since = tips[&alice][1].timestamp + 1;
+        let tips_of_alice = stats.tips(Some(alice), Some(since)).await;
+        assert_eq!(tips_of_alice.keys().collect::<Vec<_>>(), [&alice]);
+        // The sample before `since` tells the tip at that time
+        assert_eq!(tips_of_alice[&alice], tips[&alice][1..]);
+
+        let restored = Stats::new();
+        restored
+            .record_tips(vec![(alice, 2, genesis_hash.clone())])
+            .await;
+        restored
+            .restore_tips(
+                tips.into_iter()
+                    .map(|(node, samples)| (node, samples.into()))
+                    .collect(),
+            )
+            .await;
+        let heights: Vec<usize> = restored.tips(None, None).await[&alice]
+            .iter()
+            .map(|sample| sample.height)
+            .collect();
+        assert_eq!(heights, vec![0, 1, 2]);
+
+        // The samples of the removed nodes are dropped.
+        restored.forget_tips(alice).await;
+        assert!(!restored.tips(None, None).await.contains_key(&alice));
+    }
+
+    #[tokio::test]
+    async fn forget_the_oldest_tip_samples() {
+        let stats = Stats::new();
+        let alice = Address::new(1);
+        let genesis_hash = Block::genesis().hash();
+        for height in 0..STATS_MAX_TIP_SAMPLES + 2 {
+            stats
+                .record_tips(vec![(alice, height, genesis_hash.clone())])
+                .await;
+        }
+        let tips = stats.tips(None, None).await;
+        assert_eq!(tips[&alice].len(), STATS_MAX_TIP_SAMPLES);
+        assert_eq!(tips[&alice][0].height, 2);
+    }
+
+    #[tokio::test]
+    async fn measure_confirmations_by_lane() {
+        let stats = Stats::new();
+        let (miner, receiver) = (Address::new(1), Address::new(2));
+        let genesis = Block::genesis();
+        let transfer = |coins| {
+            BlockTransaction::new(
+                genesis.hash(),
+                Transaction::new(miner, receiver, Amount::from_coins(coins)),
+            )
+        };
+        let (normal, priority, other) = (transfer(1), transfer(2), transfer(3));
+        stats
+            .record_demo_transaction(normal.id, DemoLane::Normal)
+            .await;
+        stats
+            .record_demo_transaction(priority.id, DemoLane::Priority)
+            .await;
+        let block = Block::new(vec![priority, other], genesis.hash(), miner, 0);
+        stats.record_mined_block(&block, 1, 0).await;
+
+        // Only the first block that includes a transaction confirms it
+        stats.record_mined_block(&block, 1, 0).await;
+        let latencies = stats.confirmation_latencies().await;
+        let normal = &latencies[&DemoLane::Normal];
+        assert_eq!((normal.confirmed, normal.pending), (0, 1));
+        assert_eq!(normal.p50_millis, None);
+        let priority = &latencies[&DemoLane::Priority];
+        assert_eq!((priority.confirmed, priority.pending), (1, 0));
+        assert!(priority.p95_millis.is_some());
+        // The difficulty is the one of the blockchain of the miner
+        assert_eq!(stats.block_rows().await[0].difficulty, 0);
+    }
+
+    #[tokio::test]
+    async fn forget_the_oldest_pending_demo_transactions() {
+        let stats = Stats::new();
+        let oldest = TransactionId::new_random();
+        stats
+            .record_demo_transaction(oldest, DemoLane::Normal)
+            .await;
+        tokio::time::sleep(Duration::from_millis(2)).await;
+        for _ in 0..STATS_MAX_PENDING_DEMO_TRANSACTIONS {
+            stats
+                .record_demo_transaction(TransactionId::new_random(), DemoLane::Normal)
+                .await;
+        }
+        let pending = stats.pending_demo_transactions.read().await;
+        assert_eq!(pending.len(), STATS_MAX_PENDING_DEMO_TRANSACTIONS);
+        assert!(!pending.contains_key(&oldest));
+    }
+
+    #[test]
+    fn quote_csv_fields() {
+        struct Row(&'static str);
+        impl CsvRow for Row {
+            fn header() -> &'static [&'static str] {
+                &["label", "count"]
+            }
+
+            fn values(&self) -> Vec<String> {
+                vec![self.0.to_string(), "1".to_string()]
+            }
+        }
+        let rows = [
+            Row("plain"),
+            Row("a,b"),
+            Row("say \"hi\""),
+            Row("two\nlines"),
+        ];
+        assert_eq!(
+            to_csv(&rows),
+            "label,count\nplain,1\n\"a,b\",1\n\"say \"\"hi\"\"\",1\n\"two\nlines\",1\n"
+        );
+    }
+
+    #[test]
+    fn histogram_buckets() {
+        let buckets = histogram(&[0, 5, 10, 25], 10).unwrap();
+        assert_eq!(
+            buckets,
+            vec![
+                HistogramBucket {
+                    from: 0,
+                    to: 10,
+                    count: 2
+                },
+                HistogramBucket {
+                    from: 10,
+                    to: 20,
+                    count: 1
+                },
+                HistogramBucket {
+                    from: 20,
+                    to: 30,
+                    count: 1
+                },
+            ]
+        );
+        assert_eq!(histogram(&[], 10), Some(vec![]));
+
+        // The bucket width bounds the number of buckets.
+        let max_buckets = STATS_MAX_HISTOGRAM_BUCKETS;
+        assert_eq!(
+            histogram(&[max_buckets - 1], 1).map(|buckets| buckets.len()),
+            Some(max_buckets as usize)
+        );
+        assert_eq!(histogram(&[max_buckets], 1), None);
+        assert_eq!(histogram(&[u64::MAX], 0), None);
+        let buckets = histogram(&[u64::MAX], u64::MAX).unwrap();
+        assert_eq!(
+            buckets.last().map(|bucket| (bucket.to, bucket.count)),
+            Some((u64::MAX, 1))
+        );
+    }
+
+    #[test]
+    fn wealth_concentration() {
+        assert_eq!(gini(&[]), 0.0);
+        assert_eq!(gini(&[5, 5, 5, 5]), 0.0);
+        assert_eq!(gini(&[0, 0, 0, 100]), 0.75);
+        assert_eq!(top_share(&[0, 0, 0, 100], 10), 1.0);
+        let values: Vec<u64> = (1..=20).collect();
+        assert_eq!(top_share(&values, 10), (19 + 20) as f64 / 210.0);
+
+        // The faucet holds the million coins of the genesis, which buckets of one coin cannot
+        // count.
+        let blockchain = BlockChain::with_difficulty(0);
+        let distribution = WealthDistribution::new(&blockchain, 1000, 10).unwrap();
+        assert_eq!(distribution.balance_histogram.len(), 1001);
+        assert!(WealthDistribution::new(&blockchain, 1, 10).is_none());
+    }
+
+    #[test]
+    fn nearest_rank_percentile() {
+        assert_eq!(percentile(&mut [], 95), None);
+        assert_eq!(percentile(&mut [7], 95), Some(7));
+        let mut values: Vec<u64> = (1..=100).rev().collect();
+        assert_eq!(percentile(&mut values, 95), Some(95));
+        assert_eq!(percentile(&mut values, 50), Some(50));
+    }
+}
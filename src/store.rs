@@ -0,0 +1,574 @@
+use crate::encoding::{Decode, DecodeError, Decoder, Encode, Encoder};
+use crate::prelude::*;
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+#[cfg(feature = "runtime")]
+use std::sync::atomic::{AtomicU64, Ordering};
+#[cfg(feature = "runtime")]
+use std::sync::Arc;
+#[cfg(feature = "runtime")]
+use tokio::sync::{mpsc, oneshot};
+
+/// A durable copy of the blocks of a blockchain at heights 1 to `len`, i.e. after the genesis
+/// block, and of its balances at the last stored block. `BlockChain::persist` keeps it in sync
+/// with a blockchain.
+pub trait BlockStore: std::fmt::Debug + Send + Sync {
+    /// How many blocks are stored.
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The hash of the stored block at a height, from 1 to `len`.
+    fn hash_at(&self, height: usize) -> Option<&BlockHash>;
+
+    /// Store a block at the next height, returning once it is durable.
+    fn append(&mut self, block: &Block) -> io::Result<()>;
+
+    /// Drop the stored blocks above a height.
+    fn truncate(&mut self, height: usize) -> io::Result<()>;
+
+    /// Read the stored blocks, from height 1.
+    fn blocks(&self) -> io::Result<Vec<Block>>;
+
+    /// Store the balances of the blockchain, returning once they are durable.
+    fn store_balances(&mut self, balances: &StoredBalances) -> io::Result<()>;
+
+    /// Read the stored balances, if any were stored.
+    fn balances(&self) -> io::Result<Option<StoredBalances>>;
+}
+
+/// The balances of a blockchain after one of its blocks, stored with its blocks so that a
+/// blockchain rebuilt from the stored blocks can be checked against them. Addresses without coins
+/// are left out.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct StoredBalances {
+    pub height: usize,
+    pub hash: BlockHash,
+    pub balances: BTreeMap<Address, Amount>,
+}
+
+impl Encode for StoredBalances {
+    fn encode_into(&self, encoder: &mut Encoder) {
+        encoder.u64(self.height as u64);
+        self.hash.encode_into(encoder);
+        let count = u32::try_from(self.balances.len()).expect("Too many balances to be stored");
+        encoder.u32(count);
+        for (address, amount) in &self.balances {
+            address.encode_into(encoder);
+            encoder.u64(amount.units());
+        }
+    }
+}
+
+impl Decode for StoredBalances {
+    fn decode_from(decoder: &mut Decoder) -> Result<Self, DecodeError> {
+        let height =
+            usize::try_from(decoder.u64()?).map_err(|_| DecodeError::InvalidField("height"))?;
+        let hash = BlockHash::decode_from(decoder)?;
+        let mut balances = BTreeMap::new();
+        for _ in 0..decoder.u32()? {
+            let address = Address::decode_from(decoder)?;
+            balances.insert(address, Amount::from_units(decoder.u64()?));
+        }
+        Ok(StoredBalances {
+            height,
+            hash,
+            balances,
+        })
+    }
+}
+
+/// A block store in a single file, to which each block is appended as a `u32` little-endian
+/// length followed by the binary encoding of the block. Appending a block does not rewrite the
+/// previous ones, and the file is flushed to the disk before returning. The balances are encoded
+/// in a file next to it, with the `balances` extension, which is replaced as a whole.
+#[derive(Debug)]
+pub struct FileBlockStore {
+    path: PathBuf,
+    /// The offset in the file of the end of each stored block.
+    ends: Vec<u64>,
+    /// The hash of each stored block.
+    hashes: Vec<BlockHash>,
+}
+
+impl FileBlockStore {
+    /// Open the store in a file, which is created when the first block is appended. A block cut
+    /// short at the end of the file, e.g. by a crash while appending it, is dropped.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut store = FileBlockStore {
+            path: path.to_path_buf(),
+            ends: vec![],
+            hashes: vec![],
+        };
+        let bytes = match std::fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(store),
+            Err(err) => return Err(err),
+        };
+        let mut end = 0;
+        for block in decode_blocks(&bytes) {
+            let (block, block_end) = block.map_err(|err| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "{}: block at height {}: {err}",
+                        path.display(),
+                        store.len() + 1
+                    ),
+                )
+            })?;
+            end = block_end;
+            store.hashes.push(block.hash());
+            store.ends.push(end as u64);
+        }
+        if end < bytes.len() {
+            warn!(
+                "Dropping the last {} bytes of {}, which do not hold a whole block",
+                bytes.len() - end,
+                path.display()
+            );
+            store.truncate(store.len())?;
+        }
+        Ok(store)
+    }
+}
+
+impl BlockStore for FileBlockStore {
+    fn len(&self) -> usize {
+        self.hashes.len()
+    }
+
+    fn hash_at(&self, height: usize) -> Option<&BlockHash> {
+        self.hashes.get(height.checked_sub(1)?)
+    }
+
+    fn append(&mut self, block: &Block) -> io::Result<()> {
+        let encoded = block.encode();
+        let length = u32::try_from(encoded.len()).expect("Block too large to be stored");
+        let mut record = Vec::with_capacity(4 + encoded.len());
+        record.extend_from_slice(&length.to_le_bytes());
+        record.extend_from_slice(&encoded);
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        let start = self.ends.last().copied().unwrap_or(0);
+        if let Err(err) = file.write_all(&record).and_then(|()| file.sync_data()) {
+            // Drop the part of the block that was written, so that the next one follows the
+            // previous whole block.
+            let _ = file.set_len(start);
+            return Err(err);
+        }
+        let end = start + record.len() as u64;
+        self.ends.push(end);
+        self.hashes.push(block.hash());
+        Ok(())
+    }
+
+    fn truncate(&mut self, height: usize) -> io::Result<()> {
+        let height = height.min(self.len());
+        let end = match height {
+            0 => 0,
+            _ => self.ends[height - 1],
+        };
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&self.path)?;
+        file.set_len(end)?;
+        file.sync_data()?;
+        self.ends.truncate(height);
+        self.hashes.truncate(height);
+        Ok(())
+    }
+
+    fn store_balances(&mut self, balances: &StoredBalances) -> io::Result<()> {
+        let path = self.path.with_extension("balances");
+        let temporary_path = self.path.with_extension("balances.tmp");
+        let mut file = std::fs::File::create(&temporary_path)?;
+        file.write_all(&balances.encode())?;
+        file.sync_data()?;
+        std::fs::rename(temporary_path, path)
+    }
+
+    fn balances(&self) -> io::Result<Option<StoredBalances>> {
+        let path = self.path.with_extension("balances");
+        let bytes = match std::fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        StoredBalances::decode(&bytes).map(Some).map_err(|err| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {err}", path.display()),
+            )
+        })
+    }
+
+    fn blocks(&self) -> io::Result<Vec<Block>> {
+        let bytes = match std::fs::read(&self.path) {
+            Ok(bytes) => bytes,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => vec![],
+            Err(err) => return Err(err),
+        };
+        decode_blocks(&bytes)
+            .take(self.len())
+            .map(|block| {
+                block
+                    .map(|(block, _)| block)
+                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+            })
+            .collect()
+    }
+}
+
+/// A change of a block store: keep the blocks stored up to `height`, store `blocks` after them,
+/// then the `balances` after the last block.
+#[derive(Debug)]
+pub struct StoreUpdate {
+    pub height: usize,
+    pub blocks: Vec<Block>,
+    pub balances: StoredBalances,
+}
+
+impl StoreUpdate {
+    /// Apply the update to a store. The stored blocks above `height` that are also in `blocks` are
+    /// kept rather than written again. Fails if the store has fewer than `height` blocks.
+    pub fn apply(&self, store: &mut dyn BlockStore) -> io::Result<()> {
+        if store.len() < self.height {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "the store has {} blocks, fewer than the {} to keep",
+                    store.len(),
+                    self.height
+                ),
+            ));
+        }
+        let mut kept = store.len().min(self.height + self.blocks.len());
+        while kept > self.height
+            && store.hash_at(kept) != Some(&self.blocks[kept - self.height - 1].hash())
+        {
+            kept -= 1;
+        }
+        if kept < store.len() {
+            store.truncate(kept)?;
+        }
+        for block in &self.blocks[kept - self.height..] {
+            store.append(block)?;
+        }
+        store.store_balances(&self.balances)
+    }
+}
+
+#[cfg(feature = "runtime")]
+/// Writes the blocks of a node to a store in a task of its own, so that the node does not wait for
+/// the disk while it holds its lock. After a failure, which is sent on the event bus, the task
+/// ignores the blocks sent until the node sends them all again, after
+/// `PERSISTENCE_STORE_RETRY_INTERVAL`.
+#[derive(Debug)]
+pub struct BlockStoreWriter {
+    /// The hashes of the blocks sent to the task, from height 1.
+    sent: Vec<BlockHash>,
+    /// How many times the task had failed when the blocks were sent.
+    failures: u64,
+    /// How many times the task failed.
+    task_failures: Arc<AtomicU64>,
+    commands: mpsc::UnboundedSender<WriterCommand>,
+}
+
+#[cfg(feature = "runtime")]
+#[derive(Debug)]
+enum WriterCommand {
+    /// Apply an update, unless the task failed more than `failures` times.
+    Update { failures: u64, update: StoreUpdate },
+    /// Report when the previous commands are done.
+    Flush(oneshot::Sender<()>),
+}
+
+#[cfg(feature = "runtime")]
+impl BlockStoreWriter {
+    /// Start writing the blocks of a node to a store. Must be called within a Tokio runtime.
+    pub fn start(node: Address, store: Box<dyn BlockStore>) -> Self {
+        let sent = (1..=store.len())
+            .filter_map(|height| store.hash_at(height).cloned())
+            .collect();
+        let (commands, receiver) = mpsc::unbounded_channel();
+        let task_failures = Arc::new(AtomicU64::new(0));
+        tokio::spawn(write_blocks(node, store, receiver, task_failures.clone()));
+        BlockStoreWriter {
+            sent,
+            failures: 0,
+            task_failures,
+            commands,
+        }
+    }
+
+    /// Send to the task the blocks that changed in a blockchain since the last update.
+    pub fn update(&mut self, blockchain: &BlockChain) {
+        let failures = self.task_failures.load(Ordering::Acquire);
+        if failures != self.failures {
+            // The task ignores the blocks sent since its failure, which may not be stored.
+            self.failures = failures;
+            self.sent.clear();
+        }
+        let Some(update) = blockchain.store_update(&self.sent) else {
+            return;
+        };
+        self.sent.truncate(update.height);
+        self.sent
+            .extend(blockchain.hashes().skip(update.height + 1).cloned());
+        let _ = self
+            .commands
+            .send(WriterCommand::Update { failures, update });
+    }
+
+    /// Wait until the task has written the blocks sent so far, or failed to.
+    pub fn flushed(&self) -> impl std::future::Future<Output = ()> {
+        let (flushed, done) = oneshot::channel();
+        let _ = self.commands.send(WriterCommand::Flush(flushed));
+        async move {
+            let _ = done.await;
+        }
+    }
+}
+
+#[cfg(feature = "runtime")]
+/// Apply the updates sent by the node to its store, until the node is dropped.
+async fn write_blocks(
+    node: Address,
+    mut store: Box<dyn BlockStore>,
+    mut commands: mpsc::UnboundedReceiver<WriterCommand>,
+    failures: Arc<AtomicU64>,
+) {
+    while let Some(command) = commands.recv().await {
+        let update = match command {
+            WriterCommand::Update {
+                failures: sent_failures,
+                update,
+            } if sent_failures == failures.load(Ordering::Acquire) => update,
+            WriterCommand::Update { .. } => continue,
+            WriterCommand::Flush(flushed) => {
+                let _ = flushed.send(());
+                continue;
+            }
+        };
+        let applied = tokio::task::spawn_blocking(move || {
+            let result = update.apply(store.as_mut());
+            (store, result)
+        })
+        .await;
+        let Ok((returned_store, result)) = applied else {
+            error!("Node {node}: The task storing its blocks panicked, they are no longer stored");
+            return;
+        };
+        store = returned_store;
+        if let Err(err) = result {
+            error!(
+                "Node {node}: Failed to store its blocks, retrying in {PERSISTENCE_STORE_RETRY_INTERVAL:?}: {err}"
+            );
+            events().await.publish(Event::StoreFailed {
+                node,
+                reason: err.to_string(),
+            });
+            tokio::time::sleep(PERSISTENCE_STORE_RETRY_INTERVAL).await;
+            failures.fetch_add(1, Ordering::AcqRel);
+        }
+    }
+}
+
+/// Decode the whole blocks stored in the bytes of a file, with the offset of the end of each one.
+/// Stops at the first block cut short.
+fn decode_blocks(bytes: &[u8]) -> impl Iterator<Item = Result<(Block, usize), DecodeError>> + '_ {
+    let mut offset = 0;
+    std::iter::from_fn(move || {
+        let length = bytes.get(offset..offset + 4)?;
+        let length = u32::from_le_bytes(length.try_into().unwrap()) as usize;
+        let encoded = bytes.get(offset + 4..offset + 4 + length)?;
+        offset += 4 + length;
+        Some(Block::decode(encoded).map(|block| (block, offset)))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn store_blocks_in_a_file() {
+        let path = std::env::temp_dir().join(format!("smallchain-store-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut blockchain = BlockChain::with_difficulty(0);
+        for _ in 0..3 {
+            let block = attempt_mining_block_with_difficulty(
+                blockchain.last_hash().clone(),
+                Address::new(1),
+                vec![],
+                0..1,
+                0,
+            )
+            .unwrap();
+            blockchain.append_block(block).unwrap();
+        }
+
+        let mut store = FileBlockStore::open(&path).unwrap();
+        assert!(store.is_empty());
+        blockchain.persist(&mut store).unwrap();
+        assert_eq!(store.len(), 3);
+        assert_eq!(store.hash_at(3), Some(blockchain.last_hash()));
+        assert_eq!(
+            store.balances().unwrap(),
+            Some(blockchain.stored_balances())
+        );
+
+        // A reorganization only rewrites the blocks that changed.
+        blockchain.pop_block().unwrap();
+        let block = attempt_mining_block_with_difficulty(
+            blockchain.last_hash().clone(),
+            Address::new(2),
+            vec![],
+            0..1,
+            0,
+        )
+        .unwrap();
+        blockchain.append_block(block).unwrap();
+        blockchain.persist(&mut store).unwrap();
+        let stored: Vec<Block> = blockchain.blocks().skip(1).cloned().collect();
+        assert_eq!(store.blocks().unwrap(), stored);
+        assert_eq!(
+            store.balances().unwrap(),
+            Some(blockchain.stored_balances())
+        );
+
+        // The store survives a restart, even if the last block was only partially written.
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&[100, 0, 0, 0, 4]).unwrap();
+        drop(file);
+        let mut store = FileBlockStore::open(&path).unwrap();
+        assert_eq!(store.blocks().unwrap(), stored);
+        assert_eq!(store.hash_at(3), Some(blockchain.last_hash()));
+        assert_eq!(
+            store.balances().unwrap(),
+            Some(blockchain.stored_balances())
+        );
+        store.truncate(1).unwrap();
+        assert_eq!(FileBlockStore::open(&path).unwrap().len(), 1);
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(path.with_extension("balances")).unwrap();
+    }
+
+    /// A store in memory, whose blocks are shared with the test, and whose appends fail while
+    /// `failing_appends` is positive.
+    #[derive(Debug, Default)]
+    struct MemoryStore {
+        hashes: Vec<BlockHash>,
+        blocks: Arc<std::sync::Mutex<Vec<Block>>>,
+        balances: Option<StoredBalances>,
+        failing_appends: Arc<AtomicU64>,
+    }
+
+    impl BlockStore for MemoryStore {
+        fn len(&self) -> usize {
+            self.hashes.len()
+        }
+
+        fn hash_at(&self, height: usize) -> Option<&BlockHash> {
+            self.hashes.get(height.checked_sub(1)?)
+        }
+
+        fn append(&mut self, block: &Block) -> io::Result<()> {
+            let failing =
+                self.failing_appends
+                    .fetch_update(Ordering::AcqRel, Ordering::Acquire, |left| {
+                        left.checked_sub(1)
+                    });
+            if failing.is_ok() {
+                return Err(io::Error::other("disk full"));
+            }
+            self.hashes.push(block.hash());
+            self.blocks.lock().unwrap().push(block.clone());
+            Ok(())
+        }
+
+        fn truncate(&mut self, height: usize) -> io::Result<()> {
+            self.hashes.truncate(height);
+            self.blocks.lock().unwrap().truncate(height);
+            Ok(())
+        }
+
+        fn blocks(&self) -> io::Result<Vec<Block>> {
+            Ok(self.blocks.lock().unwrap().clone())
+        }
+
+        fn store_balances(&mut self, balances: &StoredBalances) -> io::Result<()> {
+            self.balances = Some(balances.clone());
+            Ok(())
+        }
+
+        fn balances(&self) -> io::Result<Option<StoredBalances>> {
+            Ok(self.balances.clone())
+        }
+    }
+
+    fn mine(blockchain: &mut BlockChain, miner: u64) {
+        let block = attempt_mining_block_with_difficulty(
+            blockchain.last_hash().clone(),
+            Address::new(miner),
+            vec![],
+            0..1,
+            0,
+        )
+        .unwrap();
+        blockchain.append_block(block).unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn write_blocks_in_the_background() {
+        let store = MemoryStore::default();
+        let (blocks, failing_appends) = (store.blocks.clone(), store.failing_appends.clone());
+        let node = Address::new_random();
+        let mut writer = BlockStoreWriter::start(node, Box::new(store));
+        let mut blockchain = BlockChain::with_difficulty(0);
+        let stored = |blockchain: &BlockChain| -> Vec<Block> {
+            blockchain.blocks().skip(1).cloned().collect()
+        };
+        for _ in 0..3 {
+            mine(&mut blockchain, 1);
+        }
+        writer.update(&blockchain);
+        writer.flushed().await;
+        assert_eq!(*blocks.lock().unwrap(), stored(&blockchain));
+
+        // A reorganization only rewrites the blocks that changed.
+        blockchain.pop_block().unwrap();
+        mine(&mut blockchain, 2);
+        writer.update(&blockchain);
+        writer.flushed().await;
+        assert_eq!(*blocks.lock().unwrap(), stored(&blockchain));
+
+        // After a failure, the blocks are all sent again, and the missing ones are written.
+        let mut events = events().await.subscribe();
+        failing_appends.store(1, Ordering::Release);
+        mine(&mut blockchain, 1);
+        writer.update(&blockchain);
+        writer.flushed().await;
+        assert_eq!(blocks.lock().unwrap().len(), 3);
+        loop {
+            let record = events.recv().await.unwrap();
+            if record.event.node() == node {
+                assert_eq!(record.event.kind(), "store_failed");
+                break;
+            }
+        }
+        mine(&mut blockchain, 1);
+        writer.update(&blockchain);
+        writer.flushed().await;
+        assert_eq!(*blocks.lock().unwrap(), stored(&blockchain));
+    }
+}
@@ -0,0 +1,597 @@
+use crate::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+/// A check that a block must pass to be appended to a blockchain. The checks of a block run in the
+/// order of `BLOCK_RULES`, and stop at the first one that fails.
+pub trait BlockRule: Sync {
+    /// The name of the rule, with which it is skipped.
+    fn name(&self) -> &'static str;
+
+    /// Check a block on top of the last block of `blockchain`, logging why it is invalid.
+    fn check_block(&self, blockchain: &BlockChain, block: &Block) -> Result<(), ()>;
+}
+
+/// A check that a transaction must pass to be admitted in a mempool. The checks of a transaction
+/// run in the order of `TRANSACTION_RULES`, and stop at the first one that fails.
+pub trait TransactionRule: Sync {
+    /// The name of the rule, with which it is skipped.
+    fn name(&self) -> &'static str;
+
+    /// Check a transaction on top of the pending transactions of `mempool`.
+    fn check_transaction(
+        &self,
+        mempool: &MemPool,
+        transaction: &BlockTransaction,
+    ) -> Result<(), TxError>;
+}
+
+/// The pipeline of the checks of the blocks.
+pub static BLOCK_RULES: [&dyn BlockRule; 8] = [
+    &PrefixLink,
+    &TransactionIds,
+    &ProofOfWorkRule,
+    &Uniqueness,
+    &RuleCompliance,
+    &Weight,
+    &BaseFeeRule,
+    &Balances,
+];
+
+/// The pipeline of the checks of the transactions admitted in a mempool.
+pub static TRANSACTION_RULES: [&dyn TransactionRule; 8] = [
+    &PrefixLink,
+    &TransactionIds,
+    &Uniqueness,
+    &Capacity,
+    &RuleCompliance,
+    &BaseFeeRule,
+    &Dust,
+    &Balances,
+];
+
+/// The names of all the rules, of blocks and of transactions. New rules go at the end, so that
+/// the bits of `SkippedRules` keep their meaning.
+pub const RULE_NAMES: [&str; 10] = [
+    "prefix_link",
+    "proof_of_work",
+    "uniqueness",
+    "transaction_rules",
+    "weight",
+    "base_fee",
+    "balances",
+    "capacity",
+    "dust",
+    "transaction_id",
+];
+
+/// The name under which the nodes count the blocks rejected by their `ConsensusEngine`.
+pub const CONSENSUS_RULE: &str = "consensus";
+
+/// The rules that the nodes skip, e.g. to study what happens when they do not check the balances.
+/// Serialized as the list of their names.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "Vec<String>", into = "Vec<String>")]
+pub struct SkippedRules(u16);
+
+impl SkippedRules {
+    pub const NONE: SkippedRules = SkippedRules(0);
+
+    /// Whether the rule called `name` is skipped.
+    pub fn contains(&self, name: &str) -> bool {
+        RULE_NAMES
+            .iter()
+            .position(|&rule| rule == name)
+            .is_some_and(|index| self.0 & (1 << index) != 0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// A bit for each of the `RULE_NAMES`, set if the rule is skipped.
+    pub fn bits(&self) -> u16 {
+        self.0
+    }
+}
+
+impl TryFrom<Vec<String>> for SkippedRules {
+    type Error = String;
+
+    fn try_from(names: Vec<String>) -> Result<Self, Self::Error> {
+        let mut bits = 0;
+        for name in names {
+            let Some(index) = RULE_NAMES.iter().position(|&rule| rule == name) else {
+                return Err(format!(
+                    "unknown rule {name:?}, expected one of {}",
+                    RULE_NAMES.join(", ")
+                ));
+            };
+            bits |= 1 << index;
+        }
+        Ok(SkippedRules(bits))
+    }
+}
+
+impl From<SkippedRules> for Vec<String> {
+    fn from(skipped: SkippedRules) -> Self {
+        RULE_NAMES
+            .iter()
+            .filter(|&&name| skipped.contains(name))
+            .map(|name| name.to_string())
+            .collect()
+    }
+}
+
+/// Blocks extend the last block of the blockchain, and their transactions were created on top of
+/// it. Transactions extend the last block of the mempool.
+pub struct PrefixLink;
+
+impl BlockRule for PrefixLink {
+    fn name(&self) -> &'static str {
+        "prefix_link"
+    }
+
+    fn check_block(&self, blockchain: &BlockChain, block: &Block) -> Result<(), ()> {
+        if &block.prefix_hash != blockchain.last_hash() {
+            warn!("Tried to append a block with an invalid prefix");
+            return Err(());
+        }
+        if block
+            .transactions
+            .iter()
+            .any(|t| t.prefix_hash != block.prefix_hash)
+        {
+            warn!("Tried to append a block with a transaction with an invalid `prefix_hash`");
+            return Err(());
+        }
+        Ok(())
+    }
+}
+
+impl TransactionRule for PrefixLink {
+    fn name(&self) -> &'static str {
+        "prefix_link"
+    }
+
+    fn check_transaction(
+        &self,
+        mempool: &MemPool,
+        transaction: &BlockTransaction,
+    ) -> Result<(), TxError> {
+        if &transaction.prefix_hash != mempool.prefix_hash() {
+            warn!("Transaction {transaction} has a `prefix_hash` that is invalid for this mempool");
+            return Err(TxError::InvalidPrefix);
+        }
+        Ok(())
+    }
+}
+
+/// The identifiers of the transactions are derived from their content, so that a transaction has
+/// the same identifier on every node.
+pub struct TransactionIds;
+
+impl BlockRule for TransactionIds {
+    fn name(&self) -> &'static str {
+        "transaction_id"
+    }
+
+    fn check_block(&self, _: &BlockChain, block: &Block) -> Result<(), ()> {
+        if let Some(t) = block.transactions.iter().find(|t| !t.has_valid_id()) {
+            warn!("Tried to append a block with transaction {t}, whose identifier is invalid");
+            return Err(());
+        }
+        Ok(())
+    }
+}
+
+impl TransactionRule for TransactionIds {
+    fn name(&self) -> &'static str {
+        "transaction_id"
+    }
+
+    fn check_transaction(
+        &self,
+        _: &MemPool,
+        transaction: &BlockTransaction,
+    ) -> Result<(), TxError> {
+        if !transaction.has_valid_id() {
+            warn!("Transaction {transaction} has an identifier that does not match its content");
+            return Err(TxError::InvalidId);
+        }
+        Ok(())
+    }
+}
+
+/// The hash of the block has the leading zero bits required by the difficulty of the blockchain.
+pub struct ProofOfWorkRule;
+
+impl BlockRule for ProofOfWorkRule {
+    fn name(&self) -> &'static str {
+        "proof_of_work"
+    }
+
+    fn check_block(&self, blockchain: &BlockChain, block: &Block) -> Result<(), ()> {
+        if !block.meets_difficulty(blockchain.difficulty()) {
+            warn!("Tried to append an invalid block");
+            return Err(());
+        }
+        Ok(())
+    }
+}
+
+/// The transactions of a block have distinct identifiers, and a mempool admits a transaction once.
+pub struct Uniqueness;
+
+impl BlockRule for Uniqueness {
+    fn name(&self) -> &'static str {
+        "uniqueness"
+    }
+
+    fn check_block(&self, _: &BlockChain, block: &Block) -> Result<(), ()> {
+        let mut transaction_ids = HashSet::new();
+        if !block
+            .transactions
+            .iter()
+            .all(|t| transaction_ids.insert(t.id))
+        {
+            warn!("Tried to append a block with duplicate transaction ids");
+            return Err(());
+        }
+        Ok(())
+    }
+}
+
+impl TransactionRule for Uniqueness {
+    fn name(&self) -> &'static str {
+        "uniqueness"
+    }
+
+    fn check_transaction(
+        &self,
+        mempool: &MemPool,
+        transaction: &BlockTransaction,
+    ) -> Result<(), TxError> {
+        if mempool.contains_pending(&transaction.id) {
+            warn!("Transaction {transaction} is already in the mempool");
+            return Err(TxError::Duplicate);
+        }
+        Ok(())
+    }
+}
+
+/// The transactions satisfy the `TransactionRules` of the blockchain.
+pub struct RuleCompliance;
+
+impl BlockRule for RuleCompliance {
+    fn name(&self) -> &'static str {
+        "transaction_rules"
+    }
+
+    fn check_block(&self, blockchain: &BlockChain, block: &Block) -> Result<(), ()> {
+        for t in &block.transactions {
+            if let Err(err) = blockchain.rules().check(&t.info) {
+                warn!(
+                    "Tried to append a block with transaction {t}, which breaks the rules: {err}"
+                );
+                return Err(());
+            }
+        }
+        Ok(())
+    }
+}
+
+impl TransactionRule for RuleCompliance {
+    fn name(&self) -> &'static str {
+        "transaction_rules"
+    }
+
+    fn check_transaction(
+        &self,
+        mempool: &MemPool,
+        transaction: &BlockTransaction,
+    ) -> Result<(), TxError> {
+        if let Err(err) = mempool.rules().check(&transaction.info) {
+            warn!("Transaction {transaction} breaks the rules: {err}");
+            return Err(err);
+        }
+        Ok(())
+    }
+}
+
+/// The total weight of the transactions of a block does not exceed the maximum of the rules.
+pub struct Weight;
+
+impl BlockRule for Weight {
+    fn name(&self) -> &'static str {
+        "weight"
+    }
+
+    fn check_block(&self, blockchain: &BlockChain, block: &Block) -> Result<(), ()> {
+        let max_block_weight = blockchain.rules().max_block_weight;
+        if block.weight() > max_block_weight {
+            warn!(
+                "Tried to append a block with weight {}, more than {max_block_weight}",
+                block.weight()
+            );
+            return Err(());
+        }
+        Ok(())
+    }
+}
+
+/// With the base-fee policy, blocks are not too full and every transaction pays the base fee.
+pub struct BaseFeeRule;
+
+impl BlockRule for BaseFeeRule {
+    fn name(&self) -> &'static str {
+        "base_fee"
+    }
+
+    fn check_block(&self, blockchain: &BlockChain, block: &Block) -> Result<(), ()> {
+        let Some(base_fee) = blockchain.next_base_fee() else {
+            return Ok(());
+        };
+        if block.transactions.len() > BASE_FEE_MAX_TRANSACTIONS {
+            warn!(
+                "Tried to append a block with {} transactions, more than {BASE_FEE_MAX_TRANSACTIONS}",
+                block.transactions.len()
+            );
+            return Err(());
+        }
+        if let Some(t) = block.transactions.iter().find(|t| t.info.fee < base_fee) {
+            warn!("Tried to append a block with transaction {t}, below the base fee of {base_fee}");
+            return Err(());
+        }
+        Ok(())
+    }
+}
+
+impl TransactionRule for BaseFeeRule {
+    fn name(&self) -> &'static str {
+        "base_fee"
+    }
+
+    fn check_transaction(
+        &self,
+        mempool: &MemPool,
+        transaction: &BlockTransaction,
+    ) -> Result<(), TxError> {
+        if let Some(base_fee) = mempool
+            .base_fee()
+            .filter(|&base_fee| transaction.info.fee < base_fee)
+        {
+            warn!("Transaction {transaction} does not pay the base fee of {base_fee}");
+            return Err(TxError::BelowBaseFee {
+                fee: transaction.info.fee,
+                base_fee,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// The senders can afford their transactions, in order, and no balance overflows.
+pub struct Balances;
+
+impl BlockRule for Balances {
+    fn name(&self) -> &'static str {
+        "balances"
+    }
+
+    fn check_block(&self, blockchain: &BlockChain, block: &Block) -> Result<(), ()> {
+        let transactions = block.transactions.iter().map(|t| &t.info);
+        let mut balance = touched_balances(blockchain.balance(), transactions);
+        for t in &block.transactions {
+            if let Err(err) = apply_transaction(&mut balance, &t.info) {
+                warn!("Tried to append a block with invalid transaction {t}: {err}");
+                return Err(());
+            }
+        }
+        Ok(())
+    }
+}
+
+impl TransactionRule for Balances {
+    fn name(&self) -> &'static str {
+        "balances"
+    }
+
+    fn check_transaction(
+        &self,
+        mempool: &MemPool,
+        transaction: &BlockTransaction,
+    ) -> Result<(), TxError> {
+        let Some(cost) = transaction.info.cost() else {
+            warn!("The cost of transaction {transaction} overflows");
+            return Err(TxError::CostOverflow);
+        };
+        let sender_balance = mempool
+            .balance()
+            .get(&transaction.info.sender)
+            .map_or(Amount::ZERO, Balance::amount);
+        if sender_balance < cost {
+            warn!(
+                "Insufficient funds to transfer {} with a fee of {} from {}",
+                transaction.info.amount, transaction.info.fee, transaction.info.sender,
+            );
+            return Err(TxError::InsufficientFunds);
+        }
+        let mut balance = touched_balances(mempool.balance(), [&transaction.info]);
+        if let Err(err) = apply_transaction(&mut balance, &transaction.info) {
+            warn!("Transaction {transaction} cannot be applied: {err}");
+            return Err(TxError::BalanceOverflow);
+        }
+        Ok(())
+    }
+}
+
+/// A copy of the balances of the senders and the receivers of some transactions, to apply them
+/// without copying all the balances.
+fn touched_balances<'a>(
+    balance: &HashMap<Address, Balance>,
+    transactions: impl IntoIterator<Item = &'a Transaction>,
+) -> HashMap<Address, Balance> {
+    transactions
+        .into_iter()
+        .flat_map(|transaction| [Some(transaction.sender), transaction.receiver])
+        .flatten()
+        .filter_map(|address| Some((address, *balance.get(&address)?)))
+        .collect()
+}
+
+/// The mempool has room for the transaction.
+pub struct Capacity;
+
+impl TransactionRule for Capacity {
+    fn name(&self) -> &'static str {
+        "capacity"
+    }
+
+    fn check_transaction(
+        &self,
+        mempool: &MemPool,
+        transaction: &BlockTransaction,
+    ) -> Result<(), TxError> {
+        if mempool
+            .capacity()
+            .is_some_and(|capacity| mempool.len() >= capacity)
+        {
+            warn!("The mempool is full, so transaction {transaction} cannot be admitted");
+            return Err(TxError::MempoolFull);
+        }
+        Ok(())
+    }
+}
+
+/// The transaction satisfies the `TransactionPolicy` of the mempool.
+pub struct Dust;
+
+impl TransactionRule for Dust {
+    fn name(&self) -> &'static str {
+        "dust"
+    }
+
+    fn check_transaction(
+        &self,
+        mempool: &MemPool,
+        transaction: &BlockTransaction,
+    ) -> Result<(), TxError> {
+        if let Err(err) = mempool.policy().check(&transaction.info) {
+            warn!("Transaction {transaction} is dust: {err}");
+            return Err(err);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_skipped_rules() {
+        let skipped = SkippedRules::try_from(vec!["dust".to_string(), "balances".to_string()]);
+        let skipped = skipped.unwrap();
+        assert!(skipped.contains("balances") && skipped.contains("dust"));
+        assert!(!skipped.contains("proof_of_work"));
+        assert_eq!(Vec::<String>::from(skipped), ["balances", "dust"]);
+        assert!(SkippedRules::try_from(vec!["signatures".to_string()]).is_err());
+    }
+
+    #[test]
+    fn skip_the_balances_rule() {
+        let (miner, alice, bob) = (Address::new(1), Address::new(2), Address::new(3));
+        let mut blockchain = BlockChain::with_difficulty(0);
+        let overdraft = BlockTransaction::new(
+            blockchain.last_hash().clone(),
+            Transaction::new(alice, bob, Amount::from_coins(10)),
+        );
+
+        // The mempool and the blockchain reject the transaction, in the `balances` rule
+        let mempool = MemPool::new(&blockchain);
+        assert_eq!(
+            mempool.validate_transaction(&overdraft),
+            Err(("balances", TxError::InsufficientFunds))
+        );
+        let block = attempt_mining_block_with_difficulty(
+            blockchain.last_hash().clone(),
+            miner,
+            vec![overdraft.clone()],
+            0..1,
+            0,
+        )
+        .unwrap();
+        assert_eq!(blockchain.validate_block(&block), Err("balances"));
+
+        // Unless they skip it: the coins of bob are created
+        blockchain.set_rules(TransactionRules {
+            skipped: SkippedRules::try_from(vec!["balances".to_string()]).unwrap(),
+            ..TransactionRules::DEFAULT
+        });
+        let mut mempool = MemPool::new(&blockchain);
+        assert!(mempool.add_transaction(overdraft).is_ok());
+        assert_eq!(mempool.balance_of(bob), Amount::from_coins(10));
+        let balance = blockchain.balance().clone();
+        let audit = blockchain.audit_supply();
+        assert!(blockchain.append_block(block).is_ok());
+        assert_eq!(blockchain.balance_of(alice), Amount::ZERO);
+        assert_eq!(blockchain.balance_of(bob), Amount::from_coins(10));
+
+        // Popping the block removes the created coins, instead of refunding alice
+        assert!(blockchain.pop_block().is_some());
+        assert_eq!(blockchain.balance(), &balance);
+        assert_eq!(blockchain.audit_supply(), audit);
+        assert!(audit.consistent);
+    }
+
+    #[test]
+    fn stop_at_the_first_broken_rule() {
+        let miner = Address::new(1);
+        let mut blockchain = BlockChain::with_difficulty(0);
+        let block = attempt_mining_block_with_difficulty(
+            blockchain.last_hash().clone(),
+            miner,
+            vec![],
+            0..1,
+            0,
+        )
+        .unwrap();
+        let stale = block.clone();
+        blockchain.append_block(block).unwrap();
+        assert_eq!(blockchain.validate_block(&stale), Err("prefix_link"));
+
+        // A self transfer on top of the wrong block breaks the prefix link first
+        let self_transfer = BlockTransaction::new(
+            stale.prefix_hash.clone(),
+            Transaction::new(miner, miner, Amount::from_coins(1)),
+        );
+        assert_eq!(
+            MemPool::new(&blockchain).validate_transaction(&self_transfer),
+            Err(("prefix_link", TxError::InvalidPrefix))
+        );
+
+        // Moving it on top of the right block without deriving its identifier again breaks the
+        // identifier
+        let tampered = BlockTransaction {
+            prefix_hash: blockchain.last_hash().clone(),
+            ..self_transfer.clone()
+        };
+        assert_eq!(
+            MemPool::new(&blockchain).validate_transaction(&tampered),
+            Err(("transaction_id", TxError::InvalidId))
+        );
+        let mut block = stale;
+        block.prefix_hash = blockchain.last_hash().clone();
+        block.transactions = vec![tampered];
+        assert_eq!(blockchain.validate_block(&block), Err("transaction_id"));
+
+        // Once on top of the right block, the duplicate breaks the uniqueness before the rules
+        let self_transfer =
+            BlockTransaction::new(blockchain.last_hash().clone(), self_transfer.info);
+        block.transactions = vec![self_transfer.clone(), self_transfer.clone()];
+        assert_eq!(blockchain.validate_block(&block), Err("uniqueness"));
+        block.transactions = vec![self_transfer];
+        assert_eq!(blockchain.validate_block(&block), Err("transaction_rules"));
+    }
+}
@@ -0,0 +1,327 @@
+use crate::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{OnceCell, RwLock};
+
+/// The watch-only wallets: named sets of addresses whose balances and transactions are tracked,
+/// without holding their keys.
+pub struct Wallets {
+    wallets: RwLock<BTreeMap<String, BTreeSet<Address>>>,
+}
+
+static WALLETS: OnceCell<Wallets> = OnceCell::const_new();
+
+/// Get the singleton of the watch-only wallets.
+pub async fn wallets() -> &'static Wallets {
+    WALLETS.get_or_init(|| async { Wallets::new() }).await
+}
+
+/// A watch-only wallet.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Wallet {
+    pub name: String,
+    /// The watched addresses.
+    pub addresses: BTreeSet<Address>,
+}
+
+/// Whether a transaction of a wallet is waiting in a mempool or included in a block.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WalletTransactionStatus {
+    Pending,
+    Confirmed,
+}
+
+/// A transaction that moves coins from or to an address of a wallet.
+#[derive(Clone, Debug, Serialize)]
+pub struct WalletTransaction {
+    pub status: WalletTransactionStatus,
+    /// The height of the block that includes the transaction, if it is confirmed.
+    pub height: Option<usize>,
+    pub transaction: BlockTransaction,
+}
+
+/// The balance of an address of a wallet.
+#[derive(Clone, Debug, Serialize)]
+pub struct AddressBalance {
+    pub address: Address,
+    /// The balance in the blockchain.
+    pub confirmed: Amount,
+    /// The balance including the pending transactions.
+    pub pending: Amount,
+}
+
+/// The balances of the addresses of a wallet, in the blockchain and the mempool of a node.
+#[derive(Clone, Debug, Serialize)]
+pub struct WalletBalance {
+    pub name: String,
+    /// The node whose blockchain and mempool were queried.
+    pub node: Address,
+    /// The height of the blockchain of the node.
+    pub height: usize,
+    pub addresses: Vec<AddressBalance>,
+    pub confirmed: Amount,
+    pub pending: Amount,
+}
+
+impl Default for Wallets {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Wallets {
+    pub fn new() -> Self {
+        Wallets {
+            wallets: RwLock::new(BTreeMap::new()),
+        }
+    }
+
+    /// Start watching the addresses of a new wallet. Wallets with an empty or already used name,
+    /// or with too many addresses, are rejected, as well as new wallets beyond the maximum number.
+    pub async fn add(&self, wallet: Wallet) -> Result<(), ()> {
+        if wallet.name.is_empty() || wallet.addresses.len() > WALLET_MAX_ADDRESSES {
+            warn!(
+                "A wallet must have a name and at most {WALLET_MAX_ADDRESSES} addresses, not {}",
+                wallet.addresses.len()
+            );
+            return Err(());
+        }
+        let mut wallets = self.wallets.write().await;
+        if wallets.len() >= WALLETS_MAX_COUNT {
+            warn!("Cannot watch more than {WALLETS_MAX_COUNT} wallets");
+            return Err(());
+        }
+        if wallets.contains_key(&wallet.name) {
+            warn!("The wallet {} already exists", wallet.name);
+            return Err(());
+        }
+        info!(
+            "Watching the {} addresses of wallet {}",
+            wallet.addresses.len(),
+            wallet.name
+        );
+        wallets.insert(wallet.name, wallet.addresses);
+        Ok(())
+    }
+
+    /// Stop watching the addresses of a wallet.
+    pub async fn remove(&self, name: &str) -> Result<(), ()> {
+        if self.wallets.write().await.remove(name).is_none() {
+            warn!("Cannot find wallet {name}");
+            return Err(());
+        }
+        info!("Stopped watching wallet {name}");
+        Ok(())
+    }
+
+    pub async fn get(&self, name: &str) -> Option<Wallet> {
+        let wallets = self.wallets.read().await;
+        wallets.get(name).map(|addresses| Wallet {
+            name: name.to_string(),
+            addresses: addresses.clone(),
+        })
+    }
+
+    /// All the wallets, sorted by name.
+    pub async fn list(&self) -> Vec<Wallet> {
+        let wallets = self.wallets.read().await;
+        wallets
+            .iter()
+            .map(|(name, addresses)| Wallet {
+                name: name.clone(),
+                addresses: addresses.clone(),
+            })
+            .collect()
+    }
+
+    /// The `wallet_transaction` events to publish after an event, one for each wallet that watches
+    /// an address involved in a transaction accepted in a mempool or included in a mined block.
+    pub async fn notifications(&self, event: &Event) -> Vec<Event> {
+        let (node, transactions, status, height) = match event {
+            Event::TransactionAccepted { node, transaction } => (
+                *node,
+                std::slice::from_ref(transaction),
+                WalletTransactionStatus::Pending,
+                None,
+            ),
+            Event::BlockMined {
+                node,
+                height,
+                block,
+            } => (
+                *node,
+                block.transactions.as_slice(),
+                WalletTransactionStatus::Confirmed,
+                Some(*height),
+            ),
+            _ => return vec![],
+        };
+        let wallets = self.wallets.read().await;
+        let mut notifications = vec![];
+        for (name, addresses) in wallets.iter() {
+            for transaction in transactions {
+                if touches(addresses, transaction) {
+                    notifications.push(Event::WalletTransaction {
+                        wallet: name.clone(),
+                        node,
+                        transaction: WalletTransaction {
+                            status,
+                            height,
+                            transaction: transaction.clone(),
+                        },
+                    });
+                }
+            }
+        }
+        notifications
+    }
+}
+
+/// Whether a transaction moves coins from or to one of the addresses.
+fn touches(addresses: &BTreeSet<Address>, transaction: &BlockTransaction) -> bool {
+    addresses.contains(&transaction.info.sender)
+        || transaction
+            .info
+            .receiver
+            .is_some_and(|receiver| addresses.contains(&receiver))
+}
+
+impl Wallet {
+    /// The balances of the addresses of the wallet, according to a node.
+    pub fn balance(&self, node: &Node) -> WalletBalance {
+        let blockchain = node.blockchain();
+        let pending_balance = node.mempool().balance();
+        let addresses: Vec<AddressBalance> = self
+            .addresses
+            .iter()
+            .map(|&address| AddressBalance {
+                address,
+                confirmed: blockchain.balance_of(address),
+                pending: pending_balance
+                    .get(&address)
+                    .map_or(Amount::ZERO, Balance::amount),
+            })
+            .collect();
+        WalletBalance {
+            name: self.name.clone(),
+            node: node.address(),
+            height: blockchain.height(),
+            confirmed: addresses.iter().fold(Amount::ZERO, |sum, balance| {
+                sum.saturating_add(balance.confirmed)
+            }),
+            pending: addresses.iter().fold(Amount::ZERO, |sum, balance| {
+                sum.saturating_add(balance.pending)
+            }),
+            addresses,
+        }
+    }
+
+    /// The transactions of the addresses of the wallet, according to a node: the ones in its
+    /// blockchain, from the oldest to the most recent, followed by the ones in its mempool.
+    pub fn transactions(&self, node: &Node) -> Vec<WalletTransaction> {
+        let confirmed = node
+            .blockchain()
+            .blocks()
+            .enumerate()
+            .flat_map(|(height, block)| {
+                block
+                    .transactions
+                    .iter()
+                    .map(move |transaction| (height, transaction))
+            })
+            .filter(|(_, transaction)| touches(&self.addresses, transaction))
+            .map(|(height, transaction)| WalletTransaction {
+                status: WalletTransactionStatus::Confirmed,
+                height: Some(height),
+                transaction: transaction.clone(),
+            });
+        let pending = node
+            .mempool()
+            .transactions()
+            .iter()
+            .filter(|transaction| touches(&self.addresses, transaction))
+            .map(|transaction| WalletTransaction {
+                status: WalletTransactionStatus::Pending,
+                height: None,
+                transaction: transaction.clone(),
+            });
+        confirmed.chain(pending).collect()
+    }
+}
+
+/// Publish a `wallet_transaction` event each time that a transaction of a watched address is
+/// accepted in the mempool of a node or included in a mined block.
+pub async fn notify_wallets() {
+    let mut receiver = events().await.subscribe();
+    loop {
+        let record = match receiver.recv().await {
+            Ok(record) => record,
+            Err(RecvError::Lagged(skipped)) => {
+                warn!("The wallets missed {skipped} events");
+                continue;
+            }
+            Err(RecvError::Closed) => return,
+        };
+        for notification in wallets().await.notifications(&record.event).await {
+            events().await.publish(notification);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn notify_watched_transactions() {
+        let wallets = Wallets::new();
+        let watched = Address::new(2);
+        let wallet = Wallet {
+            name: "savings".to_string(),
+            addresses: BTreeSet::from([watched]),
+        };
+        assert!(wallets.add(wallet.clone()).await.is_ok());
+        assert!(wallets.add(wallet).await.is_err());
+
+        let node = Address::new(1);
+        let prefix_hash = Block::genesis().hash();
+        let transaction = |sender, receiver| {
+            BlockTransaction::new(
+                prefix_hash.clone(),
+                Transaction::new(sender, receiver, Amount::from_coins(1)),
+            )
+        };
+        let incoming = transaction(Address::new(3), watched);
+        let unrelated = transaction(Address::new(3), Address::new(4));
+        let accepted = Event::TransactionAccepted {
+            node,
+            transaction: incoming.clone(),
+        };
+        let notifications = wallets.notifications(&accepted).await;
+        assert_eq!(notifications.len(), 1);
+        assert!(matches!(
+            &notifications[0],
+            Event::WalletTransaction { wallet, transaction, .. }
+                if wallet == "savings" && transaction.status == WalletTransactionStatus::Pending
+        ));
+
+        let block = Block {
+            transactions: vec![unrelated, incoming],
+            ..Block::genesis()
+        };
+        let mined = Event::BlockMined {
+            node,
+            height: 1,
+            block,
+        };
+        let notifications = wallets.notifications(&mined).await;
+        assert_eq!(notifications.len(), 1);
+        assert!(notifications[0].touches(watched));
+
+        assert!(wallets.remove("savings").await.is_ok());
+        assert!(wallets.notifications(&mined).await.is_empty());
+    }
+}
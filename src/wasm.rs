@@ -0,0 +1,101 @@
+use crate::prelude::*;
+use std::str::FromStr;
+use wasm_bindgen::prelude::*;
+
+/// A local blockchain together with its pool of pending transactions, for in-browser demos.
+#[wasm_bindgen]
+pub struct Ledger {
+    blockchain: BlockChain,
+    mempool: MemPool,
+}
+
+/// A mined block, which can be appended to a ledger.
+#[wasm_bindgen]
+pub struct MinedBlock(Block);
+
+#[wasm_bindgen]
+impl MinedBlock {
+    /// The hash of the block.
+    pub fn hash(&self) -> String {
+        self.0.hash().to_string()
+    }
+
+    /// The number of transactions in the block.
+    pub fn transactions(&self) -> usize {
+        self.0.transactions.len()
+    }
+}
+
+#[wasm_bindgen]
+impl Ledger {
+    /// Create a ledger whose blocks must have `difficulty` leading zero bits. Use a low difficulty
+    /// (e.g., 8) to mine blocks instantly.
+    #[wasm_bindgen(constructor)]
+    pub fn new(difficulty: u32) -> Self {
+        let blockchain = BlockChain::with_difficulty(difficulty);
+        let mempool = MemPool::new(&blockchain);
+        Ledger {
+            blockchain,
+            mempool,
+        }
+    }
+
+    /// The height of the last block.
+    pub fn height(&self) -> usize {
+        self.blockchain.height()
+    }
+
+    /// The hash of the last block.
+    pub fn last_hash(&self) -> String {
+        self.blockchain.last_hash().to_string()
+    }
+
+    /// Add a pending transaction, which will be included in the next mined block. The amount is
+    /// in coins, e.g. `"1.5"`.
+    pub fn send(&mut self, sender: &str, receiver: &str, amount: &str) -> Result<(), JsError> {
+        let amount = Amount::from_str(amount)
+            .map_err(|err| JsError::new(&format!("Invalid amount {amount:?}: {err}")))?;
+        let transaction = BlockTransaction::new(
+            self.blockchain.last_hash().clone(),
+            Transaction::new(parse_address(sender)?, parse_address(receiver)?, amount),
+        );
+        self.mempool
+            .add_transaction(transaction)
+            .map_err(|err| JsError::new(&format!("Invalid transaction: {err}")))
+    }
+
+    /// Mine a block with the pending transactions, rewarding `miner`.
+    pub fn mine(&self, miner: &str) -> Result<MinedBlock, JsError> {
+        attempt_mining_block_with_difficulty(
+            self.blockchain.last_hash().clone(),
+            parse_address(miner)?,
+            self.mempool.select_transactions(&self.blockchain),
+            0..=u64::MAX,
+            self.blockchain.difficulty(),
+        )
+        .map(MinedBlock)
+        .ok_or_else(|| JsError::new("Failed to mine a block"))
+    }
+
+    /// Append a mined block, checking its validity.
+    pub fn append(&mut self, block: &MinedBlock) -> Result<(), JsError> {
+        self.blockchain
+            .append_block(block.0.clone())
+            .map_err(|rule| JsError::new(&format!("Invalid block, which breaks rule {rule}")))?;
+        self.mempool.reset(&self.blockchain);
+        Ok(())
+    }
+
+    /// The balance of an address, according to the blockchain, formatted in coins.
+    pub fn balance_of(&self, address: &str) -> Result<String, JsError> {
+        Ok(self
+            .blockchain
+            .balance_of(parse_address(address)?)
+            .to_string())
+    }
+}
+
+fn parse_address(raw_address: &str) -> Result<Address, JsError> {
+    Address::from_str(raw_address)
+        .map_err(|err| JsError::new(&format!("Invalid address {raw_address:?}: {err}")))
+}
@@ -1,14 +1,47 @@
 use crate::prelude::*;
+use crate::server::NodeListener;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::Duration;
 use std::{collections::HashMap, sync::Arc};
-use tokio::sync::OnceCell;
-use tokio::sync::RwLock;
+use tokio::sync::{Mutex, OnceCell};
+use tokio::sync::{OwnedRwLockWriteGuard, RwLock, RwLockReadGuard};
+use tracing::Instrument;
 
 /// The world that contains all nodes of the blockchain network.
+///
+/// To avoid deadlocks, the locks are acquired in this order: `run_gate`, then `admission`, then at
+/// most one node, then the maps of the world, which are only held for short accesses that do not
+/// wait for other locks. A node holding its lock never waits indefinitely for another node:
+/// messages are pushed to inboxes, which do not need the lock of the receiver, and queries to
+/// other nodes give up after `NETWORK_QUERY_TIMEOUT`.
 pub struct World {
     /// The nodes in the blockchain network.
     /// The outer `RwLock` is only write-locked when adding or removing nodes.
     /// The inner `RwLock` is periodically write-locked when a node is running.
     nodes: RwLock<HashMap<Address, Arc<RwLock<Node>>>>,
+    /// The inboxes of the nodes, to deliver messages without locking the nodes.
+    inboxes: RwLock<HashMap<Address, Arc<Inbox>>>,
+    /// The regions of the nodes that have one, to simulate their links without locking the nodes.
+    regions: RwLock<HashMap<Address, String>>,
+    /// Why the nodes whose task failed, and that have not been restarted, stopped.
+    failures: RwLock<HashMap<Address, String>>,
+    /// The hashrates of the nodes, to check the capacity of the world without locking the nodes.
+    hashrates: RwLock<HashMap<Address, f64>>,
+    /// Held while checking that the world can admit new nodes and adding them, so that concurrent
+    /// requests cannot exceed its capacity together.
+    admission: Mutex<()>,
+    /// The HTTP listeners of the nodes that have their own port.
+    listeners: RwLock<HashMap<Address, NodeListener>>,
+    /// Read-locked by the nodes during each iteration of their run loop, and write-locked while
+    /// the world is paused.
+    run_gate: Arc<RwLock<()>>,
+    /// The write lock of `run_gate`, while the world is paused.
+    pause_guard: Mutex<Option<OwnedRwLockWriteGuard<()>>>,
+    /// The consensus engine of the nodes started from now on.
+    consensus: std::sync::RwLock<Arc<dyn ConsensusEngine>>,
+    /// The network that carries the messages between the nodes, created when first used.
+    network: std::sync::OnceLock<Arc<dyn NetworkInterface>>,
 }
 
 static WORLD: OnceCell<World> = OnceCell::const_new();
@@ -18,11 +51,91 @@ pub async fn world() -> &'static World {
     WORLD.get_or_init(|| async { World::new() }).await
 }
 
+impl std::fmt::Debug for World {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("World").finish_non_exhaustive()
+    }
+}
+
 impl World {
-    fn new() -> Self {
+    /// Create an empty world. The simulation runs in the singleton returned by `world()`; other
+    /// worlds are only meant for tests, e.g. to connect two networks in the same process.
+    pub(crate) fn new() -> Self {
         World {
             nodes: RwLock::new(HashMap::new()),
+            inboxes: RwLock::new(HashMap::new()),
+            regions: RwLock::new(HashMap::new()),
+            failures: RwLock::new(HashMap::new()),
+            hashrates: RwLock::new(HashMap::new()),
+            admission: Mutex::new(()),
+            listeners: RwLock::new(HashMap::new()),
+            run_gate: Arc::new(RwLock::new(())),
+            pause_guard: Mutex::new(None),
+            consensus: std::sync::RwLock::new(Arc::new(ProofOfWork)),
+            network: std::sync::OnceLock::new(),
+        }
+    }
+
+    /// The consensus engine of the nodes started from now on.
+    pub fn consensus_engine(&self) -> Arc<dyn ConsensusEngine> {
+        self.consensus.read().unwrap().clone()
+    }
+
+    /// Register the consensus engine of the nodes started from now on, e.g. one implemented by a
+    /// downstream crate. The nodes that already run keep their engine.
+    pub fn set_consensus_engine(&self, consensus: impl ConsensusEngine + 'static) {
+        info!(
+            "The new nodes follow the consensus engine {}",
+            consensus.name()
+        );
+        *self.consensus.write().unwrap() = Arc::new(consensus);
+    }
+
+    /// The network of the world. It is created when first used, with the backend selected by the
+    /// settings at that time, so custom backends must be registered before.
+    pub fn network(&'static self) -> Arc<dyn NetworkInterface> {
+        self.network
+            .get_or_init(|| {
+                let name = config().network_backend();
+                info!("The nodes communicate through the {name} network");
+                create_network(&name, self).unwrap_or_else(|| {
+                    error!("Cannot find the {name} network backend, using the simulated one");
+                    create_network(DEFAULT_NETWORK_BACKEND, self).unwrap()
+                })
+            })
+            .clone()
+    }
+
+    /// Wait until the world is not paused. The world cannot be paused while the returned guard is
+    /// alive, so nodes hold it while they run an iteration of their run loop.
+    pub async fn running(&self) -> RwLockReadGuard<'_, ()> {
+        self.run_gate.read().await
+    }
+
+    /// Freeze the world, waiting for the nodes to finish their current iteration and preventing
+    /// them from starting a new one until the world is resumed. Messages sent in the meantime
+    /// wait in the inboxes of the nodes.
+    pub async fn pause(&self) {
+        let mut pause_guard = self.pause_guard.lock().await;
+        if pause_guard.is_some() {
+            return;
+        }
+        *pause_guard = Some(self.run_gate.clone().write_owned().await);
+        info!("Paused the world");
+        stats().await.record_world_event(WorldEvent::Paused).await;
+    }
+
+    /// Let the nodes run again, after the world has been paused.
+    pub async fn resume(&self) {
+        if self.pause_guard.lock().await.take().is_none() {
+            return;
         }
+        info!("Resumed the world");
+        stats().await.record_world_event(WorldEvent::Resumed).await;
+    }
+
+    pub async fn is_paused(&self) -> bool {
+        self.pause_guard.lock().await.is_some()
     }
 
     /// Get a node by its address.
@@ -30,29 +143,1177 @@ impl World {
         self.nodes.read().await.get(&address).cloned()
     }
 
+    /// Get the inbox of a node by its address.
+    pub async fn get_inbox(&self, address: Address) -> Option<Arc<Inbox>> {
+        self.inboxes.read().await.get(&address).cloned()
+    }
+
+    /// Get the region of a node, if it has one.
+    pub async fn get_region(&self, address: Address) -> Option<String> {
+        self.regions.read().await.get(&address).cloned()
+    }
+
+    /// Get the reason why the task of a node failed, if it did and the node has not been restarted.
+    pub async fn get_failure(&self, address: Address) -> Option<String> {
+        self.failures.read().await.get(&address).cloned()
+    }
+
+    /// Get the port on which a node serves its own endpoints, if it has one.
+    pub async fn get_node_port(&self, address: Address) -> Option<u16> {
+        self.listeners
+            .read()
+            .await
+            .get(&address)
+            .map(|listener| listener.port)
+    }
+
+    /// Get the ports on which the nodes serve their own endpoints, for the nodes that have one.
+    pub async fn get_node_ports(&self) -> BTreeMap<Address, u16> {
+        self.listeners
+            .read()
+            .await
+            .iter()
+            .map(|(address, listener)| (*address, listener.port))
+            .collect()
+    }
+
     /// Get the addresses of all nodes.
     pub async fn get_node_addresses(&self) -> Vec<Address> {
         self.nodes.read().await.keys().cloned().collect()
     }
 
+    /// An overview of every node, sorted by address.
+    pub async fn node_summaries(&self) -> Vec<NodeSummary> {
+        let paused = self.is_paused().await;
+        let mut nodes: Vec<_> = self
+            .nodes
+            .read()
+            .await
+            .iter()
+            .map(|(address, node)| (*address, node.clone()))
+            .collect();
+        nodes.sort_unstable_by_key(|(address, _)| *address);
+        let mut summaries = Vec::with_capacity(nodes.len());
+        for (address, node) in nodes {
+            let failed = self.failures.read().await.contains_key(&address);
+            let readable_node = node.read().await;
+            let blockchain = readable_node.blockchain();
+            let status = if failed {
+                NodeStatus::Failed
+            } else if !readable_node.is_alive() {
+                NodeStatus::Stopped
+            } else if paused {
+                NodeStatus::Paused
+            } else if !readable_node.sync_status().is_synced(blockchain.len()) {
+                NodeStatus::Syncing
+            } else {
+                NodeStatus::Running
+            };
+            summaries.push(NodeSummary {
+                address,
+                status,
+                height: blockchain.height(),
+                tip: blockchain.last_hash().to_string(),
+                labels: readable_node.config().labels.clone(),
+            });
+        }
+        summaries
+    }
+
     /// Add a new node to the world, starting its execution.
-    pub async fn add_node(&self) -> Address {
-        let node = Node::new();
-        info!("Create node {node}");
+    pub async fn add_node(&'static self) -> Result<Address, CapacityError> {
+        self.add_node_with_config(NodeConfig::default()).await
+    }
+
+    /// Add a new node with the given settings to the world, starting its execution.
+    pub async fn add_node_with_config(
+        &'static self,
+        config: NodeConfig,
+    ) -> Result<Address, CapacityError> {
+        let mut addresses = self.add_nodes_with_config(config, 1).await?;
+        Ok(addresses.remove(0))
+    }
+
+    /// Add several new nodes with the given settings to the world, starting their execution. Either
+    /// all the nodes are added, or none of them if the world cannot admit them all.
+    pub async fn add_nodes_with_config(
+        &'static self,
+        node_config: NodeConfig,
+        count: usize,
+    ) -> Result<Vec<Address>, CapacityError> {
+        let _admission = self.admission.lock().await;
+        self.check_capacity(&config(), count, node_config.hashrate * count as f64)
+            .await?;
+        let mut addresses = Vec::with_capacity(count);
+        for _ in 0..count {
+            let mut node = Node::new();
+            node.set_config(node_config.clone());
+            info!("Create node {node}");
+            addresses.push(self.start_node(node).await);
+        }
+        Ok(addresses)
+    }
+
+    /// Add a copy of an existing node, with a new address, to the world. Returns the address of
+    /// the new node, or `None` if the node to copy does not exist.
+    pub async fn clone_node(
+        &'static self,
+        address: Address,
+    ) -> Option<Result<Address, CapacityError>> {
+        let node = self.get_node(address).await?.read().await.fork();
+        info!("Create node {node} as a copy of node {address}");
+        Some(self.insert_node(node).await)
+    }
+
+    /// Add an existing node to the world, starting its execution.
+    pub async fn insert_node(&'static self, node: Node) -> Result<Address, CapacityError> {
+        let _admission = self.admission.lock().await;
+        self.check_capacity(&config(), 1, node.config().hashrate)
+            .await?;
+        Ok(self.start_node(node).await)
+    }
+
+    /// Change the settings of a running node, which apply from the next iteration of its run loop.
+    /// Returns `None` if the node does not exist, or an error if raising its hashrate would exceed
+    /// the capacity of the world.
+    pub async fn set_node_config(
+        &self,
+        address: Address,
+        node_config: NodeConfig,
+    ) -> Option<Result<(), CapacityError>> {
+        let _admission = self.admission.lock().await;
+        let node = self.get_node(address).await?;
+        let mut writable_node = node.write().await;
+        let extra_hashrate = node_config.hashrate - writable_node.config().hashrate;
+        if extra_hashrate > 0.0 {
+            if let Err(err) = self.check_capacity(&config(), 0, extra_hashrate).await {
+                return Some(Err(err));
+            }
+        }
+        info!("Change the settings of node {address}");
+        match &node_config.region {
+            Some(region) => self.regions.write().await.insert(address, region.clone()),
+            None => self.regions.write().await.remove(&address),
+        };
+        self.hashrates
+            .write()
+            .await
+            .insert(address, node_config.hashrate);
+        writable_node.set_config(node_config.clone());
+        drop(writable_node);
+        stats()
+            .await
+            .record_world_event(WorldEvent::NodeConfigChanged {
+                node: address,
+                config: node_config,
+            })
+            .await;
+        Some(Ok(()))
+    }
+
+    /// Check that the world can admit `count` more nodes, at most `WORLD_MAX_NEW_NODES`, whose
+    /// hashrates add up to `hashrate`, within the limits of `limits`.
+    async fn check_capacity(
+        &self,
+        limits: &Config,
+        count: usize,
+        hashrate: f64,
+    ) -> Result<(), CapacityError> {
+        if count > WORLD_MAX_NEW_NODES {
+            warn!("Cannot add {count} nodes at once");
+            return Err(CapacityError::TooManyNewNodes {
+                max_new_nodes: WORLD_MAX_NEW_NODES,
+            });
+        }
+        if let Some(max_nodes) = limits.max_nodes() {
+            let nodes = self.nodes.read().await.len();
+            if count > 0
+                && nodes
+                    .checked_add(count)
+                    .is_none_or(|total| total > max_nodes)
+            {
+                warn!("Cannot add {count} nodes to the {nodes} nodes of the world");
+                return Err(CapacityError::TooManyNodes { max_nodes });
+            }
+        }
+        if let Some(max_total_hashrate) = limits.max_total_hashrate() {
+            let total_hashrate = self.hashrates.read().await.values().sum::<f64>() + hashrate;
+            if total_hashrate > max_total_hashrate {
+                warn!("Cannot raise the total hashrate of the world to {total_hashrate}");
+                return Err(CapacityError::TooMuchHashrate {
+                    total_hashrate,
+                    max_total_hashrate,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Start the execution of a node that the world has admitted.
+    async fn start_node(&'static self, mut node: Node) -> Address {
+        node.set_consensus_engine(self.consensus_engine());
+        node.set_world(self);
         let address = node.address();
+        if let Some(store) = crate::persistence::open_block_store(address) {
+            node.set_block_store(store);
+        }
+        self.inboxes
+            .write()
+            .await
+            .insert(address, node.inbox().clone());
+        if let Some(region) = &node.config().region {
+            self.regions.write().await.insert(address, region.clone());
+        }
+        self.hashrates
+            .write()
+            .await
+            .insert(address, node.config().hashrate);
         let node_arc = Arc::new(RwLock::new(node));
         self.nodes.write().await.insert(address, node_arc.clone());
-        tokio::spawn(run_node(node_arc));
+        let span = tracing::info_span!(crate::logging::NODE_SPAN, address = %address);
+        tokio::spawn(supervise_node(self, address, node_arc).instrument(span));
+        if config().node_ports {
+            if let Ok(listener) = crate::server::serve_node(address) {
+                info!(
+                    "Node {address} serves its endpoints on port {}",
+                    listener.port
+                );
+                self.listeners.write().await.insert(address, listener);
+            }
+        }
+        events().await.publish(Event::NodeAdded { node: address });
+        stats()
+            .await
+            .record_world_event(WorldEvent::NodeAdded { node: address })
+            .await;
         address
     }
 
     /// Remove a node from the world, stopping its execution.
     pub async fn delete_node(&self, address: Address) {
         info!("Remove node {address}");
+        self.inboxes.write().await.remove(&address);
+        self.regions.write().await.remove(&address);
+        self.hashrates.write().await.remove(&address);
+        self.failures.write().await.remove(&address);
+        self.listeners.write().await.remove(&address);
         let Some(node) = self.nodes.write().await.remove(&address) else {
             warn!("Cannot remove inexistent node {address}");
             return;
         };
         node.write().await.stop();
+        events().await.publish(Event::NodeRemoved { node: address });
+        let stats = stats().await;
+        stats
+            .record_world_event(WorldEvent::NodeRemoved { node: address })
+            .await;
+        stats.forget_tips(address).await;
+    }
+
+    /// Record that the task of a node failed. Returns false if the node is not in the world
+    /// anymore.
+    async fn fail_node(&self, address: Address, reason: String) -> bool {
+        if self.get_node(address).await.is_none() {
+            return false;
+        }
+        error!("Node {address} failed: {reason}");
+        self.failures.write().await.insert(address, reason.clone());
+        events().await.publish(Event::NodeFailed {
+            node: address,
+            reason: reason.clone(),
+        });
+        stats()
+            .await
+            .record_world_event(WorldEvent::NodeFailed {
+                node: address,
+                reason,
+            })
+            .await;
+        true
+    }
+
+    /// Replace a failed node with a fresh one recovered from its blocks and pending transactions.
+    /// Returns the new node, or `None` if the failed node is not in the world anymore. The node is
+    /// recovered from the memory of the failed node only, not from the data directory: its blocks
+    /// then replace the ones in the store, if any.
+    async fn restart_node(&'static self, address: Address) -> Option<Arc<RwLock<Node>>> {
+        let mut node = self.get_node(address).await?.read().await.recover();
+        node.set_world(self);
+        if let Some(store) = crate::persistence::open_block_store(address) {
+            node.set_block_store(store);
+        }
+        let node_arc = Arc::new(RwLock::new(node));
+        *self.nodes.write().await.get_mut(&address)? = node_arc.clone();
+        self.failures.write().await.remove(&address);
+        info!("Restart node {address}");
+        events()
+            .await
+            .publish(Event::NodeRestarted { node: address });
+        stats()
+            .await
+            .record_world_event(WorldEvent::NodeRestarted { node: address })
+            .await;
+        Some(node_arc)
+    }
+
+    /// Remove a node from the world gracefully: it stops mining, hands its pending transactions
+    /// over to its peers and announces its departure before being removed.
+    pub async fn drain_node(&self, address: Address) {
+        let Some(node) = self.get_node(address).await else {
+            warn!("Cannot drain inexistent node {address}");
+            return;
+        };
+        let departure = node.write().await.drain();
+        let transactions = departure.send().await;
+        info!("Node {address} handed {transactions} pending transactions over to its peers");
+        self.delete_node(address).await;
+    }
+
+    /// Group the nodes by the last block of their local blockchain.
+    pub async fn convergence(&self) -> Convergence {
+        let nodes: Vec<_> = self.nodes.read().await.values().cloned().collect();
+        let mut tips: BTreeMap<BlockHash, Tip> = BTreeMap::new();
+        for node in nodes {
+            let readable_node = node.read().await;
+            let blockchain = readable_node.blockchain();
+            tips.entry(blockchain.last_hash().clone())
+                .or_insert_with(|| Tip {
+                    hash: blockchain.last_hash().to_string(),
+                    height: blockchain.height(),
+                    supporters: vec![],
+                })
+                .supporters
+                .push(readable_node.address());
+        }
+        let mut tips: Vec<Tip> = tips.into_values().collect();
+        tips.sort_by_key(|tip| std::cmp::Reverse(tip.height));
+        Convergence {
+            converged: tips.len() <= 1,
+            tips,
+        }
+    }
+
+    /// Merge the local blockchains of all nodes, from `min_height` on, into a tree of blocks, e.g.
+    /// to draw the forks. Each block tells how many nodes have it and which nodes end with it.
+    /// The blocks are sorted by height and then by hash, so every block comes after its parent.
+    pub async fn block_tree(&self, min_height: usize) -> Vec<BlockTreeEntry> {
+        let nodes: Vec<_> = self.nodes.read().await.values().cloned().collect();
+        let mut entries: BTreeMap<(usize, BlockHash), BlockTreeEntry> = BTreeMap::new();
+        for node in nodes {
+            let readable_node = node.read().await;
+            let blockchain = readable_node.blockchain();
+            let blocks = blockchain.hashes().zip(blockchain.blocks());
+            for (height, (hash, block)) in blocks.enumerate().skip(min_height) {
+                let entry =
+                    entries
+                        .entry((height, hash.clone()))
+                        .or_insert_with(|| BlockTreeEntry {
+                            hash: hash.clone(),
+                            parent: (!block.is_genesis()).then(|| block.prefix_hash.clone()),
+                            height,
+                            miner: block.miner,
+                            transactions: block.transactions.len(),
+                            nodes: 0,
+                            tip_of: vec![],
+                        });
+                entry.nodes += 1;
+                if height == blockchain.height() {
+                    entry.tip_of.push(readable_node.address());
+                }
+            }
+        }
+        let mut entries: Vec<BlockTreeEntry> = entries.into_values().collect();
+        for entry in &mut entries {
+            entry.tip_of.sort_unstable();
+        }
+        entries
+    }
+
+    /// Audit the supply of the blockchain of every node, raising an alarm for each node whose
+    /// balances do not add up to the issued coins.
+    pub async fn audit_supply(&self) {
+        let nodes: Vec<_> = self.nodes.read().await.values().cloned().collect();
+        for node in nodes {
+            let (address, audit) = {
+                let readable_node = node.read().await;
+                (
+                    readable_node.address(),
+                    readable_node.blockchain().audit_supply(),
+                )
+            };
+            if !audit.consistent {
+                error!(
+                    "Node {address}: The balances add up to {} coins at height {}, but {} coins \
+                    have been issued",
+                    audit.total_balance, audit.height, audit.issued_coins
+                );
+                events().await.publish(Event::SupplyAlarm {
+                    node: address,
+                    audit,
+                });
+            }
+        }
+    }
+
+    /// The statistics of the blocks mined by each node, from the node that earned the most rewards
+    /// to the one that earned the least.
+    pub async fn miner_leaderboard(&self) -> Vec<MinerRank> {
+        let nodes: Vec<_> = self.nodes.read().await.values().cloned().collect();
+        let mut leaderboard = vec![];
+        for node in nodes {
+            let readable_node = node.read().await;
+            leaderboard.push(MinerRank {
+                node: readable_node.address(),
+                stats: readable_node.miner_stats(),
+            });
+        }
+        leaderboard.sort_by_key(|rank| {
+            (
+                std::cmp::Reverse(rank.stats.earned_rewards),
+                std::cmp::Reverse(rank.stats.canonical_blocks),
+                rank.node,
+            )
+        });
+        leaderboard
+    }
+
+    /// Wait until all nodes share the same last block, or until the timeout expires.
+    pub async fn wait_for_convergence(&self, timeout: Duration) -> Convergence {
+        // A timeout too long to represent never expires.
+        let deadline = tokio::time::Instant::now().checked_add(timeout);
+        loop {
+            let convergence = self.convergence().await;
+            if convergence.converged
+                || deadline.is_some_and(|deadline| tokio::time::Instant::now() >= deadline)
+            {
+                return convergence;
+            }
+            tokio::time::sleep(WORLD_POLL_INTERVAL).await;
+        }
+    }
+
+    /// Wait until the last block of a node is not `after` anymore (by default, the last block at
+    /// the time of the call), or until the timeout expires. Returns `None` if the node does not
+    /// exist.
+    pub async fn wait_for_block(
+        &self,
+        address: Address,
+        after: Option<BlockHash>,
+        timeout: Duration,
+    ) -> Option<NodeTip> {
+        let mut after = after;
+        self.wait_for_node(address, timeout, |node| {
+            let blockchain = node.blockchain();
+            let after = after.get_or_insert_with(|| blockchain.last_hash().clone());
+            let advanced = blockchain.last_hash() != after;
+            let tip = NodeTip {
+                advanced,
+                hash: blockchain.last_hash().to_string(),
+                height: blockchain.height(),
+            };
+            (advanced, tip)
+        })
+        .await
+    }
+
+    /// Wait until a transaction has at least the given number of confirmations in the blockchain
+    /// of a node, or until the timeout expires. Returns `None` if the node does not exist.
+    pub async fn wait_for_confirmations(
+        &self,
+        address: Address,
+        transaction_id: TransactionId,
+        confirmations: usize,
+        timeout: Duration,
+    ) -> Option<TransactionConfirmations> {
+        self.wait_for_node(address, timeout, |node| {
+            let blockchain = node.blockchain();
+            let height = blockchain.transaction_height(&transaction_id);
+            let found = height.map_or(0, |height| blockchain.confirmations_at(height));
+            let status = TransactionConfirmations {
+                confirmed: found >= confirmations,
+                confirmations: found,
+                block: height
+                    .and_then(|height| blockchain.hash_at(height))
+                    .map(|hash| hash.to_string()),
+                height,
+            };
+            (status.confirmed, status)
+        })
+        .await
+    }
+
+    /// Check a node until `check` reports that the awaited condition holds, or until the timeout
+    /// expires. Returns the last result of `check`, or `None` if the node does not exist.
+    async fn wait_for_node<T>(
+        &self,
+        address: Address,
+        timeout: Duration,
+        mut check: impl FnMut(&Node) -> (bool, T),
+    ) -> Option<T> {
+        let node = self.get_node(address).await?;
+        // A timeout too long to represent never expires.
+        let deadline = tokio::time::Instant::now().checked_add(timeout);
+        loop {
+            let (done, result) = check(&*node.read().await);
+            if done || deadline.is_some_and(|deadline| tokio::time::Instant::now() >= deadline) {
+                return Some(result);
+            }
+            tokio::time::sleep(WORLD_POLL_INTERVAL).await;
+        }
+    }
+}
+
+/// Run a node in its own task, so that a panic does not go unnoticed: the node is then marked as
+/// failed in the world and, if the configuration says so, restarted after a wait that doubles after
+/// each failure.
+async fn supervise_node(world: &'static World, address: Address, mut node: Arc<RwLock<Node>>) {
+    let mut backoff = WORLD_RESTART_MIN_BACKOFF;
+    loop {
+        let started = std::time::Instant::now();
+        let Err(err) = tokio::spawn(run_node(node.clone()).in_current_span()).await else {
+            return;
+        };
+        let reason = match err.try_into_panic() {
+            Ok(panic) => match panic.downcast::<String>() {
+                Ok(message) => *message,
+                Err(panic) => panic
+                    .downcast_ref::<&str>()
+                    .map_or("unknown panic".to_string(), |message| message.to_string()),
+            },
+            Err(err) => err.to_string(),
+        };
+        if !world.fail_node(address, reason).await || !config().restart_failed_nodes {
+            return;
+        }
+        if started.elapsed() > WORLD_RESTART_MAX_BACKOFF {
+            backoff = WORLD_RESTART_MIN_BACKOFF;
+        }
+        info!("Restarting node {address} in {backoff:?}");
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(WORLD_RESTART_MAX_BACKOFF);
+        let Some(restarted) = world.restart_node(address).await else {
+            return;
+        };
+        node = restarted;
+    }
+}
+
+/// What a node is doing, as shown in the list of the nodes.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NodeStatus {
+    /// The node processes messages and mines.
+    Running,
+    /// The world is paused.
+    Paused,
+    /// The node is catching up with the best known blockchain.
+    Syncing,
+    /// The task of the node failed.
+    Failed,
+    /// The node has been stopped, e.g. while it is being removed.
+    Stopped,
+}
+
+/// An overview of a node, in the list of the nodes.
+#[derive(Debug, Serialize)]
+pub struct NodeSummary {
+    pub address: Address,
+    pub status: NodeStatus,
+    /// The height of the last block of the local blockchain.
+    pub height: usize,
+    /// The hash of the last block of the local blockchain.
+    pub tip: String,
+    /// The labels of the node, from its settings.
+    pub labels: BTreeMap<String, String>,
+}
+
+/// Whether all nodes agree on the last block of the blockchain.
+#[derive(Debug, Serialize)]
+pub struct Convergence {
+    /// True if all nodes share the same last block.
+    pub converged: bool,
+    /// The distinct last blocks of the nodes, from the highest to the lowest.
+    pub tips: Vec<Tip>,
+}
+
+/// The last block of the local blockchain of some nodes.
+#[derive(Debug, Serialize)]
+pub struct Tip {
+    /// The hash of the last block.
+    pub hash: String,
+    /// The height of the last block.
+    pub height: usize,
+    /// The addresses of the nodes whose local blockchain ends with this block.
+    pub supporters: Vec<Address>,
+}
+
+/// A block in the tree of the blocks known by the nodes.
+#[derive(Debug, Serialize)]
+pub struct BlockTreeEntry {
+    pub hash: BlockHash,
+    /// The hash of the previous block, or `None` for the genesis block.
+    pub parent: Option<BlockHash>,
+    pub height: usize,
+    pub miner: Address,
+    /// The number of transactions in the block.
+    pub transactions: usize,
+    /// How many nodes have the block in their local blockchain.
+    pub nodes: usize,
+    /// The addresses of the nodes whose local blockchain ends with this block.
+    pub tip_of: Vec<Address>,
+}
+
+/// The statistics of the blocks mined by a node, in the leaderboard of the miners.
+#[derive(Debug, Serialize)]
+pub struct MinerRank {
+    pub node: Address,
+    #[serde(flatten)]
+    pub stats: MinerStats,
+}
+
+/// The last block of the local blockchain of a node, after waiting for a new one.
+#[derive(Debug, Serialize)]
+pub struct NodeTip {
+    /// True if the last block changed, false if the wait timed out.
+    pub advanced: bool,
+    /// The hash of the last block.
+    pub hash: String,
+    /// The height of the last block.
+    pub height: usize,
+}
+
+/// How deep a transaction is in the blockchain of a node, after waiting for its confirmations.
+#[derive(Debug, Serialize)]
+pub struct TransactionConfirmations {
+    /// True if the transaction has the requested number of confirmations, false if the wait timed
+    /// out.
+    pub confirmed: bool,
+    /// The number of blocks from the one that includes the transaction to the last one.
+    pub confirmations: usize,
+    /// The hash of the block that includes the transaction, if any.
+    pub block: Option<String>,
+    /// The height of the block that includes the transaction, if any.
+    pub height: Option<usize>,
+}
+
+/// The reasons why the world does not admit new nodes.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CapacityError {
+    /// The world would have more nodes than the maximum.
+    TooManyNodes { max_nodes: usize },
+    /// More nodes than the maximum would be created at once.
+    TooManyNewNodes { max_new_nodes: usize },
+    /// The hashrates of the nodes would add up to more than the maximum.
+    TooMuchHashrate {
+        total_hashrate: f64,
+        max_total_hashrate: f64,
+    },
+}
+
+impl CapacityError {
+    /// A stable identifier of the error, reported by the REST API.
+    pub fn code(&self) -> &'static str {
+        match self {
+            CapacityError::TooManyNodes { .. } => "too_many_nodes",
+            CapacityError::TooManyNewNodes { .. } => "too_many_new_nodes",
+            CapacityError::TooMuchHashrate { .. } => "too_much_hashrate",
+        }
+    }
+}
+
+impl std::fmt::Display for CapacityError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            CapacityError::TooManyNodes { max_nodes } => {
+                write!(f, "the world cannot have more than {max_nodes} nodes")
+            }
+            CapacityError::TooManyNewNodes { max_new_nodes } => {
+                write!(f, "cannot create more than {max_new_nodes} nodes at once")
+            }
+            CapacityError::TooMuchHashrate {
+                total_hashrate,
+                max_total_hashrate,
+            } => write!(
+                f,
+                "the total hashrate {total_hashrate} would exceed the maximum of {max_total_hashrate}"
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A new empty world, which lives until the end of the tests like the singleton.
+    fn new_world() -> &'static World {
+        Box::leak(Box::new(World::new()))
+    }
+
+    #[tokio::test]
+    async fn wait_without_deadline() {
+        let world = new_world();
+        let convergence = world.wait_for_convergence(Duration::MAX).await;
+        assert!(convergence.converged);
+    }
+
+    #[tokio::test]
+    async fn wait_for_a_new_block() {
+        let world = new_world();
+        let mut node = Node::restore(Address::new(1), BlockChain::with_difficulty(0), vec![]);
+        node.set_config(NodeConfig {
+            hashrate: 0.0,
+            ..NodeConfig::default()
+        });
+        let genesis_hash = node.blockchain().last_hash().clone();
+        let address = world.insert_node(node).await.unwrap();
+
+        let timeout = Duration::from_millis(200);
+        let tip = world.wait_for_block(address, None, timeout).await.unwrap();
+        assert!(!tip.advanced);
+        assert_eq!(tip.height, 0);
+
+        let block =
+            attempt_mining_block_with_difficulty(genesis_hash.clone(), address, vec![], 0..1, 0)
+                .unwrap();
+        let node = world.get_node(address).await.unwrap();
+        let (tip, height) = tokio::join!(
+            world.wait_for_block(address, Some(genesis_hash), Duration::MAX),
+            async {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                node.write().await.submit_block(block).await
+            }
+        );
+        let tip = tip.unwrap();
+        assert!(tip.advanced);
+        assert_eq!((tip.height, height), (1, Ok(1)));
+        assert!(world
+            .wait_for_block(Address::new(2), None, timeout)
+            .await
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn wait_for_confirmations() {
+        let world = new_world();
+        let mut node = Node::restore(Address::new(1), BlockChain::with_difficulty(0), vec![]);
+        node.set_config(NodeConfig {
+            hashrate: 0.0,
+            ..NodeConfig::default()
+        });
+        let genesis_hash = node.blockchain().last_hash().clone();
+        let address = world.insert_node(node).await.unwrap();
+        let transaction = BlockTransaction::new(
+            genesis_hash.clone(),
+            Transaction::new(Address::FAUCET, Address::new(2), Amount::from_coins(10)),
+        );
+        let first = attempt_mining_block_with_difficulty(
+            genesis_hash,
+            address,
+            vec![transaction.clone()],
+            0..1,
+            0,
+        )
+        .unwrap();
+        let second =
+            attempt_mining_block_with_difficulty(first.hash(), address, vec![], 0..1, 0).unwrap();
+
+        let node = world.get_node(address).await.unwrap();
+        let (status, heights) = tokio::join!(
+            world.wait_for_confirmations(address, transaction.id, 2, Duration::MAX),
+            async {
+                let mut heights = vec![];
+                for block in [first, second] {
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                    heights.push(node.write().await.submit_block(block).await);
+                }
+                heights
+            }
+        );
+        let status = status.unwrap();
+        assert_eq!(heights, vec![Ok(1), Ok(2)]);
+        assert!(status.confirmed);
+        assert_eq!((status.confirmations, status.height), (2, Some(1)));
+    }
+
+    #[tokio::test]
+    async fn merge_forks_in_block_tree() {
+        let world = new_world();
+        let genesis_hash = Block::genesis().hash();
+        let mut tips = vec![];
+        for miner in [Address::new(1), Address::new(2)] {
+            let mut blockchain = BlockChain::with_difficulty(0);
+            let block =
+                attempt_mining_block_with_difficulty(genesis_hash.clone(), miner, vec![], 0..1, 0)
+                    .unwrap();
+            tips.push(block.hash());
+            blockchain.append_block(block).unwrap();
+            let mut node = Node::restore(miner, blockchain, vec![]);
+            node.set_config(NodeConfig {
+                hashrate: 0.0,
+                ..NodeConfig::default()
+            });
+            world.insert_node(node).await.unwrap();
+        }
+
+        let tree = world.block_tree(0).await;
+        assert_eq!(tree.len(), 3);
+        assert_eq!(
+            (tree[0].hash.clone(), tree[0].nodes),
+            (genesis_hash.clone(), 2)
+        );
+        assert!(tree[0].parent.is_none() && tree[0].tip_of.is_empty());
+        for entry in &tree[1..] {
+            assert_eq!(entry.parent.as_ref(), Some(&genesis_hash));
+            assert_eq!(entry.nodes, 1);
+            assert_eq!(entry.tip_of, vec![entry.miner]);
+            assert!(tips.contains(&entry.hash));
+        }
+        assert_eq!(world.block_tree(1).await.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn send_through_the_network_of_the_world() {
+        let idle = || {
+            let mut node = Node::new();
+            node.set_config(NodeConfig {
+                hashrate: 0.0,
+                ..NodeConfig::default()
+            });
+            node
+        };
+        let world = new_world();
+        let alice = world.insert_node(idle()).await.unwrap();
+        let bob = world.insert_node(idle()).await.unwrap();
+        let node = world.get_node(alice).await.unwrap();
+        let network = node.read().await.network();
+        network
+            .send_message(Message::Departure { source: alice }, bob)
+            .await;
+
+        let link = |stats: NetworkStats| stats.links.get(&(alice, bob)).map(|link| link.messages);
+        assert_eq!(link(world.network().stats()), Some(1));
+        assert_eq!(link(crate::world::world().await.network().stats()), None);
+        // The network of the world only reaches the nodes of the world.
+        crate::world::world()
+            .await
+            .network()
+            .send_message(Message::Departure { source: alice }, alice, bob)
+            .await;
+        assert_eq!(link(world.network().stats()), Some(1));
+    }
+
+    #[tokio::test]
+    async fn drain_a_node_to_its_peers() {
+        let world = new_world();
+        let alice = Address::new_random();
+        let mut blockchain = BlockChain::with_difficulty(0);
+        let block = attempt_mining_block_with_difficulty(
+            blockchain.last_hash().clone(),
+            alice,
+            vec![],
+            0..1,
+            0,
+        )
+        .unwrap();
+        blockchain.append_block(block).unwrap();
+        let transaction = BlockTransaction::new(
+            blockchain.last_hash().clone(),
+            Transaction::new(alice, Address::new_random(), Amount::from_coins(1)),
+        );
+        let idle = |node: &mut Node| {
+            node.set_config(NodeConfig {
+                hashrate: 0.0,
+                ..NodeConfig::default()
+            })
+        };
+        let mut node = Node::restore(alice, blockchain, vec![transaction]);
+        idle(&mut node);
+        world.insert_node(node).await.unwrap();
+        let mut peers = vec![];
+        for _ in 0..2 {
+            let mut node = Node::new();
+            idle(&mut node);
+            peers.push(world.insert_node(node).await.unwrap());
+        }
+
+        world.drain_node(alice).await;
+        assert!(world.get_node(alice).await.is_none());
+        // Each peer is sent the pending transaction, then the departure.
+        let stats = world.network().stats();
+        for peer in peers {
+            assert_eq!(stats.links[&(alice, peer)].messages, 2);
+        }
+        let message_types = &stats.message_types;
+        assert_eq!(message_types["transaction"].messages, 2);
+        assert_eq!(message_types["departure"].messages, 2);
+    }
+
+    #[tokio::test]
+    async fn download_the_longest_blockchain_when_joining() {
+        let world = new_world();
+        let idle = || NodeConfig {
+            hashrate: 0.0,
+            ..NodeConfig::default()
+        };
+        let miner = Address::new_random();
+        let mut blockchain = BlockChain::with_difficulty(0);
+        for _ in 0..3 {
+            let prefix_hash = blockchain.last_hash().clone();
+            let block =
+                attempt_mining_block_with_difficulty(prefix_hash, miner, vec![], 0..1, 0).unwrap();
+            blockchain.append_block(block).unwrap();
+        }
+        let mut node = Node::restore(miner, blockchain, vec![]);
+        node.set_config(idle());
+        world.insert_node(node).await.unwrap();
+
+        let mut node = Node::restore(
+            Address::new_random(),
+            BlockChain::with_difficulty(0),
+            vec![],
+        );
+        node.set_config(idle());
+        let address = world.insert_node(node).await.unwrap();
+        assert!(
+            world
+                .wait_for_convergence(Duration::from_secs(10))
+                .await
+                .converged
+        );
+        let node = world.get_node(address).await.unwrap();
+        let readable_node = node.read().await;
+        assert_eq!(readable_node.blockchain().height(), 3);
+        let sync_status = readable_node.sync_status();
+        assert!(!sync_status.syncing);
+        assert_eq!(sync_status.downloaded_blocks, 3);
+        assert_eq!(sync_status.best_known_length, 4);
+        assert!(sync_status.is_synced(4));
+        assert_eq!(sync_status.blocks_remaining(4), 0);
+    }
+
+    #[tokio::test]
+    async fn restart_a_failed_node_from_its_memory() {
+        let world = new_world();
+        let address = Address::new_random();
+        let mut blockchain = BlockChain::with_difficulty(0);
+        let block = attempt_mining_block_with_difficulty(
+            blockchain.last_hash().clone(),
+            address,
+            vec![],
+            0..1,
+            0,
+        )
+        .unwrap();
+        blockchain.append_block(block).unwrap();
+        let mut node = Node::restore(address, blockchain, vec![]);
+        node.set_config(NodeConfig {
+            hashrate: 0.0,
+            ..NodeConfig::default()
+        });
+        world.insert_node(node).await.unwrap();
+        let failed = world.get_node(address).await.unwrap();
+        // The task of a failed node is over.
+        failed.write().await.stop();
+        assert!(world.fail_node(address, "panic".to_string()).await);
+        assert!(world.failures.read().await.contains_key(&address));
+
+        let restarted = world.restart_node(address).await.unwrap();
+        assert!(!Arc::ptr_eq(&restarted, &failed));
+        assert!(Arc::ptr_eq(
+            &world.get_node(address).await.unwrap(),
+            &restarted
+        ));
+        assert!(!world.failures.read().await.contains_key(&address));
+        assert_eq!(
+            restarted.read().await.blockchain().last_hash(),
+            failed.read().await.blockchain().last_hash()
+        );
+        assert_eq!(restarted.read().await.blockchain().height(), 1);
+
+        // A node removed meanwhile is not restarted.
+        world.delete_node(address).await;
+        assert!(world.restart_node(address).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn pause_and_resume() {
+        let world = Arc::new(World::new());
+        let running = world.running().await;
+        let pausing = tokio::spawn({
+            let world = world.clone();
+            async move { world.pause().await }
+        });
+        // The world is paused only after the current iteration finishes.
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(!pausing.is_finished());
+        drop(running);
+        pausing.await.unwrap();
+        assert!(world.is_paused().await);
+
+        let waiting = tokio::spawn({
+            let world = world.clone();
+            async move {
+                let _running = world.running().await;
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(!waiting.is_finished());
+        world.resume().await;
+        waiting.await.unwrap();
+        assert!(!world.is_paused().await);
+    }
+
+    #[tokio::test]
+    async fn pause_the_nodes_of_the_world() {
+        let world = new_world();
+        let mut node = Node::new();
+        node.set_config(NodeConfig {
+            hashrate: 0.0,
+            ..NodeConfig::default()
+        });
+        let address = world.insert_node(node).await.unwrap();
+        world.pause().await;
+        let inbox = world.get_inbox(address).await.unwrap();
+        inbox.push(Message::Departure {
+            source: Address::new_random(),
+        });
+        for _ in 0..100 {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(inbox.len(), 1);
+
+        world.resume().await;
+        while !inbox.is_empty() {
+            tokio::task::yield_now().await;
+        }
+    }
+
+    #[tokio::test]
+    async fn check_the_capacity() {
+        let world = new_world();
+        let node = Node::restore(Address::new(1), BlockChain::with_difficulty(0), vec![]);
+        let address = node.address();
+        world
+            .nodes
+            .write()
+            .await
+            .insert(address, Arc::new(RwLock::new(node)));
+        world.hashrates.write().await.insert(address, 1.5);
+
+        let limits = Config {
+            max_nodes: 3,
+            max_total_hashrate: 2.0,
+            ..Config::default()
+        };
+        assert_eq!(world.check_capacity(&limits, 2, 0.5).await, Ok(()));
+        assert_eq!(
+            world.check_capacity(&limits, 3, 0.0).await,
+            Err(CapacityError::TooManyNodes { max_nodes: 3 })
+        );
+        assert_eq!(
+            world.check_capacity(&limits, 1, 1.0).await,
+            Err(CapacityError::TooMuchHashrate {
+                total_hashrate: 2.5,
+                max_total_hashrate: 2.0
+            })
+        );
+        let unlimited = Config {
+            max_nodes: 0,
+            ..Config::default()
+        };
+        assert_eq!(world.check_capacity(&unlimited, 1000, 1000.0).await, Ok(()));
+        for limits in [limits, unlimited] {
+            assert_eq!(
+                world.check_capacity(&limits, usize::MAX, 0.0).await,
+                Err(CapacityError::TooManyNewNodes {
+                    max_new_nodes: WORLD_MAX_NEW_NODES
+                })
+            );
+        }
+        let too_many = world
+            .add_nodes_with_config(NodeConfig::default(), usize::MAX)
+            .await;
+        assert!(too_many.is_err());
+        assert_eq!(world.nodes.read().await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn change_the_config_of_a_node() {
+        let world = new_world();
+        let node = Node::restore(Address::new(1), BlockChain::with_difficulty(0), vec![]);
+        let address = node.address();
+        world
+            .nodes
+            .write()
+            .await
+            .insert(address, Arc::new(RwLock::new(node)));
+        world.hashrates.write().await.insert(address, 1.0);
+
+        let node_config = NodeConfig {
+            hashrate: 0.5,
+            max_mempool_transactions: Some(10),
+            peer_policy: PeerPolicy::BlocksOnly,
+            region: Some("eu".to_string()),
+            ..NodeConfig::default()
+        };
+        assert_eq!(
+            world.set_node_config(address, node_config.clone()).await,
+            Some(Ok(()))
+        );
+        let node = world.get_node(address).await.unwrap();
+        assert_eq!(node.read().await.config(), &node_config);
+        assert_eq!(world.hashrates.read().await[&address], 0.5);
+        assert_eq!(world.get_region(address).await.as_deref(), Some("eu"));
+
+        let other = Address::new(2);
+        assert_eq!(
+            world.set_node_config(other, NodeConfig::default()).await,
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn summarize_the_nodes() {
+        let world = new_world();
+        for id in [3, 1, 2] {
+            let mut node = Node::restore(Address::new(id), BlockChain::with_difficulty(0), vec![]);
+            node.set_config(NodeConfig {
+                labels: BTreeMap::from([("role".to_string(), format!("node {id}"))]),
+                ..NodeConfig::default()
+            });
+            if id == 3 {
+                node.stop();
+            }
+            world
+                .nodes
+                .write()
+                .await
+                .insert(Address::new(id), Arc::new(RwLock::new(node)));
+        }
+        world
+            .failures
+            .write()
+            .await
+            .insert(Address::new(2), "boom".to_string());
+
+        let summaries = world.node_summaries().await;
+        let statuses: Vec<_> = summaries
+            .iter()
+            .map(|summary| (summary.address, summary.status))
+            .collect();
+        assert_eq!(
+            statuses,
+            vec![
+                (Address::new(1), NodeStatus::Running),
+                (Address::new(2), NodeStatus::Failed),
+                (Address::new(3), NodeStatus::Stopped),
+            ]
+        );
+        assert_eq!(summaries[0].height, 0);
+        assert_eq!(summaries[0].labels["role"], "node 1");
+
+        world.pause().await;
+        assert_eq!(world.node_summaries().await[0].status, NodeStatus::Paused);
     }
 }